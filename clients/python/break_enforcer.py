@@ -0,0 +1,295 @@
+"""Thin client for the break-enforcer api, only needs the standard library.
+
+Follows the wire format described in `src/proto.rs` (run `cargo doc --open`
+and look at `break_enforcer::proto`). Keep the constants below in sync.
+
+    import break_enforcer
+    api = break_enforcer.Api.connect()
+    print(api.status())
+    for update in api.subscribe():
+        print(update["type"], update)
+
+Durations and times in state updates are converted to seconds (floats), times
+are seconds since the unix epoch.
+"""
+
+import os
+import socket
+
+STOP_BYTE = b"\0"
+NAME = "break-enforcer"
+PROTOCOL_VERSION = 2
+PORTS = [49151, 28769, 19788, 62738, 34342, 12846, 8797]
+PORT_ENV = "BREAK_ENFORCER_API_PORT"
+ABSTRACT_SOCKET = "break-enforcer"
+
+UNKNOWN_REQUEST = 1
+INVALID_ARGUMENT = 2
+FAILED = 3
+UNAUTHORIZED = 4
+
+
+class Error(Exception):
+    pass
+
+
+class ServerError(Error):
+    """The service answered with an error frame"""
+
+    def __init__(self, code, message):
+        super().__init__(f"the api server returned an error ({code}): {message}")
+        self.code = code
+        self.message = message
+
+
+class Api:
+    def __init__(self, sock):
+        self._sock = sock
+        self._buf = b""
+        hello = self.request("hello").split(" ")
+        if len(hello) != 3 or hello[0] != NAME:
+            raise Error("the service is not break-enforcer")
+        self.server_version = hello[1]
+        if int(hello[2]) != PROTOCOL_VERSION:
+            raise Error(
+                f"the service speaks protocol version {hello[2]} while this "
+                f"client speaks {PROTOCOL_VERSION}, update the older of the two"
+            )
+
+    @classmethod
+    def connect(cls, port=None):
+        """Tries the abstract unix socket then the loopback ports"""
+        if port is None and PORT_ENV in os.environ:
+            port = int(os.environ[PORT_ENV])
+        if port is None:
+            try:
+                sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+                sock.connect("\0" + ABSTRACT_SOCKET)
+                return cls(sock)
+            except OSError:
+                pass
+        for host in ["127.0.0.1", "::1"]:
+            for port in [port] if port is not None else PORTS:
+                try:
+                    sock = socket.create_connection((host, port), timeout=2)
+                    sock.settimeout(None)
+                    return cls(sock)
+                except (OSError, Error):
+                    continue
+        raise Error("could not connect, is break-enforcer running with --tcp-api?")
+
+    def _read_frame(self):
+        while STOP_BYTE not in self._buf:
+            data = self._sock.recv(4096)
+            if not data:
+                raise Error("the api server closed the connection")
+            self._buf += data
+        frame, self._buf = self._buf.split(STOP_BYTE, 1)
+        frame = frame.decode()
+        if frame.startswith("ERR "):
+            code, _, message = frame[4:].partition(" ")
+            raise ServerError(int(code) if code.isdigit() else 0, message)
+        return frame
+
+    def request(self, name, argument=None):
+        frame = name if argument is None else f"{name} {argument}"
+        self._sock.sendall(frame.encode() + STOP_BYTE)
+        return self._read_frame()
+
+    def status(self):
+        return self.request("status_msg")
+
+    def idle_since(self):
+        return int(self.request("idle_since"))
+
+    def idle_since_ms(self):
+        """Milliseconds idle and when the input stopped, in milliseconds on
+        CLOCK_MONOTONIC (time.monotonic() * 1000)"""
+        idle, since = self.request("idle_since_ms").split(" ")
+        return int(idle), int(since)
+
+    def relock_latency_ms(self):
+        """Milliseconds it took to block the last device that connected
+        while blocked"""
+        return int(self.request("relock_latency_ms"))
+
+    def health(self):
+        """Dict with the `subsystems` of the service, `integrator_lag`,
+        `last_grab_error` and `dropped_events`"""
+        return parse_ron(self.request("health"))
+
+    def daily_budget(self):
+        return int(self.request("daily_budget"))
+
+    def progress(self):
+        """How far through the current period from 0.0 to 1.0"""
+        return float(self.request("progress"))
+
+    def suggest_activity(self):
+        return self.request("suggest_activity")
+
+    def history(self):
+        return parse_ron(self.request("history"))
+
+    def schedule_preview(self, count):
+        """The next `count` breaks (at most 50) as dicts with `at`,
+        `duration`, `kind` and `hard`"""
+        return parse_ron(self.request("schedule_preview", int(count)))
+
+    def activity_map(self):
+        """Start of the oldest minute and a list with a bool per minute"""
+        week = parse_ron(self.request("activity_map"))
+        minutes = [bool(byte >> bit & 1) for byte in week["minutes"] for bit in range(8)]
+        return week["start"], minutes
+
+    def pause(self, seconds):
+        self.request("pause", int(seconds))
+
+    def resume(self):
+        self.request("resume")
+
+    def skip(self):
+        self.request("skip")
+
+    def snooze(self, seconds=None):
+        self.request("snooze", None if seconds is None else int(seconds))
+
+    def unlock(self):
+        self.request("unlock")
+
+    def break_now(self, seconds=None):
+        self.request("break_now", None if seconds is None else int(seconds))
+
+    def extend(self, seconds):
+        self.request("extend", int(seconds))
+
+    def lift_curfew(self):
+        self.request("lift_curfew")
+
+    def meeting(self, seconds):
+        self.request("meeting", int(seconds))
+
+    def end_meeting(self):
+        self.request("end_meeting")
+
+    def block_device(self, device_id):
+        self.request("block_device", device_id)
+
+    def unblock_device(self, device_id):
+        self.request("unblock_device", device_id)
+
+    def reload_config(self):
+        """Have the service read the devices to block from its config again"""
+        self.request("reload_config")
+
+    def subscribe(self):
+        """State updates as they happen, the first is the current state. The
+        connection can not be used for requests afterwards."""
+        self._sock.sendall(b"subscribe" + STOP_BYTE)
+        while True:
+            try:
+                yield parse_ron(self._read_frame())
+            except Error:
+                return
+
+
+def parse_ron(text):
+    """Parses the part of ron the api uses. `Variant(field: ..)` becomes a
+    dict with the variant name under "type"."""
+    value, rest = _parse_value(text.strip())
+    if rest.strip():
+        raise Error(f"unexpected trailing data: {rest[:20]}")
+    return value
+
+
+def _parse_value(text):
+    text = text.lstrip()
+    if text.startswith("["):
+        items, rest = _parse_items(text[1:], "]")
+        return items, rest
+    if text.startswith("("):
+        return _parse_struct(text[1:], None)
+    if text.startswith('"'):
+        return _parse_string(text[1:])
+    end = 0
+    while end < len(text) and (text[end].isalnum() or text[end] in "_.-+"):
+        end += 1
+    word, rest = text[:end], text[end:]
+    if not word:
+        raise Error(f"unexpected: {text[:20]}")
+    if word[0].isdigit() or word[0] in "-+":
+        return (float(word) if "." in word or "e" in word else int(word)), rest
+    if word in ("true", "false"):
+        return word == "true", rest
+    if word == "None":
+        return None, rest
+    if rest.lstrip().startswith("("):
+        rest = rest.lstrip()[1:]
+        if word == "Some":
+            value, rest = _parse_value(rest)
+            return value, _expect(rest, ")")
+        return _parse_struct(rest, word)
+    return word, rest
+
+
+def _parse_struct(text, name):
+    fields = {} if name is None else {"type": name}
+    rest = text.lstrip()
+    while not rest.startswith(")"):
+        key, _, rest = rest.partition(":")
+        value, rest = _parse_value(rest)
+        fields[key.strip()] = value
+        rest = rest.lstrip()
+        if rest.startswith(","):
+            rest = rest[1:].lstrip()
+    rest = rest[1:]
+    if set(fields) == {"secs", "nanos"}:
+        return fields["secs"] + fields["nanos"] / 1e9, rest
+    if set(fields) == {"secs_since_epoch", "nanos_since_epoch"}:
+        return fields["secs_since_epoch"] + fields["nanos_since_epoch"] / 1e9, rest
+    return fields, rest
+
+
+def _parse_items(text, close):
+    items = []
+    rest = text.lstrip()
+    while not rest.startswith(close):
+        value, rest = _parse_value(rest)
+        items.append(value)
+        rest = rest.lstrip()
+        if rest.startswith(","):
+            rest = rest[1:].lstrip()
+    return items, rest[1:]
+
+
+def _parse_string(text):
+    out = []
+    i = 0
+    while text[i] != '"':
+        if text[i] == "\\":
+            i += 1
+            out.append({"n": "\n", "t": "\t", "r": "\r", "0": "\0"}.get(text[i], text[i]))
+        else:
+            out.append(text[i])
+        i += 1
+    return "".join(out), text[i + 1 :]
+
+
+def _expect(text, token):
+    text = text.lstrip()
+    if not text.startswith(token):
+        raise Error(f"expected {token!r} at: {text[:20]}")
+    return text[len(token) :]
+
+
+if __name__ == "__main__":
+    import sys
+
+    api = Api.connect()
+    if sys.argv[1:] == ["subscribe"]:
+        for update in api.subscribe():
+            print(update)
+    elif len(sys.argv) > 1:
+        print(api.request(sys.argv[1], *sys.argv[2:3]))
+    else:
+        print(api.status())
@@ -0,0 +1,5 @@
+use break_enforcer::EventLogger;
+
+fn main() {
+    EventLogger::new("breaks.jsonl").run().unwrap();
+}
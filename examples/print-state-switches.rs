@@ -0,0 +1,16 @@
+use break_enforcer::{Api, StateUpdate};
+
+fn main() {
+    let updates = Api::new().unwrap().subscribe().unwrap();
+
+    for update in updates {
+        match update.unwrap() {
+            StateUpdate::Progress {
+                remaining, total, ..
+            } => {
+                println!("break {:?} of {:?} left", remaining, total);
+            }
+            other => println!("{other:?}"),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use break_enforcer::streamdeck::{Action, StreamDeck};
+
+/// The last two keys pause for half an hour and skip, the others show the
+/// state. Run with: cargo run --example stream-deck --features streamdeck
+fn main() {
+    let deck = StreamDeck::open().unwrap();
+    let last = deck.keys() - 1;
+    deck.run(&[
+        (last - 1, Action::Pause(Duration::from_secs(30 * 60))),
+        (last, Action::Skip),
+    ])
+    .unwrap();
+}
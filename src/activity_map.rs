@@ -0,0 +1,123 @@
+//! Remembers which minutes of the last week had input, one bit per minute in
+//! a ring buffer. Only kept in memory.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use break_enforcer::ActivityMap;
+
+const MINUTES: u64 = 7 * 24 * 60;
+
+struct Ring {
+    /// bit `minute % MINUTES` is set if there was input in that minute
+    bits: Vec<u8>,
+    /// minutes since the unix epoch, later minutes are not cleared yet
+    newest: Option<u64>,
+}
+
+impl Ring {
+    fn index(minute: u64) -> (usize, u8) {
+        let slot = minute % MINUTES;
+        ((slot / 8) as usize, 1 << (slot % 8))
+    }
+
+    fn get(&self, minute: u64) -> bool {
+        let (byte, bit) = Self::index(minute);
+        self.bits[byte] & bit != 0
+    }
+
+    /// Clears the slots of the minutes up to `minute`, they still hold the
+    /// previous week
+    fn advance(&mut self, minute: u64) {
+        let from = match self.newest {
+            Some(newest) if newest >= minute => return,
+            Some(newest) => newest + 1,
+            None => minute,
+        };
+        for minute in (from..=minute).take(MINUTES as usize) {
+            let (byte, bit) = Self::index(minute);
+            self.bits[byte] &= !bit;
+        }
+        self.newest = Some(minute);
+    }
+}
+
+fn unix_minute(at: SystemTime) -> u64 {
+    at.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
+
+#[derive(Clone)]
+pub(crate) struct Recorder(Arc<Mutex<Ring>>);
+
+impl Recorder {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(Mutex::new(Ring {
+            bits: vec![0; MINUTES.div_ceil(8) as usize],
+            newest: None,
+        })))
+    }
+
+    pub(crate) fn record(&self, at: SystemTime) {
+        let minute = unix_minute(at);
+        let mut ring = self.0.lock().expect("nothing can panic with lock held");
+        ring.advance(minute);
+        let newest = ring.newest.expect("set by advance");
+        // the wall clock went back more then a week
+        if newest - minute >= MINUTES {
+            return;
+        }
+        let (byte, bit) = Ring::index(minute);
+        ring.bits[byte] |= bit;
+    }
+
+    /// The week up to and including the minute `now` is in
+    pub(crate) fn week(&self, now: SystemTime) -> ActivityMap {
+        let newest = unix_minute(now);
+        let oldest = (newest + 1).saturating_sub(MINUTES);
+        let mut ring = self.0.lock().expect("nothing can panic with lock held");
+        ring.advance(newest);
+
+        let mut minutes = vec![0u8; MINUTES.div_ceil(8) as usize];
+        for (i, minute) in (oldest..=newest).enumerate() {
+            if ring.get(minute) {
+                minutes[i / 8] |= 1 << (i % 8);
+            }
+        }
+        ActivityMap {
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(oldest * 60),
+            minutes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forgets_after_a_week() {
+        let minute = |m: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(m * 60 + 30);
+        let recorder = Recorder::new();
+        recorder.record(minute(MINUTES + 5));
+        recorder.record(minute(MINUTES + 7));
+
+        let week = recorder.week(minute(MINUTES + 10));
+        assert_eq!(week.len(), MINUTES as usize);
+        let oldest = 11;
+        assert_eq!(
+            week.minute_start(0),
+            minute(oldest) - Duration::from_secs(30)
+        );
+        let active: Vec<_> = (0..week.len()).filter(|&i| week.active(i)).collect();
+        assert_eq!(active, [MINUTES as usize - 6, MINUTES as usize - 4]);
+
+        // a week later the first is forgotten, the second just not yet
+        recorder.record(minute(2 * MINUTES + 6));
+        let week = recorder.week(minute(2 * MINUTES + 6));
+        let active: Vec<_> = (0..week.len()).filter(|&i| week.active(i)).collect();
+        assert_eq!(active, [0, MINUTES as usize - 1]);
+    }
+}
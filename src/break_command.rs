@@ -0,0 +1,214 @@
+//! Running a command for the length of a break, see `--break-command`. For
+//! example a stretching video: `--break-command "mpv ~/stretch.mkv"`. The
+//! command is stopped once the break is over and its output ends up in the
+//! log. A command that can not start or fails does not affect the break.
+//! As root it runs as the user of the active session, see `session::active`,
+//! a `~` is that user's home. Anything it starts is stopped with it.
+
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::{self, Pid};
+use tracing::{debug, info, warn};
+
+use crate::integration::notification::User;
+use crate::session;
+
+/// How long the command gets to exit after being asked to stop
+const STOP_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BreakCommand {
+    args: Vec<String>,
+}
+
+impl BreakCommand {
+    /// Splits the command like a shell would, without running one
+    pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+        let args = shell_words::split(arg).map_err(|e| format!("Invalid command: {e}"))?;
+        if args.is_empty() {
+            return Err("The command can not be empty".to_string());
+        }
+        Ok(Self { args })
+    }
+
+    fn command(&self) -> Result<Command> {
+        if let sudo::RunningAs::User = sudo::check() {
+            let home = unistd::User::from_uid(unistd::getuid())
+                .ok()
+                .flatten()
+                .map(|user| user.dir);
+            let args = expand_home(&self.args, home.as_deref());
+            let (program, args) = args.split_first().expect("parse rejects empty");
+            let mut command = Command::new(program);
+            command.args(args);
+            return Ok(command);
+        }
+
+        // running as root, the command belongs in the session of the user
+        let User { id, name } = session::active()
+            .wrap_err("Could not find the user to run the command for")?
+            .user;
+        let home = unistd::User::from_name(&name)
+            .ok()
+            .flatten()
+            .map(|user| user.dir);
+        let args = expand_home(&self.args, home.as_deref());
+        let (program, args) = args.split_first().expect("parse rejects empty");
+        let mut command = Command::new("sudo");
+        command
+            .args(["-u", &name])
+            .arg(format!("XDG_RUNTIME_DIR=/run/user/{id}"))
+            .arg(format!(
+                "DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{id}/bus"
+            ))
+            .arg(program)
+            .args(args);
+        Ok(command)
+    }
+}
+
+impl Display for BreakCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&shell_words::join(&self.args))
+    }
+}
+
+/// Like a shell expands `~` at the start of a word, without a home it stays
+fn expand_home(args: &[String], home: Option<&Path>) -> Vec<String> {
+    let Some(home) = home else {
+        return args.to_vec();
+    };
+    args.iter()
+        .map(|arg| match arg.strip_prefix('~') {
+            Some("") => home.display().to_string(),
+            Some(rest) if rest.starts_with('/') => format!("{}{rest}", home.display()),
+            _ => arg.clone(),
+        })
+        .collect()
+}
+
+/// The command running during the break, `stop` it once the break is over
+#[must_use]
+pub(crate) struct Running {
+    child: Child,
+    command: String,
+}
+
+pub(crate) fn start(command: &BreakCommand) -> Result<Running> {
+    let mut child = command
+        .command()?
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // its own group, stopping it then also stops what it started
+        .process_group(0)
+        .spawn()
+        .wrap_err("Could not start the break command")
+        .with_note(|| format!("command: {command}"))?;
+    info!("Started break command: {command}");
+    log_lines(child.stdout.take().expect("is set to piped"), "stdout");
+    log_lines(child.stderr.take().expect("is set to piped"), "stderr");
+    Ok(Running {
+        child,
+        command: command.to_string(),
+    })
+}
+
+/// Not joined, anything the command started could keep the pipe open
+fn log_lines(output: impl Read + Send + 'static, name: &'static str) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => info!("break command {name}: {line}"),
+                Err(e) => {
+                    debug!("Stopped reading break command {name}: {e}");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+impl Running {
+    /// Asks the command and anything it started to exit, kills them if the
+    /// command does not in time
+    pub(crate) fn stop(mut self) {
+        let group = Pid::from_raw(self.child.id().try_into().expect("pids fit in an i32"));
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                if status.success() {
+                    debug!("Break command exited by itself: {}", self.command);
+                } else {
+                    warn!("Break command failed ({status}): {}", self.command);
+                }
+                // it could have left something running
+                let _ = signal::killpg(group, Signal::SIGTERM);
+                return;
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Could not check on the break command: {e}"),
+        }
+
+        match signal::killpg(group, Signal::SIGTERM) {
+            Ok(()) | Err(Errno::ESRCH) => (),
+            Err(e) => warn!("Could not ask the break command to stop: {e}"),
+        }
+        let asked = Instant::now();
+        while asked.elapsed() < STOP_GRACE {
+            match self.child.try_wait() {
+                Ok(Some(_)) => {
+                    debug!("Break command stopped: {}", self.command);
+                    return;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(50)),
+                Err(_) => break,
+            }
+        }
+        warn!(
+            "Break command did not stop within {STOP_GRACE:?}, killing it: {}",
+            self.command
+        );
+        let _ = signal::killpg(group, Signal::SIGKILL);
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_like_a_shell() {
+        let command = BreakCommand::parse("mpv --fs '/home/me/stretch video.mkv'").unwrap();
+        assert_eq!(command.args, ["mpv", "--fs", "/home/me/stretch video.mkv"]);
+        assert_eq!(BreakCommand::parse(&command.to_string()), Ok(command));
+        assert!(BreakCommand::parse("  ").is_err());
+        assert!(BreakCommand::parse("mpv 'unclosed").is_err());
+    }
+
+    #[test]
+    fn expands_home_like_a_shell() {
+        let args = ["mpv", "~/stretch.mkv", "~", "a~/b", "~other/c"].map(String::from);
+        assert_eq!(
+            expand_home(&args, Some(Path::new("/home/me"))),
+            [
+                "mpv",
+                "/home/me/stretch.mkv",
+                "/home/me",
+                "a~/b",
+                "~other/c"
+            ]
+        );
+        assert_eq!(expand_home(&args, None), args);
+    }
+}
@@ -0,0 +1,299 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use color_eyre::{Result, Section};
+
+use crate::cli::RunArgs;
+use crate::compositor::Backend;
+use crate::config;
+use crate::watch_and_block;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Severity {
+    Warning,
+    Error,
+}
+
+struct Finding {
+    severity: Severity,
+    msg: String,
+    suggestion: Option<&'static str>,
+}
+
+impl Finding {
+    fn error(msg: impl Into<String>, suggestion: &'static str) -> Self {
+        Self {
+            severity: Severity::Error,
+            msg: msg.into(),
+            suggestion: Some(suggestion),
+        }
+    }
+
+    fn warning(msg: impl Into<String>, suggestion: &'static str) -> Self {
+        Self {
+            severity: Severity::Warning,
+            msg: msg.into(),
+            suggestion: Some(suggestion),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{label}: {}", self.msg)?;
+        if let Some(suggestion) = self.suggestion {
+            write!(f, "\n  suggestion: {suggestion}")?;
+        }
+        Ok(())
+    }
+}
+
+fn check_config(config_path: Option<PathBuf>, no_block: bool, findings: &mut Vec<Finding>) {
+    if let Err(report) = config::settings(config_path.clone()) {
+        findings.push(Finding::error(
+            format!("{report:#}"),
+            "Fix the settings at the top of the config",
+        ));
+    }
+    let to_block = match config::read(config_path) {
+        Ok(to_block) => to_block,
+        Err(report) => {
+            findings.push(Finding::error(
+                format!("{report:#}"),
+                "Fix the config by hand or recreate it using the wizard",
+            ));
+            return;
+        }
+    };
+
+    if to_block.is_empty() {
+        if !no_block {
+            findings.push(Finding::error(
+                "No devices configured, nothing would be blocked",
+                "Run the wizard or pass --no-block",
+            ));
+        }
+        return;
+    }
+
+    let (online, _) = watch_and_block::devices();
+    let online = match online.list_inputs() {
+        Ok(online) => online,
+        Err(report) => {
+            findings.push(Finding::warning(
+                format!("Could not list connected devices: {report:#}"),
+                "Run the devices subcommand for more details",
+            ));
+            return;
+        }
+    };
+
+    for filter in &to_block {
+        for name in &filter.names {
+            let connected = online
+                .iter()
+                .filter(|input| input.id == filter.id)
+                .any(|input| input.names.contains(name));
+            if !connected {
+                findings.push(Finding::warning(
+                    format!("Device is not connected: {name} (id: {})", filter.id),
+                    "Connect it or remove it from the config using the wizard",
+                ));
+            }
+        }
+    }
+}
+
+fn check_durations(args: &RunArgs, findings: &mut Vec<Finding>) {
+    if args.work_duration.is_zero() {
+        findings.push(Finding::error(
+            "The work duration is zero",
+            "Pass a longer work duration",
+        ));
+    }
+    if args.break_duration.is_zero() {
+        findings.push(Finding::error(
+            "The break duration is zero, input would never be blocked",
+            "Pass a longer break duration",
+        ));
+    }
+    if args
+        .break_jitter
+        .is_some_and(|jitter| jitter >= args.work_duration)
+    {
+        findings.push(Finding::error(
+            "The break jitter is as long as or longer than the work duration",
+            "Pass a break jitter shorter than the work duration",
+        ));
+    }
+    if args
+        .first_work_duration
+        .is_some_and(|first| first.is_zero())
+    {
+        findings.push(Finding::error(
+            "The first work duration is zero, the first break would start right away",
+            "Pass a longer first work duration or leave it out",
+        ));
+    }
+    if let Some(lock_warning) = args.lock_warning {
+        if lock_warning >= args.work_duration {
+            findings.push(Finding::warning(
+                "The lock warning is as long as or longer than the work duration, \
+                it will be send as soon as work starts",
+                "Pass a lock warning shorter than the work duration",
+            ));
+        }
+    }
+    if args.progress_interval.is_zero() {
+        findings.push(Finding::error(
+            "The progress interval is zero",
+            "Pass a longer progress interval",
+        ));
+    }
+    if args.api_port.is_some() && !args.tcp_api {
+        findings.push(Finding::warning(
+            "An api port is set but the tcp api is not enabled",
+            "Pass --tcp-api or drop --api-port",
+        ));
+    }
+    if args.lock_warning.is_some() && args.lock_warning_type.is_empty() {
+        findings.push(Finding::warning(
+            "A lock warning is set but no way to deliver it",
+            "Pass one or more notification types using --lock-warning-type",
+        ));
+    }
+}
+
+fn check_dependencies(args: &RunArgs, findings: &mut Vec<Finding>) {
+    for warning_type in &args.lock_warning_type {
+        if let Err(report) = warning_type.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not send {warning_type} notifications: {report:#}"),
+                "Install the missing program or drop the notification type",
+            ));
+        }
+    }
+    if !args.no_block {
+        if let Err(report) = args.block_backend.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not block using {}: {report:#}", args.block_backend),
+                "Use the grab backend (the default) or fix the problem above",
+            ));
+        }
+    }
+    if args.allow_pointer {
+        if let Err(report) = crate::forward::check_available() {
+            findings.push(Finding::error(
+                format!("Can not keep the pointer moving: {report:#}"),
+                "Run as root, load the uinput module or drop --allow-pointer",
+            ));
+        }
+        if args.block_backend.resolve() != Backend::Grab {
+            findings.push(Finding::error(
+                "The pointer can only be kept moving by the grab backend",
+                "Use the grab backend (the default) or drop --allow-pointer",
+            ));
+        }
+    }
+    if args.nag_bar || args.break_workspace.is_some() {
+        if let Err(report) = crate::integration::check_window_manager(args.nag_bar) {
+            findings.push(Finding::error(
+                format!("Can not show the nag bar or switch workspace: {report:#}"),
+                "Run inside sway or i3 or drop --nag-bar and --break-workspace",
+            ));
+        }
+    }
+    if let Some(path) = &args.break_activities {
+        if let Err(report) = crate::integration::Activities::read(path) {
+            findings.push(Finding::error(
+                format!("Can not use the break activities: {report:#}"),
+                "Fix the file or drop --break-activities to use the built in list",
+            ));
+        }
+    }
+    if args.notifications {
+        if let Err(report) = crate::integration::NotificationType::System.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not send state notifications: {report:#}"),
+                "Install the missing program or drop --notifications",
+            ));
+        }
+    }
+    if args.notify_warnings {
+        if let Err(report) = crate::integration::NotificationType::System.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not send warnings: {report:#}"),
+                "Install the missing program or drop --notify-warnings",
+            ));
+        }
+    }
+    if args.ask_new_keyboards {
+        if let Err(report) = crate::integration::NotificationType::System.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not ask about new keyboards: {report:#}"),
+                "Install the missing program or drop --ask-new-keyboards",
+            ));
+        }
+    }
+    if args.min_notice.is_some() {
+        if let Err(report) = crate::integration::NotificationType::System.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not notify ahead of locking: {report:#}"),
+                "Install the missing program or drop --min-notice",
+            ));
+        }
+    }
+    if args.announce.is_some() {
+        if let Err(report) = crate::integration::speech::check_available() {
+            findings.push(Finding::error(
+                format!("Can not announce breaks: {report:#}"),
+                "Install speech-dispatcher or drop --announce",
+            ));
+        }
+    }
+    if args.when_unplugged == Some(crate::run::WhenUnplugged::Notify) {
+        if let Err(report) = crate::integration::NotificationType::System.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not notify when the devices are unplugged: {report:#}"),
+                "Install the missing program or pick another --when-unplugged policy",
+            ));
+        }
+    }
+    if args.daily_goal.is_some() || args.summary_at.is_some() {
+        if let Err(report) = crate::integration::NotificationType::System.check_dependency() {
+            findings.push(Finding::error(
+                format!("Can not send the summary of the day: {report:#}"),
+                "Install the missing program or drop --daily-goal and --summary-at",
+            ));
+        }
+    }
+}
+
+/// Prints all problems with the config and arguments. Returns an error if any
+/// of them would stop `run` from working.
+pub(crate) fn run(args: &RunArgs, config_path: Option<PathBuf>) -> Result<()> {
+    let mut findings = Vec::new();
+    check_config(config_path, args.no_block, &mut findings);
+    check_durations(args, &mut findings);
+    check_dependencies(args, &mut findings);
+
+    for finding in &findings {
+        println!("{finding}");
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        Err(eyre!("Found {errors} problem(s) that need fixing")).suppress_backtrace(true)
+    } else {
+        println!("Config and arguments look good");
+        Ok(())
+    }
+}
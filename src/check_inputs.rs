@@ -1,142 +1,473 @@
 use std::{
-    fs::{self, File},
+    collections::VecDeque,
+    fs::File,
     io::{self, Read},
     sync::{
-        mpsc::{self, channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
         Arc, Mutex,
     },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
-use color_eyre::eyre::Context;
+use break_enforcer::PlannedBreak;
+use evdev::{EventType, KeyCode, SwitchCode};
+use tracing::{debug, info, warn};
 
-use crate::{config::InputFilter, watch_and_block::NewInput};
+use crate::{
+    activity_map,
+    clock::{self, Alarm, Clock},
+    config::{ActivityThreshold, BlockList},
+    control::{Control, Reply},
+    input_queue::{self, InputReceiver, InputSender, Sent},
+    joystick::{self, LegacyJoystick},
+    password_prompt, paths, privsep, screen_lock, sync,
+    watch_and_block::{GrabFailed, NewInput, SlowRelock},
+};
 
-pub struct InactivityTracker {
-    idle_since: Arc<Mutex<Instant>>,
-    reset_notify: mpsc::Receiver<color_eyre::Result<()>>,
+/// Everything the run loop reacts to
+pub(crate) enum Event {
+    /// Input on a blocked device, at most a few per second
+    Input,
+    /// The break chord was held down, see `--break-chord`
+    BreakChord,
+    /// No input on blocked devices for a break duration
+    Idle,
+    InputError(color_eyre::Report),
+    Control(Control, Reply),
+    /// Asks the run loop for the next breaks, see `Api::schedule_preview`
+    Preview(usize, mpsc::Sender<Vec<PlannedBreak>>),
+    /// A peer started a work period or break, see `sync`
+    Remote(sync::Message),
+    /// An input device was connected or disconnected
+    DevicesChanged,
+    /// A device could not be blocked
+    GrabFailed(GrabFailed),
+    /// A device that could not be blocked was on a retry, holds its name
+    GrabRetried(String),
+    /// A device that reconnected was not blocked again right away
+    SlowRelock(SlowRelock),
+    /// The lid or dock switch changed, see `PowerAction`
+    Power(PowerEvent),
+    /// Wakes `InactivityTracker::next`, see `clock::Alarm`. Never returned.
+    Alarm,
+}
+
+/// Switches on a laptop that hint at a break, see `--on-lid-close` and
+/// `--on-undock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PowerEvent {
+    LidClosed,
+    LidOpened,
+    Undocked,
+    Docked,
+}
+
+impl PowerEvent {
+    /// True for the lid closing or undocking, the user probably left
+    pub(crate) fn away(self) -> bool {
+        matches!(self, PowerEvent::LidClosed | PowerEvent::Undocked)
+    }
 }
 
-pub enum TrackResult {
-    ShouldReset,
-    ShouldBreak { user_idle: Duration },
-    Error(color_eyre::Report),
+impl std::fmt::Display for PowerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PowerEvent::LidClosed => "Lid closed",
+            PowerEvent::LidOpened => "Lid opened",
+            PowerEvent::Undocked => "Undocked",
+            PowerEvent::Docked => "Docked",
+        })
+    }
+}
+
+pub struct InactivityTracker {
+    idle_since: Arc<Mutex<Instant>>,
+    /// input is ignored while set, see `set_away`
+    away: Arc<AtomicBool>,
+    activity_map: activity_map::Recorder,
+    clock: Arc<dyn Clock>,
+    events: Receiver<Event>,
+    sender: Sender<Event>,
+    /// wakes `next` at its deadline even across a suspend
+    alarm: Option<Alarm>,
+    /// an `Event::Input` is queued, see `watch_activity`
+    input_queued: Arc<AtomicBool>,
+    /// controls and peer messages received while discarding stale events
+    pending: VecDeque<Event>,
 }
 
 impl InactivityTracker {
-    pub fn new(input_receiver: Receiver<InputResult>, break_duration: Duration) -> Self {
-        let idle_since = Arc::new(Mutex::new(Instant::now()));
+    pub(crate) fn new(
+        input_receiver: InputReceiver,
+        break_duration: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let idle_since = Arc::new(Mutex::new(clock.now()));
+        let away = Arc::new(AtomicBool::new(false));
+        let activity_map = activity_map::Recorder::new();
+        let input_queued = Arc::new(AtomicBool::new(false));
         let (tx, rx) = mpsc::channel();
         {
             let idle_since = idle_since.clone();
-            thread::spawn(move || watch_activity(&input_receiver, break_duration, idle_since, tx));
+            let away = away.clone();
+            let activity_map = activity_map.clone();
+            let clock = clock.clone();
+            let input_queued = input_queued.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                watch_activity(
+                    &input_receiver,
+                    break_duration,
+                    idle_since,
+                    &away,
+                    &activity_map,
+                    &*clock,
+                    &input_queued,
+                    tx,
+                );
+            });
         }
 
+        let alarm = {
+            let tx = tx.clone();
+            clock.alarm(Box::new(move || tx.send(Event::Alarm).is_ok()))
+        };
         Self {
             idle_since,
-            reset_notify: rx,
+            away,
+            activity_map,
+            clock,
+            alarm,
+            events: rx,
+            sender: tx,
+            input_queued,
+            pending: VecDeque::new(),
         }
     }
-    pub fn reset_or_timeout(&mut self, work_duration: Duration) -> TrackResult {
-        // Empty the reset_notify. At this point in the program we just left a
-        // period without input (waiting or break). Therefore there has been no user
-        // activity until here. Any reset notification received after emptying
-        // the channel must have been send after the period without input and
-        // therefore at least a break duration must have elapsed.
+
+    /// Lets the api send controls to the run loop
+    pub(crate) fn sender(&self) -> Sender<Event> {
+        self.sender.clone()
+    }
+
+    /// Drops the input and idle events received until now. Call when
+    /// switching state, these events belong to the previous state. For
+    /// example after a break: the idle event send because there was no input
+    /// during the break should not reset the work period that follows.
+    pub(crate) fn discard_stale(&mut self) -> color_eyre::Result<()> {
+        loop {
+            match self.events.try_recv().inspect(|event| self.taken(event)) {
+                Ok(Event::Input | Event::Idle | Event::BreakChord | Event::Alarm) => (),
+                Ok(Event::InputError(e)) => return Err(e),
+                Ok(
+                    event @ (Event::Control(..)
+                    | Event::Preview(..)
+                    | Event::Remote(_)
+                    | Event::DevicesChanged
+                    | Event::GrabFailed(_)
+                    | Event::GrabRetried(_)
+                    | Event::SlowRelock(_)
+                    | Event::Power(_)),
+                ) => {
+                    self.pending.push_back(event);
+                }
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => unreachable!("we hold a sender"),
+            }
+        }
+    }
+
+    /// None once `timeout` passed without an event
+    pub(crate) fn next(&mut self, timeout: Duration) -> Option<Event> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(event);
+        }
+        if let Some(alarm) = &self.alarm {
+            alarm.set(timeout);
+        }
+        let deadline = self.clock.now().checked_add(timeout);
         loop {
-            match self.reset_notify.try_recv() {
-                Ok(Err(e)) => return TrackResult::Error(e),
-                Ok(Ok(())) => (),
-                Err(TryRecvError::Empty) => break,
-                Err(TryRecvError::Disconnected) => unreachable!(),
+            let remaining = deadline.map_or(Duration::MAX, |at| self.clock.duration_until(at));
+            match clock::recv_timeout(&*self.clock, &self.events, remaining) {
+                // this alarm or one set by an earlier call, either way the
+                // deadline is checked again
+                Ok(Event::Alarm) => (),
+                Ok(event) => {
+                    self.taken(&event);
+                    return Some(event);
+                }
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => unreachable!("we hold a sender"),
             }
         }
+    }
 
-        match self.reset_notify.recv_timeout(work_duration) {
-            Ok(Ok(())) => TrackResult::ShouldReset,
-            Ok(Err(e)) => TrackResult::Error(e),
-            Err(RecvTimeoutError::Timeout) => TrackResult::ShouldBreak {
-                user_idle: self.idle_since.lock().unwrap().elapsed(),
-            },
-            Err(RecvTimeoutError::Disconnected) => unreachable!(),
+    fn taken(&self, event: &Event) {
+        if let Event::Input = event {
+            self.input_queued.store(false, Ordering::Relaxed);
         }
     }
 
+    pub(crate) fn user_idle(&self) -> Duration {
+        self.clock.elapsed(*self.idle_since.lock().unwrap())
+    }
+
+    /// While away, for example with the lid closed, input from external
+    /// devices does not count and the idle time keeps growing
+    pub(crate) fn set_away(&self, away: bool) {
+        self.away.store(away, Ordering::Relaxed);
+    }
+
     pub fn idle_handle(&self) -> Arc<Mutex<Instant>> {
         self.idle_since.clone()
     }
+
+    pub(crate) fn activity_map(&self) -> activity_map::Recorder {
+        self.activity_map.clone()
+    }
 }
 
+/// At most one `Event::Input` is queued for the run loop, more would say
+/// nothing new. Anything else is always send.
+#[allow(clippy::too_many_arguments)]
 fn watch_activity(
-    input_receiver: &Receiver<InputResult>,
+    input_receiver: &InputReceiver,
     break_duration: Duration,
     idle_since: Arc<Mutex<Instant>>,
-    reset_notify: mpsc::Sender<color_eyre::Result<()>>,
+    away: &AtomicBool,
+    activity_map: &activity_map::Recorder,
+    clock: &dyn Clock,
+    input_queued: &AtomicBool,
+    events: mpsc::Sender<Event>,
 ) {
+    let mut idle_at = clock.now() + break_duration;
+    let mut screen_lock = ScreenLock::default();
     loop {
-        match input_receiver.recv_timeout(break_duration) {
-            Ok(Ok(())) => *idle_since.lock().unwrap() = Instant::now(),
-            Err(RecvTimeoutError::Timeout) => reset_notify.send(Ok(())).unwrap(),
-            Err(RecvTimeoutError::Disconnected) => unreachable!(),
-            Ok(err @ Err(_)) => {
-                let err = err.wrap_err("test");
-                reset_notify.send(err).unwrap();
+        let timeout = clock.duration_until(idle_at);
+        let event = match clock::recv_timeout(clock, input_receiver, timeout) {
+            Ok(Ok(Input::ScreenLock(true))) => {
+                screen_lock.signalled = Some((clock.now(), true));
+                continue;
+            }
+            Ok(Ok(Input::Power(event))) => Event::Power(event),
+            Ok(Ok(Input::Activity | Input::Chord)) if away.load(Ordering::Relaxed) => continue,
+            // typing the password does not count, locked time is idle time
+            Ok(Ok(Input::Activity | Input::Chord)) if screen_lock.locked(clock) => continue,
+            Ok(Ok(input)) => {
+                idle_at = clock.now() + break_duration;
+                *idle_since.lock().unwrap() = clock.now();
+                activity_map.record(SystemTime::now());
+                match input {
+                    Input::Activity => Event::Input,
+                    Input::Chord => Event::BreakChord,
+                    // the user is back
+                    Input::ScreenLock(_) => {
+                        screen_lock.signalled = Some((clock.now(), false));
+                        Event::Input
+                    }
+                    Input::Power(_) => unreachable!("handled above"),
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                idle_at = clock.now() + break_duration;
+                Event::Idle
+            }
+            // the watcher is gone, happens in tests
+            Err(RecvTimeoutError::Disconnected) => return,
+            Ok(Err(e)) => Event::InputError(
+                color_eyre::Report::new(e).wrap_err("Could not read input device"),
+            ),
+        };
+        if let Event::Input = event {
+            if input_queued.swap(true, Ordering::Relaxed) {
+                dropped(&DROPPED_INPUT_EVENTS, "input events for the run loop");
+                continue;
             }
         }
+        if events.send(event).is_err() {
+            return; // the tracker is gone
+        }
+    }
+}
+
+/// Whether the screen is locked. A signal from logind is trusted for
+/// `SIGNALLED_VALID`, a missed signal then does not stick. Otherwise logind
+/// is asked, at most every `VALID` as that is slow.
+#[derive(Default)]
+struct ScreenLock {
+    checked: Option<(Instant, bool)>,
+    /// set by the last signal, see `screen_lock`
+    signalled: Option<(Instant, bool)>,
+}
+
+impl ScreenLock {
+    const VALID: Duration = Duration::from_secs(5);
+    const SIGNALLED_VALID: Duration = Duration::from_secs(60);
+
+    fn locked(&mut self, clock: &dyn Clock) -> bool {
+        match self.signalled {
+            Some((at, locked)) if clock.elapsed(at) < Self::SIGNALLED_VALID => return locked,
+            Some(_) => self.signalled = None,
+            None => (),
+        }
+        match self.checked {
+            Some((at, locked)) if clock.elapsed(at) < Self::VALID => locked,
+            _ => {
+                let locked = password_prompt::screen_locked();
+                self.checked = Some((clock.now(), locked));
+                locked
+            }
+        }
+    }
+}
+
+/// What a monitored device reports
+pub enum Input {
+    Activity,
+    /// The break chord was held down long enough
+    Chord,
+    /// The screen was locked (true) or unlocked, see `screen_lock`
+    ScreenLock(bool),
+    /// Reported for every device, blocked or not
+    Power(PowerEvent),
+}
+
+pub type InputResult = Result<Input, Arc<io::Error>>;
+
+/// Room for input between the devices and `watch_activity`, see
+/// `input_queue`
+const INPUT_CAPACITY: usize = 64;
+
+/// Activity dropped as `watch_activity` fell behind, the oldest goes first
+pub(crate) static DROPPED_ACTIVITY: AtomicU64 = AtomicU64::new(0);
+/// `Event::Input` not send as one was still queued for the run loop
+pub(crate) static DROPPED_INPUT_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a dropped event, logs the total on powers of two so a stuck
+/// receiver shows up without flooding the log
+fn dropped(counter: &AtomicU64, what: &str) {
+    let total = counter.fetch_add(1, Ordering::Relaxed) + 1;
+    if total.is_power_of_two() {
+        info!("Dropped {total} {what} so far, the receiver is falling behind");
+    }
+}
+
+/// False once the receiver is gone
+fn send_activity(tx: &InputSender) -> bool {
+    match tx.send_activity() {
+        Some(Sent::Queued) => true,
+        Some(Sent::DroppedOldest | Sent::Dropped) => {
+            dropped(&DROPPED_ACTIVITY, "activity reports");
+            true
+        }
+        None => false,
     }
 }
 
-pub type InputResult = Result<(), Arc<io::Error>>;
+/// What `watcher` follows besides the input of the devices
+#[derive(Default)]
+pub(crate) struct WatchOptions {
+    /// see `--break-chord`, with how long it is held
+    pub(crate) break_chord: Option<(Chord, Duration)>,
+    /// report the screen locking and unlocking
+    pub(crate) follow_screen_lock: bool,
+    /// also monitor joysticks that only have a `js*` node, see `joystick`
+    pub(crate) legacy_joysticks: bool,
+    /// report activity this often, it only counts while the screen is
+    /// unlocked
+    pub(crate) assume_active: Option<Duration>,
+}
 
+/// Every device is monitored, only input from devices on the block list is
+/// reported. That way devices blocked at runtime count as activity without
+/// them needing to be reconnected.
 pub(crate) fn watcher(
     just_connected: Receiver<NewInput>,
-    to_block: Vec<InputFilter>,
-) -> (Receiver<InputResult>, Receiver<InputResult>) {
-    let (tx1, rx1) = channel();
-    let (tx2, rx2) = channel();
+    to_block: BlockList,
+    options: WatchOptions,
+) -> InputReceiver {
+    let WatchOptions {
+        break_chord,
+        follow_screen_lock,
+        legacy_joysticks,
+        assume_active,
+    } = options;
+    let (tx, rx) = input_queue::bounded(INPUT_CAPACITY);
+    if follow_screen_lock {
+        screen_lock::follow(tx.clone());
+    }
+    if let Some(period) = assume_active {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            while send_activity(&tx) {
+                thread::sleep(period);
+            }
+        });
+    }
+    if legacy_joysticks {
+        let tx = tx.clone();
+        let to_block = to_block.clone();
+        joystick::follow(paths::dev_dir().to_path_buf(), move |joystick| {
+            if to_block.matches(&joystick.id, &joystick.name, None) {
+                warn!(
+                    "{} only has a legacy joystick node, its input counts as \
+                    activity but it can not be blocked",
+                    joystick.name
+                );
+            }
+            let tx = tx.clone();
+            let to_block = to_block.clone();
+            thread::spawn(move || monitor_legacy(&joystick, &to_block, &tx));
+        });
+    }
 
     thread::spawn(move || loop {
         let new_device = just_connected
             .recv()
             .expect("only disconnects at program exit");
-        if !to_block
-            .iter()
-            .filter(|filter| filter.id == new_device.id)
-            .any(|filter| filter.names.contains(&new_device.name))
-        {
-            continue;
-        }
 
-        let tx1 = tx1.clone();
-        let tx2 = tx2.clone();
+        let tx = tx.clone();
+        let to_block = to_block.clone();
+        let chord = break_chord
+            .clone()
+            .map(|(chord, hold)| ChordDetector::new(chord, hold));
         thread::Builder::new()
-            .spawn(move || monitor_input(new_device, &tx1, &tx2))
+            .spawn(move || monitor_input(new_device, &to_block, chord, &tx))
             .expect("the OS should be able to spawn a thread");
     });
 
-    (rx1, rx2)
+    rx
 }
 
 fn monitor_input(
     input: NewInput,
-    tx1: &Sender<InputResult>,
-    tx2: &Sender<InputResult>,
+    to_block: &BlockList,
+    mut chord: Option<ChordDetector>,
+    tx: &InputSender,
 ) {
-    let mut file = match fs::File::open(input.path) {
+    let mut file = match privsep::open_device(&input.path) {
         // means the device is disconnected
         Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) if !to_block.matches(&input.id, &input.name, input.address.as_deref()) => {
+            debug!("Not monitoring unblocked device {}: {e}", input.name);
+            return;
+        }
         Err(e) => {
             // unexpected error, report to main thread
-            let err = Arc::new(e); // make cloneable
-            let _ig_err = tx1.send(Err(err.clone()));
-            let _ig_err = tx2.send(Err(err));
+            let _ig_err = tx.send(Err(Arc::new(e)));
             return;
         }
         Ok(file) => file,
     };
+    let mut coalesce = Coalesce::new();
+    let mut threshold = to_block
+        .activity_threshold(&input.id, &input.name, input.address.as_deref())
+        .map(Threshold::new);
+    let mut events = [0u8; EVENT_SIZE * 64];
     loop {
-        match wait_for_input(&mut file) {
+        let read = match wait_for_input(&mut file, &mut events) {
             // means the device is disconnected
             Err(e) if e.kind() == io::ErrorKind::NotFound => {
                 // device was disconnected
@@ -146,26 +477,427 @@ fn monitor_input(
                 // device was disconnected
                 break;
             }
+            Err(e) if !to_block.matches(&input.id, &input.name, input.address.as_deref()) => {
+                debug!("Stopped monitoring unblocked device {}: {e}", input.name);
+                return;
+            }
             Err(e) => {
                 // unexpected error, report to main thread
-                let err = Arc::new(e); // make cloneable
-                let _ig_err = tx1.send(Err(err.clone()));
-                let _ig_err = tx2.send(Err(err));
+                let _ig_err = tx.send(Err(Arc::new(e)));
+                return;
+            }
+            Ok(read) => read,
+        };
+
+        for event in power_events(&events[..read]) {
+            let _ = tx.send(Ok(Input::Power(event)));
+        }
+        if !to_block.matches(&input.id, &input.name, input.address.as_deref()) {
+            continue;
+        }
+        let now = Instant::now();
+        if let Some(chord) = &mut chord {
+            let held = key_events(&events[..read]).any(|(key, value)| chord.key(key, value, now));
+            if held {
+                let _ = tx.send(Ok(Input::Chord));
+                continue;
+            }
+        }
+        if let Some(threshold) = &mut threshold {
+            if !threshold.reached(reports(&events[..read]), now) {
+                continue;
+            }
+        }
+        if !coalesce.should_forward(now) {
+            continue;
+        }
+        send_activity(tx);
+    }
+}
+
+/// Like `monitor_input` for a joystick without an event node
+fn monitor_legacy(joystick: &LegacyJoystick, to_block: &BlockList, tx: &InputSender) {
+    let mut file = match privsep::open_device(&joystick.path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            debug!("Not monitoring legacy joystick {}: {e}", joystick.name);
+            return;
+        }
+        Ok(file) => file,
+    };
+    let mut coalesce = Coalesce::new();
+    let mut events = [0u8; joystick::EVENT_SIZE * 64];
+    loop {
+        let read = match wait_for_input(&mut file, &mut events) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound || device_removed(&e) => return,
+            Err(e) => {
+                debug!("Stopped monitoring legacy joystick {}: {e}", joystick.name);
                 return;
             }
-            Ok(()) => (),
+            Ok(read) => read,
         };
+        if !to_block.matches(&joystick.id, &joystick.name, None)
+            || !joystick::any_input(&events[..read])
+            || !coalesce.should_forward(Instant::now())
+        {
+            continue;
+        }
+        send_activity(tx);
+    }
+}
+
+/// Keys that start the break early when held down together, see
+/// `--break-chord`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord(Vec<KeyCode>);
+
+impl Chord {
+    /// Parses key names joined by `+`, like `leftshift+rightshift`. The
+    /// names are those of the linux input headers, the `KEY_` is optional.
+    pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+        let keys = arg
+            .split('+')
+            .map(|name| {
+                let name = name.trim().to_uppercase();
+                let name = if name.starts_with("KEY_") || name.starts_with("BTN_") {
+                    name
+                } else {
+                    format!("KEY_{name}")
+                };
+                name.parse::<KeyCode>()
+                    .map_err(|_| format!("Not a known key: {name}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.len() < 2 {
+            return Err("A chord needs at least two keys, like leftshift+rightshift".to_string());
+        }
+        Ok(Self(keys))
+    }
+}
+
+impl std::fmt::Display for Chord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<_> = self.0.iter().map(|key| format!("{key:?}")).collect();
+        f.write_str(&names.join("+"))
+    }
+}
+
+/// Tracks the chord keys on one device. Relies on key repeat to notice the
+/// chord was held long enough.
+struct ChordDetector {
+    keys: Vec<(KeyCode, bool)>,
+    hold: Duration,
+    held_since: Option<Instant>,
+    fired: bool,
+}
+
+impl ChordDetector {
+    fn new(chord: Chord, hold: Duration) -> Self {
+        Self {
+            keys: chord.0.into_iter().map(|key| (key, false)).collect(),
+            hold,
+            held_since: None,
+            fired: false,
+        }
+    }
+
+    /// True once per time the chord is held for `hold`
+    fn key(&mut self, code: u16, value: i32, now: Instant) -> bool {
+        const RELEASE: i32 = 0;
+        const PRESS: i32 = 1;
+        if let Some((_, down)) = self.keys.iter_mut().find(|(key, _)| key.code() == code) {
+            match value {
+                RELEASE => *down = false,
+                PRESS => *down = true,
+                _repeat => (),
+            }
+        }
+        if !self.keys.iter().all(|(_, down)| *down) {
+            self.held_since = None;
+            self.fired = false;
+            return false;
+        }
+        let held_since = *self.held_since.get_or_insert(now);
+        if self.fired || now.saturating_duration_since(held_since) < self.hold {
+            return false;
+        }
+        self.fired = true;
+        true
+    }
+}
+
+/// Size of a `struct input_event` on 64 bit linux
+const EVENT_SIZE: usize = 24;
+
+/// The code and value of the key events among raw `input_event`s
+fn key_events(events: &[u8]) -> impl Iterator<Item = (u16, i32)> + '_ {
+    events.chunks_exact(EVENT_SIZE).filter_map(|event| {
+        // skips the timestamp, two 64 bit integers
+        let type_ = u16::from_ne_bytes([event[16], event[17]]);
+        let code = u16::from_ne_bytes([event[18], event[19]]);
+        let value = i32::from_ne_bytes([event[20], event[21], event[22], event[23]]);
+        (type_ == EventType::KEY.0).then_some((code, value))
+    })
+}
+
+/// The lid and dock switch changes among raw `input_event`s
+fn power_events(events: &[u8]) -> impl Iterator<Item = PowerEvent> + '_ {
+    events.chunks_exact(EVENT_SIZE).filter_map(|event| {
+        let type_ = u16::from_ne_bytes([event[16], event[17]]);
+        let code = u16::from_ne_bytes([event[18], event[19]]);
+        let on = event[20..24] != [0; 4];
+        if type_ != EventType::SWITCH.0 {
+            return None;
+        }
+        match (SwitchCode(code), on) {
+            (SwitchCode::SW_LID, true) => Some(PowerEvent::LidClosed),
+            (SwitchCode::SW_LID, false) => Some(PowerEvent::LidOpened),
+            (SwitchCode::SW_DOCK, true) => Some(PowerEvent::Docked),
+            (SwitchCode::SW_DOCK, false) => Some(PowerEvent::Undocked),
+            _ => None,
+        }
+    })
+}
+
+/// How many of the raw `input_event`s end a report (`SYN_REPORT`), a report
+/// is one movement or key press
+fn reports(events: &[u8]) -> usize {
+    events
+        .chunks_exact(EVENT_SIZE)
+        .filter(|event| {
+            let type_ = u16::from_ne_bytes([event[16], event[17]]);
+            let code = u16::from_ne_bytes([event[18], event[19]]);
+            type_ == EventType::SYNCHRONIZATION.0 && code == 0
+        })
+        .count()
+}
+
+/// Only lets input through once enough reports came in recently, see
+/// `InputFilter::activity`
+struct Threshold {
+    threshold: ActivityThreshold,
+    recent: VecDeque<Instant>,
+}
+
+impl Threshold {
+    fn new(threshold: ActivityThreshold) -> Self {
+        Self {
+            threshold,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn reached(&mut self, reports: usize, now: Instant) -> bool {
+        let needed = self.threshold.reports as usize;
+        self.recent
+            .extend(std::iter::repeat_n(now, reports.min(needed)));
+        while self.recent.len() > needed
+            || self
+                .recent
+                .front()
+                .is_some_and(|at| now.saturating_duration_since(*at) > self.threshold.within)
+        {
+            self.recent.pop_front();
+        }
+        self.recent.len() >= needed
+    }
+}
+
+/// Input is only used to track activity, during fast mouse movement there
+/// are thousands of events per second. Forwarding one per tick is enough.
+const ACTIVITY_TICK: Duration = Duration::from_millis(250);
+
+struct Coalesce {
+    last_forwarded: Option<Instant>,
+}
+
+impl Coalesce {
+    fn new() -> Self {
+        Self {
+            last_forwarded: None,
+        }
+    }
 
-        let _ = tx1.send(Ok(()));
-        let _ = tx2.send(Ok(()));
+    fn should_forward(&mut self, now: Instant) -> bool {
+        if self
+            .last_forwarded
+            .is_some_and(|last| now.saturating_duration_since(last) < ACTIVITY_TICK)
+        {
+            return false;
+        }
+        self.last_forwarded = Some(now);
+        true
     }
 }
 
-pub fn wait_for_input(file: &mut File) -> std::io::Result<()> {
-    let mut packet = [0u8; 24];
-    file.read_exact(&mut packet)
+/// Reads all events that are ready at once, returns how many bytes that was
+pub fn wait_for_input(file: &mut File, events: &mut [u8]) -> std::io::Result<usize> {
+    match file.read(events)? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        read => Ok(read),
+    }
 }
 
 pub fn device_removed(e: &std::io::Error) -> bool {
     e.raw_os_error() == Some(19i32) && e.to_string().contains("No such device")
 }
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "bench")]
+    extern crate test;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const BREAK: Duration = Duration::from_secs(5 * 60);
+    const STEP: Duration = Duration::from_secs(5);
+
+    /// Moves the clock forward `STEP` at a time for half an hour, `active` decides
+    /// if there is input during that step. Returns when the first idle event
+    /// arrived.
+    fn first_idle(active: impl Fn(Duration) -> bool) -> Option<Duration> {
+        let clock = Arc::new(ManualClock::new());
+        let start = clock.now();
+        let (input_tx, input_rx) = input_queue::bounded(INPUT_CAPACITY);
+        let mut tracker = InactivityTracker::new(input_rx, BREAK, clock.clone());
+
+        while clock.elapsed(start) < Duration::from_secs(30 * 60) {
+            while let Some(event) = tracker.next(Duration::ZERO) {
+                if let Event::Idle = event {
+                    return Some(clock.elapsed(start));
+                }
+            }
+            if active(clock.elapsed(start)) {
+                input_tx.send(Ok(Input::Activity)).unwrap();
+            }
+            clock.advance(STEP);
+            thread::sleep(Duration::from_millis(2));
+        }
+        None
+    }
+
+    /// Pushes a burst of input through the activity channel
+    #[cfg(feature = "bench")]
+    fn forward_burst(coalesce: Option<Coalesce>) {
+        let (tx, rx) = mpsc::channel::<InputResult>();
+        let receiver = thread::spawn(move || rx.iter().count());
+
+        let mut coalesce = coalesce;
+        for _ in 0..100_000 {
+            if coalesce
+                .as_mut()
+                .is_some_and(|c| !c.should_forward(Instant::now()))
+            {
+                continue;
+            }
+            tx.send(Ok(Input::Activity)).unwrap();
+        }
+        drop(tx);
+        receiver.join().unwrap();
+    }
+
+    // compare using: cargo +nightly bench --features bench forward
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn forward_every_event(b: &mut test::Bencher) {
+        b.iter(|| forward_burst(None));
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn forward_coalesced(b: &mut test::Bencher) {
+        b.iter(|| forward_burst(Some(Coalesce::new())));
+    }
+
+    #[test]
+    fn drops_activity_once_full() {
+        let (tx, rx) = input_queue::bounded(1);
+        let before = DROPPED_ACTIVITY.load(Ordering::Relaxed);
+        assert!(send_activity(&tx));
+        assert!(send_activity(&tx));
+        // other tests drop activity too
+        assert!(DROPPED_ACTIVITY.load(Ordering::Relaxed) > before);
+        assert!(rx.try_recv().is_some());
+        assert!(rx.try_recv().is_none(), "the first was dropped");
+
+        drop(rx);
+        assert!(!send_activity(&tx));
+    }
+
+    #[test]
+    fn no_idle_while_active() {
+        assert_eq!(first_idle(|_| true), None);
+    }
+
+    #[test]
+    fn idle_after_a_break_without_input() {
+        let active_for = Duration::from_secs(60);
+        let idle = first_idle(|t| t < active_for).expect("should become idle");
+        assert!(idle > BREAK && idle < active_for + BREAK + 10 * STEP);
+    }
+
+    #[test]
+    fn drift_below_threshold_is_ignored() {
+        let mut threshold = Threshold::new(ActivityThreshold {
+            reports: 3,
+            within: Duration::from_secs(2),
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        // one report every few seconds, like a drifting sensor
+        assert!(!threshold.reached(1, at(0)));
+        assert!(!threshold.reached(1, at(3)));
+        assert!(!threshold.reached(1, at(6)));
+        // moving the mouse
+        assert!(!threshold.reached(1, at(10)));
+        assert!(threshold.reached(2, at(11)));
+        assert!(threshold.reached(5, at(11)));
+    }
+
+    #[test]
+    fn lid_and_dock_switches() {
+        let event = |type_: EventType, code: u16, value: i32| {
+            let mut raw = [0u8; EVENT_SIZE];
+            raw[16..18].copy_from_slice(&type_.0.to_ne_bytes());
+            raw[18..20].copy_from_slice(&code.to_ne_bytes());
+            raw[20..24].copy_from_slice(&value.to_ne_bytes());
+            raw
+        };
+        let events = [
+            event(EventType::SWITCH, SwitchCode::SW_LID.0, 1),
+            event(EventType::SYNCHRONIZATION, 0, 0),
+            event(EventType::SWITCH, SwitchCode::SW_DOCK.0, 0),
+            event(EventType::SWITCH, SwitchCode::SW_HEADPHONE_INSERT.0, 1),
+            event(EventType::KEY, KeyCode::KEY_A.0, 1),
+        ]
+        .concat();
+        let found: Vec<_> = power_events(&events).collect();
+        assert_eq!(found, [PowerEvent::LidClosed, PowerEvent::Undocked]);
+    }
+
+    #[test]
+    fn chord_fires_once_when_held() {
+        let chord = Chord::parse("leftshift+KEY_RIGHTSHIFT").unwrap();
+        assert_eq!(chord.to_string(), "KEY_LEFTSHIFT+KEY_RIGHTSHIFT");
+        assert_eq!(Chord::parse(&chord.to_string()), Ok(chord.clone()));
+        assert!(Chord::parse("leftshift").is_err());
+        assert!(Chord::parse("leftshift+nokey").is_err());
+
+        let left = KeyCode::KEY_LEFTSHIFT.code();
+        let right = KeyCode::KEY_RIGHTSHIFT.code();
+        let mut detector = ChordDetector::new(chord, Duration::from_secs(2));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(!detector.key(left, 1, at(0)));
+        assert!(!detector.key(right, 1, at(0)));
+        assert!(!detector.key(right, 2, at(1)));
+        assert!(detector.key(right, 2, at(2)));
+        assert!(!detector.key(right, 2, at(3)), "fires only once");
+        // released and pressed again starts over
+        assert!(!detector.key(left, 0, at(4)));
+        assert!(!detector.key(left, 1, at(5)));
+        assert!(!detector.key(left, 2, at(6)));
+        assert!(detector.key(left, 2, at(7)));
+    }
+}
@@ -1,45 +1,417 @@
-use clap::{Args, Parser, Subcommand};
+use chrono::{NaiveDate, NaiveTime};
+use clap::builder::ArgPredicate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::net::SocketAddr;
 use std::num::ParseFloatError;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use crate::integration::NotificationType;
+use crate::break_command::BreakCommand;
+use crate::check_inputs::Chord;
+use crate::compositor::Backend;
+use crate::curfew::Window;
+use crate::enforcement::Escalation;
+use crate::integration::speech::Verbosity;
+use crate::integration::{BatteryPolicy, NotificationType};
+use crate::run::{IdleCredit, PowerAction, WhenUnplugged};
+use crate::watch_and_block::InputId;
 
 #[allow(clippy::struct_field_names)]
 #[derive(Debug, Args, PartialEq, Eq)]
 pub struct RunArgs {
+    /// Fills in the work and break durations, lock warning and lock delay.
+    /// Any of these that are also passed override the preset. Install
+    /// writes the values the preset expands to into the service.
+    #[arg(long, value_enum)]
+    pub preset: Option<Preset>,
     /// Period after which input will be disabled.  
     /// Note: run help command to see the duration format.
-    #[arg(short, long, value_name = "duration", value_parser = parse_duration)]
+    #[arg(
+        short,
+        long,
+        visible_alias = "work",
+        value_name = "duration",
+        value_parser = parse_duration,
+        required = false,
+        required_unless_present = "preset",
+        default_value_ifs = [
+            ("preset", "eyes", "20m"),
+            ("preset", "rsi", "30m"),
+            ("preset", "pomodoro", "25m"),
+        ]
+    )]
     pub work_duration: Duration,
+    /// Optional takes a duration, if set each work period is up to this much
+    /// shorter or longer, picked at random. Breaks then do not start at a
+    /// predictable minute. Must be shorter than the work duration.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration)]
+    pub break_jitter: Option<Duration>,
+    /// Optional takes a duration, if set the first break after starting (so
+    /// after boot or login) comes after this much work instead of the work
+    /// duration. For example longer when the machine starts right after a
+    /// break. Not affected by the break jitter.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration)]
+    pub first_work_duration: Option<Duration>,
     /// Length of the breaks, after this period input is resumed.
     /// Note: run help command to see the duration format.
-    #[arg(short, long, value_name = "duration", value_parser = parse_duration)]
+    #[arg(
+        short,
+        long,
+        visible_alias = "break",
+        value_name = "duration",
+        value_parser = parse_duration,
+        required = false,
+        required_unless_present = "preset",
+        default_value_ifs = [
+            ("preset", "eyes", "20s"),
+            ("preset", "rsi", "5m"),
+            ("preset", "pomodoro", "5m"),
+        ]
+    )]
     pub break_duration: Duration,
+    /// Optional takes a duration, if set every `--long-break-every`th break
+    /// lasts this long instead of the break duration.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration)]
+    pub long_break: Option<Duration>,
+    /// Which of the breaks are long ones, see `--long-break`
+    #[arg(
+        long,
+        value_name = "n",
+        default_value_t = 4,
+        requires = "long_break",
+        value_parser = clap::value_parser!(u32).range(2..)
+    )]
+    pub long_break_every: u32,
+    /// How much idle time during a work period shortens the next break:
+    /// `full`, `none`, a percentage like `50%` or a duration to shorten it
+    /// by at most that much. The status shows the credit while idle.
+    #[arg(long, value_name = "credit", value_parser = IdleCredit::parse, default_value = "full")]
+    pub idle_credit: IdleCredit,
     /// Optional takes a duration, if set sends a notification ahead of the break.
     /// Note: run help command to see the duration format.
-    #[arg(short, long, value_name = "duration", value_parser = parse_duration)]
+    #[arg(
+        short,
+        long,
+        value_name = "duration",
+        value_parser = parse_duration,
+        default_value_ifs = [
+            ("preset", "eyes", "10s"),
+            ("preset", "rsi", "1m"),
+            ("preset", "pomodoro", "1m"),
+        ]
+    )]
     pub lock_warning: Option<Duration>,
     /// Type of notification to get as lock warning.
     /// - For audio you need aplay installed.
     /// - For system you need notify-send installed.
-    #[arg(short('a'), long, value_enum)]
+    #[arg(
+        short('a'),
+        long,
+        value_enum,
+        default_value_if("preset", ArgPredicate::IsPresent, "system")
+    )]
     pub lock_warning_type: Vec<NotificationType>,
+    /// Optional takes a duration, if set locking waits up to this long for
+    /// a pause in typing of a second. The break then never starts in the
+    /// middle of a word or password.
+    /// Note: run help command to see the duration format.
+    #[arg(
+        long,
+        value_name = "duration",
+        value_parser = parse_duration,
+        default_value_if("preset", ArgPredicate::IsPresent, "10s")
+    )]
+    pub lock_delay: Option<Duration>,
+    /// Put off a break for up to two minutes while a password prompt is open:
+    /// pinentry, an askpass or polkit dialog, a lock screen or a greeter
+    #[arg(long)]
+    pub defer_for_passwords: bool,
+    /// Show a sway or i3 nag bar during the lock warning (see
+    /// `--lock-warning`) and the break
+    #[arg(long)]
+    pub nag_bar: bool,
+    /// Switch to this sway or i3 workspace during a break, back to the
+    /// previous one after
+    #[arg(long, value_name = "name")]
+    pub break_workspace: Option<String>,
+    /// Once this much time was spent working (or paused) today, for example
+    /// 9h, lock until the next day starts. Warnings are send 30, 10 and 1
+    /// minute ahead. This lock can not be skipped, snoozed or paused.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration)]
+    pub daily_limit: Option<Duration>,
+    /// When a new day starts for `--daily-limit`
+    #[arg(
+        long,
+        value_name = "hh:mm",
+        value_parser = parse_time_of_day,
+        default_value = "04:00",
+        requires = "daily_limit"
+    )]
+    pub day_starts_at: NaiveTime,
+    /// Lock every night in this window regardless of work and breaks, for
+    /// example 23:30-06:00. Breaks during it can not be skipped, snoozed or
+    /// paused.
+    #[arg(long, value_name = "hh:mm-hh:mm", value_parser = Window::parse)]
+    pub curfew: Option<Window>,
+    /// How many times a week the curfew can be lifted for the rest of a
+    /// night, using `ctl lift-curfew`
+    #[arg(long, value_name = "n", default_value_t = 0, requires = "curfew")]
+    pub curfew_overrides: u32,
+    /// Suggest a stretch or eye exercise for every break. It is shown in the
+    /// status, the notifications and the overlay.
+    #[arg(long)]
+    pub break_coach: bool,
+    /// Pick the break coach suggestions from this file instead of the built
+    /// in list, for example:
+    /// `[(text: "Roll your shoulders"), (text: "Look outside", weight: 3)]`
+    /// Suggestions with a higher weight (default 1) come up more often.
+    #[arg(long, value_name = "path", requires = "break_coach")]
+    pub break_activities: Option<PathBuf>,
+    /// Aim for this many completed work periods a day, once reached a
+    /// notification summarizes the day: time worked and breaks taken or
+    /// skipped
+    #[arg(long, value_name = "n", value_parser = clap::value_parser!(u32).range(1..))]
+    pub daily_goal: Option<u32>,
+    /// Also send the summary of the day at this time
+    #[arg(long, value_name = "hh:mm", value_parser = parse_time_of_day)]
+    pub summary_at: Option<NaiveTime>,
     /// Enable the tcp api. Enables the `Status` command and other apps
     /// to interface using the break-enforcer library. The API only
     /// accepts connections from the same system.
     #[arg(short, long)]
     pub tcp_api: bool,
+    /// Only listen on this port instead of the first free one out of a
+    /// fixed list. Clients need the same port, for the `status` command and
+    /// library set it using the environment variable.
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+    /// How often clients subscribed to the api get a progress update during
+    /// a break.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration, default_value = "1s")]
+    pub progress_interval: Duration,
+    /// Also serve the api over http on this loopback port: `GET /status`,
+    /// `GET /events` (server sent events) and `POST /pause?seconds=<n>`,
+    /// `/resume`, `/skip`, `/snooze` and `/unlock`.
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "port", requires = "tcp_api")]
+    pub http_port: Option<u16>,
+    /// Also serve the api over the network on this address, for example
+    /// 0.0.0.0:4781, so other devices can show the status and pause or skip.
+    /// Uses tls and clients must send the token before anything else. The
+    /// loopback api stays as is.
+    #[arg(
+        long,
+        value_name = "address",
+        requires = "tcp_api",
+        requires_all = ["api_cert", "api_key", "api_token_file"]
+    )]
+    pub listen: Option<SocketAddr>,
+    /// Certificate (PEM) served on the `--listen` address. Clients must reach
+    /// this machine using a host name in the certificate.
+    #[arg(long, value_name = "path")]
+    pub api_cert: Option<PathBuf>,
+    /// Private key (PEM) of the api certificate
+    #[arg(long, value_name = "path")]
+    pub api_key: Option<PathBuf>,
+    /// File containing the token clients on the network must send
+    #[arg(long, value_name = "path")]
+    pub api_token_file: Option<PathBuf>,
     /// Enable the status file. It contains a string describing the time till
     /// the next break, the time till the current break is over or that the user
-    /// is idle. The file is located at `/var/run/break_enforcer` and is called
-    /// `status.txt`
+    /// is idle. The file is located in the runtime dir (by default
+    /// `/run/break_enforcer`) and is called `status.txt`
     #[arg(short, long)]
     pub status_file: bool,
     /// verbose notifications. Sends notifications when:
     /// the break begins, a work session begins, we are waiting for input
     #[arg(short, long)]
     pub notifications: bool,
+    /// Send a notification when a break does not block everything it should,
+    /// for example when another program grabbed one of the devices
+    #[arg(long)]
+    pub notify_warnings: bool,
+    /// Send a notification when a keyboard that was never seen before is
+    /// connected and is not blocked. It says how to block it:
+    /// `break-enforcer ctl block <id>`
+    #[arg(long)]
+    pub ask_new_keyboards: bool,
+    /// Never lock without a system notification delivered at least this long
+    /// before, for example 30s. Until one is delivered the break waits and
+    /// the failure shows up as a warning. Applies to every lock, also those
+    /// of the curfew and the daily limit.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration)]
+    pub min_notice: Option<Duration>,
+    /// Say out loud when a break starts and ends, for screen reader users.
+    /// Goes through speech-dispatcher (spd-say) and is independent of
+    /// `--lock-warning-type audio`.
+    #[arg(long, value_enum, value_name = "verbosity")]
+    pub announce: Option<Verbosity>,
+    /// What changes while the laptop runs on battery, read from
+    /// /sys/class/power_supply. By default the status is updated less often
+    /// and audio notifications are left out.
+    #[arg(long, value_enum, value_name = "policy", default_value_t = BatteryPolicy::Save)]
+    pub on_battery: BatteryPolicy,
+    /// Also write all state transitions to `history.jsonl` in the state dir.
+    /// The `log` command reads it when the service is not running. Past
+    /// 4 MiB it is moved to `history.jsonl.1`, replacing the one before.
+    #[arg(long)]
+    pub persist_history: bool,
+    /// Also block the virtual devices of key remappers like keyd and kmonad
+    /// during breaks. These grab the physical devices themselves so blocking
+    /// those does nothing. Input on them then also counts as activity.
+    #[arg(long)]
+    pub block_remappers: bool,
+    /// Do not follow the session as it is locked and unlocked. By default
+    /// unlocking starts a work period right away and locking starts the idle
+    /// time. Without it the lock state is only asked for on input.
+    #[arg(long)]
+    pub no_follow_screen_lock: bool,
+    /// How to block the devices. Grabbing them can break gestures and leave
+    /// modifiers stuck on some wayland compositors, sway can be asked to
+    /// ignore them instead. Auto uses sway when running in a sway session.
+    /// Screen-lock only locks the session, again each time it is unlocked.
+    #[arg(long, value_enum, default_value_t = Backend::Grab)]
+    pub block_backend: Backend,
+    /// Keep the pointer moving during breaks, clicks and keys stay blocked.
+    /// Handy for reading. Mice are passed on through a virtual mouse, this
+    /// needs the grab backend and access to /dev/uinput.
+    #[arg(long, conflicts_with = "drop_privileges")]
+    pub allow_pointer: bool,
+    /// What to do while none of the devices to block are connected, for
+    /// example when the only configured keyboard is unplugged. The status
+    /// shows it either way.
+    #[arg(long, value_enum, value_name = "policy")]
+    pub when_unplugged: Option<WhenUnplugged>,
+    /// What closing the laptop lid does to the schedule, opening it undoes
+    /// it. Needs a lid switch input device.
+    #[arg(long, value_enum, value_name = "action")]
+    pub on_lid_close: Option<PowerAction>,
+    /// What disconnecting from a dock does to the schedule, connecting again
+    /// undoes it. Needs a dock switch input device, not every dock has one.
+    #[arg(long, value_enum, value_name = "action")]
+    pub on_undock: Option<PowerAction>,
+    /// Holding these keys down together on a blocked device starts the break
+    /// right away, for example `leftshift+rightshift`. The work time left is
+    /// counted as done. Key names are those of the linux input headers,
+    /// like `KEY_LEFTSHIFT`, the `KEY_` can be left out.
+    #[arg(long, value_name = "keys", value_parser = Chord::parse)]
+    pub break_chord: Option<Chord>,
+    /// How long the break chord has to be held down.
+    /// Note: run help command to see the duration format.
+    #[arg(
+        long,
+        value_name = "duration",
+        value_parser = parse_duration,
+        default_value = "2s",
+        requires = "break_chord"
+    )]
+    pub break_chord_hold: Duration,
+    /// Share activity, work periods and breaks with break-enforcer on these
+    /// machines, for example when switching between them using a KVM.
+    #[arg(long, value_name = "host:port", requires = "sync_listen")]
+    pub sync_peer: Vec<String>,
+    /// Accept sync peers on this address, for example 0.0.0.0:4780
+    #[arg(
+        long,
+        value_name = "address",
+        requires_all = ["sync_cert", "sync_key", "sync_ca"]
+    )]
+    pub sync_listen: Option<SocketAddr>,
+    /// Certificate (PEM) of this machine signed by the sync CA. Peers must
+    /// reach this machine using a host name in the certificate.
+    #[arg(long, value_name = "path")]
+    pub sync_cert: Option<PathBuf>,
+    /// Private key (PEM) of the sync certificate
+    #[arg(long, value_name = "path")]
+    pub sync_key: Option<PathBuf>,
+    /// CA certificate (PEM) that signed the certificates of all machines that
+    /// sync, only they are accepted
+    #[arg(long, value_name = "path")]
+    pub sync_ca: Option<PathBuf>,
+    /// Run as this user once the devices, status file and api are set up.
+    /// A small helper keeps running as root to open newly connected devices
+    /// and send notifications. Changes made through the api can then not be
+    /// saved to the config.
+    #[arg(long, value_name = "user")]
+    pub drop_privileges: Option<String>,
+    /// Only keep the schedule, notifications and status, nothing is blocked
+    /// during breaks. Needs no root. Input from the devices in the config
+    /// counts as activity, without a config or access to the devices the
+    /// time the screen is unlocked does.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "allow_pointer",
+            "block_remappers",
+            "when_unplugged",
+            "ask_new_keyboards",
+            "drop_privileges",
+        ]
+    )]
+    pub no_block: bool,
+    /// Start breaks gentle and escalate while there is still input. Stages
+    /// are warn, screen-lock and block, separated by commas. Every stage but
+    /// the last gets a time after which input moves on to the next stage,
+    /// for example: warn:1m,screen-lock:2m,block. The curfew and daily limit
+    /// always block right away.
+    #[arg(long, value_name = "stages", value_parser = Escalation::parse, conflicts_with = "no_block")]
+    pub escalate: Option<Escalation>,
+    /// Run this command during breaks, for example a stretching video:
+    /// "mpv --fs ~/stretch.mkv". It is stopped once the break is over, its
+    /// output goes to the log. As root it runs as the user of the active
+    /// session, a `~` is their home.
+    #[arg(long, value_name = "command", value_parser = BreakCommand::parse)]
+    pub break_command: Option<BreakCommand>,
+}
+
+/// Bundles of settings for common goals
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum Preset {
+    /// 20-20-20 rule against eye strain: every 20 minutes look 20 feet
+    /// (6 meters) away for 20 seconds
+    Eyes,
+    /// Against repetitive strain injury: 5 minutes off every half hour
+    Rsi,
+    /// 25 minutes of work then 5 minutes off
+    Pomodoro,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct InstallArgs {
+    #[command(flatten)]
+    pub run_args: RunArgs,
+    /// Set up a systemd user service instead of a system wide one. It runs
+    /// without root, therefore your user needs access to the input devices.
+    /// Instructions are printed if it does not.
+    #[arg(long)]
+    pub user: bool,
+    /// Only show how the installed service differs from what install would
+    /// set up. Does not change anything.
+    #[arg(long, conflicts_with = "check")]
+    pub diff: bool,
+    /// Exit with an error if the installed service differs from what install
+    /// would set up. Does not change anything.
+    #[arg(long)]
+    pub check: bool,
+    /// Also start the overlay (a full screen countdown during breaks) when
+    /// logging in. Adds an XDG autostart entry for all users, or only for
+    /// you with `--user`.
+    #[cfg(feature = "overlay")]
+    #[arg(long)]
+    pub autostart_overlay: bool,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct RemoveArgs {
+    /// Remove the systemd user service set up with `install --user`
+    #[arg(long)]
+    pub user: bool,
 }
 
 #[allow(clippy::struct_field_names)]
@@ -48,30 +420,393 @@ pub struct StatusArgs {
     /// Instead of printing the status once print it every `update` period
     #[arg(short, long, value_name = "duration", value_parser = parse_duration)]
     pub update_period: Option<Duration>,
-    /// Output the status as json like this: {'msg': 'break in 5m'}
+    /// Output the status as json like this:
+    /// {"msg": "break in 5m", "class": "work"}. Same as `--format json`.
+    #[arg(short = 'j', long, conflicts_with = "format")]
+    pub use_json: bool,
+    /// How to print the status. Json and waybar include the state as a class
+    /// so bars can style it without parsing the status line.
+    #[arg(long, value_enum, default_value_t = StatusFormat::Plain)]
+    pub format: StatusFormat,
+    /// The class is `imminent` instead of `work` this close to a break.
+    /// Note: run help command to see the duration format.
+    #[arg(long, value_name = "duration", value_parser = parse_duration, default_value = "5m")]
+    pub imminent: Duration,
+    /// Ron file with the text and icon to show per class for
+    /// `--format waybar`, for example:
+    /// `(imminent: (icon: Some("!"), text: Some("in {left}m")), break: (icon: Some("B")))`
+    /// In the text `{msg}` is the status line and `{left}` the whole minutes
+    /// left in the current state.
+    #[arg(long, value_name = "path")]
+    pub style: Option<PathBuf>,
+    /// Connect only on this port, needed if the service was started with
+    /// `--api-port`
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+    /// With `--update-period` print an empty status instead of an error while
+    /// break-enforcer can not be reached. Useful in bars.
+    #[arg(short, long, requires = "update_period")]
+    pub quiet_errors: bool,
+    /// With `--update-period` send a desktop notification once break-enforcer
+    /// could not be reached for 30 seconds, at most once an hour. Otherwise
+    /// breaks silently stop being enforced when the service dies.
+    #[arg(long, requires = "update_period")]
+    pub notify_unreachable: bool,
+    /// Exit with a code for the state, for scripts: 0 work, 1 break,
+    /// 2 waiting, 3 paused, 4 meeting and 10 if the service can not be
+    /// reached
+    #[arg(long, conflicts_with = "update_period")]
+    pub exit_code: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum StatusFormat {
+    /// Only the status line
+    Plain,
+    /// An object with the status line as `msg` and the state as `class`
+    Json,
+    /// The json of a waybar custom module (`return-type: json`), the state
+    /// is both the `alt` and the `class`
+    Waybar,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct LogArgs {
+    /// Keep printing new state transitions as they happen
+    #[arg(short, long)]
+    pub follow: bool,
+    /// Output one json object per transition
+    #[arg(short = 'j', long)]
+    pub use_json: bool,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct WatchArgs {
+    #[arg(long, value_enum, default_value_t = WatchFormat::Plain)]
+    pub format: WatchFormat,
+    /// Also print how much of a running break is left, by default only state
+    /// changes and warnings are printed
+    #[arg(long)]
+    pub progress: bool,
+    /// Connect only on this port, needed if the service was started with
+    /// `--api-port`
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum WatchFormat {
+    /// The time and a description, like `log`
+    Plain,
+    /// One json object per line
+    Json,
+    /// One ron `StateUpdate` per line, as the api sends them
+    Ron,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct StatsArgs {
+    #[command(subcommand)]
+    pub command: StatsCommand,
+}
+
+#[derive(Debug, Subcommand, PartialEq, Eq, Clone, Copy)]
+pub enum StatsCommand {
+    /// Prints a table of all work periods, breaks and pauses with when they
+    /// started and ended, for import in a spreadsheet or time tracker
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Leave out everything that started before this day
+        #[arg(long, value_name = "yyyy-mm-dd")]
+        since: Option<NaiveDate>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One row per period with a header
+    Csv,
+    /// An array with an object per period
+    Json,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct CtlArgs {
+    #[command(subcommand)]
+    pub control: CtlCommand,
+    /// Connect only on this port, needed if the service was started with
+    /// `--api-port`
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct TrayArgs {
+    /// Connect only on this port, needed if the service was started with
+    /// `--api-port`
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct OverlayArgs {
+    /// Connect only on this port, needed if the service was started with
+    /// `--api-port`
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+}
+
+#[derive(Debug, Subcommand, PartialEq, Eq, Clone, Copy)]
+pub enum CtlCommand {
+    /// No breaks and no counting work time for a while. Ends a running break.
+    Pause {
+        #[arg(value_name = "duration", value_parser = parse_duration)]
+        duration: Duration,
+    },
+    /// End a pause early, the work period continues where it was paused
+    Resume,
+    /// End a running break or restart the work period, either way a full work
+    /// period starts now
+    Skip,
+    /// Move the coming break back, during a break end it and work a bit
+    /// longer. The service picks the duration (5m) if none is given.
+    Snooze {
+        #[arg(value_name = "duration", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// End a running break
+    Unlock,
+    /// End the work period and start a break right away. The service uses
+    /// its break duration if none is given.
+    BreakNow {
+        #[arg(value_name = "duration", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Make the running break longer, the devices stay blocked
+    Extend {
+        #[arg(value_name = "duration", value_parser = parse_duration)]
+        duration: Duration,
+    },
+    /// Lift the curfew for the rest of the night, uses up one of the
+    /// overrides of this week (see `--curfew-overrides`)
+    LiftCurfew,
+    /// Block the device with this id from the next break on, adds it to the
+    /// config. The id is in the output of `devices`.
+    Block {
+        #[arg(value_name = "id")]
+        id: InputId,
+    },
+    /// Restart the service using the binary on disk without losing the
+    /// current work period or break, run after upgrading. SIGUSR2 does the
+    /// same.
+    Reexec,
+    /// Hold off breaks during a meeting, for example `meeting on 1h`. Unlike
+    /// a pause work time keeps counting, the breaks due in the meantime are
+    /// taken together once the meeting ends.
+    Meeting {
+        #[arg(value_enum)]
+        state: Toggle,
+        #[arg(
+            value_name = "duration",
+            value_parser = parse_duration,
+            required_if_eq("state", "on")
+        )]
+        duration: Option<Duration>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct DevicesArgs {
+    /// Output the devices as a json array
     #[arg(short = 'j', long)]
     pub use_json: bool,
 }
 
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+    /// Connect only on this port for `--apply`, needed if the service was
+    /// started with `--api-port`
+    #[arg(long, value_name = "port", env = break_enforcer::proto::PORT_ENV)]
+    pub api_port: Option<u16>,
+}
+
+#[derive(Debug, Subcommand, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// Converts the config between ron and toml. A config ending in .toml is
+    /// toml, anything else ron. The original is kept unless `--remove` is
+    /// passed.
+    Convert {
+        /// Where to write the converted config. Default: the config with the
+        /// other extension
+        #[arg(value_name = "path")]
+        output: Option<PathBuf>,
+        /// Remove the original config. Otherwise at the default location the
+        /// ron config is used while both exist
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Prints the config with the drop-ins merged in, a device in it or one
+    /// field of that device
+    Get {
+        /// The id of a device, it is in the output of `devices`
+        #[arg(value_name = "id")]
+        id: Option<InputId>,
+        #[arg(value_enum, requires = "id")]
+        field: Option<ConfigField>,
+    },
+    /// Changes a field of a device in the config
+    Set {
+        /// The id of a device, it is in the output of `devices`
+        #[arg(value_name = "id")]
+        id: InputId,
+        #[arg(value_enum)]
+        field: ConfigField,
+        /// names: one or more names, address: a bluetooth address,
+        /// activity: reports/duration, for example 3/2s. For address and
+        /// activity `none` removes it.
+        #[arg(value_name = "value", required = true, num_args = 1..)]
+        value: Vec<String>,
+        /// Have the running service read the changed config
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Adds a device to block, or more names to block of a device already
+    /// in the config
+    AddDevice {
+        /// The id of the device, it is in the output of `devices`
+        #[arg(value_name = "id")]
+        id: InputId,
+        /// The names of the device to block, as shown by `devices`
+        #[arg(value_name = "name", required = true, num_args = 1..)]
+        names: Vec<String>,
+        /// Have the running service read the changed config
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Stops blocking a device, removes it from the config
+    RemoveDevice {
+        #[arg(value_name = "id")]
+        id: InputId,
+        /// Have the running service read the changed config
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+/// A field of a device in the config, see `config get` and `config set`
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum ConfigField {
+    Names,
+    Address,
+    Activity,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct CompletionsArgs {
+    /// The shell to complete for
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Debug, Args, PartialEq, Eq)]
+pub struct ManpageArgs {
+    /// Write a page for every subcommand into this directory instead of
+    /// printing the main page
+    #[arg(long, value_name = "dir")]
+    pub dir: Option<PathBuf>,
+}
+
 #[derive(Debug, Subcommand, PartialEq, Eq)]
 pub enum Commands {
     /// Periodically block devices in config (setup using wizard).
     Run(#[command(flatten)] RunArgs),
+    /// Only keeps the schedule, notifications and status, the same as
+    /// `run --no-block`. Nothing is blocked during breaks.
+    #[command(mut_arg("no_block", |arg| arg.default_value("true").hide(true)))]
+    Timer(#[command(flatten)] RunArgs),
     /// Pick the devices to block and write them to a config file.
     /// (Interactive UI)
     Wizard,
     /// Moves the executable to a suitable location and set up a service.
-    Install(#[command(flatten)] RunArgs),
+    Install(#[command(flatten)] InstallArgs),
     /// Removed the installed service and executable.
-    Remove,
+    Remove(#[command(flatten)] RemoveArgs),
+    /// Validates the config together with the arguments you would pass to
+    /// run or install. Exits with an error if anything needs fixing.
+    Check(#[command(flatten)] RunArgs),
     /// Prints a status line describing the time till the next break,
     /// the time till the current break is over or that the user is idle.
     Status(#[command(flatten)] StatusArgs),
+    /// Lists the detected input devices, what they can do and whether they
+    /// are blocked. Useful to find out why a device is not blocked.
+    Devices(#[command(flatten)] DevicesArgs),
+    /// Checks permissions, dependencies, the config, the api port and the
+    /// service. Prints what passed and how to fix what did not.
+    Doctor,
+    /// Reads and changes the config file, for scripts and dotfiles
+    Config(#[command(flatten)] ConfigArgs),
+    /// Shows the recent state transitions of the running service. Falls back
+    /// to the persisted history if the service is not running.
+    Log(#[command(flatten)] LogArgs),
+    /// Prints the state changes of the running service as they happen, the
+    /// first line is the current state. Ends with an error once the service
+    /// stops.
+    Watch(#[command(flatten)] WatchArgs),
+    /// Reports on past work periods and breaks. Reads the persisted history
+    /// (see `run --persist-history`) or the recent history of the running
+    /// service.
+    Stats(#[command(flatten)] StatsArgs),
+    /// Changes the schedule of the running service. Meant to be bound to
+    /// clicks on a status bar.
+    Ctl(#[command(flatten)] CtlArgs),
+    /// Shows the state as a tray icon with a menu to pause and skip. Needs a
+    /// panel that supports StatusNotifierItem.
+    #[cfg(feature = "tray")]
+    Tray(#[command(flatten)] TrayArgs),
+    /// Shows a full screen countdown during breaks. Runs as your user in
+    /// your session, `install --autostart-overlay` starts it on login.
+    #[cfg(feature = "overlay")]
+    Overlay(#[command(flatten)] OverlayArgs),
+    /// Prints the shell completions, for packagers and to source from your
+    /// shell config.
+    Completions(#[command(flatten)] CompletionsArgs),
+    /// Prints the man page in roff, for packagers.
+    Manpage(#[command(flatten)] ManpageArgs),
 }
 
 impl Commands {
     pub fn needs_sudo(&self) -> bool {
-        !matches!(self, Commands::Status { .. })
+        match self {
+            Commands::Status { .. }
+            | Commands::Log(_)
+            | Commands::Watch(_)
+            | Commands::Stats(_)
+            | Commands::Doctor
+            | Commands::Ctl(_)
+            | Commands::Completions(_)
+            | Commands::Manpage(_) => false,
+            Commands::Install(InstallArgs {
+                user, diff, check, ..
+            }) => !(*user || *diff || *check),
+            Commands::Remove(RemoveArgs { user }) => !user,
+            // a user service runs without root if it has access to the devices
+            Commands::Run(args) | Commands::Timer(args) => {
+                !args.no_block && !crate::install::has_input_access()
+            }
+            #[cfg(feature = "tray")]
+            Commands::Tray(_) => false,
+            #[cfg(feature = "overlay")]
+            Commands::Overlay(_) => false,
+            _ => true,
+        }
     }
 }
 
@@ -88,19 +823,64 @@ impl Commands {
 ///    * example: 10:40
 ///         ten minutes and 40 seconds
 ///
+// the doc comment is the verbatim --help text, keep its layout
+#[allow(clippy::doc_overindented_list_items)]
 #[derive(Parser, Debug)]
 #[command(version, about, verbatim_doc_comment)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
-    /// Path to create/read/update list of devices to/from
-    /// Default: /etc/break-enforcer.ron
+    /// Path to create/read/update list of devices to/from, the format
+    /// (ron or toml) follows the extension. Devices in the drop-in configs
+    /// next to it (for /etc/break_enforcer.ron: /etc/break_enforcer.d/*.toml
+    /// and *.ron) are added to it.
+    /// Default: /etc/break_enforcer.ron or /etc/break_enforcer.toml
     #[arg(short, long)]
     #[arg(verbatim_doc_comment)]
     pub config_path: Option<PathBuf>,
-    /// Print many traces and logs
-    #[arg(short, long)]
-    pub verbose: bool,
+    /// Directory for state kept across reboots
+    /// Default: /var/lib/break_enforcer
+    #[arg(long, value_name = "dir", env = "BREAK_ENFORCER_STATE_DIR")]
+    #[arg(verbatim_doc_comment)]
+    pub state_dir: Option<PathBuf>,
+    /// Directory for state that is lost on reboot, like the status file
+    /// Default: /run/break_enforcer
+    #[arg(long, value_name = "dir", env = "BREAK_ENFORCER_RUNTIME_DIR")]
+    #[arg(verbatim_doc_comment)]
+    pub runtime_dir: Option<PathBuf>,
+    /// Directory with the input devices, for containers and Flatpak where
+    /// they are mounted elsewhere
+    /// Default: /dev/input
+    #[arg(long, value_name = "dir", env = "BREAK_ENFORCER_DEV_DIR")]
+    #[arg(verbatim_doc_comment)]
+    pub dev_dir: Option<PathBuf>,
+    /// What to log, in tracing's EnvFilter syntax. For example:
+    /// `warn,break_enforcer::watch_and_block=trace` traces device grabs while
+    /// only logging warnings for everything else. Use `trace` to log everything.
+    #[arg(long, value_name = "filter", env = "RUST_LOG", default_value = DEFAULT_LOG_FILTER)]
+    pub log_filter: String,
+    /// Log to this file instead of stderr. A new file is started every day
+    /// (the date is appended to the name) and the last 7 are kept.
+    #[arg(long, value_name = "path")]
+    pub log_file: Option<PathBuf>,
+}
+
+const DEFAULT_LOG_FILTER: &str = "warn";
+
+impl Cli {
+    /// The arguments needed to pass the log settings on to another invocation
+    pub fn log_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.log_filter != DEFAULT_LOG_FILTER {
+            args.push("--log-filter".to_string());
+            args.push(self.log_filter.clone());
+        }
+        if let Some(log_file) = &self.log_file {
+            args.push("--log-file".to_string());
+            args.push(log_file.display().to_string());
+        }
+        args
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -152,6 +932,10 @@ pub(crate) fn parse_colon_duration(arg: &str) -> Result<f32, ParseError> {
     Ok(seconds)
 }
 
+pub(crate) fn parse_time_of_day(arg: &str) -> Result<NaiveTime, chrono::ParseError> {
+    NaiveTime::parse_from_str(arg, "%H:%M")
+}
+
 /// Parse a string in two different formats to a `Duration`. The formats are:
 ///  - 10h
 ///  - 15m
@@ -176,6 +960,33 @@ pub(crate) fn parse_duration(arg: &str) -> Result<Duration, ParseError> {
 mod test {
     use super::*;
 
+    #[test]
+    fn preset_fills_in_unset_args() {
+        let cli = Cli::try_parse_from(["break-enforcer", "run", "--preset", "eyes", "-b", "30s"])
+            .unwrap();
+        let Commands::Run(args) = cli.command else {
+            panic!("parsed a run command");
+        };
+        assert_eq!(args.work_duration, Duration::from_secs(20 * 60));
+        assert_eq!(args.break_duration, Duration::from_secs(30));
+        assert_eq!(args.lock_warning_type, [NotificationType::System]);
+        assert!(Cli::try_parse_from(["break-enforcer", "run", "-b", "30s"]).is_err());
+    }
+
+    #[test]
+    fn timer_without_blocking() {
+        let cli =
+            Cli::try_parse_from(["break-enforcer", "timer", "--work", "50m", "--break", "10m"])
+                .unwrap();
+        assert!(!cli.command.needs_sudo());
+        let Commands::Timer(args) = cli.command else {
+            panic!("parsed a timer command");
+        };
+        assert_eq!(args.work_duration, Duration::from_secs(50 * 60));
+        assert_eq!(args.break_duration, Duration::from_secs(10 * 60));
+        assert!(args.no_block);
+    }
+
     #[test]
     fn test_colon_duration() {
         assert_eq!(parse_colon_duration("10:00").unwrap(), 60. * 10.);
@@ -0,0 +1,231 @@
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{
+    ClockId as TimerClock, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags,
+};
+use nix::time::{clock_gettime, ClockId};
+
+/// Source of time for the break scheduling. Everything that decides when to
+/// break or reset goes through this so it can be tested without waiting.
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// How long to block in real time while waiting for `remaining` to pass
+    /// on this clock. Callers check `now` again after blocking.
+    fn real_timeout(&self, remaining: Duration) -> Duration;
+
+    /// Lets a loop waiting on this clock wake right at its deadline, `None`
+    /// if the timeouts from `real_timeout` are exact
+    fn alarm(&self, _ring: Box<dyn Fn() -> bool + Send>) -> Option<Alarm> {
+        None
+    }
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    fn duration_until(&self, deadline: Instant) -> Duration {
+        deadline.saturating_duration_since(self.now())
+    }
+}
+
+/// A channel `recv_timeout` can wait on
+pub(crate) trait RecvTimeout<T> {
+    /// Waits forever if `timeout` is too long to add to an `Instant`
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError>;
+}
+
+impl<T> RecvTimeout<T> for Receiver<T> {
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        Receiver::recv_timeout(self, timeout)
+    }
+}
+
+/// `Receiver::recv_timeout` with the timeout measured on `clock`
+pub(crate) fn recv_timeout<T>(
+    clock: &dyn Clock,
+    rx: &impl RecvTimeout<T>,
+    timeout: Duration,
+) -> Result<T, RecvTimeoutError> {
+    let Some(deadline) = clock.now().checked_add(timeout) else {
+        return rx.recv_timeout(Duration::MAX);
+    };
+    loop {
+        let remaining = clock.duration_until(deadline);
+        match rx.recv_timeout(clock.real_timeout(remaining)) {
+            Err(RecvTimeoutError::Timeout) if !clock.duration_until(deadline).is_zero() => (),
+            res => return res,
+        }
+    }
+}
+
+/// Counts time spent suspended. `Instant` stops while the system is
+/// suspended, a break would then continue after resume as if no time passed.
+pub(crate) struct SystemClock;
+
+/// Channel timeouts stop during suspend too. Waiting in slices of this bounds
+/// how long it takes to notice that a deadline passed while suspended.
+const MAX_BLOCK: Duration = Duration::from_secs(60);
+
+impl SystemClock {
+    /// Total time the system spent suspended since boot
+    fn suspended() -> Duration {
+        let boottime = clock_gettime(ClockId::CLOCK_BOOTTIME)
+            .expect("CLOCK_BOOTTIME is always supported on linux");
+        let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .expect("CLOCK_MONOTONIC is always supported on linux");
+        Duration::from(boottime).saturating_sub(Duration::from(monotonic))
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now() + Self::suspended()
+    }
+
+    fn real_timeout(&self, remaining: Duration) -> Duration {
+        remaining.min(MAX_BLOCK)
+    }
+
+    fn alarm(&self, ring: Box<dyn Fn() -> bool + Send>) -> Option<Alarm> {
+        Some(Alarm::new(ring))
+    }
+}
+
+/// Alarms further out are not set, the `MAX_BLOCK` slices catch them
+const MAX_ALARM: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// A timer on `CLOCK_BOOTTIME`, it keeps running during suspend. A deadline
+/// that passed while suspended is noticed right at resume instead of up to
+/// `MAX_BLOCK` later.
+pub(crate) struct Alarm(Arc<TimerFd>);
+
+impl Alarm {
+    /// `ring` is called every time the alarm goes off, once it returns
+    /// false the alarm stops
+    fn new(ring: Box<dyn Fn() -> bool + Send>) -> Self {
+        let timer = TimerFd::new(TimerClock::CLOCK_BOOTTIME, TimerFlags::TFD_CLOEXEC)
+            .expect("CLOCK_BOOTTIME timers are always supported on linux");
+        let timer = Arc::new(timer);
+        let waiting = timer.clone();
+        thread::spawn(move || while waiting.wait().is_ok() && ring() {});
+        Self(timer)
+    }
+
+    /// Replaces the previous time, a zero or very long `after` only unsets
+    pub(crate) fn set(&self, after: Duration) {
+        if after.is_zero() || after > MAX_ALARM {
+            self.0
+                .unset()
+                .expect("unsetting a valid timer can not fail");
+            return;
+        }
+        self.0
+            .set(
+                Expiration::OneShot(TimeSpec::from_duration(after)),
+                TimerSetTimeFlags::empty(),
+            )
+            .expect("the duration is valid");
+    }
+}
+
+/// Jumps smaller than this are normal NTP slewing or measuring noise
+const MIN_WALL_CLOCK_JUMP: Duration = Duration::from_secs(2);
+
+/// Notices when the wall clock is set, for example by NTP or by hand. Only
+/// the timestamps in state updates use the wall clock, scheduling does not.
+pub(crate) struct WallClockWatch {
+    /// wall clock minus `CLOCK_BOOTTIME`, constant unless the wall clock is set
+    offset: Duration,
+}
+
+/// How far the wall clock was set forward or backward
+pub(crate) enum Jump {
+    Forward(Duration),
+    Backward(Duration),
+}
+
+impl WallClockWatch {
+    pub(crate) fn new() -> Self {
+        Self {
+            offset: Self::offset(),
+        }
+    }
+
+    fn offset() -> Duration {
+        let realtime = clock_gettime(ClockId::CLOCK_REALTIME)
+            .expect("CLOCK_REALTIME is always supported on linux");
+        let boottime = clock_gettime(ClockId::CLOCK_BOOTTIME)
+            .expect("CLOCK_BOOTTIME is always supported on linux");
+        Duration::from(realtime).saturating_sub(Duration::from(boottime))
+    }
+
+    /// The jump since the previous call if there was one
+    pub(crate) fn check(&mut self) -> Option<Jump> {
+        let offset = Self::offset();
+        let previous = std::mem::replace(&mut self.offset, offset);
+        if offset.abs_diff(previous) < MIN_WALL_CLOCK_JUMP {
+            None
+        } else if offset > previous {
+            Some(Jump::Forward(offset - previous))
+        } else {
+            Some(Jump::Backward(previous - offset))
+        }
+    }
+}
+
+/// Only moves forward when `advance` is called
+#[cfg(test)]
+pub(crate) struct ManualClock {
+    now: std::sync::Mutex<Instant>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            now: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn advance(&self, dur: Duration) {
+        *self.now.lock().unwrap() += dur;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    /// poll often, the time jumps whenever the test advances it
+    fn real_timeout(&self, _: Duration) -> Duration {
+        Duration::from_millis(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn alarm_wakes_a_waiting_receiver() {
+        let (tx, rx) = mpsc::channel();
+        let alarm = SystemClock
+            .alarm(Box::new(move || tx.send(()).is_ok()))
+            .expect("the system clock has alarms");
+        alarm.set(Duration::from_millis(10));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(()));
+
+        alarm.set(Duration::from_secs(60));
+        alarm.set(Duration::ZERO);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+}
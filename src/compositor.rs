@@ -0,0 +1,188 @@
+//! Blocking through the compositor instead of grabbing the devices. On some
+//! wayland compositors grabbing breaks gestures and leaves modifiers stuck,
+//! the compositor ignoring the devices does not.
+
+use std::fmt::Display;
+use std::process::Command;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use tracing::{debug, warn};
+
+use crate::config::InputFilter;
+use crate::integration::notification::command_available;
+
+/// How devices are blocked during a break
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Eq, PartialEq, Default)]
+pub(crate) enum Backend {
+    /// Take exclusive access to the devices, works everywhere
+    #[default]
+    Grab,
+    /// Ask sway to ignore the devices, needs the sway ipc socket (`SWAYSOCK`)
+    Sway,
+    /// Sway when running inside a sway session, grab otherwise
+    Auto,
+    /// Lock the session and lock it again whenever it is unlocked during the
+    /// break, the devices are not blocked. Needs loginctl.
+    ScreenLock,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Grab => f.write_str("grab"),
+            Backend::Sway => f.write_str("sway"),
+            Backend::Auto => f.write_str("auto"),
+            Backend::ScreenLock => f.write_str("screen-lock"),
+        }
+    }
+}
+
+impl Backend {
+    /// Picks the backend for `Auto` based on the environment
+    pub(crate) fn resolve(self) -> Self {
+        match self {
+            Backend::Auto if std::env::var_os("SWAYSOCK").is_some() => Backend::Sway,
+            Backend::Auto => Backend::Grab,
+            other => other,
+        }
+    }
+
+    pub(crate) fn check_dependency(self) -> Result<()> {
+        match self.resolve() {
+            Backend::Grab | Backend::Auto => Ok(()),
+            Backend::Sway => {
+                command_available("swaymsg", "swaymsg version", "swaymsg comes with sway")?;
+                if std::env::var_os("SWAYSOCK").is_none() {
+                    return Err(eyre!("SWAYSOCK is not set, can not reach sway"))
+                        .suggestion("Run as a user service inside the sway session")
+                        .suggestion(
+                            "Import the variable into the service environment: \
+                            systemctl --user import-environment SWAYSOCK",
+                        );
+                }
+                Ok(())
+            }
+            Backend::ScreenLock => command_available(
+                "loginctl",
+                "systemd",
+                "loginctl comes with systemd (elogind on other systems)",
+            ),
+        }
+    }
+}
+
+/// The identifier sway uses for an input: `vendor:product:name` with
+/// decimal ids and spaces in the name replaced by underscores
+fn sway_identifier(filter: &InputFilter, name: &str) -> String {
+    format!(
+        "{}:{}:{}",
+        filter.id.vendor(),
+        filter.id.product(),
+        name.replace(' ', "_")
+    )
+}
+
+/// The identifier ends up inside a quoted swaymsg command, a quote could end
+/// it and a `,` or `;` start the next command. No real device needs these.
+fn safe_identifier(identifier: &str) -> bool {
+    !identifier
+        .chars()
+        .any(|c| matches!(c, '"' | '\\' | ',' | ';') || c.is_control())
+}
+
+/// Devices sway ignores until `enable` is called or this is dropped
+#[must_use]
+pub(crate) struct Disabled {
+    identifiers: Vec<String>,
+}
+
+fn set_events(identifiers: &[String], state: &str) -> Result<()> {
+    if identifiers.is_empty() {
+        return Ok(());
+    }
+    let commands: Vec<_> = identifiers
+        .iter()
+        .map(|identifier| format!("input \"{identifier}\" events {state}"))
+        .collect();
+    debug!("Setting events {state} for: {identifiers:?}");
+    let output = Command::new("swaymsg")
+        .arg(commands.join(", "))
+        .output()
+        .wrap_err("Could not run swaymsg")?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(eyre!("swaymsg failed"))
+            .with_note(|| format!("output: {}", String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+pub(crate) fn disable(filters: &[InputFilter]) -> Result<Disabled> {
+    let identifiers: Vec<_> = filters
+        .iter()
+        .flat_map(|filter| {
+            filter
+                .names
+                .iter()
+                .map(move |name| sway_identifier(filter, name))
+        })
+        .filter(|identifier| {
+            let safe = safe_identifier(identifier);
+            if !safe {
+                warn!("Not disabling input with unusual name in sway: {identifier:?}");
+            }
+            safe
+        })
+        .collect();
+    set_events(&identifiers, "disabled").wrap_err("Could not disable inputs in sway")?;
+    Ok(Disabled { identifiers })
+}
+
+impl Disabled {
+    pub(crate) fn enable(mut self) -> Result<()> {
+        let identifiers = std::mem::take(&mut self.identifiers);
+        set_events(&identifiers, "enabled").wrap_err("Could not enable inputs in sway")
+    }
+}
+
+impl Drop for Disabled {
+    /// Not released through `enable`, for example on an error during the
+    /// break. The inputs must not stay disabled.
+    fn drop(&mut self) {
+        if let Err(report) = set_events(&self.identifiers, "enabled") {
+            warn!("Could not enable inputs in sway: {report:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identifier_matches_swaymsg_get_inputs() {
+        let filter = InputFilter {
+            // vendor 1, product 1, version 0xab41
+            id: "AAEAAatB".parse().unwrap(),
+            names: vec!["AT Translated Set 2 keyboard".to_string()],
+            address: None,
+            activity: None,
+        };
+        assert_eq!(
+            sway_identifier(&filter, &filter.names[0]),
+            "1:1:AT_Translated_Set_2_keyboard"
+        );
+    }
+
+    #[test]
+    fn refuses_identifiers_that_escape_the_command() {
+        assert!(safe_identifier("1:1:AT_Translated_Set_2_keyboard"));
+        assert!(safe_identifier("1133:49291:Logitech_G502_(HERO)"));
+        assert!(!safe_identifier(
+            "1:1:x\" events enabled, exec rm -rf ~, input \"y"
+        ));
+        assert!(!safe_identifier("1:1:x;exec_foo"));
+        assert!(!safe_identifier("1:1:back\\slash"));
+    }
+}
@@ -2,32 +2,501 @@ use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
 use serde::{Deserialize, Serialize};
 
+use std::collections::HashSet;
+use std::fmt::{self, Write};
 use std::fs;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use crate::cli::{ConfigArgs, ConfigCommand, ConfigField};
+use crate::compositor::Backend;
+use crate::integration::ActivityWatch;
 use crate::watch_and_block::InputId;
+use crate::{device_class, status};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct InputFilter {
     pub id: InputId,
     /// names, a single deviceid can have multiple blockable inputs with
     /// different names
     pub names: Vec<String>,
+    /// Bluetooth address (MAC), bluetooth devices reconnect under a new event
+    /// path and sometimes with another name. With this set any device with
+    /// the address matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Input only counts as activity once the device sends this much of it,
+    /// for example to ignore a mouse sensor that drifts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity: Option<ActivityThreshold>,
 }
 
-fn setup_default_path() -> PathBuf {
-    let dir = Path::new(concat!("/etc/", env!("CARGO_CRATE_NAME"), ".ron"));
+/// At least `reports` input reports within `within`, in the config for
+/// example: `activity: Some((reports: 3, within: (secs: 2, nanos: 0)))`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ActivityThreshold {
+    pub reports: u32,
+    pub within: Duration,
+}
+
+impl ActivityThreshold {
+    /// `<reports>/<duration>`, for example `3/2s`
+    pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+        let (reports, within) = arg
+            .split_once('/')
+            .ok_or_else(|| "Expected <reports>/<duration>, for example 3/2s".to_string())?;
+        let reports = reports
+            .parse()
+            .map_err(|e| format!("Invalid number of reports: {e}"))?;
+        let within = crate::cli::parse_duration(within).map_err(|e| e.to_string())?;
+        if reports == 0 || within.is_zero() {
+            return Err("The reports and duration can not be zero".to_string());
+        }
+        Ok(Self { reports, within })
+    }
+}
+
+impl fmt::Display for ActivityThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}s", self.reports, self.within.as_secs_f32())
+    }
+}
+
+/// Six hex bytes separated by colons, like the kernel shows them
+fn parse_address(arg: &str) -> Result<String, String> {
+    let bytes: Vec<_> = arg.split(':').collect();
+    let valid = bytes.len() == 6
+        && bytes
+            .iter()
+            .all(|b| b.len() == 2 && b.chars().all(|c| c.is_ascii_hexdigit()));
+    if valid {
+        Ok(arg.to_ascii_uppercase())
+    } else {
+        Err(format!(
+            "Not a bluetooth address, expected for example AA:BB:CC:DD:EE:FF got: {arg}"
+        ))
+    }
+}
+
+impl InputFilter {
+    pub(crate) fn matches(&self, id: &InputId, name: &str, address: Option<&str>) -> bool {
+        let same_address = self
+            .address
+            .as_deref()
+            .is_some_and(|a| address.is_some_and(|b| a.eq_ignore_ascii_case(b)));
+        same_address || (self.id == *id && self.names.iter().any(|n| n == name))
+    }
+}
+
+/// `/etc/break_enforcer.ron`, unless only `/etc/break_enforcer.toml` exists
+pub(crate) fn setup_default_path() -> PathBuf {
+    let ron = Path::new(concat!("/etc/", env!("CARGO_CRATE_NAME"), ".ron"));
     assert!(
-        dir.parent().expect("path has two components").is_dir(),
+        ron.parent().expect("path has two components").is_dir(),
         "/etc should exist on unix"
     );
-    dir.to_path_buf()
+    let toml = ron.with_extension("toml");
+    if !ron.exists() && toml.exists() {
+        toml
+    } else {
+        ron.to_path_buf()
+    }
+}
+
+/// Configs ending in `.toml` are toml, anything else is ron
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Ron,
+    Toml,
+}
+
+impl Format {
+    pub(crate) fn of(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            Format::Toml
+        } else {
+            Format::Ron
+        }
+    }
+
+    pub(crate) fn other(self) -> Self {
+        match self {
+            Format::Ron => Format::Toml,
+            Format::Toml => Format::Ron,
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Format::Ron => "ron",
+            Format::Toml => "toml",
+        }
+    }
+
+    fn deserialize(self, s: &str) -> Result<Vec<InputFilter>> {
+        match self {
+            Format::Ron => ron::from_str(s).wrap_err("Could not deserialize to list of devices"),
+            Format::Toml => from_toml(s),
+        }
+    }
+
+    fn serialize(self, to_lock: &[InputFilter]) -> Result<String> {
+        match self {
+            Format::Ron => ron::ser::to_string_pretty(&to_lock, ron::ser::PrettyConfig::default())
+                .wrap_err("Could not serialize list of devices to ron"),
+            Format::Toml => to_toml(to_lock),
+        }
+    }
+}
+
+/// Settings at the top of a toml config, above the devices. The ron config
+/// is only a list of devices and has none, drop-ins can not change them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Settings {
+    /// How breaks are enforced: `enforcement = "screenlock"`. Like
+    /// `--block-backend`, also takes grab, sway and auto.
+    pub(crate) enforcement: Option<Backend>,
+    /// Send finished work periods, breaks and pauses to ActivityWatch:
+    /// ```toml
+    /// [activitywatch]
+    /// address = "127.0.0.1:5600"
+    /// bucket = "break-enforcer"
+    /// ```
+    /// Both fields are optional, these are the defaults.
+    pub(crate) activitywatch: Option<ActivityWatch>,
+}
+
+impl Settings {
+    const KEYS: &'static [&'static str] = &["enforcement", "activitywatch"];
+
+    fn from_toml(document: &toml_edit::Table) -> Result<Self> {
+        let activitywatch = document
+            .get("activitywatch")
+            .map(|table| {
+                serde_json::from_value(toml_item_to_json(table)?)
+                    .wrap_err("Could not read the activitywatch settings")
+            })
+            .transpose()?;
+        let Some(enforcement) = document.get("enforcement") else {
+            return Ok(Self {
+                enforcement: None,
+                activitywatch,
+            });
+        };
+        let enforcement = enforcement
+            .as_str()
+            .and_then(|name| {
+                Some(match name {
+                    "grab" => Backend::Grab,
+                    "sway" => Backend::Sway,
+                    "auto" => Backend::Auto,
+                    "screenlock" => Backend::ScreenLock,
+                    _ => return None,
+                })
+            })
+            .ok_or_else(|| eyre!("Unknown enforcement in config"))
+            .suggestion("Use one of: \"grab\", \"sway\", \"auto\" or \"screenlock\"")?;
+        Ok(Self {
+            enforcement: Some(enforcement),
+            activitywatch,
+        })
+    }
+
+    /// Keys before tables, tables before the `[[device]]` tables
+    fn to_toml(&self) -> String {
+        let mut toml = String::new();
+        if let Some(enforcement) = self.enforcement {
+            let name = match enforcement {
+                Backend::Grab => "grab",
+                Backend::Sway => "sway",
+                Backend::Auto => "auto",
+                Backend::ScreenLock => "screenlock",
+            };
+            writeln!(toml, "enforcement = \"{name}\"\n").expect("can write to a string");
+        }
+        if let Some(activitywatch) = &self.activitywatch {
+            let bucket =
+                toml_value(&activitywatch.bucket.as_str().into()).expect("strings are valid toml");
+            writeln!(
+                toml,
+                "[activitywatch]\naddress = \"{}\"\nbucket = {bucket}\n",
+                activitywatch.address
+            )
+            .expect("can write to a string");
+        }
+        toml
+    }
+}
+
+/// The settings in the main config, see `Settings`
+pub(crate) fn settings(custom_path: Option<PathBuf>) -> Result<Settings> {
+    read_settings(&custom_path.unwrap_or_else(setup_default_path))
+}
+
+fn read_settings(path: &Path) -> Result<Settings> {
+    if Format::of(path) != Format::Toml {
+        return Ok(Settings::default());
+    }
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Settings::default()),
+        Err(err) => {
+            return Err(err)
+                .wrap_err("Could not read config which might exist")
+                .with_note(|| format!("path: {}", path.display()))
+        }
+    };
+    let document = toml_edit::Document::parse(&s).wrap_err("Config is not valid toml")?;
+    Settings::from_toml(document.as_table()).with_note(|| format!("path: {}", path.display()))
+}
+
+/// The devices are `[[device]]` tables with the same fields as in ron, for
+/// example: `id = { vendor = 1, product = 2, version = 3 }`. The toml is
+/// turned into json to reuse the serde implementations.
+fn from_toml(s: &str) -> Result<Vec<InputFilter>> {
+    let document = toml_edit::Document::parse(s).wrap_err("Config is not valid toml")?;
+    if let Some((key, _)) = document
+        .iter()
+        .find(|(key, _)| *key != "device" && !Settings::KEYS.contains(key))
+    {
+        return Err(eyre!("Unknown key in config: {key}"))
+            .suggestion("The devices to block go in [[device]] tables");
+    }
+    let devices = match document.get("device") {
+        Some(devices) => toml_item_to_json(devices)?,
+        None => serde_json::Value::Array(Vec::new()),
+    };
+    serde_json::from_value(devices).wrap_err("Could not deserialize to list of devices")
+}
+
+fn toml_item_to_json(item: &toml_edit::Item) -> Result<serde_json::Value> {
+    match item {
+        toml_edit::Item::None => Ok(serde_json::Value::Null),
+        toml_edit::Item::Value(value) => toml_value_to_json(value),
+        toml_edit::Item::Table(table) => toml_table_to_json(table),
+        toml_edit::Item::ArrayOfTables(tables) => tables
+            .iter()
+            .map(toml_table_to_json)
+            .collect::<Result<_>>()
+            .map(serde_json::Value::Array),
+    }
+}
+
+fn toml_table_to_json(table: &toml_edit::Table) -> Result<serde_json::Value> {
+    table
+        .iter()
+        .map(|(key, item)| Ok((key.to_string(), toml_item_to_json(item)?)))
+        .collect::<Result<_>>()
+        .map(serde_json::Value::Object)
+}
+
+fn toml_value_to_json(value: &toml_edit::Value) -> Result<serde_json::Value> {
+    use serde_json::Value as Json;
+    use toml_edit::Value as Toml;
+
+    Ok(match value {
+        Toml::String(s) => Json::String(s.value().clone()),
+        Toml::Integer(i) => Json::from(*i.value()),
+        Toml::Float(f) => serde_json::Number::from_f64(*f.value())
+            .map(Json::Number)
+            .ok_or_else(|| eyre!("The config can not contain nan or inf"))?,
+        Toml::Boolean(b) => Json::Bool(*b.value()),
+        Toml::Datetime(d) => return Err(eyre!("The config has no dates, got: {}", d.value())),
+        Toml::Array(items) => Json::Array(
+            items
+                .iter()
+                .map(toml_value_to_json)
+                .collect::<Result<_>>()?,
+        ),
+        Toml::InlineTable(table) => Json::Object(
+            table
+                .iter()
+                .map(|(key, value)| Ok((key.to_string(), toml_value_to_json(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+/// The fields of `InputFilter` in the order they are written, json sorts them
+const FIELD_ORDER: [&str; 4] = ["id", "names", "address", "activity"];
+
+fn to_toml(to_lock: &[InputFilter]) -> Result<String> {
+    let mut toml = String::new();
+    for filter in to_lock {
+        let fields = serde_json::to_value(filter).wrap_err("Could not serialize device")?;
+        let serde_json::Value::Object(fields) = fields else {
+            unreachable!("InputFilter is a struct");
+        };
+        let mut fields: Vec<_> = fields.into_iter().collect();
+        fields.sort_by_key(|(key, _)| FIELD_ORDER.iter().position(|field| field == key));
+        toml.push_str("[[device]]\n");
+        for (key, value) in &fields {
+            writeln!(toml, "{key} = {}", toml_value(value)?).expect("can write to a string");
+        }
+        toml.push('\n');
+    }
+    Ok(toml)
+}
+
+/// Inline, the fields of `InputFilter` are all short
+fn toml_value(value: &serde_json::Value) -> Result<String> {
+    use serde_json::Value as Json;
+
+    Ok(match value {
+        Json::Null => return Err(eyre!("toml has no null, skip serializing it instead")),
+        Json::Bool(b) => b.to_string(),
+        Json::Number(n) => n.to_string(),
+        // json escapes are valid in toml, except that toml also escapes DEL
+        Json::String(s) => serde_json::to_string(s)
+            .expect("strings always serialize")
+            .replace('\u{7f}', "\\u007F"),
+        Json::Array(items) => {
+            let items: Vec<_> = items.iter().map(toml_value).collect::<Result<_>>()?;
+            format!("[{}]", items.join(", "))
+        }
+        Json::Object(fields) => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|(key, value)| Ok(format!("{key} = {}", toml_value(value)?)))
+                .collect::<Result<_>>()?;
+            format!("{{ {} }}", fields.join(", "))
+        }
+    })
 }
 
+/// The main config with the drop-ins merged over it, see `drop_ins`
 pub(crate) fn read(custom_path: Option<PathBuf>) -> Result<Vec<InputFilter>> {
     let path = custom_path.unwrap_or_else(setup_default_path);
-    let bytes = match fs::read(&path) {
+    let mut filters = read_file(&path)?;
+    for (_, drop_in) in drop_ins(&path)? {
+        for filter in drop_in {
+            merge(&mut filters, filter);
+        }
+    }
+    Ok(filters)
+}
+
+/// Only writes the main config. What changed compared to `read` is applied
+/// to it, what the drop-ins add is left out.
+pub(crate) fn write(to_lock: &[InputFilter], custom_path: Option<PathBuf>) -> Result<()> {
+    let path = custom_path.unwrap_or_else(setup_default_path);
+    let original = read_file(&path)?;
+    let mut before = original.clone();
+    for (_, drop_in) in drop_ins(&path)? {
+        for filter in drop_in {
+            merge(&mut before, filter);
+        }
+    }
+
+    let mut main = original.clone();
+    apply_changes(&mut main, &before, to_lock);
+    if main == original && path.exists() {
+        return Ok(());
+    }
+    write_file(&main, &path)
+}
+/// Configs in `<config without extension>.d/`, for example
+/// `/etc/break_enforcer.d/laptop.toml`. Read in the order of their names,
+/// files not ending in `.ron` or `.toml` are ignored.
+fn drop_ins(config: &Path) -> Result<Vec<(PathBuf, Vec<InputFilter>)>> {
+    let dir = config.with_extension("d");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err)
+                .wrap_err("Could not list the drop-in configs")
+                .with_note(|| format!("dir: {}", dir.display()))
+        }
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry.wrap_err("Could not list the drop-in configs")?.path();
+        if path
+            .extension()
+            .is_some_and(|ext| ext == "ron" || ext == "toml")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| Ok((path.clone(), read_file(&path)?)))
+        .collect()
+}
+
+/// A device already in the list gets the names of `filter` added, its
+/// address and activity threshold are replaced if `filter` has them
+fn merge(filters: &mut Vec<InputFilter>, filter: InputFilter) {
+    let Some(existing) = filters.iter_mut().find(|f| f.id == filter.id) else {
+        filters.push(filter);
+        return;
+    };
+    for name in filter.names {
+        if !existing.names.contains(&name) {
+            existing.names.push(name);
+        }
+    }
+    existing.address = filter.address.or(existing.address.take());
+    existing.activity = filter.activity.or(existing.activity);
+}
+
+/// Changes `main` the way `before` changed into `after`. Fields that did
+/// not change keep what `main` has, even where a drop-in overrides them.
+fn apply_changes(main: &mut Vec<InputFilter>, before: &[InputFilter], after: &[InputFilter]) {
+    main.retain(|filter| after.iter().any(|f| f.id == filter.id));
+    for new in after {
+        let old = before.iter().find(|f| f.id == new.id);
+        if old == Some(new) {
+            continue;
+        }
+        let index = match main.iter().position(|f| f.id == new.id) {
+            Some(index) => index,
+            None => {
+                main.push(InputFilter {
+                    id: new.id,
+                    names: Vec::new(),
+                    address: None,
+                    activity: None,
+                });
+                main.len() - 1
+            }
+        };
+        let filter = &mut main[index];
+        let old_names = old.map(|old| old.names.as_slice()).unwrap_or_default();
+        filter
+            .names
+            .retain(|name| new.names.contains(name) || !old_names.contains(name));
+        for name in &new.names {
+            if !old_names.contains(name) && !filter.names.contains(name) {
+                filter.names.push(name.clone());
+            }
+        }
+        if old.map(|old| &old.address) != Some(&new.address) {
+            filter.address.clone_from(&new.address);
+        }
+        if old.map(|old| old.activity) != Some(new.activity) {
+            filter.activity = new.activity;
+        }
+    }
+    main.retain(|f| !f.names.is_empty() || f.address.is_some() || f.activity.is_some());
+}
+
+/// The drop-in that blocks devices with this `id`, if any
+fn drop_in_blocking(config: &Path, id: &InputId) -> Result<Option<PathBuf>> {
+    Ok(drop_ins(config)?
+        .into_iter()
+        .find(|(_, filters)| filters.iter().any(|filter| filter.id == *id))
+        .map(|(path, _)| path))
+}
+
+fn read_file(path: &Path) -> Result<Vec<InputFilter>> {
+    let bytes = match fs::read(path) {
         Ok(bytes) => bytes,
         Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
         Err(err) => {
@@ -38,14 +507,22 @@ pub(crate) fn read(custom_path: Option<PathBuf>) -> Result<Vec<InputFilter>> {
     };
 
     let s = String::from_utf8(bytes).wrap_err("Corrupt config, contained non utf8")?;
-    ron::from_str(&s).wrap_err("Could not deserialize to list of devices")
+    Format::of(path)
+        .deserialize(&s)
+        .with_note(|| format!("path: {}", path.display()))
 }
 
-pub(crate) fn write(to_lock: &[InputFilter], custom_path: Option<PathBuf>) -> Result<()> {
-    let data = ron::ser::to_string_pretty(&to_lock, ron::ser::PrettyConfig::default())
-        .wrap_err("Could not serialize list of devices to toml")?;
+/// The settings of a toml config are kept
+fn write_file(to_lock: &[InputFilter], path: &Path) -> Result<()> {
+    write_with_settings(&read_settings(path)?, to_lock, path)
+}
+
+fn write_with_settings(settings: &Settings, to_lock: &[InputFilter], path: &Path) -> Result<()> {
+    let mut data = Format::of(path).serialize(to_lock)?;
+    if Format::of(path) == Format::Toml {
+        data.insert_str(0, &settings.to_toml());
+    }
 
-    let path = custom_path.unwrap_or_else(setup_default_path);
     if let Some(dir) = path.parent() {
         if !dir.is_dir() {
             return Err(
@@ -56,3 +533,460 @@ pub(crate) fn write(to_lock: &[InputFilter], custom_path: Option<PathBuf>) -> Re
 
     fs::write(path, data.as_bytes()).wrap_err("Could not write serialized list to file")
 }
+
+pub(crate) fn run(args: ConfigArgs, config_path: Option<PathBuf>) -> Result<()> {
+    let api_port = args.api_port;
+    match args.command {
+        ConfigCommand::Convert { output, remove } => convert(config_path, output, remove),
+        ConfigCommand::Get { id, field } => get(config_path, id, field),
+        ConfigCommand::Set {
+            id,
+            field,
+            value,
+            apply,
+        } => edit(config_path, apply, api_port, |filters, path| {
+            let filter = find(filters, &id, path)?;
+            set(filter, field, value).map_err(|e| eyre!(e))
+        }),
+        ConfigCommand::AddDevice { id, names, apply } => {
+            edit(config_path, apply, api_port, |filters, _| {
+                merge(
+                    filters,
+                    InputFilter {
+                        id,
+                        names,
+                        address: None,
+                        activity: None,
+                    },
+                );
+                Ok(())
+            })
+        }
+        ConfigCommand::RemoveDevice { id, apply } => {
+            edit(config_path, apply, api_port, |filters, path| {
+                find(filters, &id, path)?;
+                filters.retain(|filter| filter.id != id);
+                Ok(())
+            })
+        }
+    }
+}
+
+fn get(
+    config_path: Option<PathBuf>,
+    id: Option<InputId>,
+    field: Option<ConfigField>,
+) -> Result<()> {
+    let path = config_path.clone().unwrap_or_else(setup_default_path);
+    let filters = read(config_path)?;
+    let Some(id) = id else {
+        print!("{}", Format::of(&path).serialize(&filters)?);
+        return Ok(());
+    };
+    let filter = filters
+        .into_iter()
+        .find(|filter| filter.id == id)
+        .ok_or_else(|| eyre!("Device {id} is not in the config"))?;
+    match field {
+        None => print!("{}", Format::of(&path).serialize(&[filter])?),
+        Some(ConfigField::Names) => filter.names.iter().for_each(|name| println!("{name}")),
+        Some(ConfigField::Address) => filter.address.into_iter().for_each(|a| println!("{a}")),
+        Some(ConfigField::Activity) => filter.activity.into_iter().for_each(|a| println!("{a}")),
+    }
+    Ok(())
+}
+
+/// The device in the main config, devices only in a drop-in have to be
+/// changed there
+fn find<'a>(
+    filters: &'a mut [InputFilter],
+    id: &InputId,
+    config: &Path,
+) -> Result<&'a mut InputFilter> {
+    if let Some(index) = filters.iter().position(|filter| filter.id == *id) {
+        return Ok(&mut filters[index]);
+    }
+    match drop_in_blocking(config, id)? {
+        Some(drop_in) => Err(eyre!("Device {id} is only in a drop-in config"))
+            .with_note(|| format!("path: {}", drop_in.display()))
+            .suggestion("Change it there"),
+        None => Err(eyre!("Device {id} is not in the config"))
+            .suggestion("Add it using config add-device"),
+    }
+}
+
+fn set(filter: &mut InputFilter, field: ConfigField, value: Vec<String>) -> Result<(), String> {
+    if field == ConfigField::Names {
+        if value.iter().any(String::is_empty) {
+            return Err("A name can not be empty".to_string());
+        }
+        let mut seen = HashSet::new();
+        filter.names = value
+            .into_iter()
+            .filter(|name| seen.insert(name.clone()))
+            .collect();
+        return Ok(());
+    }
+
+    let [value] = &value[..] else {
+        return Err(format!(
+            "Expected one value for {field:?}, got {}",
+            value.len()
+        ));
+    };
+    let value = Some(value).filter(|value| *value != "none");
+    match field {
+        ConfigField::Names => unreachable!("handled above"),
+        ConfigField::Address => filter.address = value.map(|a| parse_address(a)).transpose()?,
+        ConfigField::Activity => {
+            filter.activity = value.map(|a| ActivityThreshold::parse(a)).transpose()?;
+        }
+    }
+    Ok(())
+}
+
+/// Changes the main config, the drop-ins are left alone
+fn edit(
+    config_path: Option<PathBuf>,
+    apply: bool,
+    api_port: Option<u16>,
+    change: impl FnOnce(&mut Vec<InputFilter>, &Path) -> Result<()>,
+) -> Result<()> {
+    let path = config_path.unwrap_or_else(setup_default_path);
+    let mut filters = read_file(&path)?;
+    change(&mut filters, &path)?;
+    write_file(&filters, &path)?;
+    if read_file(&path)? != filters {
+        return Err(eyre!("The written config does not read back the same"))
+            .with_note(|| format!("path: {}", path.display()));
+    }
+
+    if apply {
+        status::connect(api_port)
+            .and_then(|mut api| api.reload_config())
+            .wrap_err("Could not have the running service reload the config")
+            .suggestion("Restart the service instead")?;
+        println!("The service blocks the changed devices from the next break on");
+    }
+    Ok(())
+}
+
+fn convert(config_path: Option<PathBuf>, output: Option<PathBuf>, remove: bool) -> Result<()> {
+    let custom = config_path.is_some();
+    let input = config_path.unwrap_or_else(setup_default_path);
+    if !input.is_file() {
+        return Err(eyre!("There is no config to convert"))
+            .with_note(|| format!("path: {}", input.display()))
+            .suggestion("Run the wizard to create one");
+    }
+    let output =
+        output.unwrap_or_else(|| input.with_extension(Format::of(&input).other().extension()));
+    if output.exists() {
+        return Err(eyre!("The converted config would overwrite a file"))
+            .with_note(|| format!("path: {}", output.display()))
+            .suggestion("Remove it or pass another path");
+    }
+
+    let settings = read_settings(&input)?;
+    if settings != Settings::default() && Format::of(&output) != Format::Toml {
+        return Err(eyre!(
+            "The config has settings, a ron config can not hold these"
+        ))
+        .with_note(|| format!("settings: {settings:?}"))
+        .suggestion("Keep using the toml config");
+    }
+    let filters = read_file(&input)?;
+    write_with_settings(&settings, &filters, &output)?;
+    let converted = read_file(&output).wrap_err("Could not read the converted config")?;
+    if converted != filters {
+        return Err(eyre!("The converted config does not match the original"))
+            .with_note(|| format!("converted: {}", output.display()));
+    }
+    println!(
+        "Converted {} ({} device(s)) to {}",
+        input.display(),
+        filters.len(),
+        output.display()
+    );
+
+    if remove {
+        fs::remove_file(&input)
+            .wrap_err("Could not remove the original config")
+            .with_note(|| format!("path: {}", input.display()))?;
+        println!("Removed {}", input.display());
+    } else if !custom && setup_default_path() != output {
+        println!(
+            "Kept {}, it is used instead of {} until removed",
+            input.display(),
+            output.display()
+        );
+    }
+    if custom {
+        println!(
+            "Pass --config-path {} from now on, install again to update the service",
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// The devices to block. Shared between the break loop, the activity watcher
+/// and the api. Changes made at runtime are persisted to the config.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockList {
+    filters: Arc<Mutex<Vec<InputFilter>>>,
+    config_path: Option<PathBuf>,
+    /// also block the virtual devices of key remappers
+    remappers: bool,
+}
+
+impl BlockList {
+    pub(crate) fn new(
+        filters: Vec<InputFilter>,
+        config_path: Option<PathBuf>,
+        remappers: bool,
+    ) -> Self {
+        Self {
+            filters: Arc::new(Mutex::new(filters)),
+            config_path,
+            remappers,
+        }
+    }
+
+    pub(crate) fn remappers(&self) -> bool {
+        self.remappers
+    }
+
+    pub(crate) fn filters(&self) -> Vec<InputFilter> {
+        self.filters.lock().unwrap().clone()
+    }
+
+    pub(crate) fn matches(&self, id: &InputId, name: &str, address: Option<&str>) -> bool {
+        if self.remappers && device_class::is_remapper(name) {
+            return true;
+        }
+        self.filters
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|filter| filter.matches(id, name, address))
+    }
+
+    /// The threshold of the first matching filter that has one
+    pub(crate) fn activity_threshold(
+        &self,
+        id: &InputId,
+        name: &str,
+        address: Option<&str>,
+    ) -> Option<ActivityThreshold> {
+        self.filters
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|filter| filter.matches(id, name, address))
+            .find_map(|filter| filter.activity)
+    }
+
+    /// Adds the names to the filter for `id`, creating it if needed.
+    pub(crate) fn block(&self, id: InputId, names: Vec<String>) -> Result<()> {
+        let mut filters = self.filters.lock().unwrap();
+        if let Some(filter) = filters.iter_mut().find(|filter| filter.id == id) {
+            for name in names {
+                if !filter.names.contains(&name) {
+                    filter.names.push(name);
+                }
+            }
+        } else {
+            filters.push(InputFilter {
+                id,
+                names,
+                address: None,
+                activity: None,
+            });
+        }
+        write(&filters, self.config_path.clone()).wrap_err("Could not persist blocked devices")
+    }
+
+    /// Reads the config again, for changes made by `config set` and
+    /// friends. Takes effect from the next break on.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let filters = read(self.config_path.clone())?;
+        *self.filters.lock().unwrap() = filters;
+        Ok(())
+    }
+
+    /// Returns false if no device with this `id` was blocked.
+    pub(crate) fn unblock(&self, id: &InputId) -> Result<bool> {
+        let config = self.config_path.clone().unwrap_or_else(setup_default_path);
+        if let Some(drop_in) = drop_in_blocking(&config, id)? {
+            return Err(eyre!("The device is blocked by a drop-in config"))
+                .with_note(|| format!("path: {}", drop_in.display()))
+                .suggestion("Remove it from the drop-in");
+        }
+        let mut filters = self.filters.lock().unwrap();
+        let len_before = filters.len();
+        filters.retain(|filter| filter.id != *id);
+        if filters.len() == len_before {
+            return Ok(false);
+        }
+        write(&filters, self.config_path.clone()).wrap_err("Could not persist blocked devices")?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(address: Option<&str>) -> InputFilter {
+        InputFilter {
+            id: "AAEAAgAD".parse().unwrap(),
+            names: vec!["Keyboard".to_string()],
+            address: address.map(String::from),
+            activity: None,
+        }
+    }
+
+    #[test]
+    fn bluetooth_address_matches_despite_new_name() {
+        let filter = filter(Some("AA:BB:CC:DD:EE:FF"));
+        let other_id = "AAEAAgAE".parse().unwrap();
+        assert!(filter.matches(&other_id, "Keyboard 2", Some("aa:bb:cc:dd:ee:ff")));
+        assert!(!filter.matches(&other_id, "Keyboard 2", Some("AA:BB:CC:DD:EE:00")));
+        assert!(filter.matches(&filter.id, "Keyboard", None));
+    }
+
+    #[test]
+    fn reads_config_without_address() {
+        let filters: Vec<InputFilter> =
+            ron::from_str(r#"[(id: (vendor: 1, product: 2, version: 3), names: ["Keyboard"])]"#)
+                .unwrap();
+        assert_eq!(filters, vec![filter(None)]);
+        assert!(!ron::to_string(&filters).unwrap().contains("address"));
+    }
+
+    #[test]
+    fn reads_activity_threshold() {
+        let filters: Vec<InputFilter> = ron::from_str(
+            r#"[(id: (vendor: 1, product: 2, version: 3), names: ["Keyboard"],
+            activity: Some((reports: 3, within: (secs: 2, nanos: 0))))]"#,
+        )
+        .unwrap();
+        let threshold = ActivityThreshold {
+            reports: 3,
+            within: Duration::from_secs(2),
+        };
+        assert_eq!(filters[0].activity, Some(threshold));
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        let mut filters = vec![filter(Some("AA:BB:CC:DD:EE:FF")), filter(None)];
+        filters[1].names.push("Odd \"name\"\u{7f}".to_string());
+        filters[1].activity = Some(ActivityThreshold {
+            reports: 3,
+            within: Duration::from_millis(2500),
+        });
+        let toml = Format::Toml.serialize(&filters).unwrap();
+        assert_eq!(Format::Toml.deserialize(&toml).unwrap(), filters);
+        assert!(toml.starts_with(
+            "[[device]]\nid = { product = 2, vendor = 1, version = 3 }\nnames = [\"Keyboard\"]\naddress = "
+        ));
+
+        let written = r#"
+            [[device]]
+            id = { vendor = 1, product = 2, version = 3 }
+            names = ["Keyboard"]
+        "#;
+        assert_eq!(
+            Format::Toml.deserialize(written).unwrap(),
+            vec![filter(None)]
+        );
+        assert!(Format::Toml.deserialize("devices = []").is_err());
+    }
+
+    #[test]
+    fn sets_fields_from_the_command_line() {
+        let mut filter = filter(None);
+        let arg = |s: &str| vec![s.to_string()];
+        set(&mut filter, ConfigField::Activity, arg("3/2.5s")).unwrap();
+        let activity = filter.activity.unwrap();
+        assert_eq!(activity.within, Duration::from_millis(2500));
+        assert_eq!(
+            ActivityThreshold::parse(&activity.to_string()),
+            Ok(activity)
+        );
+        assert!(set(&mut filter, ConfigField::Activity, arg("0/2s")).is_err());
+
+        set(&mut filter, ConfigField::Address, arg("aa:bb:cc:dd:ee:ff")).unwrap();
+        assert_eq!(filter.address.as_deref(), Some("AA:BB:CC:DD:EE:FF"));
+        assert!(set(&mut filter, ConfigField::Address, arg("aa:bb")).is_err());
+        set(&mut filter, ConfigField::Address, arg("none")).unwrap();
+        assert_eq!(filter.address, None);
+
+        let names = ["A", "B", "A"].map(String::from).to_vec();
+        set(&mut filter, ConfigField::Names, names).unwrap();
+        assert_eq!(filter.names, ["A", "B"]);
+    }
+
+    #[test]
+    fn drop_ins_merge_over_the_config() {
+        let dir = std::env::temp_dir().join(format!("config-drop-ins-{}", std::process::id()));
+        fs::create_dir_all(dir.join("main.d")).unwrap();
+        let config = dir.join("main.ron");
+        let mut main = filter(Some("11:22:33:44:55:66"));
+        main.names.push("Shared".to_string());
+        write_file(&[main.clone()], &config).unwrap();
+        let original = fs::read_to_string(&config).unwrap();
+        let mut laptop = filter(Some("AA:BB:CC:DD:EE:FF"));
+        laptop.names = vec!["Touchpad".to_string(), "Shared".to_string()];
+        write_file(&[laptop], &dir.join("main.d/laptop.toml")).unwrap();
+        fs::write(dir.join("main.d/laptop.toml~"), "not a config").unwrap();
+
+        let filters = read(Some(config.clone())).unwrap();
+        assert_eq!(filters.len(), 1);
+        assert_eq!(filters[0].names, ["Keyboard", "Shared", "Touchpad"]);
+        assert_eq!(filters[0].address.as_deref(), Some("AA:BB:CC:DD:EE:FF"));
+
+        write(&filters, Some(config.clone())).unwrap();
+        assert_eq!(fs::read_to_string(&config).unwrap(), original);
+
+        // only the change reaches the main config
+        let mut changed = filters.clone();
+        changed[0].names.retain(|name| name != "Keyboard");
+        changed[0].names.push("Mouse".to_string());
+        write(&changed, Some(config.clone())).unwrap();
+        main.names = vec!["Shared".to_string(), "Mouse".to_string()];
+        assert_eq!(read_file(&config).unwrap(), vec![main]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn settings_survive_writing_the_devices() {
+        let dir = std::env::temp_dir().join(format!("config-settings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("main.toml");
+        fs::write(
+            &config,
+            "enforcement = \"screenlock\"\n[activitywatch]\nbucket = \"breaks\"\n",
+        )
+        .unwrap();
+        let screenlock = Settings {
+            enforcement: Some(Backend::ScreenLock),
+            activitywatch: Some(ActivityWatch {
+                address: "127.0.0.1:5600".parse().unwrap(),
+                bucket: "breaks".to_string(),
+            }),
+        };
+        assert_eq!(read_settings(&config).unwrap(), screenlock);
+        assert_eq!(read_file(&config).unwrap(), Vec::new());
+
+        write(&[filter(None)], Some(config.clone())).unwrap();
+        assert_eq!(read_settings(&config).unwrap(), screenlock);
+        assert_eq!(read_file(&config).unwrap(), vec![filter(None)]);
+
+        fs::write(&config, "enforcement = \"lasers\"\n").unwrap();
+        assert!(read_settings(&config).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Changes to the schedule requested through the api
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    /// No breaks and no counting work time until resumed. Ends a running
+    /// break.
+    Pause(Duration),
+    Resume,
+    /// Ends a running break or restarts the work period, either way a full
+    /// work period starts now
+    Skip,
+    /// Moves the coming break back, ends a running break and starts a work
+    /// period of this length
+    Snooze(Duration),
+    /// Ends a running break
+    Unlock,
+    /// Lengthens the running break, the devices stay blocked
+    Extend(Duration),
+    /// Ends the work period and starts a break of this length, or the break
+    /// duration if none is given
+    BreakNow(Option<Duration>),
+    /// Lifts the curfew for the rest of the night using one of this week's
+    /// overrides
+    LiftCurfew,
+    /// Holds off breaks while work time keeps counting, the breaks due in the
+    /// meantime are taken once it ends
+    Meeting(Duration),
+    EndMeeting,
+    /// Replaces the service with the binary on disk keeping the schedule,
+    /// used after upgrading
+    Reexec,
+}
+
+pub(crate) const DEFAULT_SNOOZE: Duration = Duration::from_secs(5 * 60);
+/// Longest pause, snooze, extension, break or meeting that can be requested
+pub(crate) const MAX_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The run loop answers with an error if the control makes no sense right
+/// now, for example unlocking while not in a break.
+pub(crate) type Reply = mpsc::Sender<Result<(), String>>;
+
+impl Control {
+    /// None if the request is not a control. Durations are in whole seconds.
+    pub(crate) fn parse(request: &str, argument: &str) -> Option<Result<Self, String>> {
+        let seconds = |argument: &str| {
+            argument
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|e| format!("Not a valid number of seconds: {e}"))
+                .and_then(|duration| {
+                    if duration > MAX_DURATION {
+                        Err(format!(
+                            "At most {} seconds are allowed",
+                            MAX_DURATION.as_secs()
+                        ))
+                    } else {
+                        Ok(duration)
+                    }
+                })
+        };
+
+        Some(match (request, argument) {
+            ("pause", argument) => seconds(argument).map(Control::Pause),
+            ("resume", "") => Ok(Control::Resume),
+            ("skip", "") => Ok(Control::Skip),
+            ("snooze", "") => Ok(Control::Snooze(DEFAULT_SNOOZE)),
+            ("snooze", argument) => seconds(argument).map(Control::Snooze),
+            ("unlock", "") => Ok(Control::Unlock),
+            ("extend", argument) => seconds(argument).map(Control::Extend),
+            ("break_now", "") => Ok(Control::BreakNow(None)),
+            ("break_now", argument) => seconds(argument).map(Some).map(Control::BreakNow),
+            ("lift_curfew", "") => Ok(Control::LiftCurfew),
+            ("reexec", "") => Ok(Control::Reexec),
+            ("meeting", argument) => seconds(argument).map(Control::Meeting),
+            ("end_meeting", "") => Ok(Control::EndMeeting),
+            ("resume" | "skip" | "unlock" | "lift_curfew" | "reexec" | "end_meeting", _) => {
+                Err(format!("{request} takes no argument"))
+            }
+            _ => return None,
+        })
+    }
+
+    /// What the control adds to the schedule, if anything
+    pub(crate) fn duration(self) -> Option<Duration> {
+        match self {
+            Control::Pause(duration)
+            | Control::Snooze(duration)
+            | Control::Extend(duration)
+            | Control::Meeting(duration)
+            | Control::BreakNow(Some(duration)) => Some(duration),
+            Control::Resume
+            | Control::Skip
+            | Control::Unlock
+            | Control::BreakNow(None)
+            | Control::LiftCurfew
+            | Control::EndMeeting
+            | Control::Reexec => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn durations_are_capped() {
+        let day = MAX_DURATION.as_secs().to_string();
+        assert_eq!(
+            Control::parse("pause", &day),
+            Some(Ok(Control::Pause(MAX_DURATION)))
+        );
+        for request in ["pause", "snooze", "extend", "break_now", "meeting"] {
+            let control = Control::parse(request, "18446744073709551615");
+            assert!(matches!(control, Some(Err(_))), "{request}");
+            let control = Control::parse(request, &(MAX_DURATION.as_secs() + 1).to_string());
+            assert!(matches!(control, Some(Err(_))), "{request}");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Writes a report when the daemon panics so problems in the field can be
+//! looked into afterwards. It holds the panic with its backtrace, the last
+//! state and the most recent log events. `status` mentions recent reports.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use tracing::field::{Field, Visit};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Log events kept for the report
+const RECENT_EVENTS: usize = 200;
+/// `status` stops mentioning a crash after this long
+const SHOWN_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+/// Older reports are removed when a new one is written, a service that keeps
+/// crashing on start must not fill the disk
+const KEEP_REPORTS: usize = 10;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static STATE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Keeps the last `RECENT_EVENTS` log events in memory
+pub(crate) struct Recent;
+
+impl<S: Subscriber> Layer<S> for Recent {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut line = format!("{:?} {}:", SystemTime::now(), event.metadata().level());
+        event.record(&mut Fields(&mut line));
+        // a panic while this is held would not be recorded anyway
+        let Ok(mut recent) = RECENT.lock() else {
+            return;
+        };
+        if recent.len() == RECENT_EVENTS {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {value:?}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
+
+/// The state to put in the report, replaced on every state change
+pub(crate) fn set_state(state: String) {
+    if let Ok(mut current) = STATE.lock() {
+        *current = Some(state);
+    }
+}
+
+/// Reports go into `dir`, it is created once needed. The hook that was
+/// installed before still runs afterwards.
+pub(crate) fn install_hook(dir: PathBuf) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_report(&dir, info) {
+            Ok(path) => eprintln!("Crash report written to: {}", path.display()),
+            Err(e) => eprintln!("Could not write crash report: {e}"),
+        }
+        previous(info);
+    }));
+}
+
+fn write_report(dir: &Path, info: &PanicHookInfo<'_>) -> std::io::Result<PathBuf> {
+    let now = SystemTime::now();
+    let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut report = format!(
+        "{} {} crashed at {}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        DateTime::<Local>::from(now).to_rfc3339()
+    );
+    let thread = std::thread::current();
+    let _ = writeln!(
+        report,
+        "thread '{}' {info}\n",
+        thread.name().unwrap_or("<unnamed>")
+    );
+    let _ = writeln!(
+        report,
+        "backtrace:\n{}",
+        std::backtrace::Backtrace::force_capture()
+    );
+    // the lock is poisoned if the panic happened while it was held
+    let state = STATE.lock().map_or(None, |state| state.clone());
+    let _ = writeln!(report, "state: {}\n", state.as_deref().unwrap_or("unknown"));
+    report.push_str("recent events:\n");
+    if let Ok(recent) = RECENT.lock() {
+        for line in recent.iter() {
+            report.push_str(line);
+            report.push('\n');
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("crash-{secs}.txt"));
+    fs::write(&path, report)?;
+    prune(dir);
+    Ok(path)
+}
+
+/// Newest first, with when they were written in seconds since the epoch
+fn reports(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut reports: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let secs = name
+                .to_str()?
+                .strip_prefix("crash-")?
+                .strip_suffix(".txt")?
+                .parse::<u64>()
+                .ok()?;
+            Some((secs, entry.path()))
+        })
+        .collect();
+    reports.sort_unstable_by(|a, b| b.cmp(a));
+    reports
+}
+
+/// Removes all but the newest `KEEP_REPORTS`
+fn prune(dir: &Path) {
+    for (_, path) in reports(dir).into_iter().skip(KEEP_REPORTS) {
+        if let Err(e) = fs::remove_file(&path) {
+            eprintln!("Could not remove old crash report {}: {e}", path.display());
+        }
+    }
+}
+
+/// The newest report if it was written less than `SHOWN_FOR` ago
+pub(crate) fn recent(dir: &Path) -> Option<(DateTime<Local>, PathBuf)> {
+    let (secs, path) = reports(dir).into_iter().next()?;
+    let at = UNIX_EPOCH + Duration::from_secs(secs);
+    if SystemTime::now().duration_since(at).unwrap_or_default() > SHOWN_FOR {
+        return None;
+    }
+    Some((DateTime::from(at), path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_the_newest_recent_report() {
+        let dir = std::env::temp_dir().join(format!("crash-reports-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(recent(&dir).is_none());
+
+        let secs = |ago: u64| {
+            (SystemTime::now() - Duration::from_secs(ago))
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        let old = dir.join(format!("crash-{}.txt", secs(2 * 24 * 60 * 60)));
+        fs::write(&old, "").unwrap();
+        assert!(recent(&dir).is_none(), "older than a day");
+        let new = dir.join(format!("crash-{}.txt", secs(60)));
+        fs::write(&new, "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        assert_eq!(recent(&dir).map(|(_, path)| path), Some(new));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_newest_reports() {
+        let dir = std::env::temp_dir().join(format!("crash-prune-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for secs in 0..KEEP_REPORTS as u64 + 3 {
+            fs::write(dir.join(format!("crash-{secs}.txt")), "").unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        prune(&dir);
+        let kept = reports(&dir);
+        assert_eq!(kept.len(), KEEP_REPORTS);
+        assert_eq!(kept.last().map(|(secs, _)| *secs), Some(3));
+        assert!(dir.join("notes.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use break_enforcer::{Error, ErrorCode};
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+
+use crate::cli::{CtlArgs, CtlCommand, Toggle};
+use crate::status;
+
+fn name(control: CtlCommand) -> &'static str {
+    match control {
+        CtlCommand::Pause { .. } => "pause",
+        CtlCommand::Resume => "resume",
+        CtlCommand::Skip => "skip",
+        CtlCommand::Snooze { .. } => "snooze",
+        CtlCommand::Unlock => "unlock",
+        CtlCommand::BreakNow { .. } => "start a break",
+        CtlCommand::Extend { .. } => "extend the break",
+        CtlCommand::LiftCurfew => "lift the curfew",
+        CtlCommand::Block { .. } => "block the device",
+        CtlCommand::Reexec => "reexec",
+        CtlCommand::Meeting {
+            state: Toggle::On, ..
+        } => "start a meeting",
+        CtlCommand::Meeting {
+            state: Toggle::Off, ..
+        } => "end the meeting",
+    }
+}
+
+pub(crate) fn run(args: &CtlArgs) -> Result<()> {
+    let mut api = status::connect(args.api_port)
+        .wrap_err("Could not connect to break-enforcer")
+        .suggestion(
+            "Is break-enforcer running and is it running with its tcp api \
+            enabled? (use --tcp-api)",
+        )?;
+
+    let res = match args.control {
+        CtlCommand::Pause { duration } => api.pause(duration),
+        CtlCommand::Resume => api.resume(),
+        CtlCommand::Skip => api.skip(),
+        CtlCommand::Snooze { duration } => api.snooze(duration),
+        CtlCommand::Unlock => api.unlock(),
+        CtlCommand::BreakNow { duration } => api.break_now(duration),
+        CtlCommand::Extend { duration } => api.extend(duration),
+        CtlCommand::LiftCurfew => api.lift_curfew(),
+        CtlCommand::Block { id } => api.block_device(&id.to_string()),
+        CtlCommand::Reexec => api.reexec(),
+        CtlCommand::Meeting {
+            state: Toggle::On,
+            duration,
+        } => api.meeting(duration.expect("clap requires it with on")),
+        CtlCommand::Meeting {
+            state: Toggle::Off, ..
+        } => api.end_meeting(),
+    };
+
+    let name = name(args.control);
+    match res {
+        Ok(()) => Ok(()),
+        Err(Error::ServerError {
+            code: ErrorCode::UnknownRequest,
+            ..
+        }) => Err(eyre!(
+            "The running break-enforcer (version {}) does not support {name}",
+            api.server_version()
+        ))
+        .suppress_backtrace(true)
+        .suggestion("Restart the service after updating break-enforcer"),
+        // nothing went wrong, the control does not apply right now
+        Err(Error::ServerError {
+            code: ErrorCode::Failed,
+            message,
+        }) => Err(eyre!("Could not {name}: {message}")).suppress_backtrace(true),
+        Err(other) => Err(other).wrap_err_with(|| format!("Could not {name}")),
+    }
+}
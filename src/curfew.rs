@@ -0,0 +1,224 @@
+//! Locks every night during a fixed window regardless of work and breaks. A
+//! few overrides per week lift it for the rest of a night, how many are used
+//! is kept in the state dir.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, TimeDelta};
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::cli::parse_time_of_day;
+use crate::clock::Clock;
+
+/// When the curfew starts and ends, the end is on the next day if it is
+/// earlier then the start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    /// Parses `hh:mm-hh:mm`
+    pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+        let (start, end) = arg
+            .split_once('-')
+            .ok_or_else(|| "Expected start and end like 23:30-06:00".to_string())?;
+        let time = |s: &str| parse_time_of_day(s).map_err(|e| format!("{e}: {s}"));
+        let window = Self {
+            start: time(start)?,
+            end: time(end)?,
+        };
+        if window.start == window.end {
+            return Err("The curfew can not start and end at the same time".to_string());
+        }
+        Ok(window)
+    }
+
+    pub(crate) fn length(&self) -> Duration {
+        let length = self.end - self.start;
+        let length = if length < TimeDelta::zero() {
+            length + TimeDelta::days(1)
+        } else {
+            length
+        };
+        length.to_std().expect("made positive above")
+    }
+
+    /// The end of the window `at` is in, None if it is outside the window
+    fn end_if_inside(&self, at: NaiveDateTime) -> Option<NaiveDateTime> {
+        let time = at.time();
+        let inside = if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        };
+        inside.then(|| next_at(at, self.end))
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// The first time after `at` the clock shows `time`
+fn next_at(at: NaiveDateTime, time: NaiveTime) -> NaiveDateTime {
+    let today = at.date().and_time(time);
+    if today > at {
+        today
+    } else {
+        today + TimeDelta::days(1)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Overrides {
+    /// iso year and week, `yyyy-Www`
+    week: String,
+    used: u32,
+}
+
+fn this_week() -> String {
+    let week = Local::now().iso_week();
+    format!("{}-W{:02}", week.year(), week.week())
+}
+
+pub(crate) struct Curfew {
+    window: Window,
+    overrides_per_week: u32,
+    overrides: Overrides,
+    /// the curfew is lifted until this night's window ends
+    lifted_until: Option<Instant>,
+    path: PathBuf,
+    clock: Arc<dyn Clock>,
+}
+
+fn to_instant(clock: &dyn Clock, at: NaiveDateTime) -> Instant {
+    let now = Local::now().naive_local();
+    clock.now() + (at - now).to_std().unwrap_or_default()
+}
+
+impl Curfew {
+    pub(crate) fn new(
+        window: Window,
+        overrides_per_week: u32,
+        path: PathBuf,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let overrides = match std::fs::read_to_string(&path) {
+            Ok(data) => ron::from_str(&data)
+                .wrap_err("Could not parse the used curfew overrides")
+                .with_note(|| format!("path: {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Overrides::default(),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not read the used curfew overrides")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        };
+        Ok(Self {
+            window,
+            overrides_per_week,
+            overrides,
+            lifted_until: None,
+            path,
+            clock,
+        })
+    }
+
+    /// The end of the curfew if it applies right now
+    pub(crate) fn active_until(&self) -> Option<Instant> {
+        let now = self.clock.now();
+        if self.lifted_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        let end = self.window.end_if_inside(Local::now().naive_local())?;
+        Some(to_instant(&*self.clock, end))
+    }
+
+    /// When the curfew starts next, or now if it applies
+    pub(crate) fn next_start(&self) -> Instant {
+        if self.active_until().is_some() {
+            return self.clock.now();
+        }
+        // also right if lifted, the next start is after tonight's window
+        let start = next_at(Local::now().naive_local(), self.window.start);
+        to_instant(&*self.clock, start)
+    }
+
+    pub(crate) fn window(&self) -> Window {
+        self.window
+    }
+
+    /// Uses up an override lifting the curfew for the rest of the night
+    pub(crate) fn lift(&mut self) -> std::result::Result<(), String> {
+        let Some(end) = self.active_until() else {
+            return Err("The curfew does not apply right now".to_string());
+        };
+        let week = this_week();
+        if self.overrides.week != week {
+            self.overrides = Overrides { week, used: 0 };
+        }
+        if self.overrides.used >= self.overrides_per_week {
+            return Err(format!(
+                "All {} curfew overrides of this week are used",
+                self.overrides_per_week
+            ));
+        }
+        self.overrides.used += 1;
+        self.lifted_until = Some(end);
+        let res = ron::to_string(&self.overrides)
+            .map_err(|e| eyre!("Could not serialize: {e}"))
+            .and_then(|data| std::fs::write(&self.path, data).wrap_err("Could not write"));
+        if let Err(report) = res {
+            error!("Could not save the used curfew overrides: {report:?}");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn overrides_left(&self) -> u32 {
+        if self.overrides.week == this_week() {
+            self.overrides_per_week.saturating_sub(self.overrides.used)
+        } else {
+            self.overrides_per_week
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let window = Window::parse("23:30-06:00").unwrap();
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+
+        assert_eq!(window.end_if_inside(at("2024-05-01 23:00")), None);
+        assert_eq!(
+            window.end_if_inside(at("2024-05-01 23:45")),
+            Some(at("2024-05-02 06:00"))
+        );
+        assert_eq!(
+            window.end_if_inside(at("2024-05-02 05:59")),
+            Some(at("2024-05-02 06:00"))
+        );
+        assert_eq!(window.end_if_inside(at("2024-05-02 06:00")), None);
+        assert_eq!(window.length(), Duration::from_secs(390 * 60));
+        let afternoon = Window::parse("13:00-14:00").unwrap();
+        assert_eq!(afternoon.length(), Duration::from_secs(60 * 60));
+        assert!(Window::parse("23:30").is_err());
+    }
+}
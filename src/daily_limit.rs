@@ -0,0 +1,193 @@
+//! Hard cap on the time spent at the computer per day. Work periods and
+//! pauses count, breaks and waiting for activity do not. Usage is kept in
+//! the state dir so restarting the service does not reset it.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::clock::Clock;
+
+/// Below this the limit counts as reached, the scheduler wakes up a little
+/// early or late
+const MARGIN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Persisted {
+    /// `yyyy-mm-dd`
+    day: String,
+    used: Duration,
+}
+
+#[derive(Debug)]
+struct Usage {
+    day: NaiveDate,
+    used: Duration,
+    counting_since: Option<Instant>,
+}
+
+#[derive(Clone)]
+pub(crate) struct DailyLimit {
+    limit: Duration,
+    /// wall clock time the day starts at
+    rollover: NaiveTime,
+    usage: Arc<Mutex<Usage>>,
+    path: PathBuf,
+    clock: Arc<dyn Clock>,
+}
+
+/// The day `at` belongs to if days start at `rollover`
+fn day_of(at: NaiveDateTime, rollover: NaiveTime) -> NaiveDate {
+    (at - rollover.signed_duration_since(NaiveTime::MIN)).date()
+}
+
+/// The first time after `now` the clock shows `rollover`
+fn next_rollover(now: NaiveDateTime, rollover: NaiveTime) -> NaiveDateTime {
+    let today = now.date().and_time(rollover);
+    if today > now {
+        today
+    } else {
+        today + TimeDelta::days(1)
+    }
+}
+
+impl DailyLimit {
+    /// Continues the usage of today stored at `path`
+    pub(crate) fn new(
+        limit: Duration,
+        rollover: NaiveTime,
+        path: PathBuf,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let today = day_of(Local::now().naive_local(), rollover);
+        let used = match std::fs::read_to_string(&path) {
+            Ok(data) => {
+                let persisted: Persisted = ron::from_str(&data)
+                    .wrap_err("Could not parse the daily usage")
+                    .with_note(|| format!("path: {}", path.display()))?;
+                if persisted.day == today.to_string() {
+                    persisted.used
+                } else {
+                    Duration::ZERO
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Duration::ZERO,
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not read the daily usage")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        };
+        debug!("Used {used:?} of the daily limit today");
+        Ok(Self {
+            limit,
+            rollover,
+            usage: Arc::new(Mutex::new(Usage {
+                day: today,
+                used,
+                counting_since: None,
+            })),
+            path,
+            clock,
+        })
+    }
+
+    fn persist(&self, usage: &Usage) {
+        let persisted = Persisted {
+            day: usage.day.to_string(),
+            used: usage.used,
+        };
+        let res = ron::to_string(&persisted)
+            .wrap_err("Could not serialize")
+            .and_then(|data| std::fs::write(&self.path, data).wrap_err("Could not write"));
+        if let Err(report) = res {
+            error!("Could not save the daily usage: {report:?}");
+        }
+    }
+
+    /// Starts a new day if the rollover passed
+    fn usage(&self) -> std::sync::MutexGuard<'_, Usage> {
+        let mut usage = self.usage.lock().expect("nothing can panic with lock held");
+        let today = day_of(Local::now().naive_local(), self.rollover);
+        if usage.day != today {
+            usage.day = today;
+            usage.used = Duration::ZERO;
+            if usage.counting_since.is_some() {
+                usage.counting_since = Some(self.clock.now());
+            }
+            self.persist(&usage);
+        }
+        usage
+    }
+
+    /// Counts time towards the limit while `counting` is true
+    pub(crate) fn set_counting(&self, counting: bool) {
+        let now = self.clock.now();
+        let mut usage = self.usage();
+        match (usage.counting_since, counting) {
+            (None, true) => usage.counting_since = Some(now),
+            (Some(since), false) => {
+                usage.used += now.saturating_duration_since(since);
+                usage.counting_since = None;
+                self.persist(&usage);
+            }
+            _ => (),
+        }
+    }
+
+    /// What is left of today's limit
+    pub(crate) fn left(&self) -> Duration {
+        let usage = self.usage();
+        let counting = usage
+            .counting_since
+            .map_or(Duration::ZERO, |since| self.clock.elapsed(since));
+        self.limit.saturating_sub(usage.used + counting)
+    }
+
+    pub(crate) fn reached(&self) -> bool {
+        self.left() < MARGIN
+    }
+
+    /// When the next day starts
+    pub(crate) fn next_day(&self) -> Instant {
+        let now = Local::now().naive_local();
+        let until = (next_rollover(now, self.rollover) - now)
+            .to_std()
+            .expect("the next rollover is in the future");
+        self.clock.now() + until
+    }
+
+    /// `hh:mm` the next day starts at
+    pub(crate) fn rollover(&self) -> String {
+        self.rollover.format("%H:%M").to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn day_starts_at_rollover() {
+        let rollover = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let day = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+        assert_eq!(day_of(at("2024-05-02 03:59"), rollover), day("2024-05-01"));
+        assert_eq!(day_of(at("2024-05-02 04:00"), rollover), day("2024-05-02"));
+        assert_eq!(
+            next_rollover(at("2024-05-02 03:00"), rollover),
+            at("2024-05-02 04:00")
+        );
+        assert_eq!(
+            next_rollover(at("2024-05-02 23:00"), rollover),
+            at("2024-05-03 04:00")
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+use evdev::{AbsoluteAxisCode, KeyCode, RelativeAxisCode};
+use serde::Serialize;
+
+/// Rough kind of input device, derived from the events it can produce.
+/// A device can fall into multiple classes, for example a keyboard with a
+/// built in trackpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DeviceClass {
+    Keyboard,
+    Mouse,
+    Touchpad,
+    Tablet,
+    Gamepad,
+    /// Flight sticks and other joysticks, also any device with only a legacy
+    /// `js*` node, see `joystick`
+    Joystick,
+}
+
+impl fmt::Display for DeviceClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeviceClass::Keyboard => "keyboard",
+            DeviceClass::Mouse => "mouse",
+            DeviceClass::Touchpad => "touchpad",
+            DeviceClass::Tablet => "tablet",
+            DeviceClass::Gamepad => "gamepad",
+            DeviceClass::Joystick => "joystick",
+        })
+    }
+}
+
+/// Name prefixes of the virtual devices key remappers send their output
+/// through: keyd, kmonad, kanata, input-remapper, evremap and xremap
+const REMAPPER_NAMES: [&str; 6] = [
+    "keyd virtual",
+    "KMonad",
+    "kanata",
+    "input-remapper",
+    "evremap",
+    "xremap",
+];
+
+pub(crate) fn is_remapper(name: &str) -> bool {
+    REMAPPER_NAMES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+pub(crate) fn classify(device: &evdev::Device) -> Vec<DeviceClass> {
+    let has_key = |key| {
+        device
+            .supported_keys()
+            .is_some_and(|keys| keys.contains(key))
+    };
+    let has_rel = |axis| {
+        device
+            .supported_relative_axes()
+            .is_some_and(|axes| axes.contains(axis))
+    };
+    let has_abs = |axis| {
+        device
+            .supported_absolute_axes()
+            .is_some_and(|axes| axes.contains(axis))
+    };
+
+    let mut classes = Vec::new();
+    // power buttons and media remotes report a handful of keys,
+    // only count devices that can type letters
+    if [
+        KeyCode::KEY_A,
+        KeyCode::KEY_Z,
+        KeyCode::KEY_SPACE,
+        KeyCode::KEY_ENTER,
+    ]
+    .into_iter()
+    .all(has_key)
+    {
+        classes.push(DeviceClass::Keyboard);
+    }
+    if has_rel(RelativeAxisCode::REL_X)
+        && has_rel(RelativeAxisCode::REL_Y)
+        && has_key(KeyCode::BTN_LEFT)
+    {
+        classes.push(DeviceClass::Mouse);
+    }
+    let absolute_pointer = has_abs(AbsoluteAxisCode::ABS_X) && has_abs(AbsoluteAxisCode::ABS_Y);
+    if absolute_pointer && (has_key(KeyCode::BTN_TOOL_PEN) || has_key(KeyCode::BTN_STYLUS)) {
+        classes.push(DeviceClass::Tablet);
+    } else if absolute_pointer && has_key(KeyCode::BTN_TOOL_FINGER) {
+        classes.push(DeviceClass::Touchpad);
+    }
+    if has_key(KeyCode::BTN_SOUTH) {
+        classes.push(DeviceClass::Gamepad);
+    } else if has_key(KeyCode::BTN_TRIGGER) && has_abs(AbsoluteAxisCode::ABS_X) {
+        classes.push(DeviceClass::Joystick);
+    }
+    classes
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::cli::DevicesArgs;
+use crate::config;
+use crate::device_class::DeviceClass;
+use crate::watch_and_block::{self, BlockableInput};
+
+#[derive(Debug, Serialize)]
+struct Row {
+    name: String,
+    id: String,
+    path: PathBuf,
+    /// bluetooth address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    capabilities: Vec<DeviceClass>,
+    blocked: bool,
+}
+
+pub(crate) fn run(
+    DevicesArgs { use_json }: DevicesArgs,
+    config_path: Option<PathBuf>,
+) -> Result<()> {
+    let (devices, _) = watch_and_block::devices();
+    let to_block =
+        config::read(config_path).wrap_err("Could not read devices to block from config")?;
+
+    let mut rows: Vec<_> = devices
+        .list_inputs()
+        .wrap_err("Could not list inputs")?
+        .into_iter()
+        .flat_map(|BlockableInput { id, details, .. }| details.into_iter().map(move |d| (id, d)))
+        .map(|(id, device)| Row {
+            blocked: to_block
+                .iter()
+                .any(|f| f.matches(&id, &device.name, device.address.as_deref())),
+            name: device.name,
+            id: id.to_string(),
+            path: device.path,
+            address: device.address,
+            capabilities: device.classes,
+        })
+        .collect();
+    rows.sort_by(|a, b| a.name.cmp(&b.name).then(a.path.cmp(&b.path)));
+
+    if use_json {
+        let json = serde_json::to_string_pretty(&rows).wrap_err("Could not serialize devices")?;
+        println!("{json}");
+    } else {
+        print_table(&rows);
+    }
+    Ok(())
+}
+
+fn print_table(rows: &[Row]) {
+    let header = ["NAME", "ID", "PATH", "CAPABILITIES", "BLOCKED"];
+    let cells: Vec<[String; 5]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row.name.clone(),
+                row.id.clone(),
+                row.path.display().to_string(),
+                row.capabilities.iter().join(","),
+                if row.blocked { "yes" } else { "no" }.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.map(String::from);
+    for row in std::iter::once(&header).chain(&cells) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
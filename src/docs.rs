@@ -0,0 +1,31 @@
+//! Completions and man pages generated from the cli definition, so they
+//! never go out of date. Packagers install them from the binary.
+
+use std::io;
+
+use clap::CommandFactory;
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+
+use crate::cli::{Cli, CompletionsArgs, ManpageArgs};
+
+pub(crate) fn completions(args: &CompletionsArgs) {
+    clap_complete::generate(
+        args.shell,
+        &mut Cli::command(),
+        env!("CARGO_BIN_NAME"),
+        &mut io::stdout(),
+    );
+}
+
+pub(crate) fn manpage(args: &ManpageArgs) -> Result<()> {
+    let command = Cli::command().name(env!("CARGO_BIN_NAME"));
+    let Some(dir) = &args.dir else {
+        return clap_mangen::Man::new(command)
+            .render(&mut io::stdout())
+            .wrap_err("Could not print the man page");
+    };
+    clap_mangen::generate_to(command, dir)
+        .wrap_err("Could not write the man pages")
+        .with_note(|| format!("dir: {}", dir.display()))
+}
@@ -0,0 +1,338 @@
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use break_enforcer::proto::PORTS;
+use break_enforcer::{Api, Error};
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, KeyCode};
+
+use crate::integration::notification;
+use crate::{config, install, joystick, paths, status};
+
+enum Outcome {
+    Pass(String),
+    Warn(String, &'static str),
+    Fail(String, &'static str),
+}
+
+fn input_devices() -> Outcome {
+    let dir = paths::dev_dir();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Outcome::Fail(
+                format!("Could not list {}: {e}", dir.display()),
+                "Run as root or see `install --user` for getting access as a user",
+            )
+        }
+    };
+    let events: Vec<PathBuf> = entries
+        .filter_map(std::result::Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+        })
+        .collect();
+    let readable = events
+        .iter()
+        .filter(|path| OpenOptions::new().read(true).open(path).is_ok())
+        .count();
+    match (readable, events.len()) {
+        (_, 0) => Outcome::Fail(
+            format!("No input devices found in {}", dir.display()),
+            "Is this running in a container without access to the devices? See --dev-dir",
+        ),
+        (0, total) => Outcome::Fail(
+            format!("None of the {total} input devices can be read"),
+            "Run as root or see `install --user` for getting access as a user",
+        ),
+        (readable, total) if readable < total => Outcome::Warn(
+            format!("Only {readable} out of {total} input devices can be read"),
+            "Run as root or see `install --user` for getting access as a user",
+        ),
+        (_, total) => Outcome::Pass(format!("All {total} input devices can be read")),
+    }
+}
+
+fn legacy_joysticks() -> Outcome {
+    let names: Vec<_> = joystick::legacy_only(paths::dev_dir())
+        .into_iter()
+        .map(|joystick| joystick.name)
+        .collect();
+    if names.is_empty() {
+        return Outcome::Pass("Every connected joystick can be blocked".to_string());
+    }
+    Outcome::Warn(
+        format!(
+            "These only have a legacy joystick node and can not be blocked: {}",
+            names.join(", ")
+        ),
+        "Load the evdev module or use a newer driver, their activity is still tracked",
+    )
+}
+
+fn grab_virtual_device() -> Result<()> {
+    let keys = AttributeSet::from_iter([KeyCode::KEY_A]);
+    let mut virtual_device = VirtualDevice::builder()
+        .wrap_err("Could not open /dev/uinput")?
+        .name("break-enforcer doctor")
+        .with_keys(&keys)
+        .wrap_err("Could not set up virtual device")?
+        .build()
+        .wrap_err("Could not create virtual device")?;
+    let path = virtual_device
+        .enumerate_dev_nodes_blocking()
+        .wrap_err("Could not find virtual device")?
+        .next()
+        .ok_or_else(|| eyre!("Virtual device has no device node"))?
+        .wrap_err("Could not find virtual device")?;
+    let mut device = evdev::Device::open(&path)
+        .wrap_err("Could not open virtual device")
+        .with_note(|| format!("path: {}", path.display()))?;
+    device.grab().wrap_err("Could not grab virtual device")?;
+    device
+        .ungrab()
+        .wrap_err("Could not release virtual device")?;
+    Ok(())
+}
+
+fn grab() -> Outcome {
+    match grab_virtual_device() {
+        Ok(()) => Outcome::Pass("Grabbed and released a virtual device".to_string()),
+        Err(report) => Outcome::Fail(
+            format!("{report:#}"),
+            "Run as root, the uinput module might also need loading: modprobe uinput",
+        ),
+    }
+}
+
+fn notifications() -> Outcome {
+    match notification::notify_available() {
+        Ok(()) => Outcome::Pass("System notifications can be send".to_string()),
+        Err(report) => Outcome::Warn(
+            format!("{report:#}"),
+            "Install notify-send to use system notifications",
+        ),
+    }
+}
+
+fn audio() -> Outcome {
+    match notification::beep_available() {
+        Ok(()) => Outcome::Pass("Audio notifications can be played".to_string()),
+        Err(report) => Outcome::Warn(
+            format!("{report:#}"),
+            "Install aplay (alsa-utils) to use audio notifications",
+        ),
+    }
+}
+
+fn config(config_path: Option<PathBuf>) -> Outcome {
+    match config::read(config_path) {
+        Ok(to_block) if to_block.is_empty() => Outcome::Fail(
+            "No devices configured, nothing would be blocked".to_string(),
+            "Run the wizard",
+        ),
+        Ok(to_block) => Outcome::Pass(format!(
+            "Config is valid and blocks {} device(s)",
+            to_block.len()
+        )),
+        Err(report) => Outcome::Fail(
+            format!("{report:#}"),
+            "Fix the config by hand or recreate it using the wizard",
+        ),
+    }
+}
+
+fn api_port() -> Outcome {
+    match Api::new() {
+        Ok(api) => {
+            if let Some(outdated) = status::outdated(api.server_version()) {
+                return Outcome::Warn(
+                    format!("The api is reachable but {outdated}"),
+                    "Restart the service to run the updated version",
+                );
+            }
+            return Outcome::Pass(format!(
+                "The api of a running service (version {}) is reachable",
+                api.server_version()
+            ));
+        }
+        Err(e @ Error::ProtocolMismatch { .. }) => {
+            return Outcome::Fail(
+                e.to_string(),
+                "Restart the service after updating break-enforcer",
+            );
+        }
+        Err(_) => (),
+    }
+
+    let free = PORTS.iter().find_map(|port| {
+        match TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], *port))) {
+            Ok(_) => Some(Ok(*port)),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => None,
+            Err(e) => Some(Err(e)),
+        }
+    });
+    match free {
+        Some(Ok(port)) if port == PORTS[0] => {
+            Outcome::Pass(format!("The api can listen on port {port}"))
+        }
+        Some(Ok(port)) => Outcome::Warn(
+            format!("Some api ports are taken by other programs, the api would use port {port}"),
+            "Pin the api to a port using --api-port",
+        ),
+        Some(Err(e)) => Outcome::Fail(
+            format!("The api can not listen: {e}"),
+            "Is the loopback interface up?",
+        ),
+        None => Outcome::Fail(
+            "All api ports are taken by other programs".to_string(),
+            "Pin the api to a free port using --api-port",
+        ),
+    }
+}
+
+/// An integrator this far behind is stuck
+const MAX_INTEGRATOR_LAG: Duration = Duration::from_secs(5);
+
+fn health() -> Outcome {
+    let Ok(mut api) = Api::new() else {
+        return Outcome::Pass("No service running to check".to_string());
+    };
+    let health = match api.health() {
+        Ok(health) => health,
+        Err(e) => {
+            return Outcome::Warn(
+                format!("Could not get the health of the running service: {e}"),
+                "Update and restart the service, older versions do not report it",
+            )
+        }
+    };
+
+    let mut failed = Vec::new();
+    for (id, name) in [("device_watcher", "device watcher"), ("inotify", "inotify")] {
+        if !health.subsystems.iter().any(|s| s.id == id) {
+            failed.push(format!("the {name} stopped"));
+        }
+    }
+    for subsystem in health.subsystems.iter().filter(|s| !s.running) {
+        let failure = subsystem
+            .last_failure
+            .as_deref()
+            .unwrap_or("unknown failure");
+        failed.push(format!("the {} is down: {failure}", subsystem.name));
+    }
+    if health.integrator_lag > MAX_INTEGRATOR_LAG {
+        failed.push(format!(
+            "the integrator is {}s behind",
+            health.integrator_lag.as_secs()
+        ));
+    }
+    if !failed.is_empty() {
+        return Outcome::Fail(
+            format!("The service runs but {}", failed.join(", ")),
+            "Look at its logs using journalctl -u break-enforcer",
+        );
+    }
+
+    let mut warnings = Vec::new();
+    for subsystem in health.subsystems.iter().filter(|s| s.restarts > 0) {
+        warnings.push(format!(
+            "the {} was restarted {} time(s)",
+            subsystem.name, subsystem.restarts
+        ));
+    }
+    if health.dropped_events > 0 {
+        warnings.push(format!(
+            "{} input events were dropped",
+            health.dropped_events
+        ));
+    }
+    if let Some(error) = health.last_grab_error {
+        warnings.push(error);
+    }
+    if warnings.is_empty() {
+        Outcome::Pass("Every part of the running service is up".to_string())
+    } else {
+        Outcome::Warn(
+            format!("The service runs but {}", warnings.join(", ")),
+            "Look at its logs using journalctl -u break-enforcer",
+        )
+    }
+}
+
+fn service() -> Outcome {
+    let mut states = Vec::new();
+    for (user, kind) in [(false, "system"), (true, "user")] {
+        match install::service_state(user) {
+            Ok(Some(state)) => states.push((kind, state)),
+            Ok(None) => (),
+            Err(report) => {
+                return Outcome::Warn(
+                    format!("Could not check the {kind} service: {report:#}"),
+                    "Check it yourself using systemctl status",
+                )
+            }
+        }
+    }
+
+    match states.as_slice() {
+        [] => Outcome::Warn(
+            "No service is installed, break-enforcer will not start on boot".to_string(),
+            "Run install",
+        ),
+        [(kind, state)] if state == "active" => {
+            Outcome::Pass(format!("The {kind} service is running"))
+        }
+        [(kind, state)] => Outcome::Fail(
+            format!("The {kind} service is installed but {state}"),
+            "Look at its logs using journalctl -u break-enforcer",
+        ),
+        _ => Outcome::Warn(
+            "Both a system and a user service are installed".to_string(),
+            "Remove one of them using remove or remove --user",
+        ),
+    }
+}
+
+/// Checks everything break-enforcer needs to work, prints a report and
+/// returns an error if any check failed.
+pub(crate) fn run(config_path: Option<PathBuf>) -> Result<()> {
+    let checks = [
+        ("input devices", input_devices()),
+        ("joysticks", legacy_joysticks()),
+        ("grabbing", grab()),
+        ("notifications", notifications()),
+        ("audio", audio()),
+        ("config", config(config_path)),
+        ("api", api_port()),
+        ("service", service()),
+        ("health", health()),
+    ];
+
+    let mut failed = 0;
+    for (name, outcome) in &checks {
+        match outcome {
+            Outcome::Pass(msg) => println!("[pass] {name}: {msg}"),
+            Outcome::Warn(msg, suggestion) => {
+                println!("[warn] {name}: {msg}\n  suggestion: {suggestion}");
+            }
+            Outcome::Fail(msg, suggestion) => {
+                failed += 1;
+                println!("[FAIL] {name}: {msg}\n  suggestion: {suggestion}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(eyre!("{failed} check(s) failed")).suppress_backtrace(true)
+    } else {
+        Ok(())
+    }
+}
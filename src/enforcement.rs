@@ -0,0 +1,159 @@
+//! What keeps a break, see `Enforcement`, and escalating between ways of
+//! keeping it (`--escalate`). Escalating starts gentle, for example with a
+//! warning, and moves on to the next stage when there is still input after
+//! the time given for a stage.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use color_eyre::Result;
+
+use crate::watch_and_block::LockGuard;
+use crate::{compositor, forward, lock_session};
+
+/// Keeps the break until released
+pub(crate) trait Enforcement {
+    /// The break is over or a stronger stage takes over
+    fn release(self: Box<Self>) -> Result<()>;
+}
+
+impl Enforcement for LockGuard {
+    fn release(self: Box<Self>) -> Result<()> {
+        self.unlock()
+    }
+}
+
+impl Enforcement for compositor::Disabled {
+    fn release(self: Box<Self>) -> Result<()> {
+        self.enable()
+    }
+}
+
+impl Enforcement for forward::Forwarder {
+    fn release(self: Box<Self>) -> Result<()> {
+        self.stop();
+        Ok(())
+    }
+}
+
+impl Enforcement for lock_session::Locker {
+    fn release(self: Box<Self>) -> Result<()> {
+        self.stop();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    /// Send a notification, needs notify-send
+    Warn,
+    /// Lock the session, see `lock_session`
+    ScreenLock,
+    /// Block the devices using the `--block-backend`
+    Block,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Warn => f.write_str("warn"),
+            Stage::ScreenLock => f.write_str("screen-lock"),
+            Stage::Block => f.write_str("block"),
+        }
+    }
+}
+
+/// The stages in order, each with how long input is tolerated before the
+/// next one takes over. The last stage lasts until the break is over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Escalation {
+    stages: Vec<(Stage, Duration)>,
+    last: Stage,
+}
+
+impl Escalation {
+    /// Parses stages separated by commas like `warn:1m,screen-lock:2m,block`
+    pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+        let mut stages = Vec::new();
+        let mut parts = arg.split(',').peekable();
+        while let Some(part) = parts.next() {
+            let (name, after) = match part.split_once(':') {
+                Some((name, after)) => (name, Some(after)),
+                None => (part, None),
+            };
+            let stage = match name.trim() {
+                "warn" => Stage::Warn,
+                "screen-lock" => Stage::ScreenLock,
+                "block" => Stage::Block,
+                other => {
+                    return Err(format!(
+                        "Unknown stage {other}, expected warn, screen-lock or block"
+                    ))
+                }
+            };
+            if parts.peek().is_none() {
+                if after.is_some() {
+                    return Err(format!("The last stage ({stage}) can not have a time"));
+                }
+                return Ok(Self {
+                    stages,
+                    last: stage,
+                });
+            }
+            let Some(after) = after else {
+                return Err(format!(
+                    "Every stage but the last needs a time, like {stage}:1m"
+                ));
+            };
+            let after = crate::cli::parse_duration(after.trim())
+                .map_err(|e| format!("Invalid time for {stage}: {e}"))?;
+            stages.push((stage, after));
+        }
+        unreachable!("split always yields at least one part")
+    }
+
+    /// The stage at `index` and how long it is tolerated, `None` for the
+    /// last stage
+    pub(crate) fn stage(&self, index: usize) -> (Stage, Option<Duration>) {
+        self.stages
+            .get(index)
+            .map_or((self.last, None), |(stage, after)| (*stage, Some(*after)))
+    }
+
+    pub(crate) fn contains(&self, stage: Stage) -> bool {
+        self.last == stage || self.stages.iter().any(|(s, _)| *s == stage)
+    }
+}
+
+impl Display for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (stage, after) in &self.stages {
+            write!(f, "{stage}:{},", crate::integration::fmt_dur(*after))?;
+        }
+        write!(f, "{}", self.last)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_stages() {
+        let escalation = Escalation::parse("warn:1m,screen-lock:2m,block").unwrap();
+        assert_eq!(
+            escalation.stage(0),
+            (Stage::Warn, Some(Duration::from_secs(60)))
+        );
+        assert_eq!(
+            escalation.stage(1),
+            (Stage::ScreenLock, Some(Duration::from_secs(120)))
+        );
+        assert_eq!(escalation.stage(2), (Stage::Block, None));
+        assert_eq!(Escalation::parse(&escalation.to_string()), Ok(escalation));
+
+        assert!(Escalation::parse("warn,block").is_err(), "needs a time");
+        assert!(Escalation::parse("warn:1m,block:1m").is_err());
+        assert!(Escalation::parse("shout:1m,block").is_err());
+    }
+}
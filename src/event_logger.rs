@@ -0,0 +1,106 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{Api, Error, StateUpdate};
+
+/// Appends every state change as a line of json to a file. Progress updates
+/// are not logged. Once the file grows beyond `max_size` it is moved to
+/// `<path>.1`, older logs move up one number, the oldest is removed.
+///
+/// ```no_run
+/// break_enforcer::EventLogger::new("breaks.jsonl").run().unwrap();
+/// ```
+pub struct EventLogger {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+}
+
+impl EventLogger {
+    /// Rotates at 10 MiB and keeps 3 old logs
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: 10 * 1024 * 1024,
+            keep: 3,
+        }
+    }
+
+    /// Size in bytes at which the log is rotated
+    #[must_use]
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Number of rotated logs to keep around, zero removes the log when
+    /// it is full.
+    #[must_use]
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Connects and logs until the service closes the connection
+    pub fn run(&self) -> Result<(), Error> {
+        let updates = Api::new()?.subscribe()?;
+        let mut file = self.open()?;
+        for update in updates {
+            let update = update?;
+            if let StateUpdate::Progress { .. } = update {
+                continue;
+            }
+
+            let mut line = serde_json::to_string(&update).map_err(Error::SerializingUpdate)?;
+            line.push('\n');
+            let size = file.metadata().map_err(Error::WritingLog)?.len();
+            if size + line.len() as u64 > self.max_size {
+                drop(file);
+                self.rotate().map_err(Error::RotatingLog)?;
+                file = self.open()?;
+            }
+            file.write_all(line.as_bytes()).map_err(Error::WritingLog)?;
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> Result<File, Error> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(Error::WritingLog)
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return remove_if_exists(&self.path);
+        }
+        remove_if_exists(&self.rotated(self.keep))?;
+        for n in (1..self.keep).rev() {
+            rename_if_exists(&self.rotated(n), &self.rotated(n + 1))?;
+        }
+        rename_if_exists(&self.path, &self.rotated(1))
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
@@ -0,0 +1,97 @@
+//! Keeps the pointer usable during a break while clicks and keys stay
+//! blocked, see `--allow-pointer`. The grabbed mice are read through a second
+//! handle and only their movement and scrolling is passed on to a virtual
+//! mouse.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, EventType, InputEvent, KeyCode, RelativeAxisCode};
+use tracing::{debug, warn};
+
+const NAME: &str = "break-enforcer pointer";
+
+/// Errors if virtual devices can not be created
+pub(crate) fn check_available() -> Result<()> {
+    VirtualDevice::builder().wrap_err("Could not open /dev/uinput")?;
+    Ok(())
+}
+
+/// Stop before releasing the mice, otherwise their movement is passed on
+/// twice until the forwarding threads notice
+pub(crate) struct Forwarder {
+    stop: Arc<AtomicBool>,
+}
+
+impl Forwarder {
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// `pointers` are second handles to grabbed mice
+pub(crate) fn start(pointers: Vec<evdev::Device>) -> Forwarder {
+    let stop = Arc::new(AtomicBool::new(false));
+    for pointer in pointers {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let name = pointer.name().unwrap_or("unknown device").to_string();
+            if let Err(e) = forward(pointer, &stop) {
+                warn!("Stopped passing on the movement of {name}: {e}");
+            }
+        });
+    }
+    Forwarder { stop }
+}
+
+fn forward(mut pointer: evdev::Device, stop: &AtomicBool) -> io::Result<()> {
+    let axes: AttributeSet<RelativeAxisCode> = pointer
+        .supported_relative_axes()
+        .map(|axes| axes.iter().collect())
+        .unwrap_or_default();
+    let mut virtual_pointer = VirtualDevice::builder()?
+        .name(NAME)
+        .with_relative_axes(&axes)?
+        // without a button it is not seen as a mouse
+        .with_keys(&AttributeSet::from_iter([KeyCode::BTN_LEFT]))?
+        .build()?;
+    debug!("Passing on the movement of {:?}", pointer.name());
+
+    loop {
+        let moved: Vec<InputEvent> = pointer.fetch_events()?.filter(forwarded).collect();
+        // the mouse is no longer grabbed, its input already arrives
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if !moved.is_empty() {
+            virtual_pointer.emit(&moved)?;
+        }
+    }
+}
+
+/// Movement and scrolling, the end of each batch is added by `emit`
+fn forwarded(event: &InputEvent) -> bool {
+    event.event_type() == EventType::RELATIVE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_movement_is_forwarded() {
+        let moved = InputEvent::new(EventType::RELATIVE.0, RelativeAxisCode::REL_X.0, 5);
+        let scrolled = InputEvent::new(EventType::RELATIVE.0, RelativeAxisCode::REL_WHEEL.0, -1);
+        let clicked = InputEvent::new(EventType::KEY.0, KeyCode::BTN_LEFT.0, 1);
+        let synced = InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0);
+        assert!(forwarded(&moved));
+        assert!(forwarded(&scrolled));
+        assert!(!forwarded(&clicked));
+        assert!(!forwarded(&synced));
+    }
+}
@@ -0,0 +1,212 @@
+//! The bounded queue between the monitored devices and `watch_activity`.
+//! Activity is all alike, once the queue is full new activity makes room by
+//! dropping the oldest activity still queued. Anything else waits for room,
+//! a lock change or power event must not get lost.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvTimeoutError, SendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::check_inputs::{Input, InputResult};
+use crate::clock::RecvTimeout;
+
+struct Shared {
+    inner: Mutex<Inner>,
+    /// signalled on a push and when the last sender is gone
+    not_empty: Condvar,
+    /// signalled on a pop and when the receiver is gone
+    not_full: Condvar,
+    capacity: usize,
+}
+
+struct Inner {
+    queue: VecDeque<InputResult>,
+    senders: usize,
+    receiver: bool,
+}
+
+impl Shared {
+    /// Nothing panics with the lock held
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) fn bounded(capacity: usize) -> (InputSender, InputReceiver) {
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner {
+            queue: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        capacity,
+    });
+    (InputSender(shared.clone()), InputReceiver(shared))
+}
+
+pub(crate) struct InputSender(Arc<Shared>);
+
+/// How activity got queued, see `InputSender::send_activity`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Sent {
+    Queued,
+    /// queued after dropping the oldest queued activity
+    DroppedOldest,
+    /// the queue is full of other input, this activity was dropped
+    Dropped,
+}
+
+impl InputSender {
+    /// Waits for room, errors once the receiver is gone
+    pub(crate) fn send(&self, input: InputResult) -> Result<(), SendError<InputResult>> {
+        let mut inner = self.0.lock();
+        while inner.receiver && inner.queue.len() >= self.0.capacity {
+            inner = self
+                .0
+                .not_full
+                .wait(inner)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if !inner.receiver {
+            return Err(SendError(input));
+        }
+        inner.queue.push_back(input);
+        self.0.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Never waits, see the module docs. `None` once the receiver is gone.
+    pub(crate) fn send_activity(&self) -> Option<Sent> {
+        let mut inner = self.0.lock();
+        if !inner.receiver {
+            return None;
+        }
+        let mut sent = Sent::Queued;
+        if inner.queue.len() >= self.0.capacity {
+            let oldest = inner
+                .queue
+                .iter()
+                .position(|input| matches!(input, Ok(Input::Activity)));
+            let Some(oldest) = oldest else {
+                return Some(Sent::Dropped);
+            };
+            inner.queue.remove(oldest);
+            sent = Sent::DroppedOldest;
+        }
+        inner.queue.push_back(Ok(Input::Activity));
+        self.0.not_empty.notify_one();
+        Some(sent)
+    }
+}
+
+impl Clone for InputSender {
+    fn clone(&self) -> Self {
+        self.0.lock().senders += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for InputSender {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock();
+        inner.senders -= 1;
+        if inner.senders == 0 {
+            self.0.not_empty.notify_all();
+        }
+    }
+}
+
+pub(crate) struct InputReceiver(Arc<Shared>);
+
+impl InputReceiver {
+    fn pop(&self, inner: &mut Inner) -> Option<InputResult> {
+        let input = inner.queue.pop_front()?;
+        self.0.not_full.notify_one();
+        Some(input)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn try_recv(&self) -> Option<InputResult> {
+        self.pop(&mut self.0.lock())
+    }
+}
+
+impl RecvTimeout<InputResult> for InputReceiver {
+    fn recv_timeout(&self, timeout: Duration) -> Result<InputResult, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut inner = self.0.lock();
+        loop {
+            if let Some(input) = self.pop(&mut inner) {
+                return Ok(input);
+            }
+            if inner.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let Some(deadline) = deadline else {
+                inner = self
+                    .0
+                    .not_empty
+                    .wait(inner)
+                    .unwrap_or_else(PoisonError::into_inner);
+                continue;
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            (inner, _) = self
+                .0
+                .not_empty
+                .wait_timeout(inner, remaining)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl Drop for InputReceiver {
+    fn drop(&mut self) {
+        self.0.lock().receiver = false;
+        self.0.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drops_oldest_activity_once_full() {
+        let (tx, rx) = bounded(2);
+        assert_eq!(tx.send_activity(), Some(Sent::Queued));
+        tx.send(Ok(Input::Chord)).unwrap();
+        assert_eq!(tx.send_activity(), Some(Sent::DroppedOldest));
+        assert!(matches!(rx.try_recv(), Some(Ok(Input::Chord))));
+        assert!(matches!(rx.try_recv(), Some(Ok(Input::Activity))));
+        assert!(rx.try_recv().is_none());
+
+        tx.send(Ok(Input::Chord)).unwrap();
+        tx.send(Ok(Input::Chord)).unwrap();
+        assert_eq!(tx.send_activity(), Some(Sent::Dropped));
+
+        drop(rx);
+        assert_eq!(tx.send_activity(), None);
+        assert!(tx.send(Ok(Input::Chord)).is_err());
+    }
+
+    #[test]
+    fn disconnects_once_senders_are_gone() {
+        let (tx, rx) = bounded(2);
+        let other = tx.clone();
+        tx.send_activity();
+        drop(tx);
+        drop(other);
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+        assert_eq!(
+            rx.recv_timeout(Duration::MAX).err(),
+            Some(RecvTimeoutError::Disconnected)
+        );
+    }
+}
@@ -1,11 +1,21 @@
-use std::path::PathBuf;
+use std::fs::{self, OpenOptions};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::time::Duration;
 
 use color_eyre::eyre::{eyre, Context, Result};
-use service_install::{install_system, tui};
+use color_eyre::Section;
+use service_install::{install_system, install_user, tui};
 
-use crate::cli::RunArgs;
+use crate::cli::{InstallArgs, RemoveArgs, RunArgs};
+use crate::compositor::Backend;
 use crate::config;
+use crate::integration::BatteryPolicy;
+use crate::paths::{self, Dirs};
+use crate::run::IdleCredit;
+
+mod drift;
 
 fn fmt_dur(dur: Duration) -> String {
     let ss = dur.as_secs() % 60;
@@ -21,22 +31,48 @@ fn fmt_dur(dur: Duration) -> String {
     }
 }
 
-pub fn set_up(run_args: &RunArgs, config_path: Option<PathBuf>) -> Result<()> {
-    let to_block = config::read(config_path.clone())
-        .wrap_err("Could not read devices to block from config")
-        .wrap_err("Could not verify the config file is not empty")?;
-    if to_block.is_empty() {
-        return Err(eyre!(
-            "No devices set up. The service would do nothing. Please run the wizard"
-        ));
-    }
-    for warning_type in &run_args.lock_warning_type {
-        warning_type
-            .check_dependency()
-            .wrap_err("Can not provide configured warning/notification")?;
-    }
+const INPUT_UDEV_RULE: &str = r#"KERNEL=="event*", SUBSYSTEM=="input", GROUP="input", MODE="0660""#;
 
-    let mut args = Vec::new();
+/// Whether the current user can open the input devices, a user service can
+/// only work if this is the case.
+pub(crate) fn has_input_access() -> bool {
+    let Ok(entries) = fs::read_dir(paths::dev_dir()) else {
+        return false;
+    };
+    entries
+        .filter_map(std::result::Result::ok)
+        .filter(|entry| entry.file_name().as_bytes().starts_with(b"event"))
+        .any(|entry| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(entry.path())
+                .is_ok()
+        })
+}
+
+fn print_input_access_instructions() {
+    println!(
+        "Your user can not access the input devices, the user service will not be \
+        able to block them. To fix this either:\n\
+        - add yourself to the input group:\n\
+        \tsudo usermod -aG input $USER\n\
+        \tthen log out and back in\n\
+        - or, if your distribution does not give the input group access, add a udev \
+        rule to /etc/udev/rules.d/70-break-enforcer.rules containing:\n\
+        \t{INPUT_UDEV_RULE}\n\
+        \tthen run: sudo udevadm control --reload && sudo udevadm trigger"
+    );
+}
+
+fn service_args(
+    run_args: &RunArgs,
+    config_path: Option<&Path>,
+    dirs: &Dirs,
+    log_args: &[String],
+) -> Vec<String> {
+    let mut args = dirs.args();
+    args.extend_from_slice(log_args);
     if let Some(config_path) = config_path {
         args.push("--config-path".to_string());
         args.push(config_path.display().to_string());
@@ -44,8 +80,26 @@ pub fn set_up(run_args: &RunArgs, config_path: Option<PathBuf>) -> Result<()> {
     args.push("run".to_string());
     args.push("--work-duration".to_string());
     args.push(fmt_dur(run_args.work_duration));
+    if let Some(jitter) = run_args.break_jitter {
+        args.push("--break-jitter".to_string());
+        args.push(fmt_dur(jitter));
+    }
+    if let Some(first) = run_args.first_work_duration {
+        args.push("--first-work-duration".to_string());
+        args.push(fmt_dur(first));
+    }
     args.push("--break-duration".to_string());
     args.push(fmt_dur(run_args.break_duration));
+    if let Some(long) = run_args.long_break {
+        args.push("--long-break".to_string());
+        args.push(fmt_dur(long));
+        args.push("--long-break-every".to_string());
+        args.push(run_args.long_break_every.to_string());
+    }
+    if run_args.idle_credit != IdleCredit::Full {
+        args.push("--idle-credit".to_string());
+        args.push(run_args.idle_credit.to_string());
+    }
     if let Some(warn_duration) = run_args.lock_warning {
         args.push("--lock-warning".to_string());
         args.push(fmt_dur(warn_duration));
@@ -54,35 +108,357 @@ pub fn set_up(run_args: &RunArgs, config_path: Option<PathBuf>) -> Result<()> {
         args.push("--lock-warning-type".to_string());
         args.push(warn_type.to_string());
     }
+    if let Some(delay) = run_args.lock_delay {
+        args.push("--lock-delay".to_string());
+        args.push(fmt_dur(delay));
+    }
+    if run_args.defer_for_passwords {
+        args.push("--defer-for-passwords".to_string());
+    }
+    if run_args.nag_bar {
+        args.push("--nag-bar".to_string());
+    }
+    if let Some(workspace) = &run_args.break_workspace {
+        args.push("--break-workspace".to_string());
+        args.push(workspace.clone());
+    }
+    if let Some(limit) = run_args.daily_limit {
+        args.push("--daily-limit".to_string());
+        args.push(fmt_dur(limit));
+        args.push("--day-starts-at".to_string());
+        args.push(run_args.day_starts_at.format("%H:%M").to_string());
+    }
+    if let Some(window) = run_args.curfew {
+        args.push("--curfew".to_string());
+        args.push(window.to_string());
+        args.push("--curfew-overrides".to_string());
+        args.push(run_args.curfew_overrides.to_string());
+    }
+    if run_args.break_coach {
+        args.push("--break-coach".to_string());
+    }
+    if let Some(path) = &run_args.break_activities {
+        args.push("--break-activities".to_string());
+        args.push(path.display().to_string());
+    }
+    if let Some(goal) = run_args.daily_goal {
+        args.push("--daily-goal".to_string());
+        args.push(goal.to_string());
+    }
+    if let Some(at) = run_args.summary_at {
+        args.push("--summary-at".to_string());
+        args.push(at.format("%H:%M").to_string());
+    }
     if run_args.status_file {
         args.push("--status-file".to_string());
     }
+    if run_args.notify_warnings {
+        args.push("--notify-warnings".to_string());
+    }
+    if run_args.ask_new_keyboards {
+        args.push("--ask-new-keyboards".to_string());
+    }
+    if let Some(notice) = run_args.min_notice {
+        args.push("--min-notice".to_string());
+        args.push(fmt_dur(notice));
+    }
+    if let Some(verbosity) = run_args.announce {
+        args.push("--announce".to_string());
+        args.push(verbosity.to_string());
+    }
+    if run_args.on_battery != BatteryPolicy::Save {
+        args.push("--on-battery".to_string());
+        args.push(run_args.on_battery.to_string());
+    }
+    if run_args.persist_history {
+        args.push("--persist-history".to_string());
+    }
+    if run_args.block_remappers {
+        args.push("--block-remappers".to_string());
+    }
+    if run_args.no_follow_screen_lock {
+        args.push("--no-follow-screen-lock".to_string());
+    }
+    if run_args.block_backend != Backend::Grab {
+        args.push("--block-backend".to_string());
+        args.push(run_args.block_backend.to_string());
+    }
+    if run_args.allow_pointer {
+        args.push("--allow-pointer".to_string());
+    }
+    if let Some(policy) = run_args.when_unplugged {
+        args.push("--when-unplugged".to_string());
+        args.push(policy.to_string());
+    }
+    if let Some(action) = run_args.on_lid_close {
+        args.push("--on-lid-close".to_string());
+        args.push(action.to_string());
+    }
+    if let Some(action) = run_args.on_undock {
+        args.push("--on-undock".to_string());
+        args.push(action.to_string());
+    }
+    if let Some(chord) = &run_args.break_chord {
+        args.push("--break-chord".to_string());
+        args.push(chord.to_string());
+        args.push("--break-chord-hold".to_string());
+        args.push(fmt_dur(run_args.break_chord_hold));
+    }
     if run_args.tcp_api {
         args.push("--tcp-api".to_string());
     }
+    if let Some(port) = run_args.api_port {
+        args.push("--api-port".to_string());
+        args.push(port.to_string());
+    }
+    if run_args.progress_interval != Duration::from_secs(1) {
+        args.push("--progress-interval".to_string());
+        // may be shorter then a second which fmt_dur can not represent
+        args.push(format!("{}s", run_args.progress_interval.as_secs_f32()));
+    }
+    #[cfg(feature = "http")]
+    if let Some(port) = run_args.http_port {
+        args.push("--http-port".to_string());
+        args.push(port.to_string());
+    }
+    if let Some(listen) = run_args.listen {
+        args.push("--listen".to_string());
+        args.push(listen.to_string());
+    }
+    for (arg, path) in [
+        ("--api-cert", &run_args.api_cert),
+        ("--api-key", &run_args.api_key),
+        ("--api-token-file", &run_args.api_token_file),
+    ] {
+        if let Some(path) = path {
+            args.push(arg.to_string());
+            args.push(path.display().to_string());
+        }
+    }
+    for peer in &run_args.sync_peer {
+        args.push("--sync-peer".to_string());
+        args.push(peer.clone());
+    }
+    if let Some(listen) = run_args.sync_listen {
+        args.push("--sync-listen".to_string());
+        args.push(listen.to_string());
+    }
+    for (arg, path) in [
+        ("--sync-cert", &run_args.sync_cert),
+        ("--sync-key", &run_args.sync_key),
+        ("--sync-ca", &run_args.sync_ca),
+    ] {
+        if let Some(path) = path {
+            args.push(arg.to_string());
+            args.push(path.display().to_string());
+        }
+    }
+    if let Some(user) = &run_args.drop_privileges {
+        args.push("--drop-privileges".to_string());
+        args.push(user.clone());
+    }
+    if run_args.no_block {
+        args.push("--no-block".to_string());
+    }
+    if let Some(escalation) = &run_args.escalate {
+        args.push("--escalate".to_string());
+        args.push(escalation.to_string());
+    }
+    if let Some(command) = &run_args.break_command {
+        args.push("--break-command".to_string());
+        args.push(command.to_string());
+    }
+    args
+}
+
+fn service_name() -> String {
+    env!("CARGO_CRATE_NAME").replace("_", "-")
+}
+
+pub fn set_up(
+    InstallArgs {
+        run_args,
+        user,
+        diff,
+        check,
+        #[cfg(feature = "overlay")]
+        autostart_overlay,
+    }: &InstallArgs,
+    config_path: Option<PathBuf>,
+    dirs: &Dirs,
+    log_args: &[String],
+) -> Result<()> {
+    let args = service_args(run_args, config_path.as_deref(), dirs, log_args);
+    let installed = drift::installed_args(*user, &service_name())
+        .wrap_err("Could not check the currently installed service")?;
+
+    if *diff || *check {
+        let differs = match &installed {
+            Some(installed) => drift::print_diff(installed, &args),
+            None => {
+                println!("The service is not installed");
+                true
+            }
+        };
+        if *check && differs {
+            return Err(eyre!("The installed service does not match the arguments"))
+                .suppress_backtrace(true)
+                .suggestion("Run install again to update it");
+        }
+        if !differs {
+            println!("The installed service matches the arguments");
+        }
+        return Ok(());
+    }
+
+    if let Some(installed) = &installed {
+        if drift::print_diff(installed, &args) {
+            println!("The existing service will be updated as shown above");
+        }
+    }
+
+    let to_block = config::read(config_path.clone())
+        .wrap_err("Could not read devices to block from config")
+        .wrap_err("Could not verify the config file is not empty")?;
+    if to_block.is_empty() && !run_args.no_block {
+        return Err(eyre!(
+            "No devices set up. The service would do nothing. Please run the wizard"
+        ))
+        .suggestion("Only want the schedule? Pass --no-block");
+    }
+    for warning_type in &run_args.lock_warning_type {
+        warning_type
+            .check_dependency()
+            .wrap_err("Can not provide configured warning/notification")?;
+    }
 
-    let name = env!("CARGO_CRATE_NAME").replace("_", "-");
-    let steps = install_system!()
-        .current_exe()?
-        .on_boot()
-        .service_name(name)
-        .description("Disables input during breaks")
-        .args(args)
-        .overwrite_existing(true)
-        .prepare_install()
-        .wrap_err("Could not set up installation")?;
+    let name = service_name();
+    let description = "Disables input during breaks";
+    let steps = if *user {
+        if run_args.status_file && dirs.uses_default_runtime() {
+            return Err(eyre!(
+                "The status file is written to /run which a user service can not access"
+            ))
+            .suggestion("Use the tcp api instead (--tcp-api)")
+            .suggestion("Pass a runtime dir your user can write to (--runtime-dir)");
+        }
+        if !has_input_access() && !run_args.no_block {
+            print_input_access_instructions();
+        }
+        install_user!()
+            .current_exe()?
+            .on_boot()
+            .service_name(name)
+            .description(description)
+            .args(args)
+            .overwrite_existing(true)
+            .prepare_install()
+    } else {
+        install_system!()
+            .current_exe()?
+            .on_boot()
+            .service_name(name)
+            .description(description)
+            .args(args)
+            .overwrite_existing(true)
+            .prepare_install()
+    }
+    .wrap_err("Could not set up installation")?;
 
     tui::install::start(steps, true).wrap_err("Failed to run install wizard")?;
+    #[cfg(feature = "overlay")]
+    if *autostart_overlay {
+        add_overlay_autostart(*user, run_args.api_port)
+            .wrap_err("Could not set up starting the overlay on login")?;
+    }
+    Ok(())
+}
+
+/// Where desktop sessions look for programs to start on login
+#[cfg(feature = "overlay")]
+fn autostart_entry(user: bool) -> Result<PathBuf> {
+    let dir = if user {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .ok_or_else(|| eyre!("Neither XDG_CONFIG_HOME nor HOME is set"))?
+            .join("autostart")
+    } else {
+        PathBuf::from("/etc/xdg/autostart")
+    };
+    Ok(dir.join(format!("{}-overlay.desktop", service_name())))
+}
+
+#[cfg(feature = "overlay")]
+fn add_overlay_autostart(user: bool, api_port: Option<u16>) -> Result<()> {
+    let path = autostart_entry(user)?;
+    let mut exec = format!("{} overlay", service_name());
+    if let Some(port) = api_port {
+        exec.push_str(&format!(" --api-port {port}"));
+    }
+    let entry = format!(
+        "[Desktop Entry]\n\
+        Type=Application\n\
+        Name=break-enforcer overlay\n\
+        Comment=Shows a countdown during breaks\n\
+        Exec={exec}\n\
+        NoDisplay=true\n"
+    );
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .wrap_err("Could not create the autostart dir")
+            .with_note(|| format!("dir: {}", dir.display()))?;
+    }
+    fs::write(&path, entry)
+        .wrap_err("Could not write the autostart entry")
+        .with_note(|| format!("path: {}", path.display()))?;
+    println!("The overlay starts on the next login");
     Ok(())
 }
 
-pub fn tear_down() -> Result<()> {
-    let steps = install_system!()
-        .service_name(env!("CARGO_CRATE_NAME"))
-        .prepare_remove()
-        .wrap_err("Could not remove installation")?;
+/// `None` if the service is not installed, otherwise the output of
+/// `systemctl is-active`, for example: active, inactive or failed.
+pub(crate) fn service_state(user: bool) -> Result<Option<String>> {
+    let name = service_name();
+    if !drift::unit_path(user, &name)?.exists() {
+        return Ok(None);
+    }
+    let mut systemctl = Command::new("systemctl");
+    if user {
+        systemctl.arg("--user");
+    }
+    let output = systemctl
+        .arg("is-active")
+        .arg(&name)
+        .output()
+        .wrap_err("Could not run systemctl")?;
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+pub fn tear_down(RemoveArgs { user }: &RemoveArgs) -> Result<()> {
+    let steps = if *user {
+        install_user!()
+            .service_name(env!("CARGO_CRATE_NAME"))
+            .prepare_remove()
+    } else {
+        install_system!()
+            .service_name(env!("CARGO_CRATE_NAME"))
+            .prepare_remove()
+    }
+    .wrap_err("Could not remove installation")?;
 
     tui::removal::start(steps).wrap_err("Failed to run removal wizard")?;
+    #[cfg(feature = "overlay")]
+    {
+        let entry = autostart_entry(*user)?;
+        if entry.exists() {
+            fs::remove_file(&entry)
+                .wrap_err("Could not remove the overlay autostart entry")
+                .with_note(|| format!("path: {}", entry.display()))?;
+        }
+    }
     Ok(())
 }
 
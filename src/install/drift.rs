@@ -0,0 +1,167 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+
+pub(super) fn unit_path(user: bool, service_name: &str) -> Result<PathBuf> {
+    let dir = if user {
+        let home = std::env::var_os("HOME").ok_or_else(|| eyre!("HOME is not set"))?;
+        PathBuf::from(home).join(".config/systemd/user")
+    } else {
+        PathBuf::from("/etc/systemd/system")
+    };
+    Ok(dir.join(format!("{service_name}.service")))
+}
+
+/// The arguments the installed service is started with, `None` if there is
+/// no service installed.
+pub(super) fn installed_args(user: bool, service_name: &str) -> Result<Option<Vec<String>>> {
+    let path = unit_path(user, service_name)?;
+    let unit = match fs::read_to_string(&path) {
+        Ok(unit) => unit,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not read installed unit file")
+                .with_note(|| format!("path: {}", path.display()))
+        }
+    };
+    exec_start_args(&unit)
+        .map(Some)
+        .with_note(|| format!("unit file: {}", path.display()))
+}
+
+/// Arguments passed on the ExecStart line, excluding the executable
+fn exec_start_args(unit: &str) -> Result<Vec<String>> {
+    let mut lines = unit.lines();
+    let mut exec_start = lines
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .ok_or_else(|| eyre!("Unit file has no ExecStart line"))?
+        .to_string();
+    // systemd allows continuing a line by ending it with a backslash
+    while let Some(continued) = exec_start.strip_suffix('\\') {
+        exec_start = continued.to_string();
+        let Some(next) = lines.next() else {
+            break;
+        };
+        exec_start.push(' ');
+        exec_start.push_str(next);
+    }
+
+    let mut args =
+        shell_words::split(&exec_start).wrap_err("Could not split ExecStart into arguments")?;
+    if args.is_empty() {
+        return Err(eyre!("ExecStart is empty"));
+    }
+    args.remove(0);
+    Ok(args)
+}
+
+/// Puts each flag on a line together with its value
+fn to_lines(args: &[String]) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut flag_without_value = false;
+    for arg in args {
+        match lines.last_mut() {
+            Some(line) if flag_without_value && !arg.starts_with('-') => {
+                line.push(' ');
+                line.push_str(arg);
+                flag_without_value = false;
+            }
+            _ => {
+                flag_without_value = arg.starts_with('-');
+                lines.push(arg.clone());
+            }
+        }
+    }
+    lines
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Diff based on the longest common subsequence
+fn diff<'a>(old: &'a [String], new: &'a [String]) -> Vec<Change<'a>> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for (i, o) in old.iter().enumerate().rev() {
+        for (j, n) in new.iter().enumerate().rev() {
+            common[i][j] = if o == n {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(Change::Same(&old[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            changes.push(Change::Removed(&old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(&new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|o| Change::Removed(o)));
+    changes.extend(new[j..].iter().map(|n| Change::Added(n)));
+    changes
+}
+
+/// Prints the difference between the installed and the wanted arguments.
+/// Returns whether they differ.
+pub(super) fn print_diff(installed: &[String], wanted: &[String]) -> bool {
+    let (installed, wanted) = (to_lines(installed), to_lines(wanted));
+    let changes = diff(&installed, &wanted);
+    if changes.iter().all(|c| matches!(c, Change::Same(_))) {
+        return false;
+    }
+
+    println!("--- installed service arguments");
+    println!("+++ new service arguments");
+    for change in changes {
+        match change {
+            Change::Same(line) => println!("  {line}"),
+            Change::Removed(line) => println!("- {line}"),
+            Change::Added(line) => println!("+ {line}"),
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_diff_exec_start() {
+        let unit = "[Service]\nType=simple\nExecStart=/usr/bin/break-enforcer run \\\n\t--work-duration 25:00 \\\n\t--tcp-api\n";
+        let installed = exec_start_args(unit).unwrap();
+        assert_eq!(installed, ["run", "--work-duration", "25:00", "--tcp-api"]);
+
+        let wanted: Vec<String> = ["run", "--work-duration", "50:00", "--tcp-api"]
+            .map(String::from)
+            .to_vec();
+        let (installed, wanted) = (to_lines(&installed), to_lines(&wanted));
+        assert_eq!(
+            diff(&installed, &wanted),
+            [
+                Change::Same("run"),
+                Change::Removed("--work-duration 25:00"),
+                Change::Added("--work-duration 50:00"),
+                Change::Same("--tcp-api"),
+            ]
+        );
+    }
+}
@@ -1,22 +1,68 @@
+use std::collections::{HashSet, VecDeque};
 use std::fmt::Display;
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use break_enforcer::{BreakKind, ResetReason, StateUpdate, Timestamp};
+use chrono::{Local, NaiveDate, NaiveTime};
 use color_eyre::eyre::Context;
 use color_eyre::Result;
+use nix::time::{clock_gettime, ClockId};
 
+use crate::check_inputs::Event;
+use crate::clock::{Clock, Jump, SystemClock, WallClockWatch};
+use crate::config::BlockList;
+use crate::daily_limit::DailyLimit;
+use crate::run::IdleCredit;
+use crate::stats::{self, DayStats};
+use crate::supervisor::{self, Subsystem};
+use crate::watch_and_block::{GrabFailed, OnlineDevices};
+
+mod activities;
+mod battery;
+mod export;
 mod file_status;
+mod history;
+#[cfg(feature = "http")]
+mod http_api;
+mod window_manager;
+pub(crate) use activities::Activities;
+pub(crate) use battery::{Battery, BatteryPolicy};
+pub(crate) use export::ActivityWatch;
+use export::Exporter;
 use file_status::FileStatus;
-use tracing::error;
-mod notification;
+pub(crate) use history::{read_persisted as read_history, History};
+use tracing::{error, info};
+pub(crate) use window_manager::check_dependency as check_window_manager;
+use window_manager::WindowManager;
+pub(crate) mod notification;
+pub(crate) mod speech;
 pub(crate) mod tcp_api;
+pub(crate) use tcp_api::{Config as ApiConfig, Inherited as InheritedApi, Remote as RemoteApi};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
-    Waiting,
+    Waiting { reset: Option<ResetReason> },
     Work { next_break: Instant },
-    Break { next_work: Instant },
+    Break { next_work: Instant, kind: BreakKind },
+    Paused { until: Instant },
+    Meeting { until: Instant, debt: Duration },
+}
+
+/// What the integrator receives from the run loop
+enum Update {
+    State(State),
+    /// New end of the running break
+    BreakExtended(Instant),
+    /// Whether none of the devices to block are connected
+    Unplugged(bool),
+    GrabFailed(GrabFailed),
+    Warning(String),
+    /// Name of a device blocked after all
+    GrabRetried(String),
+    /// A subsystem failed or was restarted
+    Health,
 }
 
 trait DurationUntil {
@@ -25,13 +71,128 @@ trait DurationUntil {
 
 impl DurationUntil for Instant {
     fn duration_until(&self) -> Duration {
-        self.saturating_duration_since(Instant::now())
+        SystemClock.duration_until(*self)
+    }
+}
+
+fn now() -> Timestamp {
+    let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .expect("CLOCK_MONOTONIC is always supported on linux");
+    Timestamp {
+        monotonic: Duration::from(monotonic),
+        wall_clock: SystemTime::now(),
+    }
+}
+
+impl State {
+    fn to_update(self) -> StateUpdate {
+        let at = now();
+        match self {
+            State::Waiting { reset } => StateUpdate::Waiting { at, reset },
+            State::Work { next_break } => StateUpdate::Work {
+                at,
+                until_break: next_break.duration_until(),
+            },
+            State::Break { next_work, kind } => StateUpdate::Break {
+                at,
+                until_work: next_work.duration_until(),
+                kind,
+            },
+            State::Paused { until } => StateUpdate::Paused {
+                at,
+                until_resume: until.duration_until(),
+            },
+            State::Meeting { until, debt } => StateUpdate::Meeting {
+                at,
+                until_end: until.duration_until(),
+                debt,
+            },
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            State::Waiting { .. } => "waiting",
+            State::Work { .. } => "work",
+            State::Break { .. } => "break",
+            State::Paused { .. } => "paused",
+            State::Meeting { .. } => "meeting",
+        }
+    }
+
+    /// How far through the current period from 0 to 1, 0 while waiting.
+    /// `started` is when the period began on `clock`, the deadlines are on
+    /// it too.
+    fn progress(self, started: Instant, clock: &dyn Clock) -> f32 {
+        let end = match self {
+            State::Waiting { .. } => return 0.0,
+            State::Work { next_break } => next_break,
+            State::Break { next_work, .. } => next_work,
+            State::Paused { until } | State::Meeting { until, .. } => until,
+        };
+        let total = end.saturating_duration_since(started);
+        if total.is_zero() {
+            return 1.0;
+        }
+        let done = clock.elapsed(started);
+        (done.as_secs_f32() / total.as_secs_f32()).min(1.0)
+    }
+}
+
+/// The fields end up in the journal, see `main::journald`. `next_break` is in
+/// seconds since the unix epoch.
+fn log_state(state: State) {
+    crate::crash::set_state(format!("{state:?}"));
+    let name = state.name();
+    if let State::Work { next_break } = state {
+        let until = next_break.duration_until();
+        let next_break = (SystemTime::now() + until)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        info!(
+            state = name,
+            next_break,
+            "Now {name}, break in {}",
+            fmt_dur(until)
+        );
+    } else {
+        info!(state = name, "Now {name}");
     }
 }
 
 pub struct Status {
-    update: mpsc::Sender<State>,
-    integrator: Option<JoinHandle<Result<()>>>,
+    update: mpsc::Sender<Update>,
+    backlog: Backlog,
+}
+
+/// When the updates the integrator did not get to yet were send
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Backlog(Arc<Mutex<VecDeque<Instant>>>);
+
+impl Backlog {
+    fn queue(&self) -> MutexGuard<'_, VecDeque<Instant>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn send(&self, tx: &mpsc::Sender<Update>, update: Update) {
+        self.queue().push_back(Instant::now());
+        if tx.send(update).is_err() {
+            // the integrator only stops once `Status` is dropped
+            self.queue().pop_back();
+        }
+    }
+
+    fn received(&self) {
+        self.queue().pop_front();
+    }
+
+    /// How long the oldest update not yet handled has been waiting
+    pub(crate) fn lag(&self) -> Duration {
+        self.queue()
+            .front()
+            .map_or(Duration::ZERO, |sent| sent.elapsed())
+    }
 }
 
 pub(crate) struct NotifyConfig {
@@ -40,46 +201,297 @@ pub(crate) struct NotifyConfig {
     pub(crate) lock_notify_type: Vec<NotificationType>,
     pub(crate) last_lock_warning: Instant,
     pub(crate) state_notifications: bool,
+    /// show a sway/i3 nag bar during the lock warning and the break
+    pub(crate) nag_bar: bool,
+    /// sway/i3 workspace to switch to during the break
+    pub(crate) break_workspace: Option<String>,
+    /// the break coach, suggests one per break
+    pub(crate) activities: Option<Arc<Activities>>,
+    /// warned about running out of it ahead of time
+    pub(crate) daily_limit: Option<DailyLimit>,
+    /// completed work periods a day, the day is summarized once reached
+    pub(crate) daily_goal: Option<u32>,
+    /// time of day to summarize the day at
+    pub(crate) summary_at: Option<NaiveTime>,
+    /// time tracker to send finished periods to
+    pub(crate) export: Option<ActivityWatch>,
+    /// notify when none of the devices to block are connected anymore
+    pub(crate) unplugged_notification: bool,
+    /// notify when a device could not be blocked
+    pub(crate) warning_notifications: bool,
+    /// speak breaks, and depending on the verbosity more, out loud
+    pub(crate) announce: Option<speech::Verbosity>,
+    /// shown while idle, how much shorter the next break gets
+    pub(crate) idle_credit: IdleCredit,
+    /// fewer wake ups and maybe no audio while on battery
+    pub(crate) battery: Battery,
+    pub(crate) clock: Arc<dyn Clock>,
 }
 
+#[allow(clippy::too_many_arguments)]
 fn integrate(
-    rx: &mpsc::Receiver<State>,
-    mut file_status: Option<FileStatus>,
-    mut api_status: Option<tcp_api::Status>,
-    idle: Arc<Mutex<Instant>>,
+    rx: &mpsc::Receiver<Update>,
+    backlog: &Backlog,
+    file_status: &mut Option<FileStatus>,
+    api_status: &mut Option<(tcp_api::Status, Duration)>,
+    history: &History,
+    idle: &Arc<Mutex<Instant>>,
     break_duration: Duration,
-    mut notify: NotifyConfig,
+    notify: &mut NotifyConfig,
+    window_manager: &mut Option<WindowManager>,
+    // survives a restart of the integrator
+    saved: &mut State,
 ) -> Result<()> {
-    let mut timeout = Duration::MAX;
-    let mut state = State::Waiting;
+    // after a restart the status is brought up to date right away
+    let mut timeout = Duration::ZERO;
+    let mut state = *saved;
+    // an extended break keeps its start, its progress then drops back
+    let mut period_start = notify.clock.now();
+    let mut break_total = Duration::ZERO;
+    let mut last_progress = Instant::now();
+    let mut wall_clock = WallClockWatch::new();
+    let mut activity = None;
+    // the smallest of `DAILY_LIMIT_WARNINGS` warned about
+    let mut limit_warned = Duration::MAX;
+    let mut summarized = Summarized::new(notify.summary_at);
+    let mut exporter = notify.export.clone().map(Exporter::start);
+    let mut unplugged = false;
+    // devices the current break could not block
+    let mut not_blocked = HashSet::new();
 
     loop {
+        let was_break = matches!(state, State::Break { .. });
         let mut state_changed = false;
-        match rx.recv_timeout(timeout) {
-            Ok(s) => {
+        // the same break continues, its suggestion and warnings stay
+        let mut extended = false;
+        let update = rx.recv_timeout(timeout);
+        if update.is_ok() {
+            backlog.received();
+        }
+        match update {
+            Ok(Update::State(s)) => {
                 state = s;
                 state_changed = true;
             }
+            Ok(Update::BreakExtended(until)) => {
+                if let State::Break { next_work, .. } = &mut state {
+                    break_total += until.saturating_duration_since(*next_work);
+                    *next_work = until;
+                    state_changed = true;
+                    extended = true;
+                }
+            }
+            Ok(Update::Unplugged(now_unplugged)) => {
+                if now_unplugged && !unplugged && notify.unplugged_notification {
+                    if let Err(report) = notification::notify(UNPLUGGED) {
+                        error!("Failed to send unplugged notification: {report}")
+                    }
+                }
+                unplugged = now_unplugged;
+            }
+            Ok(Update::GrabFailed(failure)) => {
+                let message = failure.to_string();
+                if notify.warning_notifications {
+                    if let Err(report) = notification::notify(&message) {
+                        error!("Failed to send warning: {report}")
+                    }
+                }
+                let update = StateUpdate::Warning { at: now(), message };
+                history.record(&update);
+                if let Some((status, _)) = &api_status {
+                    status.warning(&update);
+                }
+                not_blocked.insert(failure.device);
+            }
+            Ok(Update::Warning(message)) => {
+                let update = StateUpdate::Warning { at: now(), message };
+                history.record(&update);
+                if let Some((status, _)) = &api_status {
+                    status.warning(&update);
+                }
+            }
+            Ok(Update::GrabRetried(device)) => {
+                not_blocked.remove(&device);
+            }
+            Ok(Update::Health) => (),
             Err(mpsc::RecvTimeoutError::Timeout) => (),
             Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
         }
 
-        timeout = match state {
-            State::Waiting => Duration::MAX,
-            State::Work { .. } | State::Break { .. } => Duration::from_secs(1),
-        };
+        match wall_clock.check() {
+            Some(Jump::Forward(by)) => info!("Wall clock was set forward by {}", fmt_dur(by)),
+            Some(Jump::Backward(by)) => info!("Wall clock was set back by {}", fmt_dur(by)),
+            None => (),
+        }
+
+        if state_changed && !extended {
+            not_blocked.clear();
+            period_start = notify.clock.now();
+        }
+        if state_changed {
+            *saved = state;
+        }
+        let update = state_changed.then(|| state.to_update());
+        if let Some(update) = &update {
+            log_state(state);
+            history.record(update);
+            if let Some(exporter) = &mut exporter {
+                exporter.state_changed(history);
+            }
+        }
+        if let Some(verbosity) = notify.announce.filter(|_| state_changed) {
+            if let Some(text) = speech::announcement(&state, was_break, extended, verbosity) {
+                if let Err(report) = speech::say(&text) {
+                    error!("Failed to announce state change: {report}")
+                }
+            }
+        }
+        if state_changed && !extended {
+            activity = match state {
+                State::Break { .. } => notify.activities.as_ref().and_then(|a| a.pick()),
+                _ => None,
+            };
+        }
 
-        let msg = format_status(&state, &idle, break_duration);
-        if let Some(status) = &mut file_status {
+        let limit_reached = notify.daily_limit.as_ref().is_some_and(DailyLimit::reached);
+        let msg = format_status(
+            &state,
+            idle,
+            break_duration,
+            activity.as_deref(),
+            limit_reached,
+            unplugged,
+            not_blocked.len(),
+            notify.idle_credit,
+        );
+        let msg = with_degraded(msg, &supervisor::degraded());
+        if let Some(status) = file_status {
             status.update(&msg);
         }
-        if let Some(status) = &mut api_status {
+        if let Some((status, progress_interval)) = api_status {
             status.update_msg(&msg);
+            if let Some(update) = &update {
+                status.update_activity(activity.clone());
+                status.update_state(state, period_start, update);
+            }
+            if let State::Break { next_work, .. } = state {
+                if state_changed && !extended {
+                    break_total = next_work.duration_until();
+                    last_progress = Instant::now();
+                } else if last_progress.elapsed() >= *progress_interval {
+                    last_progress = Instant::now();
+                    status.progress(next_work.duration_until(), break_total);
+                }
+            }
+        }
+        notify_if_needed(&state, notify, state_changed, msg);
+        if let Some(limit) = &notify.daily_limit {
+            warn_daily_limit(limit, notify, &mut limit_warned);
+        }
+        summarize_day(history, notify, state_changed, &mut summarized);
+        if let Some(window_manager) = window_manager {
+            window_manager.update(&state, notify.lock_warning, &*notify.clock);
+        }
+
+        // wake only when something needs updating
+        timeout = match state {
+            State::Waiting { .. } => Duration::MAX,
+            State::Work { next_break } => {
+                let idle = SystemClock.elapsed(*idle.lock().unwrap());
+                let msg_changes = if idle > IDLE_SHOWN_AFTER {
+                    until_display_changes(break_duration.saturating_sub(idle))
+                } else {
+                    until_display_changes(next_break.duration_until()).min(IDLE_SHOWN_AFTER - idle)
+                };
+                let msg_changes = display_wake(msg_changes, &notify.battery);
+                let lock_warning = notify
+                    .lock_warning
+                    .and_then(|warn_at| next_break.duration_until().checked_sub(warn_at))
+                    .filter(|until| !until.is_zero())
+                    .unwrap_or(Duration::MAX);
+                let limit_warning = notify.daily_limit.as_ref().map_or(Duration::MAX, |limit| {
+                    until_next_limit_warning(limit.left(), limit_warned)
+                });
+                msg_changes.min(lock_warning).min(limit_warning)
+            }
+            State::Break { next_work, .. } => {
+                let progress = api_status.as_ref().map_or(Duration::MAX, |(_, interval)| {
+                    interval.saturating_sub(last_progress.elapsed())
+                });
+                display_wake(
+                    until_display_changes(next_work.duration_until()).min(progress),
+                    &notify.battery,
+                )
+            }
+            State::Paused { until } => {
+                let limit_warning = notify.daily_limit.as_ref().map_or(Duration::MAX, |limit| {
+                    until_next_limit_warning(limit.left(), limit_warned)
+                });
+                display_wake(
+                    until_display_changes(until.duration_until()),
+                    &notify.battery,
+                )
+                .min(limit_warning)
+            }
+            State::Meeting { until, .. } => display_wake(
+                until_display_changes(until.duration_until()),
+                &notify.battery,
+            ),
+        };
+        if let Some(at) = notify.summary_at {
+            timeout = timeout.min(until_time_of_day(at));
         }
-        notify_if_needed(&state, &mut notify, state_changed, msg);
     }
 }
 
+/// Subsystems waiting to be restarted, see `supervisor`
+fn with_degraded(msg: String, degraded: &[Subsystem]) -> String {
+    if degraded.is_empty() {
+        return msg;
+    }
+    let degraded: Vec<_> = degraded.iter().map(Subsystem::to_string).collect();
+    format!("{msg}, degraded: {}", degraded.join(", "))
+}
+
+/// On battery the status is updated less often, warnings stay on time
+fn display_wake(until_changes: Duration, battery: &Battery) -> Duration {
+    if battery.fewer_wakeups() {
+        until_changes.max(Battery::MIN_WAKE)
+    } else {
+        until_changes
+    }
+}
+
+/// How long until `fmt_dur` formats `remaining` differently
+fn until_display_changes(remaining: Duration) -> Duration {
+    const MINUTE: Duration = Duration::from_secs(60);
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+    // below this whole seconds are shown, above it rounded minutes
+    const SHOWS_SECONDS: Duration = Duration::from_secs(61);
+
+    if remaining.is_zero() {
+        return Duration::MAX; // stays at zero until the state changes
+    }
+    if remaining < SHOWS_SECONDS {
+        let partial = remaining - Duration::from_secs(remaining.as_secs());
+        return partial + Duration::from_millis(1);
+    }
+    // rounding changes on every half minute and every half hour
+    let until_rounding_changes = |unit: Duration| {
+        let past_half = remaining.checked_sub(unit / 2)?.as_millis() % unit.as_millis();
+        Some(Duration::from_millis(past_half as u64 + 1))
+    };
+    [
+        until_rounding_changes(MINUTE),
+        until_rounding_changes(HOUR),
+        Some(remaining - SHOWS_SECONDS + Duration::from_millis(1)),
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+    .expect("the last option is always some")
+}
+
 #[derive(Debug, Clone, clap::ValueEnum, Eq, PartialEq)]
 pub(crate) enum NotificationType {
     System,
@@ -108,6 +520,12 @@ impl NotificationType {
         Ok(())
     }
 
+    /// Sends an example so the user can check it arrives
+    pub(crate) fn send_sample(&self) -> color_eyre::Result<()> {
+        self.check_dependency()?;
+        self.notify("This is how break-enforcer warns before a break")
+    }
+
     pub(crate) fn check_dependency(&self) -> color_eyre::Result<()> {
         match self {
             NotificationType::System => {
@@ -121,18 +539,31 @@ impl NotificationType {
     }
 }
 
+fn muted(notify_type: &NotificationType, battery: &Battery) -> bool {
+    *notify_type == NotificationType::Audio && battery.mute_audio()
+}
+
 fn notify_if_needed(state: &State, notify: &mut NotifyConfig, state_changed: bool, msg: String) {
     const MARGIN: Duration = Duration::from_secs(1);
     if let State::Work { next_break } = *state {
         if let Some(warn_at) = notify.lock_warning {
-            if next_break.duration_until() < warn_at {
-                if notify.last_lock_warning.elapsed() > warn_at + MARGIN {
-                    let msg = format!("locking in {}", fmt_dur(warn_at));
-                    notify.last_lock_warning = Instant::now();
-                    for notify_type in &notify.lock_notify_type {
-                        if let Err(report) = notify_type.notify(&msg) {
-                            error!("Failed to send lock warning: {report}")
-                        }
+            if notify.clock.duration_until(next_break) < warn_at
+                && notify.clock.elapsed(notify.last_lock_warning) > warn_at + MARGIN
+            {
+                let msg = format!("locking in {}", fmt_dur(warn_at));
+                notify.last_lock_warning = notify.clock.now();
+                for notify_type in &notify.lock_notify_type {
+                    if muted(notify_type, &notify.battery) {
+                        continue;
+                    }
+                    if let Err(report) = notify_type.notify(&msg) {
+                        error!("Failed to send lock warning: {report}")
+                    }
+                }
+                if notify.announce >= Some(speech::Verbosity::Warnings) {
+                    let text = format!("Locking in {}", speech::fmt_spoken(warn_at));
+                    if let Err(report) = speech::say(&text) {
+                        error!("Failed to announce lock warning: {report}")
                     }
                 }
             }
@@ -146,91 +577,339 @@ fn notify_if_needed(state: &State, notify: &mut NotifyConfig, state_changed: boo
     }
 }
 
-fn format_status(state: &State, idle: &Arc<Mutex<Instant>>, break_duration: Duration) -> String {
+/// How long before the daily limit is reached to warn
+const DAILY_LIMIT_WARNINGS: [Duration; 3] = [
+    Duration::from_secs(30 * 60),
+    Duration::from_secs(10 * 60),
+    Duration::from_secs(60),
+];
+
+/// `warned` is the smallest warning send so far, it resets once a new day
+/// leaves more then the largest warning
+fn warn_daily_limit(limit: &DailyLimit, notify: &NotifyConfig, warned: &mut Duration) {
+    let left = limit.left();
+    if left > DAILY_LIMIT_WARNINGS[0] {
+        *warned = Duration::MAX;
+        return;
+    }
+    let Some(due) = DAILY_LIMIT_WARNINGS
+        .into_iter()
+        .filter(|warning| left <= *warning && *warning < *warned)
+        .min()
+    else {
+        return;
+    };
+    *warned = due;
+    if left.is_zero() {
+        return; // the lock itself shows
+    }
+    let msg = format!("daily limit reached in {}", fmt_dur(left));
+    let system = [NotificationType::System];
+    let types = if notify.lock_notify_type.is_empty() {
+        &system[..]
+    } else {
+        &notify.lock_notify_type
+    };
+    for notify_type in types {
+        if muted(notify_type, &notify.battery) {
+            continue;
+        }
+        if let Err(report) = notify_type.notify(&msg) {
+            error!("Failed to send daily limit warning: {report}")
+        }
+    }
+    if notify.announce >= Some(speech::Verbosity::Warnings) {
+        let text = format!("Daily limit reached in {}", speech::fmt_spoken(left));
+        if let Err(report) = speech::say(&text) {
+            error!("Failed to announce daily limit warning: {report}")
+        }
+    }
+}
+
+fn until_next_limit_warning(left: Duration, warned: Duration) -> Duration {
+    DAILY_LIMIT_WARNINGS
+        .into_iter()
+        .filter(|warning| *warning < warned)
+        .filter_map(|warning| left.checked_sub(warning))
+        .min()
+        .map_or(Duration::MAX, |until| until + Duration::from_millis(1))
+}
+
+/// Days the summaries were send on
+struct Summarized {
+    goal: Option<NaiveDate>,
+    scheduled: Option<NaiveDate>,
+}
+
+impl Summarized {
+    /// Starting after today's summary time does not send it
+    fn new(summary_at: Option<NaiveTime>) -> Self {
+        let now = Local::now();
+        Self {
+            goal: None,
+            scheduled: summary_at
+                .filter(|at| now.time() >= *at)
+                .map(|_| now.date_naive()),
+        }
+    }
+}
+
+fn summarize_day(
+    history: &History,
+    notify: &NotifyConfig,
+    state_changed: bool,
+    summarized: &mut Summarized,
+) {
+    let now = Local::now();
+    let today = now.date_naive();
+    let today_stats = || {
+        let periods = stats::periods(&history.all(), Some(SystemTime::now()));
+        DayStats::of(&periods, today)
+    };
+
+    let mut msg = None;
+    if let Some(goal) = notify.daily_goal {
+        // work periods only complete when the state changes
+        if state_changed && summarized.goal != Some(today) {
+            let stats = today_stats();
+            if stats.completed_work_periods >= goal {
+                summarized.goal = Some(today);
+                msg = Some(format!(
+                    "daily goal of {goal} work periods reached: {stats}"
+                ));
+            }
+        }
+    }
+    if let Some(at) = notify.summary_at {
+        if now.time() >= at && summarized.scheduled != Some(today) {
+            summarized.scheduled = Some(today);
+            msg = Some(format!("today: {}", today_stats()));
+        }
+    }
+
+    if let Some(msg) = msg {
+        if let Err(report) = notification::notify(&msg) {
+            error!("Failed to send the summary of the day: {report}")
+        }
+    }
+}
+
+/// How long until the wall clock next shows `at`
+fn until_time_of_day(at: NaiveTime) -> Duration {
+    let now = Local::now().naive_local();
+    let today = now.date().and_time(at);
+    let next = if today > now {
+        today
+    } else {
+        today + chrono::TimeDelta::days(1)
+    };
+    (next - now).to_std().unwrap_or_default() + Duration::from_millis(1)
+}
+
+/// Below this the status shows time until the break
+const IDLE_SHOWN_AFTER: Duration = Duration::from_secs(30);
+/// Put in front of the status while none of the devices to block are
+/// connected
+const UNPLUGGED: &str = "no device to block connected";
+
+#[allow(clippy::too_many_arguments)]
+fn format_status(
+    state: &State,
+    idle: &Arc<Mutex<Instant>>,
+    break_duration: Duration,
+    activity: Option<&str>,
+    limit_reached: bool,
+    unplugged: bool,
+    not_blocked: usize,
+    idle_credit: IdleCredit,
+) -> String {
     let msg = match *state {
-        State::Waiting => String::from("-"),
+        State::Waiting { .. } => String::from("-"),
         State::Work { next_break } => {
-            let idle = idle.lock().unwrap().elapsed();
-            if idle > Duration::from_secs(30) {
+            let idle = SystemClock.elapsed(*idle.lock().unwrap());
+            if idle > IDLE_SHOWN_AFTER {
                 let break_dur = break_duration.saturating_sub(idle);
                 let break_dur = fmt_dur(break_dur);
-                format!("idle, reset in {}", break_dur)
+                let credit = idle_credit.of(idle);
+                if credit.is_zero() {
+                    format!("idle, reset in {}", break_dur)
+                } else {
+                    format!(
+                        "idle, reset in {}, break {} shorter",
+                        break_dur,
+                        fmt_dur(credit)
+                    )
+                }
             } else {
                 let next_break = fmt_dur(next_break.duration_until());
                 format!("break in {}", next_break)
             }
         }
-        State::Break { next_work } => {
-            format!("unlocks in {}", fmt_dur(next_work.duration_until()))
+        State::Break { next_work, .. } => {
+            let mut unlocks = format!("unlocks in {}", fmt_dur(next_work.duration_until()));
+            if limit_reached {
+                unlocks.insert_str(0, "daily limit reached, ");
+            }
+            match activity {
+                Some(activity) => format!("{unlocks}: {activity}"),
+                None => unlocks,
+            }
+        }
+        State::Paused { until } => {
+            format!("paused for {}", fmt_dur(until.duration_until()))
+        }
+        State::Meeting { until, debt } if debt.is_zero() => {
+            format!("meeting for {}", fmt_dur(until.duration_until()))
         }
+        State::Meeting { until, debt } => format!(
+            "meeting for {}, {} of break owed",
+            fmt_dur(until.duration_until()),
+            fmt_dur(debt)
+        ),
     };
-    msg
+    let msg = match not_blocked {
+        0 => msg,
+        1 => format!("1 device not blocked, {msg}"),
+        n => format!("{n} devices not blocked, {msg}"),
+    };
+    match state {
+        _ if !unplugged => msg,
+        State::Waiting { .. } => UNPLUGGED.to_string(),
+        _ => format!("{UNPLUGGED}, {msg}"),
+    }
 }
 
 impl Status {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
-        file_integration: bool,
-        tcp_api_integration: bool,
+        status_file: Option<PathBuf>,
+        history: History,
+        tcp_api_integration: Option<tcp_api::Config>,
         notify: NotifyConfig,
         idle: Arc<Mutex<Instant>>,
         break_duration: Duration,
+        online_devices: OnlineDevices,
+        block_list: BlockList,
+        controls: mpsc::Sender<Event>,
     ) -> Result<Self> {
-        let file_status = if file_integration {
-            Some(FileStatus::new()?)
-        } else {
-            None
-        };
+        let file_status = status_file.as_deref().map(FileStatus::new).transpose()?;
+        let backlog = Backlog::default();
 
-        let api_status = if tcp_api_integration {
-            let status = tcp_api::Status::new(idle.clone());
+        let api_status = if let Some(config) = tcp_api_integration {
+            let status = tcp_api::Status::new(
+                idle.clone(),
+                online_devices,
+                block_list,
+                history.clone(),
+                controls,
+                notify.activities.clone(),
+                notify.daily_limit.clone(),
+                config.activity_map,
+                backlog.clone(),
+                notify.clock.clone(),
+            );
             {
                 let status = status.clone();
-                thread::spawn(|| {
-                    if let Err(e) = tcp_api::maintain(status) {
-                        error!("failed to maintain tcp API: {e}");
-                    }
+                let (port, remote, inherited) = (config.port, config.remote, config.inherited);
+                if let Err(e) = tcp_api::maintain(status, port, remote, inherited) {
+                    error!("failed to maintain tcp API: {e}");
+                }
+            }
+            #[cfg(feature = "http")]
+            if let Some(port) = config.http_port {
+                let status = status.clone();
+                supervisor::spawn(Subsystem::HttpApi, move || {
+                    http_api::maintain(status.clone(), port)
                 });
             }
-            Some(status)
+            Some((status, config.progress_interval))
         } else {
             None
         };
 
+        let window_manager = WindowManager::new(notify.nag_bar, notify.break_workspace.clone())
+            .wrap_err("Could not set up the window manager integration")?;
         let (tx, rx) = mpsc::channel();
-        let integrator = thread::spawn(move || {
-            integrate(&rx, file_status, api_status, idle, break_duration, notify)
+        {
+            let (tx, backlog) = (tx.clone(), backlog.clone());
+            supervisor::on_change(move || backlog.send(&tx, Update::Health));
+        }
+        let (mut file_status, mut api_status) = (file_status, api_status);
+        let (mut notify, mut window_manager) = (notify, window_manager);
+        let mut saved = State::Waiting { reset: None };
+        let integrator_backlog = backlog.clone();
+        supervisor::spawn(Subsystem::Integrator, move || {
+            integrate(
+                &rx,
+                &integrator_backlog,
+                &mut file_status,
+                &mut api_status,
+                &history,
+                &idle,
+                break_duration,
+                &mut notify,
+                &mut window_manager,
+                &mut saved,
+            )
         });
 
         Ok(Self {
             update: tx,
-            integrator: Some(integrator),
+            backlog,
         })
     }
 
-    fn send(&mut self, new_state: State) {
-        let res = self.update.send(new_state);
-        if res.is_err() {
-            // Get issues from the integrator thread and crash here on the main
-            // thread. That way the program will exit.
-            self.integrator
-                .take()
-                .expect("can only be called once")
-                .join()
-                .expect("The integrator thread panicked")
-                .expect("The integrator thread returned an error, it should not");
-        }
+    /// While the integrator is being restarted updates queue up
+    fn send(&mut self, update: Update) {
+        self.backlog.send(&self.update, update);
     }
 
-    pub(crate) fn set_waiting(&mut self) {
-        self.send(State::Waiting);
+    /// `reset` is why the previous work period ended without a break
+    pub(crate) fn set_waiting(&mut self, reset: Option<ResetReason>) {
+        self.send(Update::State(State::Waiting { reset }));
     }
 
     pub(crate) fn set_working(&mut self, next_break: Instant) {
-        self.send(State::Work { next_break });
+        self.send(Update::State(State::Work { next_break }));
+    }
+
+    pub(crate) fn set_break(&mut self, next_work: Instant, kind: BreakKind) {
+        self.send(Update::State(State::Break { next_work, kind }));
+    }
+
+    /// Moves the end of the running break to `next_work`
+    pub(crate) fn extend_break(&mut self, next_work: Instant) {
+        self.send(Update::BreakExtended(next_work));
+    }
+
+    pub(crate) fn set_paused(&mut self, until: Instant) {
+        self.send(Update::State(State::Paused { until }));
+    }
+
+    /// `debt` is the break time owed once the meeting ends
+    pub(crate) fn set_meeting(&mut self, until: Instant, debt: Duration) {
+        self.send(Update::State(State::Meeting { until, debt }));
     }
 
-    pub(crate) fn set_break(&mut self, next_work: Instant) {
-        self.send(State::Break { next_work });
+    /// Shows in the status, see `UNPLUGGED`
+    pub(crate) fn set_unplugged(&mut self, unplugged: bool) {
+        self.send(Update::Unplugged(unplugged));
+    }
+
+    /// Recorded in the history and send to api clients
+    pub(crate) fn warning(&mut self, message: String) {
+        self.send(Update::Warning(message));
+    }
+
+    /// Shows in the status until the state changes and is send to api
+    /// clients as a warning
+    pub(crate) fn grab_failed(&mut self, failure: GrabFailed) {
+        self.send(Update::GrabFailed(failure));
+    }
+
+    /// A device reported to `grab_failed` is blocked after all
+    pub(crate) fn grab_retried(&mut self, device: String) {
+        self.send(Update::GrabRetried(device));
     }
 }
 
@@ -244,7 +923,7 @@ fn fmt_mm_hh(dur: Duration) -> String {
     }
 }
 
-fn fmt_dur(dur: Duration) -> String {
+pub(crate) fn fmt_dur(dur: Duration) -> String {
     let seconds = dur.as_secs();
     if seconds > 60 {
         fmt_mm_hh(dur)
@@ -252,3 +931,79 @@ fn fmt_dur(dur: Duration) -> String {
         format!("{seconds}s")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn display_changes_at_predicted_time() {
+        for ms in (0..3 * 60 * 60 * 1000).step_by(997) {
+            let remaining = Duration::from_millis(ms);
+            let wait = until_display_changes(remaining);
+            assert!(!wait.is_zero());
+            let shown = fmt_dur(remaining);
+            let just_before = remaining.saturating_sub(wait - Duration::from_millis(1));
+            assert_eq!(fmt_dur(just_before), shown, "at {remaining:?}");
+            if wait < remaining {
+                assert_ne!(fmt_dur(remaining - wait), shown, "at {remaining:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn unplugged_shows_in_status() {
+        let idle = Arc::new(Mutex::new(Instant::now()));
+        let status = |state, unplugged| {
+            format_status(
+                &state,
+                &idle,
+                Duration::ZERO,
+                None,
+                false,
+                unplugged,
+                0,
+                IdleCredit::Full,
+            )
+        };
+        let waiting = State::Waiting { reset: None };
+        let paused = State::Paused {
+            until: Instant::now() + Duration::from_secs(10 * 60),
+        };
+        assert_eq!(status(waiting, false), "-");
+        assert_eq!(status(waiting, true), UNPLUGGED);
+        assert_eq!(status(paused, true), format!("{UNPLUGGED}, paused for 10m"));
+        assert_eq!(
+            format_status(
+                &paused,
+                &idle,
+                Duration::ZERO,
+                None,
+                false,
+                false,
+                2,
+                IdleCredit::Full
+            ),
+            "2 devices not blocked, paused for 10m"
+        );
+    }
+
+    #[test]
+    fn progress_through_period() {
+        let clock = ManualClock::new();
+        // suspended before the period started, the clock is ahead of `Instant`
+        clock.advance(Duration::from_secs(60 * 60));
+        let started = clock.now();
+        let work = State::Work {
+            next_break: started + Duration::from_secs(100),
+        };
+        assert_eq!(work.progress(started, &clock), 0.0);
+        clock.advance(Duration::from_secs(25));
+        assert_eq!(work.progress(started, &clock), 0.25);
+        let waiting = State::Waiting { reset: None };
+        assert_eq!(waiting.progress(started, &clock), 0.0);
+        clock.advance(Duration::from_secs(175));
+        assert_eq!(work.progress(started, &clock), 1.0);
+    }
+}
@@ -0,0 +1,102 @@
+//! The break coach: suggests a stretch or eye exercise for each break. The
+//! list can be replaced by a ron file like:
+//! `[(text: "Roll your shoulders"), (text: "Look outside", weight: 3)]`
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Activity {
+    text: String,
+    /// how often it comes up compared to the others
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+const BUILTIN: [(&str, u32); 7] = [
+    ("Look at something at least 6 meters away for 20 seconds", 3),
+    ("Stand up and walk around", 3),
+    ("Roll your shoulders back ten times", 2),
+    ("Tilt your head slowly to each side", 2),
+    ("Stretch your wrists and fingers", 2),
+    ("Close your eyes and breathe slowly", 1),
+    ("Drink a glass of water", 1),
+];
+
+#[derive(Debug, Clone)]
+pub(crate) struct Activities(Vec<Activity>);
+
+impl Activities {
+    pub(crate) fn builtin() -> Self {
+        Self(
+            BUILTIN
+                .iter()
+                .map(|&(text, weight)| Activity {
+                    text: text.to_string(),
+                    weight,
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .wrap_err("Could not read the break activities")
+            .with_note(|| format!("path: {}", path.display()))?;
+        let list: Vec<Activity> = ron::from_str(&data)
+            .wrap_err("Could not parse the break activities")
+            .with_note(|| format!("path: {}", path.display()))?;
+        if list.iter().all(|activity| activity.weight == 0) {
+            return Err(eyre!("The break activities list has nothing to pick"))
+                .with_note(|| format!("path: {}", path.display()));
+        }
+        Ok(Self(list))
+    }
+
+    /// A random activity, more likely the higher its weight
+    pub(crate) fn pick(&self) -> Option<String> {
+        // randomly seeded, good enough to not repeat in a predictable way
+        let roll = RandomState::new().build_hasher().finish();
+        self.pick_with(roll).map(ToOwned::to_owned)
+    }
+
+    fn pick_with(&self, roll: u64) -> Option<&str> {
+        let total: u64 = self.0.iter().map(|a| u64::from(a.weight)).sum();
+        let mut roll = roll.checked_rem(total)?;
+        for activity in &self.0 {
+            match roll.checked_sub(u64::from(activity.weight)) {
+                Some(left) => roll = left,
+                None => return Some(&activity.text),
+            }
+        }
+        unreachable!("roll is smaller then the total weight")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn picks_by_weight() {
+        let activities: Vec<Activity> =
+            ron::from_str(r#"[(text: "a", weight: 2), (text: "skipped", weight: 0), (text: "b")]"#)
+                .unwrap();
+        let activities = Activities(activities);
+        let picked: Vec<_> = (0..6)
+            .filter_map(|roll| activities.pick_with(roll))
+            .collect();
+        assert_eq!(picked, ["a", "a", "b", "a", "a", "b"]);
+        assert_eq!(Activities(Vec::new()).pick_with(3), None);
+    }
+}
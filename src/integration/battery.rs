@@ -0,0 +1,126 @@
+//! Makes the service lighter on a laptop running on battery, see
+//! `--on-battery`. The power state comes from `/sys/class/power_supply`.
+
+use std::cell::Cell;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// What changes while running on battery
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq)]
+pub(crate) enum BatteryPolicy {
+    /// Update the status less often and leave out audio notifications
+    Save,
+    /// Only update the status less often
+    FewerWakeups,
+    /// Behave the same as when plugged in
+    Ignore,
+}
+
+impl Display for BatteryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatteryPolicy::Save => f.write_str("save"),
+            BatteryPolicy::FewerWakeups => f.write_str("fewer-wakeups"),
+            BatteryPolicy::Ignore => f.write_str("ignore"),
+        }
+    }
+}
+
+/// Applies the policy, the power state is read at most every `VALID` as
+/// reading it on every wake up would defeat the purpose
+pub(crate) struct Battery {
+    policy: BatteryPolicy,
+    checked: Cell<Option<(Instant, bool)>>,
+}
+
+impl Battery {
+    const VALID: Duration = Duration::from_secs(60);
+    /// The status is not updated more often than this on battery
+    pub(super) const MIN_WAKE: Duration = Duration::from_secs(15);
+
+    pub(crate) fn new(policy: BatteryPolicy) -> Self {
+        Self {
+            policy,
+            checked: Cell::new(None),
+        }
+    }
+
+    fn on_battery(&self) -> bool {
+        if self.policy == BatteryPolicy::Ignore {
+            return false;
+        }
+        match self.checked.get() {
+            Some((at, on_battery)) if at.elapsed() < Self::VALID => on_battery,
+            _ => {
+                let on_battery = discharging(Path::new(POWER_SUPPLY));
+                self.checked.set(Some((Instant::now(), on_battery)));
+                on_battery
+            }
+        }
+    }
+
+    pub(super) fn mute_audio(&self) -> bool {
+        self.policy == BatteryPolicy::Save && self.on_battery()
+    }
+
+    pub(super) fn fewer_wakeups(&self) -> bool {
+        self.on_battery()
+    }
+}
+
+/// True if there is a battery and no mains supply is online. Machines
+/// without batteries, like desktops, are never on battery. Batteries of
+/// devices, like a wireless mouse, have scope `Device` and do not count.
+fn discharging(dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+    let mut battery = false;
+    for entry in entries.filter_map(Result::ok) {
+        let read = |attribute: &str| {
+            fs::read_to_string(entry.path().join(attribute))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        match read("type").as_str() {
+            "Mains" if read("online") == "1" => return false,
+            "Battery" if read("scope") != "Device" => battery = true,
+            _ => (),
+        }
+    }
+    battery
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn on_battery_without_mains_online() {
+        let dir = std::env::temp_dir().join(format!("power-supply-{}", std::process::id()));
+        let supply = |name: &str, type_: &str, online: Option<&str>| {
+            let path = dir.join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("type"), format!("{type_}\n")).unwrap();
+            if let Some(online) = online {
+                fs::write(path.join("online"), format!("{online}\n")).unwrap();
+            }
+        };
+
+        assert!(!discharging(&dir), "no power supplies, a desktop");
+        supply("hidpp_battery_0", "Battery", None);
+        fs::write(dir.join("hidpp_battery_0/scope"), "Device\n").unwrap();
+        assert!(!discharging(&dir), "only the battery of a mouse");
+        supply("AC", "Mains", Some("1"));
+        assert!(!discharging(&dir), "no battery");
+        supply("BAT0", "Battery", None);
+        assert!(!discharging(&dir), "plugged in");
+        supply("AC", "Mains", Some("0"));
+        assert!(discharging(&dir));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+//! Sends finished work periods, breaks and pauses to a time tracker, set up
+//! in the toml config, see `config::Settings`. Only ActivityWatch is
+//! supported, its api is plain http on loopback.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use super::History;
+use crate::stats::{self, Period};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ActivityWatch {
+    /// where aw-server listens
+    #[serde(default = "default_address")]
+    pub(crate) address: SocketAddr,
+    /// created if it does not exist yet
+    #[serde(default = "default_bucket")]
+    pub(crate) bucket: String,
+}
+
+fn default_address() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, 5600))
+}
+
+fn default_bucket() -> String {
+    env!("CARGO_PKG_NAME").to_string()
+}
+
+/// Exports from a thread of its own so a slow time tracker does not hold up
+/// the status
+pub(crate) struct Exporter {
+    tx: mpsc::Sender<Period>,
+    /// start of the period exported last
+    last: Option<SystemTime>,
+}
+
+impl Exporter {
+    pub(crate) fn start(activitywatch: ActivityWatch) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || export(&activitywatch, &rx));
+        Self { tx, last: None }
+    }
+
+    /// Call on every state change, exports the period it ended
+    pub(crate) fn state_changed(&mut self, history: &History) {
+        let Some(period) = stats::periods(&history.recent(), None).pop() else {
+            return;
+        };
+        // ending a wait for activity does not end a period
+        if self.last == Some(period.start) {
+            return;
+        }
+        self.last = Some(period.start);
+        self.tx
+            .send(period)
+            .expect("the export thread runs as long as the sender exists");
+    }
+}
+
+fn export(activitywatch: &ActivityWatch, rx: &mpsc::Receiver<Period>) {
+    info!(
+        "Exporting to ActivityWatch at {} in bucket {}",
+        activitywatch.address, activitywatch.bucket
+    );
+    let mut bucket_exists = false;
+    for period in rx {
+        if !bucket_exists {
+            match activitywatch.create_bucket() {
+                Ok(()) => bucket_exists = true,
+                Err(report) => {
+                    warn!("Could not create ActivityWatch bucket: {report:#}");
+                    continue;
+                }
+            }
+        }
+        if let Err(report) = activitywatch.insert(&period) {
+            warn!(
+                "Could not export {} period to ActivityWatch: {report:#}",
+                period.kind.name()
+            );
+        }
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+impl ActivityWatch {
+    fn create_bucket(&self) -> Result<()> {
+        let body = json!({
+            "client": env!("CARGO_PKG_NAME"),
+            "type": concat!(env!("CARGO_PKG_NAME"), ".period"),
+            "hostname": hostname(),
+        });
+        post(
+            self.address,
+            &format!("/api/0/buckets/{}", self.bucket),
+            &body.to_string(),
+        )
+    }
+
+    fn insert(&self, period: &Period) -> Result<()> {
+        let body = json!([{
+            "timestamp": DateTime::<Utc>::from(period.start).to_rfc3339(),
+            "duration": period.duration().as_secs_f64(),
+            "data": {
+                "kind": period.kind.name(),
+                "completed": period.completed,
+                "planned_seconds": period.planned.as_secs(),
+            },
+        }]);
+        post(
+            self.address,
+            &format!("/api/0/buckets/{}/events", self.bucket),
+            &body.to_string(),
+        )
+    }
+}
+
+/// Fails unless the response is a success or not modified (bucket exists)
+fn post(address: SocketAddr, path: &str, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT).wrap_err("Could not connect")?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .wrap_err("Could not set read timeout")?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {address}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .wrap_err("Could not send request")?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .wrap_err("Could not read response")?;
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') || code == "304" => Ok(()),
+        _ => Err(eyre!("Unexpected response: {}", status_line.trim())),
+    }
+}
@@ -1,9 +1,10 @@
 use std::fs::{self, OpenOptions};
-use std::io::{ErrorKind, Seek, Write};
+use std::io::{Seek, Write};
 use std::iter;
+use std::path::Path;
 
 use color_eyre::eyre::Context;
-use color_eyre::Result;
+use color_eyre::{Result, Section};
 
 pub struct FileStatus {
     max_len: usize,
@@ -11,21 +12,17 @@ pub struct FileStatus {
 }
 
 impl FileStatus {
-    pub fn new() -> Result<Self> {
+    pub fn new(path: &Path) -> Result<Self> {
         // use std::os::unix::fs::OpenOptionsExt;
-        match std::fs::create_dir("/var/run/break_enforcer") {
-            Ok(()) => (),
-            Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
-            err @ Err(_) => err.wrap_err("Could not create directory for integration file")?,
-        }
         // let owner_write_rest_read = 0o422;
         let file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
             // .mode(owner_write_rest_read)
-            .open("/var/run/break_enforcer/status.txt")
-            .wrap_err("Could not create integration file")?;
+            .open(path)
+            .wrap_err("Could not create integration file")
+            .with_note(|| format!("path: {}", path.display()))?;
 
         Ok(Self { file, max_len: 0 })
     }
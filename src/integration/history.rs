@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use break_enforcer::StateUpdate;
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use tracing::warn;
+
+/// more then a day of work and breaks
+const KEEP_IN_MEMORY: usize = 256;
+/// Months of transitions. Once the history file is this large it is moved to
+/// `<name>.1`, replacing the one before, and a new file is started.
+const ROTATE_AT: u64 = 4 * 1024 * 1024;
+
+/// The recent state transitions. Optionally all transitions are also appended
+/// to a file as json lines, see `ROTATE_AT`.
+#[derive(Clone)]
+pub(crate) struct History {
+    recent: Arc<Mutex<VecDeque<StateUpdate>>>,
+    file: Option<Arc<Mutex<File>>>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub(crate) fn new(persist_to: Option<&Path>) -> Result<Self> {
+        let file = persist_to.map(open).transpose()?;
+        Ok(Self {
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(KEEP_IN_MEMORY))),
+            file: file.map(|f| Arc::new(Mutex::new(f))),
+            path: persist_to.map(Path::to_path_buf),
+        })
+    }
+
+    pub(crate) fn record(&self, update: &StateUpdate) {
+        let mut recent = self
+            .recent
+            .lock()
+            .expect("nothing can panic with lock held");
+        if recent.len() == KEEP_IN_MEMORY {
+            recent.pop_front();
+        }
+        recent.push_back(update.clone());
+
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut line = serde_json::to_string(update).expect("state updates serialize");
+        line.push('\n');
+        let mut file = file.lock().expect("nothing can panic with lock held");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("Could not write to history file: {e}");
+        }
+        let full = file.metadata().is_ok_and(|meta| meta.len() >= ROTATE_AT);
+        if let (true, Some(path)) = (full, &self.path) {
+            match rotate(path) {
+                Ok(new) => *file = new,
+                Err(report) => warn!("Could not rotate the history file: {report:#}"),
+            }
+        }
+    }
+
+    /// Oldest first
+    pub(crate) fn recent(&self) -> Vec<StateUpdate> {
+        let recent = self
+            .recent
+            .lock()
+            .expect("nothing can panic with lock held");
+        recent.iter().cloned().collect()
+    }
+
+    /// Oldest first, from the history file if persisted. A service running
+    /// for days forgets the start of today otherwise.
+    pub(crate) fn all(&self) -> Vec<StateUpdate> {
+        let Some(path) = &self.path else {
+            return self.recent();
+        };
+        read_persisted(path).unwrap_or_else(|report| {
+            warn!("Falling back to the recent history: {report}");
+            self.recent()
+        })
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err("Could not open history file")
+        .with_note(|| format!("path: {}", path.display()))
+}
+
+fn rotated(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+fn rotate(path: &Path) -> Result<File> {
+    fs::rename(path, rotated(path))
+        .wrap_err("Could not move the history file")
+        .with_note(|| format!("path: {}", path.display()))?;
+    open(path)
+}
+
+/// Reads the history written by a daemon that persisted it, oldest first.
+/// Includes the rotated file, see `ROTATE_AT`.
+pub(crate) fn read_persisted(path: &Path) -> Result<Vec<StateUpdate>> {
+    let rotated = rotated(path);
+    let mut updates = if rotated.exists() {
+        read_file(&rotated)?
+    } else {
+        Vec::new()
+    };
+    updates.extend(read_file(path)?);
+    Ok(updates)
+}
+
+fn read_file(path: &Path) -> Result<Vec<StateUpdate>> {
+    let file = File::open(path)
+        .wrap_err("Could not open history file")
+        .with_note(|| format!("path: {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .map(|line| {
+            let line = line.wrap_err("Could not read history file")?;
+            serde_json::from_str(&line)
+                .wrap_err("Could not parse history entry")
+                .with_note(|| format!("entry: {line}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use break_enforcer::Timestamp;
+
+    use super::*;
+
+    #[test]
+    fn all_reads_the_persisted_and_rotated_history() {
+        let dir = std::env::temp_dir().join(format!("history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.jsonl");
+        let update = |secs| StateUpdate::Waiting {
+            at: Timestamp {
+                monotonic: Duration::from_secs(secs),
+                wall_clock: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            },
+            reset: None,
+        };
+
+        History::new(Some(&path)).unwrap().record(&update(1));
+        let history = History::new(Some(&path)).unwrap();
+        history.record(&update(2));
+        assert_eq!(history.recent().len(), 1);
+        assert_eq!(history.all().len(), 2);
+
+        *history.file.as_ref().unwrap().lock().unwrap() = rotate(&path).unwrap();
+        history.record(&update(3));
+        assert!(rotated(&path).exists());
+        assert_eq!(read_file(&path).unwrap().len(), 1);
+        assert_eq!(history.all().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
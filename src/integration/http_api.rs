@@ -0,0 +1,189 @@
+//! Minimal http server next to the tcp api so dashboards and Stream Deck
+//! plugins do not need to speak its framing. Serves:
+//! - `GET /status`: json with the status message, idle time, state and what
+//!   is left of the daily limit
+//! - `GET /events`: server sent events, one json state update per event
+//! - `POST /pause?seconds=<n>`, `/resume`, `/skip`, `/snooze` and `/unlock`
+//!
+//! Only listens on loopback. Browsers send an `Origin` header with every
+//! cross site request, POSTs that have one are refused so a web page can not
+//! pause breaks.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use super::tcp_api::{Status, LIVENESS_CHECK, WRITE_TIMEOUT};
+use crate::control::Control;
+use break_enforcer::ErrorCode;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Route<'a> {
+    Status,
+    Events,
+    /// the name and argument as the tcp api takes them
+    Control(&'a str, &'a str),
+    NotFound,
+    MethodNotAllowed,
+}
+
+fn route<'a>(method: &str, target: &'a str) -> Route<'a> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let seconds = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("seconds="))
+        .unwrap_or("");
+    match (method, path) {
+        ("GET", "/status") => Route::Status,
+        ("GET", "/events") => Route::Events,
+        ("POST", "/pause" | "/resume" | "/skip" | "/snooze" | "/unlock") => {
+            Route::Control(&path[1..], seconds)
+        }
+        (_, "/status" | "/events" | "/pause" | "/resume" | "/skip" | "/snooze" | "/unlock") => {
+            Route::MethodNotAllowed
+        }
+        _ => Route::NotFound,
+    }
+}
+
+pub(crate) fn maintain(status: Status, port: u16) -> Result<()> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let listener = TcpListener::bind(addr)
+        .wrap_err("Could not start listening")
+        .with_note(|| format!("address: {addr}"))?;
+    info!("Serving http on {addr}");
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed incoming http connection: {e}");
+                continue;
+            }
+        };
+        let status = status.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_conn(stream, &status) {
+                debug!("http client disconnected: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn respond(stream: &mut TcpStream, code: &str, content_type: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\n\
+        Content-Type: {content_type}\r\n\
+        Content-Length: {}\r\n\
+        Access-Control-Allow-Origin: *\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .wrap_err("Could not write response")
+}
+
+fn handle_conn(mut stream: TcpStream, status: &Status) -> Result<()> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut has_origin = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        has_origin |= header.to_ascii_lowercase().starts_with("origin:");
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    debug!("http request: {method} {target}");
+    match route(method, target) {
+        Route::Status => {
+            let body = json!({
+                "status": status.msg(),
+                "idle_seconds": status.idle_since().parse::<u64>().ok(),
+                "state": status.current(),
+                "progress": status.fraction_done(),
+                "daily_budget_seconds": status.daily_budget(),
+            });
+            respond(&mut stream, "200 OK", "application/json", &body.to_string())
+        }
+        Route::Events => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/event-stream\r\n\
+                Cache-Control: no-cache\r\n\
+                Access-Control-Allow-Origin: *\r\n\r\n"
+            )?;
+            let subscription = status.subscribe();
+            loop {
+                match subscription.recv_timeout(LIVENESS_CHECK) {
+                    Ok(update) => {
+                        let update = serde_json::to_string(&update)?;
+                        write!(stream, "data: {update}\n\n").wrap_err("Could not send event")?;
+                        subscription.written();
+                    }
+                    // a comment, fails once the client is gone
+                    Err(RecvTimeoutError::Timeout) => {
+                        write!(stream, ": alive\n\n").wrap_err("Could not send keep alive")?;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+        Route::Control(..) if has_origin => respond(
+            &mut stream,
+            "403 Forbidden",
+            "text/plain",
+            "Controls can not be used from a web page",
+        ),
+        Route::Control(request, argument) => {
+            let result = Control::parse(request, argument)
+                .expect("routes are controls")
+                .map_err(|msg| (ErrorCode::InvalidArgument, msg))
+                .and_then(|control| status.control(control));
+            match result {
+                Ok(_) => respond(&mut stream, "204 No Content", "text/plain", ""),
+                Err((ErrorCode::InvalidArgument, msg)) => {
+                    respond(&mut stream, "400 Bad Request", "text/plain", &msg)
+                }
+                Err((_, msg)) => respond(&mut stream, "409 Conflict", "text/plain", &msg),
+            }
+        }
+        Route::NotFound => respond(&mut stream, "404 Not Found", "text/plain", "Not found"),
+        Route::MethodNotAllowed => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn routes_requests() {
+        assert_eq!(route("GET", "/status"), Route::Status);
+        assert_eq!(route("GET", "/events"), Route::Events);
+        assert_eq!(
+            route("POST", "/pause?seconds=600"),
+            Route::Control("pause", "600")
+        );
+        assert_eq!(route("POST", "/skip"), Route::Control("skip", ""));
+        assert_eq!(route("GET", "/pause"), Route::MethodNotAllowed);
+        assert_eq!(route("POST", "/reexec"), Route::NotFound);
+    }
+}
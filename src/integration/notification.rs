@@ -1,16 +1,16 @@
 use std::io::Write;
-use std::process::{Command, Stdio};
+use std::process::{Command, Output, Stdio};
 
 use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
 
-struct User {
-    id: String,
-    name: String,
+pub(crate) struct User {
+    pub(crate) id: String,
+    pub(crate) name: String,
 }
 
 /// on the first failure this returns
-fn all_users() -> Result<Vec<User>> {
+pub(crate) fn all_users() -> Result<Vec<User>> {
     let users = Command::new("loginctl")
         .output()
         .wrap_err("could not run loginctl")?
@@ -37,14 +37,15 @@ fn all_users() -> Result<Vec<User>> {
 
 pub(crate) fn beep_all_users() -> Result<()> {
     fn beep(name: String, id: String) -> Result<()> {
-        let sound1 = include_bytes!("../../assets/new-notification-on-your-device-by-UNIVERSFIELD.wav");
-        let command = format!("sudo -u {name} XDG_RUNTIME_DIR=/run/user/{id} aplay");
-        let mut aplay = Command::new("sh")
-            .arg("-c")
-            .arg(command)
+        let sound1 =
+            include_bytes!("../../assets/new-notification-on-your-device-by-UNIVERSFIELD.wav");
+        let mut aplay = Command::new("sudo")
+            .args(["-u", &name])
+            .arg(format!("XDG_RUNTIME_DIR=/run/user/{id}"))
+            .arg("aplay")
             .stdin(Stdio::piped())
             .spawn()
-            .wrap_err("Could not spawn shell")
+            .wrap_err("Could not spawn aplay")
             .with_note(|| format!("as user: {id}:{name}"))?;
         let stdin = aplay.stdin.as_mut().expect("is set to piped");
         stdin
@@ -54,6 +55,19 @@ pub(crate) fn beep_all_users() -> Result<()> {
         Ok(())
     }
 
+    if let Some(res) = crate::privsep::beep() {
+        return res;
+    }
+    if let sudo::RunningAs::User = sudo::check() {
+        // running as a user service, we can only reach our own session
+        let _ = std::thread::spawn(|| {
+            if let Err(report) = beep_own_session().wrap_err("beep failed") {
+                eprintln!("{report:?}");
+            }
+        });
+        return Ok(());
+    }
+
     for User { id, name } in all_users().wrap_err("Could not get logged in users")? {
         let _ = std::thread::spawn(|| {
             if let Err(report) = beep(name, id).wrap_err("beep failed") {
@@ -65,6 +79,20 @@ pub(crate) fn beep_all_users() -> Result<()> {
     Ok(())
 }
 
+fn beep_own_session() -> Result<()> {
+    let sound1 = include_bytes!("../../assets/new-notification-on-your-device-by-UNIVERSFIELD.wav");
+    let mut aplay = Command::new("aplay")
+        .stdin(Stdio::piped())
+        .spawn()
+        .wrap_err("Could not spawn aplay")?;
+    let stdin = aplay.stdin.as_mut().expect("is set to piped");
+    stdin
+        .write_all(sound1)
+        .wrap_err("Could not pipe to aplay")?;
+    aplay.wait().wrap_err("Could not wait for command to end")?;
+    Ok(())
+}
+
 pub(crate) fn command_available(
     cmd: &str,
     expected_output: &str,
@@ -95,18 +123,53 @@ pub(crate) fn beep_available() -> color_eyre::Result<()> {
     )
 }
 
+/// Errors unless at least one logged in user got the notification
 pub(crate) fn notify(text: &str) -> Result<()> {
+    if let Some(res) = crate::privsep::notify(text) {
+        return res;
+    }
+    if let sudo::RunningAs::User = sudo::check() {
+        // running as a user service, we can only reach our own session
+        let output = Command::new("notify-send")
+            .args(["-t", "5000", "--", text])
+            .output()
+            .wrap_err("Could not run notify-send")?;
+        return delivered(&output);
+    }
+
+    let mut result = Err(eyre!("No logged in user to notify"));
     for User { id, name } in all_users().wrap_err("Could not get logged in users")? {
-        let command = format!("sudo -u {name} DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{id}/bus notify-send -t 5000 \"{text}\"");
-        Command::new("sh")
-            .arg("-c")
-            .arg(command)
+        let output = notify_send_as(&name, &id, text)
             .output()
             .wrap_err("Could not run notify-send")
             .with_note(|| format!("as user: {id}:{name}"))?;
+        if result.is_err() {
+            result = delivered(&output).with_note(|| format!("as user: {id}:{name}"));
+        }
     }
 
-    Ok(())
+    result
+}
+
+/// The text is passed as a single argument, never through a shell, as it
+/// can contain device names which anyone plugging in a device can pick.
+pub(crate) fn notify_send_as(name: &str, id: &str, text: &str) -> Command {
+    let mut command = Command::new("sudo");
+    command
+        .args(["-u", name])
+        .arg(format!(
+            "DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{id}/bus"
+        ))
+        .args(["notify-send", "-t", "5000", "--", text]);
+    command
+}
+
+fn delivered(output: &Output) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(eyre!("notify-send failed ({})", output.status))
+        .with_note(|| String::from_utf8_lossy(&output.stderr).trim().to_string())
 }
 
 pub(crate) fn notify_available() -> color_eyre::Result<()> {
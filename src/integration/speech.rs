@@ -0,0 +1,182 @@
+//! Spoken announcements through speech-dispatcher, see `--announce`. Unlike
+//! the audio beep they say what is about to happen, so they also work for
+//! those who can not see the notifications.
+
+use std::fmt::Display;
+use std::process::{Command, Output};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+
+use super::notification::{all_users, command_available, User};
+use super::{DurationUntil, State};
+
+/// What to announce, each level includes the ones before it
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Verbosity {
+    /// The start, extension and end of breaks
+    Breaks,
+    /// Also the lock warning and the daily limit warnings
+    Warnings,
+    /// Also work starting, pauses, meetings and going idle
+    All,
+}
+
+impl Display for Verbosity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verbosity::Breaks => f.write_str("breaks"),
+            Verbosity::Warnings => f.write_str("warnings"),
+            Verbosity::All => f.write_str("all"),
+        }
+    }
+}
+
+/// Errors unless at least one logged in user heard it
+pub(crate) fn say(text: &str) -> Result<()> {
+    if let Some(res) = crate::privsep::say(text) {
+        return res;
+    }
+    if let sudo::RunningAs::User = sudo::check() {
+        // running as a user service, we can only reach our own session
+        let output = Command::new("spd-say")
+            .args(["-P", "message", "--", text])
+            .output()
+            .wrap_err("Could not run spd-say")?;
+        return spoken(&output);
+    }
+
+    let mut result = Err(eyre!("No logged in user to speak to"));
+    for User { id, name } in all_users().wrap_err("Could not get logged in users")? {
+        let output = Command::new("sudo")
+            .args(["-u", &name])
+            .arg(format!("XDG_RUNTIME_DIR=/run/user/{id}"))
+            .args(["spd-say", "-P", "message", "--", text])
+            .output()
+            .wrap_err("Could not run spd-say")
+            .with_note(|| format!("as user: {id}:{name}"))?;
+        if result.is_err() {
+            result = spoken(&output).with_note(|| format!("as user: {id}:{name}"));
+        }
+    }
+
+    result
+}
+
+fn spoken(output: &Output) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(eyre!("spd-say failed ({})", output.status))
+        .with_note(|| String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+pub(crate) fn check_available() -> Result<()> {
+    command_available(
+        "spd-say",
+        "spd-say",
+        "provided by the package speech-dispatcher",
+    )
+}
+
+/// What to say now that the state changed, `None` if nothing at this
+/// verbosity
+pub(super) fn announcement(
+    state: &State,
+    was_break: bool,
+    extended: bool,
+    verbosity: Verbosity,
+) -> Option<String> {
+    let text = match *state {
+        State::Break { next_work, .. } if extended => format!(
+            "Break extended, {} left",
+            fmt_spoken(next_work.duration_until())
+        ),
+        State::Break { next_work, .. } => format!(
+            "Break started, it lasts {}",
+            fmt_spoken(next_work.duration_until())
+        ),
+        _ if was_break => "Break over".to_string(),
+        _ if verbosity < Verbosity::All => return None,
+        State::Work { next_break } => format!(
+            "Work started, next break in {}",
+            fmt_spoken(next_break.duration_until())
+        ),
+        State::Waiting { .. } => "Idle, waiting for input".to_string(),
+        State::Paused { until } => format!("Paused for {}", fmt_spoken(until.duration_until())),
+        State::Meeting { until, .. } => format!(
+            "Meeting, breaks wait for {}",
+            fmt_spoken(until.duration_until())
+        ),
+    };
+    Some(text)
+}
+
+/// Whole minutes from a minute on, `fmt_dur` is not made to be read aloud
+pub(super) fn fmt_spoken(dur: Duration) -> String {
+    fn plural(n: u64, unit: &str) -> String {
+        if n == 1 {
+            format!("1 {unit}")
+        } else {
+            format!("{n} {unit}s")
+        }
+    }
+
+    let seconds = dur.as_secs_f32().round() as u64;
+    if seconds < 60 {
+        return plural(seconds, "second");
+    }
+    let minutes = (seconds + 30) / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => plural(minutes, "minute"),
+        (hours, 0) => plural(hours, "hour"),
+        (hours, minutes) => format!(
+            "{} and {}",
+            plural(hours, "hour"),
+            plural(minutes, "minute")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use break_enforcer::BreakKind;
+
+    use super::*;
+
+    #[test]
+    fn durations_read_aloud() {
+        assert_eq!(fmt_spoken(Duration::from_secs(1)), "1 second");
+        assert_eq!(fmt_spoken(Duration::from_secs(45)), "45 seconds");
+        assert_eq!(fmt_spoken(Duration::from_millis(299_900)), "5 minutes");
+        assert_eq!(fmt_spoken(Duration::from_secs(3600)), "1 hour");
+        assert_eq!(fmt_spoken(Duration::from_secs(3660)), "1 hour and 1 minute");
+    }
+
+    #[test]
+    fn verbosity_limits_announcements() {
+        let break_ = State::Break {
+            next_work: Instant::now() + Duration::from_secs(300),
+            kind: BreakKind::Short,
+        };
+        let work = State::Work {
+            next_break: Instant::now() + Duration::from_secs(1500),
+        };
+        assert_eq!(
+            announcement(&break_, false, false, Verbosity::Breaks).as_deref(),
+            Some("Break started, it lasts 5 minutes")
+        );
+        assert_eq!(
+            announcement(&work, true, false, Verbosity::Breaks).as_deref(),
+            Some("Break over")
+        );
+        assert_eq!(announcement(&work, false, false, Verbosity::Warnings), None);
+        assert_eq!(
+            announcement(&work, false, false, Verbosity::All).as_deref(),
+            Some("Work started, next break in 25 minutes")
+        );
+    }
+}
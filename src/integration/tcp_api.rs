@@ -1,27 +1,378 @@
-/// Simple ascii protocol over tcp, uses 0 bytes as packet framing
-use std::io::{BufReader, ErrorKind, Write};
-use std::net::{SocketAddr, TcpListener};
-use std::sync::{Arc, Mutex};
+/// Simple ascii protocol over tcp, uses 0 bytes as packet framing. On linux
+/// the same protocol is also served on an abstract unix socket. Optionally
+/// it is served over tls on a network address, see `Remote`. Sockets passed
+/// in through socket activation replace both, see `Inherited`.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
+use break_enforcer::proto::{self, ABSTRACT_SOCKET, NAME, PORTS, PROTOCOL_VERSION, STOP_BYTE};
+use break_enforcer::{ErrorCode, Health, StateUpdate};
 use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
-use tracing::{debug, warn};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::socket::{getsockname, recv, AddressFamily, MsgFlags, SockaddrLike, SockaddrStorage};
+use nix::time::{clock_gettime, ClockId};
+use rustls::{ServerConfig, ServerConnection};
+use tracing::{debug, info, warn};
 
-use crate::tcp_api_config::{PORTS, STOP_BYTE};
+use super::{now, Activities, Backlog, History, State};
+use crate::activity_map;
+use crate::check_inputs::{Event, DROPPED_ACTIVITY, DROPPED_INPUT_EVENTS};
+use crate::clock::{Clock, SystemClock};
+use crate::config::BlockList;
+use crate::control::Control;
+use crate::daily_limit::DailyLimit;
+use crate::paths;
+use crate::shared_stream::SharedStream;
+use crate::supervisor::{self, Listener, Subsystem};
+use crate::sync;
+use crate::watch_and_block::{InputId, OnlineDevices};
 
-#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// listen only on this port
+    pub(crate) port: Option<u16>,
+    /// how often subscribers get a progress update during a break
+    pub(crate) progress_interval: Duration,
+    /// also serve the api over the network
+    pub(crate) remote: Option<Remote>,
+    /// also serve the api over http on this loopback port
+    #[cfg(feature = "http")]
+    pub(crate) http_port: Option<u16>,
+    /// served as the `activity_map` request
+    pub(crate) activity_map: activity_map::Recorder,
+    /// when not empty served instead of the loopback ports and abstract
+    /// socket
+    pub(crate) inherited: Inherited,
+}
+
+/// Listening sockets passed in by systemd socket activation, or a container
+/// runtime doing the same (see `sd_listen_fds(3)`). That way the api can be
+/// reached from outside a container or Flatpak without it binding anything.
+/// Input devices can be passed in the same way, named after their node (for
+/// example `event3`) in `LISTEN_FDNAMES`.
+#[derive(Default)]
+pub(crate) struct Inherited {
+    unix: Vec<UnixListener>,
+    tcp: Vec<TcpListener>,
+    devices: HashMap<PathBuf, OwnedFd>,
+}
+
+impl Inherited {
+    /// The first passed socket
+    const FIRST_FD: RawFd = 3;
+
+    /// Call before starting any thread. The environment is cleared so a
+    /// reexec does not take over sockets that are closed by then.
+    pub(crate) fn take() -> Self {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(var);
+        }
+        // set by whatever started our parent
+        if pid.and_then(|pid| pid.parse().ok()) != Some(std::process::id()) {
+            return Self::default();
+        }
+        let Some(count) = fds.and_then(|fds| fds.parse::<RawFd>().ok()) else {
+            return Self::default();
+        };
+
+        let mut inherited = Self::default();
+        let mut names = names.split(':');
+        for fd in Self::FIRST_FD..Self::FIRST_FD.saturating_add(count) {
+            let name = names.next().unwrap_or_default();
+            // SAFETY: passed to us on purpose and nothing else owns it
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            // otherwise notification commands we spawn would inherit it
+            if let Err(e) = fcntl(&fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+                warn!("Could not use passed in socket {}: {e}", fd.as_raw_fd());
+                continue;
+            }
+            let fd = match inherited.take_device(fd, name) {
+                Ok(()) => continue,
+                Err(fd) => fd,
+            };
+            let family = getsockname::<SockaddrStorage>(fd.as_raw_fd())
+                .ok()
+                .and_then(|addr| addr.family());
+            match family {
+                Some(AddressFamily::Unix) => inherited.unix.push(fd.into()),
+                Some(AddressFamily::Inet | AddressFamily::Inet6) => inherited.tcp.push(fd.into()),
+                other => warn!(
+                    "Ignoring passed in socket {} of family {other:?}",
+                    fd.as_raw_fd()
+                ),
+            }
+        }
+        if !inherited.is_empty() {
+            info!(
+                "Serving the api on {} passed in socket(s)",
+                inherited.unix.len() + inherited.tcp.len()
+            );
+        }
+        if !inherited.devices.is_empty() {
+            info!(
+                "Using {} passed in input device(s)",
+                inherited.devices.len()
+            );
+        }
+        inherited
+    }
+
+    /// Hands the fd back if it is not a character device
+    fn take_device(&mut self, fd: OwnedFd, name: &str) -> Result<(), OwnedFd> {
+        let file = File::from(fd);
+        if !file
+            .metadata()
+            .is_ok_and(|meta| meta.file_type().is_char_device())
+        {
+            return Err(file.into());
+        }
+        if !name.starts_with("event") || name.contains('/') {
+            warn!(
+                "Ignoring passed in device {} named {name:?}, name it after its node like \
+                 `event3`",
+                file.as_raw_fd()
+            );
+            return Ok(());
+        }
+        self.devices
+            .insert(paths::dev_dir().join(name), file.into());
+        Ok(())
+    }
+
+    /// The passed in input devices, by the path they are known as
+    pub(crate) fn take_devices(&mut self) -> HashMap<PathBuf, OwnedFd> {
+        std::mem::take(&mut self.devices)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.unix.is_empty() && self.tcp.is_empty()
+    }
+}
+
+/// The api served over tls on a network address. Clients have to send
+/// `auth <token>` before any request other than `hello`.
+pub(crate) struct Remote {
+    listener: TcpListener,
+    tls: Arc<ServerConfig>,
+    token: Arc<str>,
+}
+
+impl Remote {
+    /// Binds the address right away so a taken address fails the start
+    pub(crate) fn set_up(
+        listen: SocketAddr,
+        cert: &Path,
+        key: &Path,
+        token: &Path,
+    ) -> Result<Self> {
+        let token = std::fs::read_to_string(token)
+            .wrap_err("Could not read the api token")
+            .with_note(|| format!("path: {}", token.display()))?;
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(eyre!("The api token file is empty"));
+        }
+        let tls =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .wrap_err("Could not set up tls")?
+                .with_no_client_auth()
+                .with_single_cert(sync::load_certs(cert)?, sync::load_key(key)?)
+                .wrap_err("Certificate and key do not form a valid pair")?;
+        let listener = TcpListener::bind(listen)
+            .wrap_err("Could not listen for api clients on the network")
+            .with_note(|| format!("address: {listen}"))?;
+        info!("Serving the api on {listen}");
+        Ok(Self {
+            listener,
+            tls: Arc::new(tls),
+            token: token.into(),
+        })
+    }
+}
+
+/// Takes as long for any wrong token
+fn token_matches(expected: &str, got: &str) -> bool {
+    expected.len() == got.len()
+        && expected
+            .bytes()
+            .zip(got.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Clone)]
 pub(crate) struct Status {
     msg: Arc<Mutex<String>>,
+    /// and when its period started on `clock`
+    state: Arc<Mutex<(State, Instant)>>,
+    subscribers: Arc<Mutex<Subscribers>>,
     idle: Arc<Mutex<Instant>>,
+    online_devices: OnlineDevices,
+    block_list: BlockList,
+    history: History,
+    controls: mpsc::Sender<Event>,
+    activities: Option<Arc<Activities>>,
+    /// suggested for the current break
+    activity: Arc<Mutex<Option<String>>>,
+    daily_limit: Option<DailyLimit>,
+    activity_map: activity_map::Recorder,
+    integrator: Backlog,
+    /// the one the schedule runs on, period deadlines are on it
+    clock: Arc<dyn Clock>,
+}
+
+/// How long the run loop gets to answer a control
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most breaks `schedule_preview` plans ahead
+const MAX_PREVIEW: usize = 50;
+/// Most api clients subscribed at once, beyond it the one that went longest
+/// without an update is dropped
+const MAX_SUBSCRIBERS: usize = 32;
+/// A client that does not take a response or update within this is
+/// dropped, it stopped reading
+pub(super) const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// A network client has to authenticate within this, tls handshake included
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Most network clients connected at once, more are refused
+const MAX_REMOTE_CLIENTS: usize = 16;
+/// Longer requests close the connection, the longest real one is well below
+const MAX_REQUEST: usize = 4096;
+/// How often a subscriber that got no updates is checked for having
+/// disconnected
+pub(super) const LIVENESS_CHECK: Duration = Duration::from_secs(30);
+
+struct Subscriber {
+    id: u64,
+    tx: mpsc::Sender<StateUpdate>,
+    /// when the client last took an update, see `Subscription::written`
+    last_written: Instant,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    list: Vec<Subscriber>,
+}
+
+impl Subscribers {
+    fn add(&mut self, tx: mpsc::Sender<StateUpdate>) -> u64 {
+        if self.list.len() >= MAX_SUBSCRIBERS {
+            let (stalest, _) = self
+                .list
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, subscriber)| subscriber.last_written)
+                .expect("list is full");
+            // its handler stops once the sender is gone
+            self.list.swap_remove(stalest);
+            info!("Too many api subscribers, dropped the one that went longest without an update");
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push(Subscriber {
+            id,
+            tx,
+            last_written: Instant::now(),
+        });
+        id
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.list.retain(|subscriber| subscriber.id != id);
+    }
+
+    fn written(&mut self, id: u64) {
+        if let Some(subscriber) = self.list.iter_mut().find(|s| s.id == id) {
+            subscriber.last_written = Instant::now();
+        }
+    }
+
+    fn send(&mut self, update: &StateUpdate) {
+        // drops the subscribers that disconnected
+        self.list
+            .retain(|subscriber| subscriber.tx.send(update.clone()).is_ok());
+    }
+}
+
+/// Updates for one api client, unsubscribes once dropped
+pub(super) struct Subscription {
+    id: u64,
+    updates: mpsc::Receiver<StateUpdate>,
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl Subscription {
+    /// Fails with `Disconnected` if the subscriber was dropped to make room
+    /// for a new one
+    pub(super) fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<StateUpdate, mpsc::RecvTimeoutError> {
+        self.updates.recv_timeout(timeout)
+    }
+
+    /// The client took an update, it is dropped last to make room
+    pub(super) fn written(&self) {
+        self.subscribers
+            .lock()
+            .expect("nothing can panic with lock held")
+            .written(self.id);
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.subscribers
+            .lock()
+            .expect("nothing can panic with lock held")
+            .remove(self.id);
+    }
 }
 
 impl Status {
-    pub fn new(idle: Arc<Mutex<Instant>>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        idle: Arc<Mutex<Instant>>,
+        online_devices: OnlineDevices,
+        block_list: BlockList,
+        history: History,
+        controls: mpsc::Sender<Event>,
+        activities: Option<Arc<Activities>>,
+        daily_limit: Option<DailyLimit>,
+        activity_map: activity_map::Recorder,
+        integrator: Backlog,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         Self {
             msg: Arc::new(Mutex::new(String::new())),
+            state: Arc::new(Mutex::new((State::Waiting { reset: None }, clock.now()))),
+            subscribers: Arc::new(Mutex::new(Subscribers::default())),
             idle,
+            online_devices,
+            block_list,
+            history,
+            controls,
+            activities,
+            activity: Arc::new(Mutex::new(None)),
+            daily_limit,
+            activity_map,
+            integrator,
+            clock,
         }
     }
     pub fn msg(&self) -> String {
@@ -31,44 +382,339 @@ impl Status {
             .clone()
     }
     pub fn idle_since(&self) -> String {
-        self.idle
-            .lock()
-            .expect("nothing can panic with lock held")
-            .elapsed()
-            .as_secs()
-            .to_string()
+        let idle = *self.idle.lock().expect("nothing can panic with lock held");
+        SystemClock.elapsed(idle).as_secs().to_string()
+    }
+
+    /// The idle time and when it started on `CLOCK_BOOTTIME`, both in
+    /// milliseconds. Like the idle time that clock counts suspend.
+    pub fn idle_since_ms(&self) -> String {
+        let idle = *self.idle.lock().expect("nothing can panic with lock held");
+        let idle = SystemClock.elapsed(idle);
+        let boottime = clock_gettime(ClockId::CLOCK_BOOTTIME)
+            .expect("CLOCK_BOOTTIME is always supported on linux");
+        let since = Duration::from(boottime).saturating_sub(idle);
+        format!("{} {}", idle.as_millis(), since.as_millis())
+    }
+
+    pub(crate) fn health(&self) -> Health {
+        Health {
+            subsystems: supervisor::subsystems(),
+            integrator_lag: self.integrator.lag(),
+            last_grab_error: self.online_devices.last_grab_failure(),
+            dropped_events: DROPPED_ACTIVITY.load(Ordering::Relaxed)
+                + DROPPED_INPUT_EVENTS.load(Ordering::Relaxed),
+        }
     }
 
     pub(crate) fn update_msg(&self, new_status: &str) {
         let mut msg = self.msg.lock().expect("Self::msg can not panic");
         *msg = new_status.to_string();
     }
+
+    pub(super) fn update_state(&self, new_state: State, started: Instant, update: &StateUpdate) {
+        *self.state.lock().expect("nothing can panic with lock held") = (new_state, started);
+        self.broadcast(update);
+    }
+
+    pub(super) fn update_activity(&self, activity: Option<String>) {
+        *self
+            .activity
+            .lock()
+            .expect("nothing can panic with lock held") = activity;
+    }
+
+    /// The activity of the current break, outside a break a new pick
+    fn suggest_activity(&self) -> Response {
+        let Some(activities) = &self.activities else {
+            return Err((
+                ErrorCode::Failed,
+                "The break coach is off, start with --break-coach".to_string(),
+            ));
+        };
+        let current = self
+            .activity
+            .lock()
+            .expect("nothing can panic with lock held")
+            .clone();
+        Ok(current.or_else(|| activities.pick()).unwrap_or_default())
+    }
+
+    /// Seconds left of the daily limit, None without one
+    pub(super) fn daily_budget(&self) -> Option<u64> {
+        self.daily_limit
+            .as_ref()
+            .map(|limit| limit.left().as_secs())
+    }
+
+    /// The next `argument` breaks as planned by the run loop
+    fn schedule_preview(&self, argument: &str) -> Response {
+        let count = argument
+            .parse::<usize>()
+            .ok()
+            .filter(|count| (1..=MAX_PREVIEW).contains(count))
+            .ok_or_else(|| {
+                (
+                    ErrorCode::InvalidArgument,
+                    format!("Expected a number of breaks from 1 to {MAX_PREVIEW}"),
+                )
+            })?;
+        let (tx, rx) = mpsc::channel();
+        self.controls
+            .send(Event::Preview(count, tx))
+            .map_err(|_| (ErrorCode::Failed, "The schedule is not running".to_string()))?;
+        let planned = rx.recv_timeout(CONTROL_TIMEOUT).map_err(|_| {
+            (
+                ErrorCode::Failed,
+                "The schedule did not respond in time".to_string(),
+            )
+        })?;
+        ron::to_string(&planned).map_err(|e| {
+            (
+                ErrorCode::Failed,
+                format!("Could not serialize schedule: {e}"),
+            )
+        })
+    }
+
+    /// Which minutes of the last week had input
+    fn activity_map(&self) -> Response {
+        let week = self.activity_map.week(SystemTime::now());
+        ron::to_string(&week).map_err(|e| {
+            (
+                ErrorCode::Failed,
+                format!("Could not serialize activity map: {e}"),
+            )
+        })
+    }
+
+    pub(crate) fn progress(&self, remaining: Duration, total: Duration) {
+        self.broadcast(&StateUpdate::Progress {
+            at: now(),
+            remaining,
+            total,
+        });
+    }
+
+    /// Does not change the state, subscribers only see it if connected
+    pub(super) fn warning(&self, update: &StateUpdate) {
+        self.broadcast(update);
+    }
+
+    fn broadcast(&self, update: &StateUpdate) {
+        self.subscribers
+            .lock()
+            .expect("nothing can panic with lock held")
+            .send(update);
+    }
+
+    #[cfg(feature = "http")]
+    pub(super) fn current(&self) -> StateUpdate {
+        self.state
+            .lock()
+            .expect("nothing can panic with lock held")
+            .0
+            .to_update()
+    }
+
+    /// How far through the current work period, break or pause from 0 to 1
+    pub(super) fn fraction_done(&self) -> f32 {
+        let (state, started) = *self.state.lock().expect("nothing can panic with lock held");
+        state.progress(started, &*self.clock)
+    }
+
+    /// The first update is the current state
+    pub(super) fn subscribe(&self) -> Subscription {
+        let (tx, updates) = mpsc::channel();
+        let (state, _) = *self.state.lock().expect("nothing can panic with lock held");
+        tx.send(state.to_update())
+            .expect("receiver is not dropped yet");
+        let id = self
+            .subscribers
+            .lock()
+            .expect("nothing can panic with lock held")
+            .add(tx);
+        Subscription {
+            id,
+            updates,
+            subscribers: self.subscribers.clone(),
+        }
+    }
+
+    /// Blocks all currently connected devices with this id. Takes effect
+    /// from the next break on.
+    fn block_device(&self, id: InputId) -> Result<()> {
+        let names = self
+            .online_devices
+            .list_inputs()
+            .wrap_err("Could not list inputs")?
+            .into_iter()
+            .find(|input| input.id == id)
+            .map(|input| input.names)
+            .ok_or_else(|| eyre!("No device with this id is connected"))?;
+        self.block_list.block(id, names)
+    }
+
+    /// Takes effect from the next break on.
+    fn unblock_device(&self, id: InputId) -> Result<()> {
+        if self.block_list.unblock(&id)? {
+            Ok(())
+        } else {
+            Err(eyre!("No device with this id is blocked"))
+        }
+    }
+
+    pub(super) fn control(&self, control: Control) -> Response {
+        let (tx, rx) = mpsc::channel();
+        self.controls
+            .send(Event::Control(control, tx))
+            .map_err(|_| (ErrorCode::Failed, "The schedule is not running".to_string()))?;
+        match rx.recv_timeout(CONTROL_TIMEOUT) {
+            Ok(Ok(())) => Ok(String::new()),
+            Ok(Err(msg)) => Err((ErrorCode::Failed, msg)),
+            Err(_) => Err((
+                ErrorCode::Failed,
+                "The schedule did not respond in time".to_string(),
+            )),
+        }
+    }
 }
 
-pub(crate) fn maintain(status: Status) -> Result<()> {
-    let mut listener = None;
+/// Ok is send as is, Err as an error frame
+pub(super) type Response = std::result::Result<String, (ErrorCode, String)>;
 
-    for port in PORTS {
-        let addr = SocketAddr::from(([127, 0, 0, 1], port));
-        match TcpListener::bind(addr) {
-            Ok(l) => {
-                listener = Some(l);
-                break;
-            }
-            Err(e) if e.kind() == ErrorKind::AddrInUse => {
-                continue;
+/// The error chain formatted as a single line
+fn failed(report: &color_eyre::Report) -> (ErrorCode, String) {
+    let chain: Vec<_> = report.chain().map(ToString::to_string).collect();
+    (ErrorCode::Failed, chain.join(": "))
+}
+
+fn parse_id(argument: &str) -> std::result::Result<InputId, (ErrorCode, String)> {
+    argument.parse().map_err(|e| {
+        (
+            ErrorCode::InvalidArgument,
+            format!("Not a valid device id: {e}"),
+        )
+    })
+}
+
+fn respond(status: &Status, request: &str, argument: &str) -> Response {
+    if let Some(control) = Control::parse(request, argument) {
+        let control = control.map_err(|msg| (ErrorCode::InvalidArgument, msg))?;
+        return status.control(control);
+    }
+
+    match request {
+        "hello" => Ok(format!(
+            "{NAME} {} {PROTOCOL_VERSION}",
+            env!("CARGO_PKG_VERSION")
+        )),
+        "version" => Ok(format!("{} {PROTOCOL_VERSION}", env!("CARGO_PKG_VERSION"))),
+        "status_msg" => Ok(status.msg()),
+        "idle_since" => Ok(status.idle_since()),
+        "idle_since_ms" => Ok(status.idle_since_ms()),
+        "relock_latency_ms" => status
+            .online_devices
+            .relock_latency()
+            .map(|latency| latency.as_millis().to_string())
+            .ok_or((
+                ErrorCode::Failed,
+                "No device connected while blocked yet".to_string(),
+            )),
+        "health" => ron::to_string(&status.health()).map_err(|e| {
+            (
+                ErrorCode::Failed,
+                format!("Could not serialize health: {e}"),
+            )
+        }),
+        "progress" => Ok(format!("{:.3}", status.fraction_done())),
+        "suggest_activity" => status.suggest_activity(),
+        "daily_budget" => status.daily_budget().map(|left| left.to_string()).ok_or((
+            ErrorCode::Failed,
+            "No daily limit is set, start with --daily-limit".to_string(),
+        )),
+        "activity_map" => status.activity_map(),
+        "schedule_preview" => status.schedule_preview(argument),
+        "history" => ron::to_string(&status.history.recent()).map_err(|e| {
+            (
+                ErrorCode::Failed,
+                format!("Could not serialize history: {e}"),
+            )
+        }),
+        "block_device" => status
+            .block_device(parse_id(argument)?)
+            .map(|()| String::new())
+            .map_err(|report| failed(&report)),
+        "unblock_device" => status
+            .unblock_device(parse_id(argument)?)
+            .map(|()| String::new())
+            .map_err(|report| failed(&report)),
+        "reload_config" => status
+            .block_list
+            .reload()
+            .map(|()| String::new())
+            .map_err(|report| failed(&report)),
+        _ => Err((
+            ErrorCode::UnknownRequest,
+            format!("unknown request: {request}"),
+        )),
+    }
+}
+
+/// Binds the port on both the ipv4 and ipv6 loopback address. Fails if
+/// either is in use. Its fine if one of the two is not available, for example
+/// in an ipv6 only network namespace.
+fn bind_loopback(port: u16) -> std::io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    let mut last_error = None;
+    for ip in [
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ] {
+        match TcpListener::bind(SocketAddr::new(ip, port)) {
+            Ok(l) => listeners.push(l),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => return Err(e),
+            Err(e) => {
+                debug!("could not bind {ip} port {port}: {e}");
+                last_error = Some(e);
             }
-            Err(other) => return Err(other).wrap_err("Could not start listening"),
-        };
+        }
+    }
+    match last_error {
+        Some(e) if listeners.is_empty() => Err(e),
+        _ => Ok(listeners),
     }
+}
 
-    let Some(listener) = listener else {
-        return Err(eyre!(
-            "Could not find a suitable port after trying multiple options"
-        ));
+#[cfg(target_os = "linux")]
+fn bind_abstract() -> Option<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = match SocketAddr::from_abstract_name(ABSTRACT_SOCKET) {
+        Ok(addr) => addr,
+        Err(e) => {
+            warn!("Invalid abstract socket name: {e}");
+            return None;
+        }
     };
+    UnixListener::bind_addr(&addr)
+        .inspect_err(|e| warn!("Could not listen on abstract unix socket: {e}"))
+        .ok()
+}
 
-    for res in listener.incoming() {
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract() -> Option<UnixListener> {
+    None
+}
+
+fn accept_loop<S: Read + Write + AsRawFd + Send + 'static>(
+    incoming: impl Iterator<Item = std::io::Result<S>>,
+    try_clone: fn(&S) -> std::io::Result<S>,
+    set_write_timeout: fn(&S, Option<Duration>) -> std::io::Result<()>,
+    status: &Status,
+) {
+    for res in incoming {
         debug!("accepted api connection");
         let conn = match res {
             Ok(c) => c,
@@ -78,57 +724,403 @@ pub(crate) fn maintain(status: Status) -> Result<()> {
             }
         };
 
+        if let Err(e) = set_write_timeout(&conn, Some(WRITE_TIMEOUT)) {
+            warn!("Could not set a write timeout on an api connection: {e}");
+        }
         let status = status.clone();
-        thread::spawn(|| {
-            if let Err(error) = handle_conn(conn, status) {
+        thread::spawn(move || {
+            let writer = try_clone(&conn).expect("stream clone failed");
+            let socket = conn.as_raw_fd();
+            if let Err(error) = handle_conn(conn, writer, socket, status, None) {
                 warn!("ran into error handling API client: {error}");
             }
         });
     }
+}
+
+fn accept_remote(remote: &Remote, status: &Status) {
+    let connected = Arc::new(AtomicUsize::new(0));
+    for res in remote.listener.incoming() {
+        let stream = match res {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed incoming network api connection: {e}");
+                continue;
+            }
+        };
+        if connected.fetch_add(1, Ordering::Relaxed) >= MAX_REMOTE_CLIENTS {
+            connected.fetch_sub(1, Ordering::Relaxed);
+            warn!("Too many network api clients connected, refusing another");
+            continue;
+        }
+        let connected = connected.clone();
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        debug!("accepted network api connection from {peer}");
+        let conn = match ServerConnection::new(remote.tls.clone()) {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Could not start tls: {e}");
+                continue;
+            }
+        };
+        let timeouts = stream
+            .set_write_timeout(Some(WRITE_TIMEOUT))
+            .and_then(|()| stream.set_read_timeout(Some(AUTH_TIMEOUT)))
+            .and_then(|()| stream.try_clone());
+        let timeouts = match timeouts {
+            Ok(timeouts) => timeouts,
+            Err(e) => {
+                warn!("Could not set timeouts on a network api connection: {e}");
+                connected.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        let socket = stream.as_raw_fd();
+        let conn = SharedStream::new(rustls::StreamOwned::new(conn, stream));
+        let (status, token) = (status.clone(), remote.token.clone());
+        thread::spawn(move || {
+            let auth = Auth {
+                token: &token,
+                stream: &timeouts,
+                started: Instant::now(),
+            };
+            if let Err(error) = handle_conn(conn.clone(), conn, socket, status, Some(auth)) {
+                debug!("network API client {peer} disconnected: {error:#}");
+            }
+            connected.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
 
+/// Listens on `port` if set otherwise on the first free port out of `PORTS`
+pub(crate) fn maintain(
+    status: Status,
+    port: Option<u16>,
+    remote: Option<Remote>,
+    inherited: Inherited,
+) -> Result<()> {
+    if !inherited.is_empty() {
+        return serve(status, remote, inherited);
+    }
+    let mut listeners = None;
+
+    let ports = port.map_or(PORTS.to_vec(), |port| vec![port]);
+    for port in ports {
+        match bind_loopback(port) {
+            Ok(l) => {
+                listeners = Some(l);
+                break;
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                continue;
+            }
+            Err(other) => return Err(other).wrap_err("Could not start listening"),
+        };
+    }
+
+    let Some(listeners) = listeners else {
+        return match port {
+            Some(port) => Err(eyre!("The api port ({port}) is already in use"))
+                .suggestion("Is break-enforcer already running?"),
+            None => Err(eyre!(
+                "Could not find a suitable port after trying multiple options"
+            )),
+        };
+    };
+    let listening = Inherited {
+        unix: bind_abstract().into_iter().collect(),
+        tcp: listeners,
+        devices: HashMap::new(),
+    };
+    serve(status, remote, listening)
+}
+
+/// The accept loops run in the background, they are restarted if they panic
+fn serve(status: Status, remote: Option<Remote>, listeners: Inherited) -> Result<()> {
+    if let Some(remote) = remote {
+        let status = status.clone();
+        supervisor::spawn(Subsystem::Api(Listener::Remote), move || {
+            accept_remote(&remote, &status);
+            Ok(())
+        });
+    }
+    for (n, unix) in listeners.unix.into_iter().enumerate() {
+        let status = status.clone();
+        supervisor::spawn(Subsystem::Api(Listener::Unix(n)), move || {
+            accept_loop(
+                unix.incoming(),
+                UnixStream::try_clone,
+                UnixStream::set_write_timeout,
+                &status,
+            );
+            Ok(())
+        });
+    }
+    for (n, tcp) in listeners.tcp.into_iter().enumerate() {
+        let status = status.clone();
+        supervisor::spawn(Subsystem::Api(Listener::Tcp(n)), move || {
+            accept_loop(
+                tcp.incoming(),
+                TcpStream::try_clone,
+                TcpStream::set_write_timeout,
+                &status,
+            );
+            Ok(())
+        });
+    }
     Ok(())
 }
 
-fn handle_conn(conn: std::net::TcpStream, status: Status) -> Result<()> {
-    use std::io::BufRead;
+/// What a network client has to authenticate with, see `Remote`
+struct Auth<'a> {
+    token: &'a str,
+    /// its read timeout is `AUTH_TIMEOUT` until the client authenticated
+    stream: &'a TcpStream,
+    started: Instant,
+}
 
-    let mut writer = conn.try_clone().expect("tcp stream clone failed");
+/// With `auth` requests other than `hello` are refused until the client
+/// sends `auth <token>`, a wrong token or taking longer than `AUTH_TIMEOUT`
+/// closes the connection. The `socket` underneath `conn` is used to notice
+/// subscribers disconnecting.
+fn handle_conn(
+    conn: impl Read,
+    mut writer: impl Write,
+    socket: RawFd,
+    status: Status,
+    auth: Option<Auth>,
+) -> Result<()> {
     let mut reader = BufReader::new(conn);
     let mut buf = vec![];
+    let mut authorized = auth.is_none();
+    let token = auth.as_ref().map(|auth| auth.token);
 
     loop {
-        let n_read = reader.read_until(STOP_BYTE, &mut buf)?;
-        if n_read == 0 {
+        buf.clear();
+        let Some(end) = read_request(&mut reader, &mut buf)? else {
             debug!("api client disconnected");
             return Ok(());
+        };
+        if let Some(auth) = auth.as_ref().filter(|_| !authorized) {
+            if auth.started.elapsed() > AUTH_TIMEOUT {
+                return Err(eyre!("Client did not authenticate in time"));
+            }
+        }
+        let mut packet = &buf[..];
+        let mut close = false;
+        if end == b'\n' {
+            packet = packet.strip_suffix(b"\r").unwrap_or(packet);
         }
+        if packet == b"subscribe" && authorized {
+            debug!("api client subscribed");
+            return handle_subscriber(writer, socket, &status.subscribe(), end);
+        }
+        let response = match String::from_utf8(packet.to_vec()) {
+            Ok(packet) => {
+                let (request, argument) = proto::split_request(&packet);
+                match (request, token) {
+                    ("auth", None) => Ok(String::new()),
+                    ("auth", Some(token)) if token_matches(token, argument) => {
+                        authorized = true;
+                        if let Some(auth) = &auth {
+                            auth.stream
+                                .set_read_timeout(None)
+                                .wrap_err("Could not clear the read timeout")?;
+                        }
+                        Ok(String::new())
+                    }
+                    ("auth", Some(_)) => {
+                        // guessing takes a new tls handshake for every try
+                        close = true;
+                        Err((ErrorCode::Unauthorized, "wrong token".to_string()))
+                    }
+                    ("hello", _) => respond(&status, request, argument),
+                    _ if !authorized => Err((
+                        ErrorCode::Unauthorized,
+                        "send auth <token> first".to_string(),
+                    )),
+                    _ => respond(&status, request, argument),
+                }
+            }
+            Err(e) => Err((
+                ErrorCode::UnknownRequest,
+                format!("packet must consist of valid utf8: {e}"),
+            )),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err((code, msg)) => {
+                debug!("api request failed: {code}: {msg}");
+                proto::error_frame(code, &msg)
+            }
+        };
 
-        let packet = &buf[..(n_read - 1)]; // leave off STOP_BYTE
-        let packet = String::from_utf8(packet.to_vec())
-            .wrap_err("packet must consist of valid utf8")
-            .with_note(|| format!("got bytes: {packet:?})"))?;
+        writer
+            .write_all(line_safe(response, end).as_bytes())
+            .wrap_err("Could not write response to tcpstream")?;
+        writer
+            .write_all(&[end])
+            .wrap_err("Could not write response to tcpstream")?;
+        if close {
+            info!("api client sent a wrong token, closed the connection");
+            return Ok(());
+        }
+    }
+}
 
-        match packet.as_str() {
-            "status_msg" => {
-                writer
-                    .write_all(status.msg().as_bytes())
-                    .wrap_err("Could not write status msg to tcpstream")?;
-                writer
-                    .write_all(&[STOP_BYTE])
-                    .wrap_err("Could not write status msg to tcpstream")?;
+/// Reads up to a stop byte or, for line mode, a newline. Returns which of the
+/// two ended the request, None once the client disconnected. Errors on
+/// requests over `MAX_REQUEST` bytes.
+fn read_request(reader: &mut impl BufRead, buf: &mut Vec<u8>) -> std::io::Result<Option<u8>> {
+    loop {
+        if buf.len() > MAX_REQUEST {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("request over {MAX_REQUEST} bytes"),
+            ));
+        }
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Ok(None);
+        }
+        let terminator = available
+            .iter()
+            .position(|b| *b == STOP_BYTE || *b == b'\n');
+        match terminator {
+            Some(i) => {
+                let end = available[i];
+                buf.extend_from_slice(&available[..i]);
+                reader.consume(i + 1);
+                return Ok(Some(end));
             }
-            "idle_since" => {
-                writer
-                    .write_all(status.idle_since().as_bytes())
-                    .wrap_err("Could not write active or not to tcpstream")?;
-                writer
-                    .write_all(&[STOP_BYTE])
-                    .wrap_err("Could not write active or not to tcpstream")?;
+            None => {
+                let n = available.len();
+                buf.extend_from_slice(available);
+                reader.consume(n);
+            }
+        }
+    }
+}
+
+/// In line mode a newline would end the frame early
+fn line_safe(frame: String, end: u8) -> String {
+    if end == b'\n' {
+        frame.replace('\n', " ")
+    } else {
+        frame
+    }
+}
+
+/// From now on the client only receives updates, one per frame. Ends once
+/// the client disconnects, stops reading (see `WRITE_TIMEOUT`) or is dropped
+/// to make room for another.
+fn handle_subscriber(
+    mut writer: impl Write,
+    socket: RawFd,
+    subscription: &Subscription,
+    end: u8,
+) -> Result<()> {
+    loop {
+        let update = match subscription.recv_timeout(LIVENESS_CHECK) {
+            Ok(update) => update,
+            Err(mpsc::RecvTimeoutError::Timeout) if hung_up(socket) => {
+                debug!("api subscriber disconnected");
+                return Ok(());
             }
-            _ => {
-                debug!("packet: '{packet}'");
-                return Err(eyre!("got unexpected packet/api request, disconnecting"));
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                debug!("api subscriber dropped to make room");
+                return Ok(());
             }
+        };
+        let update = ron::to_string(&update).wrap_err("Could not serialize state update")?;
+        writer
+            .write_all(line_safe(update, end).as_bytes())
+            .wrap_err("Could not write state update to tcpstream")?;
+        writer
+            .write_all(&[end])
+            .wrap_err("Could not write state update to tcpstream")?;
+        subscription.written();
+    }
+}
+
+/// Whether the client closed its end, without taking anything it sent
+fn hung_up(socket: RawFd) -> bool {
+    let mut buf = [0; 1];
+    match recv(
+        socket,
+        &mut buf,
+        MsgFlags::MSG_PEEK | MsgFlags::MSG_DONTWAIT,
+    ) {
+        Ok(0) => true,
+        Ok(_) | Err(Errno::EAGAIN | Errno::EINTR) => false,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drops_stalest_subscriber_when_full() {
+        let mut subscribers = Subscribers::default();
+        let mut receivers = Vec::new();
+        for _ in 0..MAX_SUBSCRIBERS {
+            let (tx, rx) = mpsc::channel();
+            subscribers.add(tx);
+            receivers.push(rx);
+        }
+        for id in 1..MAX_SUBSCRIBERS as u64 {
+            subscribers.written(id);
         }
+
+        let (tx, _rx) = mpsc::channel();
+        subscribers.add(tx);
+        assert_eq!(subscribers.list.len(), MAX_SUBSCRIBERS);
+        assert!(subscribers.list.iter().all(|s| s.id != 0));
+        assert_eq!(
+            receivers[0].try_recv(),
+            Err(mpsc::TryRecvError::Disconnected)
+        );
+
+        subscribers.remove(1);
+        drop(receivers.remove(2));
+        subscribers.send(&StateUpdate::Waiting {
+            at: now(),
+            reset: None,
+        });
+        assert_eq!(subscribers.list.len(), MAX_SUBSCRIBERS - 2);
+    }
+    #[test]
+    fn refuses_overlong_requests() {
+        let mut buf = Vec::new();
+        let mut reader = BufReader::with_capacity(16, &b"status\0"[..]);
+        assert_eq!(read_request(&mut reader, &mut buf).unwrap(), Some(0));
+        assert_eq!(buf, b"status");
+
+        buf.clear();
+        let endless = std::io::repeat(b'a').take(2 * MAX_REQUEST as u64);
+        let mut reader = BufReader::with_capacity(16, endless);
+        let error = read_request(&mut reader, &mut buf).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(buf.len() <= MAX_REQUEST + 16);
+    }
+
+    #[test]
+    fn takes_passed_in_devices_by_name() {
+        let device = || OwnedFd::from(File::open("/dev/null").unwrap());
+        let mut inherited = Inherited::default();
+        assert!(inherited.take_device(device(), "event3").is_ok());
+        assert!(inherited.take_device(device(), "../event4").is_ok());
+        assert!(inherited.take_device(device(), "").is_ok());
+        let not_a_device = OwnedFd::from(File::open("Cargo.toml").unwrap());
+        assert!(inherited.take_device(not_a_device, "event5").is_err());
+
+        let devices = inherited.take_devices();
+        assert_eq!(devices.len(), 1);
+        assert!(devices.contains_key(&paths::dev_dir().join("event3")));
     }
 }
@@ -0,0 +1,255 @@
+//! Talks to sway or i3 over their ipc socket. Shows a nag bar during the lock
+//! warning and the break and can move to a dedicated workspace during the
+//! break.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use tracing::{debug, error};
+
+use super::notification::command_available;
+use super::State;
+use crate::clock::Clock;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flavor {
+    Sway,
+    I3,
+}
+
+impl Flavor {
+    fn detect() -> Result<(Self, PathBuf)> {
+        if let Some(path) = std::env::var_os("SWAYSOCK") {
+            Ok((Flavor::Sway, path.into()))
+        } else if let Some(path) = std::env::var_os("I3SOCK") {
+            Ok((Flavor::I3, path.into()))
+        } else {
+            Err(eyre!(
+                "Neither SWAYSOCK nor I3SOCK is set, can not reach sway or i3"
+            ))
+            .suggestion("Run as a user service inside the sway or i3 session")
+            .suggestion(
+                "Import the variable into the service environment: \
+                    systemctl --user import-environment SWAYSOCK I3SOCK",
+            )
+        }
+    }
+
+    fn nag_bar(self) -> &'static str {
+        match self {
+            Flavor::Sway => "swaynag",
+            Flavor::I3 => "i3-nagbar",
+        }
+    }
+}
+
+pub(crate) fn check_dependency(nag_bar: bool) -> Result<()> {
+    let (flavor, _) = Flavor::detect()?;
+    if nag_bar {
+        let bar = flavor.nag_bar();
+        command_available(
+            bar,
+            &format!("{bar} version"),
+            "swaynag comes with sway and i3-nagbar with i3",
+        )?;
+    }
+    Ok(())
+}
+
+fn request(socket: &PathBuf, kind: u32, payload: &str) -> Result<serde_json::Value> {
+    let mut conn = UnixStream::connect(socket)
+        .wrap_err("Could not connect to the ipc socket")
+        .with_note(|| format!("path: {}", socket.display()))?;
+    let len = u32::try_from(payload.len()).wrap_err("Ipc message too long")?;
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&len.to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    conn.write_all(&message)
+        .wrap_err("Could not send ipc message")?;
+
+    let mut header = [0; MAGIC.len() + 8];
+    conn.read_exact(&mut header)
+        .wrap_err("Could not read ipc reply")?;
+    let len = u32::from_ne_bytes(header[MAGIC.len()..][..4].try_into().expect("4 bytes"));
+    let mut reply = vec![0; len as usize];
+    conn.read_exact(&mut reply)
+        .wrap_err("Could not read ipc reply")?;
+    serde_json::from_slice(&reply).wrap_err("Ipc reply is not valid json")
+}
+
+fn run_command(socket: &PathBuf, command: &str) -> Result<()> {
+    debug!("Running window manager command: {command}");
+    let reply = request(socket, RUN_COMMAND, command)?;
+    let failed = reply
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|outcome| outcome["success"] != true);
+    match failed {
+        None => Ok(()),
+        Some(outcome) => Err(eyre!("Command failed: {}", outcome["error"]))
+            .with_note(|| format!("command: {command}")),
+    }
+}
+
+/// Workspace names can contain spaces
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\\\""))
+}
+
+fn focused_workspace(socket: &PathBuf) -> Result<String> {
+    request(socket, GET_WORKSPACES, "")?
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|workspace| workspace["focused"] == true)
+        .and_then(|workspace| workspace["name"].as_str())
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| eyre!("No workspace is focused"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bar {
+    Warning { next_break: Instant },
+    Break { next_work: Instant },
+}
+
+pub(crate) struct WindowManager {
+    flavor: Flavor,
+    socket: PathBuf,
+    nag_bar: bool,
+    break_workspace: Option<String>,
+    /// the bar shown and its process
+    bar: Option<(Bar, Child)>,
+    /// focused before switching to the break workspace
+    previous_workspace: Option<String>,
+}
+
+/// Shown in the bar, the remaining time would go stale
+fn wall_clock_time(clock: &dyn Clock, at: Instant) -> String {
+    let at = Local::now() + clock.duration_until(at);
+    at.format("%H:%M").to_string()
+}
+
+impl WindowManager {
+    /// None if neither the nag bar nor the break workspace is enabled
+    pub(crate) fn new(nag_bar: bool, break_workspace: Option<String>) -> Result<Option<Self>> {
+        if !nag_bar && break_workspace.is_none() {
+            return Ok(None);
+        }
+        let (flavor, socket) = Flavor::detect()?;
+        Ok(Some(Self {
+            flavor,
+            socket,
+            nag_bar,
+            break_workspace,
+            bar: None,
+            previous_workspace: None,
+        }))
+    }
+
+    /// `lock_warning` is how long before a break the warning starts
+    pub(crate) fn update(
+        &mut self,
+        state: &State,
+        lock_warning: Option<Duration>,
+        clock: &dyn Clock,
+    ) {
+        let bar = match *state {
+            State::Work { next_break }
+                if lock_warning.is_some_and(|warn| clock.duration_until(next_break) < warn) =>
+            {
+                Some(Bar::Warning { next_break })
+            }
+            State::Break { next_work, .. } => Some(Bar::Break { next_work }),
+            _ => None,
+        };
+        if self.nag_bar {
+            self.show(bar, clock);
+        }
+
+        let in_break = matches!(state, State::Break { .. });
+        if let Some(workspace) = &self.break_workspace {
+            let result = match (in_break, &self.previous_workspace) {
+                (true, None) => focused_workspace(&self.socket).and_then(|previous| {
+                    self.previous_workspace = Some(previous);
+                    run_command(&self.socket, &format!("workspace {}", quote(workspace)))
+                }),
+                (false, Some(previous)) => {
+                    let result =
+                        run_command(&self.socket, &format!("workspace {}", quote(previous)));
+                    self.previous_workspace = None;
+                    result
+                }
+                _ => Ok(()),
+            };
+            if let Err(report) = result {
+                error!("Could not switch workspace: {report:?}");
+            }
+        }
+    }
+
+    fn show(&mut self, bar: Option<Bar>, clock: &dyn Clock) {
+        if self.bar.as_ref().map(|(shown, _)| *shown) == bar {
+            return;
+        }
+        if let Some((_, mut child)) = self.bar.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let Some(bar) = bar else {
+            return;
+        };
+
+        let (kind, msg) = match bar {
+            Bar::Warning { next_break } => (
+                "warning",
+                format!("Break at {}", wall_clock_time(clock, next_break)),
+            ),
+            Bar::Break { next_work } => (
+                "error",
+                format!("On a break until {}", wall_clock_time(clock, next_work)),
+            ),
+        };
+        match Command::new(self.flavor.nag_bar())
+            .args(["-t", kind, "-m", &msg])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => self.bar = Some((bar, child)),
+            Err(e) => error!("Could not show {}: {e}", self.flavor.nag_bar()),
+        }
+    }
+}
+
+impl Drop for WindowManager {
+    fn drop(&mut self) {
+        if let Some((_, mut child)) = self.bar.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quotes_workspace_names() {
+        assert_eq!(quote("10: break"), r#""10: break""#);
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+    }
+}
@@ -0,0 +1,185 @@
+//! Joysticks and gamepads that only have a legacy joydev node (`js*`) and no
+//! event node. The device watcher skips `js*` nodes as they usually duplicate
+//! an event node, grabbing that event node then silences the `js*` node too
+//! (the kernel only passes input to the grab). Without an event node there is
+//! nothing to grab: input from these still counts as activity but they can
+//! not be blocked.
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use inotify::{EventMask, Inotify, WatchMask};
+use tracing::{debug, warn};
+
+use crate::watch_and_block::InputId;
+
+const SYS_CLASS_INPUT: &str = "/sys/class/input";
+/// The event node of a device can show up just after its `js*` node
+const SETTLE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub(crate) struct LegacyJoystick {
+    pub(crate) path: PathBuf,
+    pub(crate) id: InputId,
+    pub(crate) name: String,
+}
+
+/// The `js*` nodes in `dir` whose device has no event node. Nodes sysfs
+/// knows nothing about, for example in a container, are left out.
+pub(crate) fn legacy_only(dir: &Path) -> Vec<LegacyJoystick> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("js"))
+        .filter_map(|entry| legacy_node(entry.path()))
+        .collect()
+}
+
+fn legacy_node(path: PathBuf) -> Option<LegacyJoystick> {
+    let device = Path::new(SYS_CLASS_INPUT)
+        .join(path.file_name()?)
+        .join("device");
+    let joystick = legacy(&device, path.clone());
+    if joystick.is_none() {
+        debug!("Not tracking legacy node {}", path.display());
+    }
+    joystick
+}
+
+/// Calls `found` from a thread of its own with the `legacy_only` joysticks in
+/// `dir`, then with those connected later
+pub(crate) fn follow(dir: PathBuf, found: impl Fn(LegacyJoystick) + Send + 'static) {
+    thread::spawn(move || {
+        // watched before listing, a node created in between is not missed
+        let inotify = Inotify::init().and_then(|inotify| {
+            inotify
+                .watches()
+                .add(&dir, WatchMask::CREATE | WatchMask::DELETE)?;
+            Ok(inotify)
+        });
+        let mut known = HashSet::new();
+        for joystick in legacy_only(&dir) {
+            known.insert(joystick.path.clone());
+            found(joystick);
+        }
+        let mut inotify = match inotify {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                warn!("Not following legacy joysticks connected later: {e}");
+                return;
+            }
+        };
+        let mut buffer = [0; 1024];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Stopped following legacy joysticks: {e}");
+                    return;
+                }
+            };
+            let mut created = Vec::new();
+            for event in events {
+                let Some(name) = event.name.filter(|name| name.as_bytes().starts_with(b"js"))
+                else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if event.mask.contains(EventMask::DELETE) {
+                    known.remove(&path);
+                } else if known.insert(path.clone()) {
+                    created.push(path);
+                }
+            }
+            if created.is_empty() {
+                continue;
+            }
+            thread::sleep(SETTLE);
+            created.into_iter().filter_map(legacy_node).for_each(&found);
+        }
+    });
+}
+
+/// `None` if the device also has an event node or sysfs could not be read
+fn legacy(device: &Path, path: PathBuf) -> Option<LegacyJoystick> {
+    let has_event_node = fs::read_dir(device)
+        .ok()?
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name().to_string_lossy().starts_with("event"));
+    if has_event_node {
+        return None;
+    }
+    let read = |attribute: &str| {
+        fs::read_to_string(device.join(attribute))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+    let hex = |attribute: &str| u16::from_str_radix(&read(attribute)?, 16).ok();
+    let id = evdev::InputId::new(
+        evdev::BusType(hex("id/bustype")?),
+        hex("id/vendor")?,
+        hex("id/product")?,
+        hex("id/version")?,
+    );
+    Some(LegacyJoystick {
+        path,
+        id: id.into(),
+        name: read("name")?,
+    })
+}
+
+/// Size of a `struct js_event`
+pub(crate) const EVENT_SIZE: usize = 8;
+/// Set on the events describing the initial state, send right after opening
+const JS_EVENT_INIT: u8 = 0x80;
+
+/// Whether any of the raw `js_event`s is actual input
+pub(crate) fn any_input(events: &[u8]) -> bool {
+    events
+        .chunks_exact(EVENT_SIZE)
+        .any(|event| event[6] & JS_EVENT_INIT == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_legacy_device_from_sysfs() {
+        let device = std::env::temp_dir().join(format!("legacy-js-{}", std::process::id()));
+        fs::create_dir_all(device.join("id")).unwrap();
+        fs::create_dir_all(device.join("js0")).unwrap();
+        fs::write(device.join("name"), "Old Joystick\n").unwrap();
+        for (attribute, value) in [
+            ("bustype", "0003"),
+            ("vendor", "00be"),
+            ("product", "00ef"),
+            ("version", "0001"),
+        ] {
+            fs::write(device.join("id").join(attribute), value).unwrap();
+        }
+
+        let joystick = legacy(&device, PathBuf::from("/dev/input/js0")).unwrap();
+        assert_eq!(joystick.name, "Old Joystick");
+        assert_eq!(joystick.id.vendor(), 0xbe);
+        assert_eq!(joystick.id.product(), 0xef);
+
+        fs::create_dir(device.join("event3")).unwrap();
+        assert!(legacy(&device, PathBuf::from("/dev/input/js0")).is_none());
+        fs::remove_dir_all(device).unwrap();
+    }
+
+    #[test]
+    fn initial_state_is_not_input() {
+        let event = |type_: u8| [0, 0, 0, 0, 1, 0, type_, 0];
+        // button and axis state send on open
+        assert!(!any_input(&[event(0x81), event(0x82)].concat()));
+        assert!(any_input(&[event(0x81), event(0x01)].concat()));
+    }
+}
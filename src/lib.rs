@@ -1,22 +1,85 @@
-use std::io::{BufRead, BufReader, Write};
-use std::net::{SocketAddr, TcpStream};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+#[cfg(feature = "tls")]
+use std::path::Path;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
 use std::time::Duration;
 
+#[cfg(feature = "tls")]
+use rustls::pki_types::pem::PemObject;
+#[cfg(feature = "tls")]
+use rustls::pki_types::{CertificateDer, ServerName};
+#[cfg(feature = "tls")]
+use rustls::{ClientConfig, ClientConnection, RootCertStore};
 use tracing::debug;
 
-mod tcp_api_config;
-use tcp_api_config::PORTS;
-use tcp_api_config::STOP_BYTE;
+#[cfg(feature = "event-log")]
+mod event_logger;
+pub mod proto;
+#[cfg(feature = "tls")]
+mod shared_stream;
+#[cfg(feature = "streamdeck")]
+pub mod streamdeck;
+#[cfg(feature = "event-log")]
+pub use event_logger::EventLogger;
+pub use proto::{
+    ActivityMap, BreakKind, ErrorCode, Health, PlannedBreak, ResetReason, StateUpdate,
+    SubsystemHealth, Timestamp,
+};
+use proto::{NAME, PORTS, PORT_ENV, PROTOCOL_VERSION, STOP_BYTE};
+#[cfg(feature = "tls")]
+use shared_stream::SharedStream;
 
 pub struct Api {
-    reader: BufReader<TcpStream>,
-    writer: TcpStream,
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+    server_version: String,
+    set_read_timeout: Box<dyn Fn(Option<Duration>) -> std::io::Result<()> + Send>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Could not connect on any of the ports the api server listens on")]
+    #[error("Could not connect on the unix socket or any of the ports the api server listens on")]
     CouldNotConnect,
+    #[cfg(feature = "tls")]
+    #[error("Could not connect to {addr}")]
+    CouldNotConnectTo {
+        addr: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[cfg(feature = "tls")]
+    #[error("The address should look like host:port, got: {0}")]
+    InvalidAddress(String),
+    #[cfg(feature = "tls")]
+    #[error("Could not read the certificate to trust ({path}): {reason}")]
+    ReadingCertificate { path: String, reason: String },
+    #[cfg(feature = "tls")]
+    #[error("Could not set up tls")]
+    Tls(#[source] rustls::Error),
+    #[cfg(feature = "tls")]
+    #[error("Tls handshake with {addr} failed")]
+    TlsHandshake {
+        addr: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{PORT_ENV} is set but is not a valid port: {0}")]
+    InvalidPort(String),
+    #[error("The service at {addr} is not break-enforcer or a version too old to tell")]
+    NotBreakEnforcer { addr: String },
+    #[error(
+        "The break-enforcer service (version {server_version}) speaks protocol version \
+        {server} while this client speaks {ours}, update the older of the two and \
+        restart the service"
+    )]
+    ProtocolMismatch {
+        server_version: String,
+        server: u32,
+        ours: u32,
+    },
     #[error("Error writing request")]
     WritingRequest(#[source] std::io::Error),
     #[error("Error while reading response")]
@@ -25,64 +88,349 @@ pub enum Error {
     CorruptResponse(#[source] std::string::FromUtf8Error),
     #[error("The api server closed the connection, did it halt?")]
     ConnectionClosed,
+    #[error("The api server returned an error ({code}): {message}")]
+    ServerError { code: ErrorCode, message: String },
     #[error("The response should be a number, could not be parsed as one, response: {packet}")]
     IncorrectResponse {
         packet: String,
         #[source]
         error: std::num::ParseIntError,
     },
+    #[error("The response should be a fraction, could not be parsed as one, response: {packet}")]
+    IncorrectFraction {
+        packet: String,
+        #[source]
+        error: std::num::ParseFloatError,
+    },
+    #[cfg(feature = "event-log")]
+    #[error("Could not serialize state update for the event log")]
+    SerializingUpdate(#[source] serde_json::Error),
+    #[cfg(feature = "event-log")]
+    #[error("Could not write to the event log")]
+    WritingLog(#[source] std::io::Error),
+    #[cfg(feature = "event-log")]
+    #[error("Could not rotate the event log")]
+    RotatingLog(#[source] std::io::Error),
+    #[cfg(feature = "subscribe")]
+    #[error("Could not parse state update, update: {packet}")]
+    IncorrectUpdate {
+        packet: String,
+        #[source]
+        error: ron::error::SpannedError,
+    },
+    #[cfg(feature = "subscribe")]
+    #[error("No {0:?} update within the timeout")]
+    WaitTimedOut(StateUpdatePattern),
+    #[cfg(feature = "streamdeck")]
+    #[error("No supported Stream Deck (Original v2, MK.2 or XL) is connected")]
+    NoStreamDeck,
+    #[cfg(feature = "streamdeck")]
+    #[error("Could not talk to the Stream Deck")]
+    StreamDeck(#[source] hidapi::HidError),
+    #[cfg(feature = "streamdeck")]
+    #[error("Could not encode a key image")]
+    EncodingKeyImage(#[source] jpeg_encoder::EncodingError),
 }
 
-impl Api {
-    pub fn new() -> Result<Self, Error> {
-        let mut conn = None;
-
-        for port in PORTS {
-            let addr = SocketAddr::from(([127, 0, 0, 1], port));
-            match TcpStream::connect(addr) {
-                Ok(c) => {
-                    debug!("connected to break-enforcer service on port: {port}");
-                    conn = Some(c);
-                    break;
+/// A foreign service might accept the connection but never answer
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+trait Conn: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> std::io::Result<Self>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl Conn for TcpStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Conn for UnixStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(feature = "tls")]
+type TlsStream = SharedStream<rustls::StreamOwned<ClientConnection, TcpStream>>;
+
+#[cfg(feature = "tls")]
+impl Conn for TlsStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(self.clone())
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.with(|stream| stream.sock.set_read_timeout(timeout))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_config(ca: &Path) -> Result<ClientConfig, Error> {
+    let reading_failed = |reason: String| Error::ReadingCertificate {
+        path: ca.display().to_string(),
+        reason,
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).map_err(|e| reading_failed(e.to_string()))? {
+        let cert = cert.map_err(|e| reading_failed(e.to_string()))?;
+        roots.add(cert).map_err(|e| reading_failed(e.to_string()))?;
+    }
+    Ok(
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(Error::Tls)?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract() -> Option<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(proto::ABSTRACT_SOCKET).ok()?;
+    match UnixStream::connect_addr(&addr) {
+        Ok(conn) => {
+            debug!("connected to break-enforcer service on abstract unix socket");
+            Some(conn)
+        }
+        Err(e) => {
+            debug!("error connecting to api on abstract unix socket: {e}. Trying tcp");
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract() -> Option<UnixStream> {
+    None
+}
+
+/// Skips services that turn out not to be break-enforcer
+fn connect_tcp(ports: &[u16]) -> Result<Api, Error> {
+    let mut res = Err(Error::CouldNotConnect);
+    for ip in [
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ] {
+        for &port in ports {
+            match TcpStream::connect(SocketAddr::new(ip, port)) {
+                Ok(conn) => {
+                    debug!("connected to a service on {ip} port: {port}");
+                    res = Api::handshake(conn, &format!("{ip} port {port}"));
+                    match res {
+                        Err(Error::NotBreakEnforcer { .. }) => continue,
+                        _ => return res,
+                    }
                 }
                 Err(e) => {
                     debug!(
-                        "error connecting to api on port: {port}. Error: {e}. Trying another port"
+                        "error connecting to api on {ip} port: {port}. Error: {e}. Trying another port"
                     );
                 }
             };
         }
+    }
+    res
+}
+
+impl Api {
+    /// Tries the abstract unix socket (linux only) first then the ipv4 and
+    /// ipv6 loopback addresses. Verifies the other side is break-enforcer
+    /// speaking the same protocol version.
+    ///
+    /// If the `BREAK_ENFORCER_API_PORT` environment variable is set this
+    /// behaves like [`Api::with_port`] using that port.
+    pub fn new() -> Result<Self, Error> {
+        match std::env::var(PORT_ENV) {
+            Ok(port) => {
+                let port = port.parse().map_err(|_| Error::InvalidPort(port))?;
+                Self::with_port(port)
+            }
+            Err(_) => match connect_abstract() {
+                Some(conn) => Self::handshake(conn, "abstract unix socket"),
+                None => connect_tcp(&PORTS),
+            },
+        }
+    }
+
+    /// Only connect to the service listening on this port, for when it was
+    /// started with `--api-port`.
+    pub fn with_port(port: u16) -> Result<Self, Error> {
+        connect_tcp(&[port])
+    }
 
-        let Some(conn) = conn else {
-            return Err(Error::CouldNotConnect);
+    /// Connect to a service serving the api on the network (`--listen`).
+    /// The service must present a certificate for the host in `addr`
+    /// (`host:port`) signed by `ca`, or `ca` must be that certificate.
+    /// Authenticates using `token`.
+    #[cfg(feature = "tls")]
+    pub fn connect_remote(addr: &str, ca: &Path, token: &str) -> Result<Self, Error> {
+        let (host, _) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| Error::InvalidAddress(addr.to_string()))?;
+        let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+            .map_err(|_| Error::InvalidAddress(addr.to_string()))?;
+        let config = tls_config(ca)?;
+        let mut stream = TcpStream::connect(addr).map_err(|source| Error::CouldNotConnectTo {
+            addr: addr.to_string(),
+            source,
+        })?;
+        stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .map_err(Error::ReadingResponse)?;
+        let mut conn = ClientConnection::new(Arc::new(config), name).map_err(Error::Tls)?;
+        // otherwise a certificate error would look like a foreign service
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)
+                .map_err(|source| Error::TlsHandshake {
+                    addr: addr.to_string(),
+                    source,
+                })?;
+        }
+        let conn = SharedStream::new(rustls::StreamOwned::new(conn, stream));
+        let mut api = Self::handshake(conn, addr)?;
+        api.request(&format!("auth {token}"))?;
+        Ok(api)
+    }
+
+    fn handshake(conn: impl Conn, addr: &str) -> Result<Self, Error> {
+        let not_break_enforcer = || Error::NotBreakEnforcer {
+            addr: addr.to_string(),
+        };
+        conn.set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .map_err(Error::ReadingResponse)?;
+        let timeout_handle = conn.try_clone().map_err(Error::ReadingResponse)?;
+        let writer = conn.try_clone().map_err(Error::WritingRequest)?;
+        let mut api = Self {
+            reader: BufReader::new(Box::new(conn)),
+            writer: Box::new(writer),
+            server_version: String::new(),
+            set_read_timeout: Box::new(move |timeout| timeout_handle.set_read_timeout(timeout)),
         };
 
-        let writer = conn.try_clone().expect("tcp stream clone failed");
-        let reader = BufReader::new(conn);
+        let hello = api.request("hello").map_err(|e| {
+            debug!("no valid answer to hello from {addr}: {e}");
+            not_break_enforcer()
+        })?;
+        let mut parts = hello.split(' ');
+        let (Some(NAME), Some(version), Some(protocol), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            debug!("unexpected answer to hello from {addr}: {hello}");
+            return Err(not_break_enforcer());
+        };
+        let protocol: u32 = protocol.parse().map_err(|_| not_break_enforcer())?;
+        if protocol != PROTOCOL_VERSION {
+            return Err(Error::ProtocolMismatch {
+                server_version: version.to_string(),
+                server: protocol,
+                ours: PROTOCOL_VERSION,
+            });
+        }
 
-        Ok(Self { reader, writer })
+        (api.set_read_timeout)(None).map_err(Error::ReadingResponse)?;
+        api.server_version = version.to_string();
+        Ok(api)
     }
 
-    pub fn idle_since(&mut self) -> Result<Duration, Error> {
-        let mut request = b"idle_since".to_vec();
+    /// Version of the break-enforcer service we are connected to
+    #[must_use]
+    pub fn server_version(&self) -> &str {
+        &self.server_version
+    }
+
+    /// Asks the service for its version and protocol version, these are
+    /// also checked when connecting (see [`Api::server_version`])
+    pub fn version(&mut self) -> Result<(String, u32), Error> {
+        let packet = self.request("version")?;
+        let (version, protocol) = packet.split_once(' ').unwrap_or((&packet, ""));
+        let protocol = protocol.parse().map_err(|error| Error::IncorrectResponse {
+            packet: packet.clone(),
+            error,
+        })?;
+        Ok((version.to_string(), protocol))
+    }
+
+    fn request(&mut self, request: &str) -> Result<String, Error> {
+        let mut request = request.as_bytes().to_vec();
         request.push(STOP_BYTE);
         self.writer
             .write_all(&request)
             .map_err(Error::WritingRequest)?;
 
-        let mut buf = Vec::new();
-        let n_read = self
-            .reader
-            .read_until(STOP_BYTE, &mut buf)
-            .map_err(Error::ReadingResponse)?;
+        read_frame(&mut self.reader)
+    }
 
-        if n_read == 0 {
-            return Err(Error::ConnectionClosed);
-        }
+    /// Turns this connection into a stream of state updates. The first update
+    /// is the current state.
+    #[cfg(feature = "subscribe")]
+    pub fn subscribe(mut self) -> Result<Subscription, Error> {
+        let mut request = b"subscribe".to_vec();
+        request.push(STOP_BYTE);
+        self.writer
+            .write_all(&request)
+            .map_err(Error::WritingRequest)?;
+        Ok(Subscription {
+            reader: self.reader,
+            _writer: self.writer,
+            set_read_timeout: self.set_read_timeout,
+        })
+    }
 
-        let packet = &buf[..(n_read - 1)]; // leave off STOP_BYTE
-        let packet = String::from_utf8(packet.to_vec()).map_err(Error::CorruptResponse)?;
+    /// Subscribes and blocks until an update matches `pattern`, returns at
+    /// once if the current state matches. Fails with
+    /// [`Error::WaitTimedOut`] if nothing matched within `timeout`.
+    ///
+    /// ```no_run
+    /// # use break_enforcer::{Api, StateUpdatePattern};
+    /// Api::new()?.wait_for(StateUpdatePattern::Break, None)?;
+    /// println!("break started, running the backup");
+    /// # Ok::<(), break_enforcer::Error>(())
+    /// ```
+    #[cfg(feature = "subscribe")]
+    pub fn wait_for(
+        self,
+        pattern: StateUpdatePattern,
+        timeout: Option<Duration>,
+    ) -> Result<StateUpdate, Error> {
+        let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+        let mut updates = self.subscribe()?;
+        loop {
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(std::time::Instant::now());
+                if left.is_zero() {
+                    return Err(Error::WaitTimedOut(pattern));
+                }
+                (updates.set_read_timeout)(Some(left)).map_err(Error::ReadingResponse)?;
+            }
+            match updates.next() {
+                Some(Ok(update)) if pattern.matches(&update) => return Ok(update),
+                Some(Ok(_)) => (),
+                Some(Err(Error::ReadingResponse(e)))
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(Error::WaitTimedOut(pattern));
+                }
+                Some(Err(other)) => return Err(other),
+                None => return Err(Error::ConnectionClosed),
+            }
+        }
+    }
 
+    pub fn idle_since(&mut self) -> Result<Duration, Error> {
+        let packet = self.request("idle_since")?;
         let seconds_idle = packet
             .as_str()
             .parse::<u64>()
@@ -91,25 +439,285 @@ impl Api {
         Ok(Duration::from_secs(seconds_idle))
     }
 
+    /// Like [`Api::idle_since`] in milliseconds and with when the input
+    /// stopped, clients can count on from there between requests
+    pub fn idle(&mut self) -> Result<Idle, Error> {
+        let packet = self.request("idle_since_ms")?;
+        let (idle, since) = packet.split_once(' ').unwrap_or((&packet, ""));
+        let parse = |millis: &str| {
+            millis
+                .parse()
+                .map(Duration::from_millis)
+                .map_err(|error| Error::IncorrectResponse {
+                    packet: packet.clone(),
+                    error,
+                })
+        };
+        Ok(Idle {
+            duration: parse(idle)?,
+            since: parse(since)?,
+        })
+    }
+
+    /// How long it took to block the last device that connected, or
+    /// reconnected, while blocked. Fails if none did yet.
+    pub fn relock_latency(&mut self) -> Result<Duration, Error> {
+        let packet = self.request("relock_latency_ms")?;
+        let millis = packet
+            .as_str()
+            .parse::<u64>()
+            .map_err(|error| Error::IncorrectResponse { packet, error })?;
+        Ok(Duration::from_millis(millis))
+    }
+
+    /// How the parts of the service are doing, to notice a service that
+    /// runs but does not block anything
+    #[cfg(feature = "subscribe")]
+    pub fn health(&mut self) -> Result<Health, Error> {
+        let packet = self.request("health")?;
+        ron::from_str(&packet).map_err(|error| Error::IncorrectUpdate { packet, error })
+    }
+
+    /// What is left of today's limit on working (`--daily-limit`). Fails if
+    /// the service runs without one.
+    pub fn daily_budget(&mut self) -> Result<Duration, Error> {
+        let packet = self.request("daily_budget")?;
+        let seconds = packet
+            .as_str()
+            .parse::<u64>()
+            .map_err(|error| Error::IncorrectResponse { packet, error })?;
+        Ok(Duration::from_secs(seconds))
+    }
+
     pub fn status(&mut self) -> Result<String, Error> {
-        let mut request = b"status_msg".to_vec();
-        request.push(STOP_BYTE);
-        self.writer
-            .write_all(&request)
-            .map_err(Error::WritingRequest)?;
+        self.request("status_msg")
+    }
 
-        let mut buf = Vec::new();
-        let n_read = self
-            .reader
-            .read_until(STOP_BYTE, &mut buf)
-            .map_err(Error::ReadingResponse)?;
+    /// How far through the current work period, break, pause or meeting
+    /// from 0 to 1, for progress bars. 0 while waiting for activity.
+    pub fn progress(&mut self) -> Result<f32, Error> {
+        let packet = self.request("progress")?;
+        packet
+            .as_str()
+            .parse::<f32>()
+            .map_err(|error| Error::IncorrectFraction { packet, error })
+    }
+
+    /// A stretch or eye exercise to do during the break, the same one for the
+    /// whole break. Outside a break every call picks a new one. Fails if the
+    /// service runs without `--break-coach`.
+    pub fn suggest_activity(&mut self) -> Result<String, Error> {
+        self.request("suggest_activity")
+    }
+
+    /// Which minutes of the last week had input, for example to draw a
+    /// heatmap. Minutes before the service started count as inactive.
+    #[cfg(feature = "subscribe")]
+    pub fn activity_map(&mut self) -> Result<ActivityMap, Error> {
+        let packet = self.request("activity_map")?;
+        ron::from_str(&packet).map_err(|error| Error::IncorrectUpdate { packet, error })
+    }
+
+    /// The next `count` breaks if the schedule is followed from now on,
+    /// without the randomness of `--break-jitter`. Ends early with the
+    /// curfew or daily limit if one comes first.
+    #[cfg(feature = "subscribe")]
+    pub fn schedule_preview(&mut self, count: u8) -> Result<Vec<PlannedBreak>, Error> {
+        let packet = self.request(&format!("schedule_preview {count}"))?;
+        ron::from_str(&packet).map_err(|error| Error::IncorrectUpdate { packet, error })
+    }
+
+    /// The recent state transitions, oldest first. Does not include progress
+    /// updates.
+    #[cfg(feature = "subscribe")]
+    pub fn history(&mut self) -> Result<Vec<StateUpdate>, Error> {
+        let packet = self.request("history")?;
+        ron::from_str(&packet).map_err(|error| Error::IncorrectUpdate { packet, error })
+    }
+
+    /// Add all connected devices with this id to the devices blocked during
+    /// breaks. The change is persisted and takes effect from the next break.
+    ///
+    /// The id is the base64 id as stored in the config.
+    pub fn block_device(&mut self, id: &str) -> Result<(), Error> {
+        self.request(&format!("block_device {id}")).map(|_| ())
+    }
+
+    /// Stop blocking devices with this id. The change is persisted and takes
+    /// effect from the next break.
+    pub fn unblock_device(&mut self, id: &str) -> Result<(), Error> {
+        self.request(&format!("unblock_device {id}")).map(|_| ())
+    }
+
+    /// Read the devices to block from the config again, after it was
+    /// edited. Takes effect from the next break.
+    pub fn reload_config(&mut self) -> Result<(), Error> {
+        self.request("reload_config").map(|_| ())
+    }
+
+    /// No breaks and no counting work time for `duration`. Ends a running
+    /// break. Pausing while paused changes when the pause ends.
+    pub fn pause(&mut self, duration: Duration) -> Result<(), Error> {
+        self.request(&format!("pause {}", duration.as_secs()))
+            .map(|_| ())
+    }
+
+    /// Ends a pause early, the work period continues where it was paused
+    pub fn resume(&mut self) -> Result<(), Error> {
+        self.request("resume").map(|_| ())
+    }
+
+    /// Ends a running break or restarts the work period, either way a full
+    /// work period starts now.
+    pub fn skip(&mut self) -> Result<(), Error> {
+        self.request("skip").map(|_| ())
+    }
+
+    /// Moves the coming break back by `duration`, during a break ends it and
+    /// starts a work period of `duration`. The service picks the duration
+    /// (5 minutes) if it is `None`.
+    pub fn snooze(&mut self, duration: Option<Duration>) -> Result<(), Error> {
+        match duration {
+            Some(duration) => self.request(&format!("snooze {}", duration.as_secs())),
+            None => self.request("snooze"),
+        }
+        .map(|_| ())
+    }
+
+    /// Ends a running break
+    pub fn unlock(&mut self) -> Result<(), Error> {
+        self.request("unlock").map(|_| ())
+    }
+
+    /// Ends the work period and starts a break of `duration` right away. The
+    /// service uses the break duration if it is `None`. Fails during a
+    /// break.
+    pub fn break_now(&mut self, duration: Option<Duration>) -> Result<(), Error> {
+        match duration {
+            Some(duration) => self.request(&format!("break_now {}", duration.as_secs())),
+            None => self.request("break_now"),
+        }
+        .map(|_| ())
+    }
+
+    /// Lengthens the running break by `duration`, the devices stay blocked.
+    /// Fails outside a break.
+    pub fn extend(&mut self, duration: Duration) -> Result<(), Error> {
+        self.request(&format!("extend {}", duration.as_secs()))
+            .map(|_| ())
+    }
+
+    /// Lifts the curfew (`--curfew`) for the rest of the night. Fails if it
+    /// does not apply right now or the overrides of this week are used up.
+    pub fn lift_curfew(&mut self) -> Result<(), Error> {
+        self.request("lift_curfew").map(|_| ())
+    }
+
+    /// Holds off breaks for `duration` while work time keeps counting. Breaks
+    /// due in the meantime are owed and taken together once the meeting
+    /// ends. A meeting during a meeting changes when it ends.
+    pub fn meeting(&mut self, duration: Duration) -> Result<(), Error> {
+        self.request(&format!("meeting {}", duration.as_secs()))
+            .map(|_| ())
+    }
+
+    /// Ends a meeting early, the break owed starts right away
+    pub fn end_meeting(&mut self) -> Result<(), Error> {
+        self.request("end_meeting").map(|_| ())
+    }
 
-        if n_read == 0 {
-            return Err(Error::ConnectionClosed);
+    /// Makes the service replace itself with the binary on disk, for example
+    /// after an upgrade. The schedule continues and devices blocked for a
+    /// break stay blocked. The connection closes once the service restarts.
+    pub fn reexec(&mut self) -> Result<(), Error> {
+        self.request("reexec").map(|_| ())
+    }
+}
+
+/// See [`Api::idle`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Idle {
+    /// How long there has been no input
+    pub duration: Duration,
+    /// When the input stopped on `CLOCK_BOOTTIME`. Unlike
+    /// [`Timestamp::monotonic`] that clock counts suspend, as `duration` does.
+    pub since: Duration,
+}
+
+/// Which updates [`Api::wait_for`] waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateUpdatePattern {
+    Waiting,
+    Work,
+    Break,
+    Paused,
+    Meeting,
+    Warning,
+    /// Any change of state, progress updates and warnings are not
+    AnyState,
+}
+
+impl StateUpdatePattern {
+    #[must_use]
+    pub fn matches(self, update: &StateUpdate) -> bool {
+        match (self, update) {
+            (StateUpdatePattern::Waiting, StateUpdate::Waiting { .. })
+            | (StateUpdatePattern::Work, StateUpdate::Work { .. })
+            | (StateUpdatePattern::Break, StateUpdate::Break { .. })
+            | (StateUpdatePattern::Paused, StateUpdate::Paused { .. })
+            | (StateUpdatePattern::Meeting, StateUpdate::Meeting { .. })
+            | (StateUpdatePattern::Warning, StateUpdate::Warning { .. }) => true,
+            (StateUpdatePattern::AnyState, update) => !matches!(
+                update,
+                StateUpdate::Progress { .. } | StateUpdate::Warning { .. }
+            ),
+            _ => false,
         }
+    }
+}
+
+/// Receives [`StateUpdate`]s, see [`Api::subscribe`]
+#[cfg(feature = "subscribe")]
+pub struct Subscription {
+    reader: BufReader<Box<dyn Read + Send>>,
+    // closing our end would end the subscription
+    _writer: Box<dyn Write + Send>,
+    set_read_timeout: Box<dyn Fn(Option<Duration>) -> std::io::Result<()> + Send>,
+}
+
+#[cfg(feature = "subscribe")]
+impl Iterator for Subscription {
+    type Item = Result<StateUpdate, Error>;
+
+    /// Blocks till the next update, ends when the service closes the
+    /// connection
+    fn next(&mut self) -> Option<Self::Item> {
+        let packet = match read_frame(&mut self.reader) {
+            Ok(packet) => packet,
+            Err(Error::ConnectionClosed) => return None,
+            Err(other) => return Some(Err(other)),
+        };
+        Some(ron::from_str(&packet).map_err(|error| Error::IncorrectUpdate { packet, error }))
+    }
+}
+
+fn read_frame(reader: &mut impl BufRead) -> Result<String, Error> {
+    let mut buf = Vec::new();
+    let n_read = reader
+        .read_until(STOP_BYTE, &mut buf)
+        .map_err(Error::ReadingResponse)?;
+
+    if n_read == 0 {
+        return Err(Error::ConnectionClosed);
+    }
 
-        let packet = &buf[..(n_read - 1)]; // leave off STOP_BYTE
-        let status = String::from_utf8(packet.to_vec()).map_err(Error::CorruptResponse)?;
-        Ok(status)
+    let packet = &buf[..(n_read - 1)]; // leave off STOP_BYTE
+    let packet = String::from_utf8(packet.to_vec()).map_err(Error::CorruptResponse)?;
+    match proto::parse_error_frame(&packet) {
+        Some((code, message)) => Err(Error::ServerError {
+            code,
+            message: message.to_string(),
+        }),
+        None => Ok(packet),
     }
 }
@@ -0,0 +1,68 @@
+//! Enforcing breaks by locking the session instead of blocking the devices,
+//! see `enforcement = "screenlock"` in the config or `--block-backend
+//! screen-lock`. Weaker as unlocking ends it, so the session is locked again
+//! whenever it is unlocked during the break. Works where grabbing the devices
+//! conflicts with the compositor. Only the session of the user is locked,
+//! see `session::active`.
+
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use color_eyre::Result;
+use tracing::{debug, info, warn};
+
+use crate::session;
+
+/// How often to check the session is still locked
+const RELOCK_CHECK: Duration = Duration::from_secs(2);
+
+/// Keeps the session locked until `stop` is called
+#[must_use]
+pub(crate) struct Locker {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+pub(crate) fn start() -> Locker {
+    let (stop, stopped) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        let mut relocks = 0u32;
+        loop {
+            match lock_if_unlocked() {
+                Ok(false) => (),
+                Ok(true) => {
+                    if relocks > 0 {
+                        info!("Session unlocked during the break, locked it again");
+                    }
+                    relocks += 1;
+                }
+                Err(report) => warn!("Could not lock the session: {report:#}"),
+            }
+            match stopped.recv_timeout(RELOCK_CHECK) {
+                Err(RecvTimeoutError::Timeout) => (),
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        debug!("Break over, stopped locking after {relocks} lock(s)");
+    });
+    Locker { stop, thread }
+}
+
+impl Locker {
+    /// The session stays locked, the user unlocks it once the break is over
+    pub(crate) fn stop(self) {
+        let _already_stopped = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+/// True if the session had to be locked
+fn lock_if_unlocked() -> Result<bool> {
+    let session = session::active()?;
+    if session.locked()? {
+        return Ok(false);
+    }
+    session.lock()?;
+    Ok(true)
+}
@@ -0,0 +1,113 @@
+use break_enforcer::{Api, BreakKind, ResetReason, StateUpdate};
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+
+use crate::cli::LogArgs;
+use crate::integration::{self, fmt_dur};
+use crate::paths::Dirs;
+
+fn describe(update: &StateUpdate) -> String {
+    match update {
+        StateUpdate::Waiting { reset: None, .. } => "waiting for activity".to_string(),
+        StateUpdate::Waiting {
+            reset: Some(reason),
+            ..
+        } => {
+            let reason = match reason {
+                ResetReason::Idle => "idle for longer then a break",
+                ResetReason::LongIdle => "idle for longer then a long break",
+                ResetReason::Manual => "reset through the api",
+            };
+            format!("work period reset ({reason}), waiting for activity")
+        }
+        StateUpdate::Work { until_break, .. } => {
+            format!("work started, break in {}", fmt_dur(*until_break))
+        }
+        StateUpdate::Break {
+            until_work, kind, ..
+        } => {
+            let kind = match kind {
+                BreakKind::Short => "break",
+                BreakKind::Long => "long break",
+                BreakKind::Micro => "micro break",
+            };
+            format!("{kind} started, unlocks in {}", fmt_dur(*until_work))
+        }
+        StateUpdate::Paused { until_resume, .. } => {
+            format!("paused, resumes in {}", fmt_dur(*until_resume))
+        }
+        StateUpdate::Meeting {
+            until_end, debt, ..
+        } => format!(
+            "meeting, ends in {} with {} of break owed",
+            fmt_dur(*until_end),
+            fmt_dur(*debt)
+        ),
+        StateUpdate::Progress {
+            remaining, total, ..
+        } => format!("{} of {} break left", fmt_dur(*remaining), fmt_dur(*total)),
+        StateUpdate::Warning { message, .. } => format!("warning: {message}"),
+    }
+}
+
+fn wall_clock(update: &StateUpdate) -> DateTime<Local> {
+    let at = match update {
+        StateUpdate::Waiting { at, .. }
+        | StateUpdate::Work { at, .. }
+        | StateUpdate::Break { at, .. }
+        | StateUpdate::Paused { at, .. }
+        | StateUpdate::Meeting { at, .. }
+        | StateUpdate::Progress { at, .. }
+        | StateUpdate::Warning { at, .. } => at,
+    };
+    DateTime::from(at.wall_clock)
+}
+
+pub(crate) fn print(update: &StateUpdate, use_json: bool) -> Result<()> {
+    if use_json {
+        let json = serde_json::to_string(update).wrap_err("Could not serialize state update")?;
+        println!("{json}");
+    } else {
+        let time = wall_clock(update).format("%Y-%m-%d %H:%M:%S");
+        println!("{time}  {}", describe(update));
+    }
+    Ok(())
+}
+
+pub(crate) fn run(LogArgs { follow, use_json }: &LogArgs, dirs: &Dirs) -> Result<()> {
+    let mut api = match Api::new() {
+        Ok(api) => api,
+        Err(e) if !follow && dirs.history_file().exists() => {
+            eprintln!("Could not reach the service ({e}), showing the persisted history");
+            for update in integration::read_history(&dirs.history_file())? {
+                print(&update, *use_json)?;
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not connect to the service")
+                .suggestion("Is break-enforcer running with its tcp api enabled? (use --tcp-api)")
+                .suggestion("Run the service with --persist-history to view the log while it is not running");
+        }
+    };
+
+    for update in api.history().wrap_err("Could not get history")? {
+        print(&update, *use_json)?;
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let updates = api.subscribe().wrap_err("Could not subscribe to updates")?;
+    // the first update is the current state which is already in the history
+    for update in updates.skip(1) {
+        let update = update.wrap_err("Could not receive update")?;
+        if let StateUpdate::Progress { .. } = update {
+            continue;
+        }
+        print(&update, *use_json)?;
+    }
+    Err(eyre!("The service closed the connection")).suggestion("Did it stop?")
+}
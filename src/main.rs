@@ -1,21 +1,58 @@
-#![feature(thread_sleep_until)]
-#![feature(iter_intersperse)]
-#![feature(io_error_more)]
-#![feature(iter_collect_into)]
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
 
 use clap::Parser;
 use color_eyre::eyre::Context;
 use color_eyre::{eyre::eyre, Section};
+use std::path::Path;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::fmt::time::uptime;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
 
+mod activity_map;
+mod break_command;
+mod check;
 mod check_inputs;
 mod cli;
+mod clock;
+mod compositor;
 mod config;
+mod control;
+mod crash;
+mod ctl;
+mod curfew;
+mod daily_limit;
+mod device_class;
+mod devices;
+mod docs;
+mod doctor;
+mod enforcement;
+mod forward;
+mod input_queue;
 mod install;
-mod status;
 mod integration;
+mod joystick;
+mod lock_session;
+mod log;
+mod new_keyboards;
+#[cfg(feature = "overlay")]
+mod overlay;
+mod password_prompt;
+mod paths;
+mod privsep;
+mod reexec;
 mod run;
-mod tcp_api_config;
+mod screen_lock;
+mod session;
+mod shared_stream;
+mod stats;
+mod status;
+mod supervisor;
+mod sync;
+#[cfg(feature = "tray")]
+mod tray;
+mod watch;
 mod watch_and_block;
 mod wizard;
 
@@ -27,18 +64,7 @@ fn main() -> color_eyre::Result<()> {
 
     let cli = cli::Cli::parse();
 
-    let trace_level = if cli.verbose {
-        tracing::Level::TRACE
-    } else {
-        tracing::Level::WARN
-    };
-
-    tracing_subscriber::fmt()
-        .with_max_level(trace_level)
-        .with_file(false)
-        .with_target(false)
-        .with_timer(uptime())
-        .init();
+    setup_tracing(&cli.log_filter, cli.log_file.as_deref())?;
 
     // check after args such that help can run without root
     if let sudo::RunningAs::User = sudo::check() {
@@ -53,13 +79,99 @@ fn main() -> color_eyre::Result<()> {
         }
     }
 
+    let log_args = cli.log_args();
+    let dirs = paths::Dirs::new(cli.state_dir, cli.runtime_dir);
+    paths::set_dev_dir(cli.dev_dir);
     match cli.command {
-        cli::Commands::Run(args) => run::run(args, cli.config_path),
-        cli::Commands::Wizard => wizard::run(cli.config_path).wrap_err("Error running wizard"),
-        cli::Commands::Status(args) => status::run(args).wrap_err("Could not print status"),
+        cli::Commands::Run(args) | cli::Commands::Timer(args) => {
+            run::run(args, cli.config_path, dirs)
+        }
+        cli::Commands::Wizard => {
+            wizard::run(cli.config_path, &dirs, &log_args).wrap_err("Error running wizard")
+        }
+        cli::Commands::Status(args) => status::run(args, &dirs).wrap_err("Could not print status"),
+        cli::Commands::Log(args) => log::run(&args, &dirs).wrap_err("Could not show log"),
+        cli::Commands::Watch(args) => watch::run(&args),
+        cli::Commands::Stats(args) => stats::run(&args, &dirs).wrap_err("Could not get stats"),
         cli::Commands::Install(args) => {
-            install::set_up(&args, cli.config_path).wrap_err("Could not install")
+            install::set_up(&args, cli.config_path, &dirs, &log_args).wrap_err("Could not install")
         }
-        cli::Commands::Remove => install::tear_down().wrap_err("Could not remove"),
+        cli::Commands::Remove(args) => install::tear_down(&args).wrap_err("Could not remove"),
+        cli::Commands::Check(args) => check::run(&args, cli.config_path),
+        cli::Commands::Doctor => doctor::run(cli.config_path),
+        cli::Commands::Config(args) => config::run(args, cli.config_path),
+        cli::Commands::Ctl(args) => ctl::run(&args),
+        #[cfg(feature = "tray")]
+        cli::Commands::Tray(args) => tray::run(&args, cli.config_path),
+        #[cfg(feature = "overlay")]
+        cli::Commands::Overlay(args) => overlay::run(&args),
+        cli::Commands::Devices(args) => {
+            devices::run(args, cli.config_path).wrap_err("Could not list devices")
+        }
+        cli::Commands::Completions(args) => {
+            docs::completions(&args);
+            Ok(())
+        }
+        cli::Commands::Manpage(args) => docs::manpage(&args),
     }
 }
+
+fn setup_tracing(filter: &str, log_file: Option<&Path>) -> color_eyre::Result<()> {
+    let filter = EnvFilter::try_new(filter)
+        .wrap_err("Invalid log filter")
+        .suggestion("See the EnvFilter docs of the tracing-subscriber crate for the syntax")?;
+    if log_file.is_none() {
+        if let Some(journald) = journald() {
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(journald)
+                .with(crash::Recent)
+                .init();
+            return Ok(());
+        }
+    }
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_file(false)
+        .with_target(false)
+        .with_timer(uptime());
+
+    let Some(log_file) = log_file else {
+        subscriber.finish().with(crash::Recent).init();
+        return Ok(());
+    };
+
+    let name = log_file
+        .file_name()
+        .ok_or_else(|| eyre!("The log file path must end in a file name"))?;
+    let dir = log_file
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(name.to_string_lossy())
+        .max_log_files(7)
+        .build(dir)
+        .wrap_err("Could not open log file")
+        .with_note(|| format!("path: {}", log_file.display()))?;
+    subscriber
+        .with_writer(appender)
+        .with_ansi(false)
+        .finish()
+        .with(crash::Recent)
+        .init();
+    Ok(())
+}
+
+/// When systemd passes our output to the journal log there directly, that
+/// keeps the fields of events (like `STATE` and `DEVICE`) queryable with
+/// `journalctl -t break-enforcer -o json`
+fn journald() -> Option<tracing_journald::Layer> {
+    std::env::var_os("JOURNAL_STREAM")?;
+    tracing_journald::layer().ok().map(|layer| {
+        layer
+            .with_syslog_identifier(env!("CARGO_PKG_NAME").to_string())
+            .with_field_prefix(None)
+    })
+}
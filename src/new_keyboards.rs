@@ -0,0 +1,144 @@
+//! Asks to block keyboards the first time they are connected, see
+//! `--ask-new-keyboards`. Otherwise new hardware is not blocked until the
+//! wizard is run again.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use tracing::{error, info};
+
+use crate::config::BlockList;
+use crate::device_class::DeviceClass;
+use crate::integration::notification;
+use crate::watch_and_block::{InputId, OnlineDevices};
+
+/// The keyboards asked about before, kept across reboots
+struct Seen {
+    ids: HashSet<InputId>,
+    path: PathBuf,
+}
+
+impl Seen {
+    fn load(path: PathBuf) -> Result<Self> {
+        let ids = match std::fs::read_to_string(&path) {
+            Ok(data) => ron::from_str(&data)
+                .wrap_err("Could not parse the seen keyboards")
+                .with_note(|| format!("path: {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not read the seen keyboards")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        };
+        Ok(Self { ids, path })
+    }
+
+    /// Remembers the ids, true if any of them is new
+    fn first_time(&mut self, ids: impl IntoIterator<Item = InputId>) -> bool {
+        let before = self.ids.len();
+        self.ids.extend(ids);
+        if self.ids.len() == before {
+            return false;
+        }
+        let res = ron::to_string(&self.ids)
+            .map_err(|e| eyre!("Could not serialize: {e}"))
+            .and_then(|data| std::fs::write(&self.path, data).wrap_err("Could not write"));
+        if let Err(report) = res {
+            error!("Could not save the seen keyboards: {report:?}");
+        }
+        true
+    }
+}
+
+/// The keyboards connected now count as seen, only those connected later
+/// are asked about
+pub(crate) fn ask(online: &OnlineDevices, to_block: BlockList, path: PathBuf) -> Result<()> {
+    let mut seen = Seen::load(path)?;
+    let connected = online
+        .list_inputs()
+        .wrap_err("Could not list inputs")?
+        .into_iter()
+        .filter(|input| {
+            input
+                .details
+                .iter()
+                .any(|details| details.classes.contains(&DeviceClass::Keyboard))
+        })
+        .map(|input| input.id);
+    seen.first_time(connected);
+
+    let seen = Mutex::new(seen);
+    online.on_keyboard_added(move |input| {
+        if to_block.matches(&input.id, &input.name, input.address.as_deref()) {
+            return;
+        }
+        if !seen.lock().unwrap().first_time([input.id]) {
+            return;
+        }
+        let text = question(&input.name, input.id);
+        info!(device = input.name, "{text}");
+        // the devices stay locked while the callback runs
+        thread::spawn(move || {
+            if let Err(report) = notification::notify(&text) {
+                error!("Failed to ask about the new keyboard: {report}");
+            }
+        });
+    });
+    Ok(())
+}
+
+fn question(name: &str, id: InputId) -> String {
+    format!(
+        "New keyboard detected: {name}. To block it during breaks run: \
+        break-enforcer ctl block {id}"
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn device_name_is_not_interpreted() {
+        let name = "x\"$(touch /tmp/pwned)`id`; rm -rf / #";
+        let text = question(name, "AAEAAgAD".parse().unwrap());
+        let command = notification::notify_send_as("user", "1000", &text);
+
+        assert_eq!(command.get_program(), "sudo");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-u",
+                "user",
+                "DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/1000/bus",
+                "notify-send",
+                "-t",
+                "5000",
+                "--",
+                text.as_str(),
+            ]
+        );
+    }
+
+    #[test]
+    fn asks_once_per_keyboard() {
+        let path = std::env::temp_dir().join(format!("seen-keyboards-{}", std::process::id()));
+        let keyboard: InputId = "AAEAAgAD".parse().unwrap();
+        let other: InputId = "AAEAAgAE".parse().unwrap();
+
+        let mut seen = Seen::load(path.clone()).unwrap();
+        assert!(seen.first_time([keyboard]));
+        assert!(!seen.first_time([keyboard]));
+        // kept across restarts
+        let mut seen = Seen::load(path.clone()).unwrap();
+        assert!(!seen.first_time([keyboard]));
+        assert!(seen.first_time([keyboard, other]));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,414 @@
+//! Full screen countdown during breaks, runs as the user in their session.
+//! The service blocks input, this shows how long that lasts.
+
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use break_enforcer::{Api, StateUpdate};
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use softbuffer::Surface;
+use tracing::{debug, error};
+use winit::application::ApplicationHandler;
+use winit::event::{StartCause, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
+use winit::window::{Fullscreen, Window, WindowId};
+
+use crate::cli::OverlayArgs;
+use crate::status;
+
+const RECONNECT: Duration = Duration::from_secs(5);
+const BACKGROUND: u32 = 0x0010_1418;
+const FOREGROUND: u32 = 0x00e0_e0e0;
+
+#[derive(Debug, Clone)]
+enum Update {
+    Break {
+        next_work: Instant,
+        /// suggested by the break coach
+        activity: Option<String>,
+    },
+    Hide,
+}
+
+struct Shown {
+    window: Rc<Window>,
+    canvas: Canvas,
+    next_work: Instant,
+    activity: Option<String>,
+}
+
+type Canvas = Surface<Rc<Window>, Rc<Window>>;
+
+#[derive(Default)]
+struct Overlay {
+    shown: Option<Shown>,
+}
+
+impl Overlay {
+    fn show(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        next_work: Instant,
+        activity: Option<String>,
+    ) -> Result<()> {
+        if let Some(shown) = &mut self.shown {
+            shown.next_work = next_work;
+            shown.activity = activity;
+            return Ok(());
+        }
+        let attributes = Window::default_attributes()
+            .with_title("break-enforcer")
+            .with_decorations(false)
+            .with_fullscreen(Some(Fullscreen::Borderless(None)));
+        let window = Rc::new(
+            event_loop
+                .create_window(attributes)
+                .wrap_err("Could not open a window")?,
+        );
+        let context = softbuffer::Context::new(window.clone())
+            .map_err(|e| eyre!("{e}"))
+            .wrap_err("Could not draw in the window")?;
+        let canvas = Surface::new(&context, window.clone())
+            .map_err(|e| eyre!("{e}"))
+            .wrap_err("Could not draw in the window")?;
+        window.request_redraw();
+        self.shown = Some(Shown {
+            window,
+            canvas,
+            next_work,
+            activity,
+        });
+        Ok(())
+    }
+
+    fn redraw(&mut self) -> Result<()> {
+        let Some(Shown {
+            window,
+            canvas,
+            next_work,
+            activity,
+        }) = &mut self.shown
+        else {
+            return Ok(());
+        };
+        let size = window.inner_size();
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            return Ok(());
+        };
+        canvas
+            .resize(width, height)
+            .map_err(|e| eyre!("{e}"))
+            .wrap_err("Could not resize")?;
+        let mut buffer = canvas.buffer_mut().map_err(|e| eyre!("{e}"))?;
+        let left = next_work.saturating_duration_since(Instant::now());
+        draw(
+            &mut buffer,
+            size.width as usize,
+            &countdown(left),
+            activity.as_deref(),
+        );
+        buffer.present().map_err(|e| eyre!("{e}"))
+    }
+}
+
+impl ApplicationHandler<Update> for Overlay {
+    fn resumed(&mut self, _: &ActiveEventLoop) {}
+
+    fn new_events(&mut self, _: &ActiveEventLoop, cause: StartCause) {
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            if let Some(shown) = &self.shown {
+                shown.window.request_redraw();
+            }
+        }
+    }
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, update: Update) {
+        match update {
+            Update::Break {
+                next_work,
+                activity,
+            } => {
+                if let Err(report) = self.show(event_loop, next_work, activity) {
+                    error!("Could not show the overlay: {report:?}");
+                }
+            }
+            Update::Hide => self.shown = None,
+        }
+    }
+
+    fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        if let WindowEvent::RedrawRequested = event {
+            if let Err(report) = self.redraw() {
+                error!("Could not draw the overlay: {report:?}");
+            }
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        // the countdown changes every second
+        let flow = match self.shown {
+            Some(_) => ControlFlow::WaitUntil(Instant::now() + Duration::from_secs(1)),
+            None => ControlFlow::Wait,
+        };
+        event_loop.set_control_flow(flow);
+    }
+}
+
+/// `m:ss`
+fn countdown(left: Duration) -> String {
+    // round up, the break is not over while showing 0:00
+    let secs = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Seven segment layout, bit 0 to 6: top, top right, bottom right, bottom,
+/// bottom left, top left, middle
+fn segments(digit: char) -> u8 {
+    match digit {
+        '0' => 0b011_1111,
+        '1' => 0b000_0110,
+        '2' => 0b101_1011,
+        '3' => 0b100_1111,
+        '4' => 0b110_0110,
+        '5' => 0b110_1101,
+        '6' => 0b111_1101,
+        '7' => 0b000_0111,
+        '8' => 0b111_1111,
+        '9' => 0b110_1111,
+        _ => 0,
+    }
+}
+
+fn fill(buffer: &mut [u32], width: usize, (x, y): (usize, usize), (w, h): (usize, usize)) {
+    let height = buffer.len() / width;
+    for row in y.min(height)..(y + h).min(height) {
+        let start = row * width;
+        buffer[start + x.min(width)..start + (x + w).min(width)].fill(FOREGROUND);
+    }
+}
+
+/// 5 by 7 pixel glyphs, one row per byte with the leftmost pixel in bit 4.
+/// Lowercase letters are drawn as uppercase.
+#[rustfmt::skip]
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        '.' => [0, 0, 0, 0, 0, 0b01100, 0b01100],
+        ',' => [0, 0, 0, 0, 0b01100, 0b00100, 0b01000],
+        '\'' => [0b00100, 0b00100, 0b01000, 0, 0, 0, 0],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        ':' => [0, 0b01100, 0b01100, 0, 0b01100, 0b01100, 0],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0, 0b00100],
+        '?' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0, 0b00100],
+        '/' => [0b00001, 0b00010, 0b00010, 0b00100, 0b01000, 0b01000, 0b10000],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        _ => [0; 7],
+    }
+}
+
+/// Draws `text` centered on a line starting at `y`, as large as fits but
+/// no taller then `max_h`
+fn draw_text(buffer: &mut [u32], width: usize, y: usize, max_h: usize, text: &str) {
+    // a glyph and the space after it are 6 pixels wide
+    let chars = text.chars().count().max(1);
+    let scale = (width * 9 / 10 / (chars * 6)).min(max_h / 7).max(1);
+    let text_w = (chars * 6 - 1) * scale;
+    let left = width.saturating_sub(text_w) / 2;
+    for (i, c) in text.chars().enumerate() {
+        let x = left + i * 6 * scale;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0b10000 >> col) != 0 {
+                    fill(
+                        buffer,
+                        width,
+                        (x + col * scale, y + row * scale),
+                        (scale, scale),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Draws `text` (digits and colons) centered in seven segment style with the
+/// `caption` below it
+fn draw(buffer: &mut [u32], width: usize, text: &str, caption: Option<&str>) {
+    buffer.fill(BACKGROUND);
+    let height = buffer.len() / width;
+    let digit_h = (height / 4).min(width / (text.len() * 3 / 4 + 1));
+    let (digit_w, stroke) = (digit_h / 2, (digit_h / 10).max(1));
+    let gap = digit_w / 2;
+    let advance = |c: char| {
+        if c == ':' {
+            stroke + gap
+        } else {
+            digit_w + gap
+        }
+    };
+    let text_w: usize = text.chars().map(advance).sum::<usize>().saturating_sub(gap);
+    let mut x = width.saturating_sub(text_w) / 2;
+    let y = height.saturating_sub(digit_h) / 2;
+    let half = digit_h / 2;
+
+    for c in text.chars() {
+        if c == ':' {
+            fill(buffer, width, (x, y + half / 2), (stroke, stroke));
+            fill(buffer, width, (x, y + half + half / 2), (stroke, stroke));
+        } else {
+            let on = segments(c);
+            let rects = [
+                ((x, y), (digit_w, stroke)),
+                ((x + digit_w - stroke, y), (stroke, half)),
+                ((x + digit_w - stroke, y + half), (stroke, half)),
+                ((x, y + digit_h - stroke), (digit_w, stroke)),
+                ((x, y + half), (stroke, half)),
+                ((x, y), (stroke, half)),
+                ((x, y + half - stroke / 2), (digit_w, stroke)),
+            ];
+            for (i, (at, size)) in rects.into_iter().enumerate() {
+                if on & (1 << i) != 0 {
+                    fill(buffer, width, at, size);
+                }
+            }
+        }
+        x += advance(c);
+    }
+
+    if let Some(caption) = caption {
+        draw_text(
+            buffer,
+            width,
+            y + digit_h + digit_h / 2,
+            digit_h / 4,
+            caption,
+        );
+    }
+}
+
+/// None if the service runs without the break coach
+fn suggested_activity(api_port: Option<u16>) -> Option<String> {
+    match status::connect(api_port).and_then(|mut api| api.suggest_activity()) {
+        Ok(activity) => Some(activity).filter(|a| !a.is_empty()),
+        Err(e) => {
+            debug!("No break activity: {e}");
+            None
+        }
+    }
+}
+
+fn follow_service(api_port: Option<u16>, proxy: &EventLoopProxy<Update>) {
+    loop {
+        match status::connect(api_port).and_then(Api::subscribe) {
+            Ok(updates) => {
+                let mut activity = None;
+                for update in updates {
+                    let update = match update {
+                        Ok(update) => update,
+                        Err(e) => {
+                            debug!("Lost connection to break-enforcer: {e}");
+                            break;
+                        }
+                    };
+                    let update = match update {
+                        StateUpdate::Break { until_work, .. } => {
+                            activity = suggested_activity(api_port);
+                            Update::Break {
+                                next_work: Instant::now() + until_work,
+                                activity: activity.clone(),
+                            }
+                        }
+                        StateUpdate::Progress { remaining, .. } => Update::Break {
+                            next_work: Instant::now() + remaining,
+                            activity: activity.clone(),
+                        },
+                        // does not change the state
+                        StateUpdate::Warning { .. } => continue,
+                        _ => Update::Hide,
+                    };
+                    if proxy.send_event(update).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => debug!("Could not connect to break-enforcer: {e}"),
+        }
+        if proxy.send_event(Update::Hide).is_err() {
+            return;
+        }
+        thread::sleep(RECONNECT);
+    }
+}
+
+pub(crate) fn run(args: &OverlayArgs) -> Result<()> {
+    let event_loop = EventLoop::with_user_event()
+        .build()
+        .wrap_err("Could not connect to the display server")?;
+    let proxy = event_loop.create_proxy();
+    let api_port = args.api_port;
+    thread::spawn(move || follow_service(api_port, &proxy));
+    event_loop
+        .run_app(&mut Overlay::default())
+        .wrap_err("Overlay stopped")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn draws_countdown_inside_buffer() {
+        assert_eq!(countdown(Duration::from_millis(299_500)), "5:00");
+        let width = 64;
+        let mut buffer = vec![0; width * 48];
+        draw(
+            &mut buffer,
+            width,
+            "10:00",
+            Some("Stretch your wrists (20s)"),
+        );
+        assert!(buffer.contains(&FOREGROUND));
+        assert!(buffer.contains(&BACKGROUND));
+    }
+}
@@ -0,0 +1,89 @@
+//! Guesses whether the user is typing a password. Starting a break then would
+//! leave a half typed password on screen. Also tells whether the screen is
+//! locked, the locker then holds the input devices itself.
+
+use std::fs;
+use std::process::Command;
+
+use tracing::debug;
+
+/// Programs that only run while asking for a password
+const PROMPTS: [&str; 4] = [
+    "pinentry",
+    "polkit-agent-helper",
+    "gcr-prompter",
+    // ssh-askpass, ksshaskpass, gnome-ssh-askpass...
+    "askpass",
+];
+
+fn is_prompt(comm: &str) -> bool {
+    PROMPTS.iter().any(|prompt| comm.contains(prompt))
+}
+
+fn prompt_running() -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+    entries
+        .filter_map(std::result::Result::ok)
+        .filter_map(|entry| fs::read_to_string(entry.path().join("comm")).ok())
+        .find(|comm| is_prompt(comm.trim_end()))
+        .inspect(|comm| debug!("Password prompt running: {}", comm.trim_end()))
+        .is_some()
+}
+
+/// An active session showing a lock screen or a greeter, both ask for a
+/// password
+fn login_prompt_active() -> bool {
+    any_active_session(|properties| {
+        properties.contains(&"LockedHint=yes") || properties.contains(&"Class=greeter")
+    })
+}
+
+/// An active session is locked according to logind
+pub(crate) fn screen_locked() -> bool {
+    any_active_session(|properties| properties.contains(&"LockedHint=yes"))
+}
+
+/// `matches` gets the lock state and class of each active session
+fn any_active_session(matches: impl Fn(&[&str]) -> bool) -> bool {
+    let Ok(output) = Command::new("loginctl")
+        .args(["list-sessions", "--no-legend"])
+        .output()
+    else {
+        return false;
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .any(|session| {
+            let Ok(output) = Command::new("loginctl")
+                .args(["show-session", session])
+                .args(["--property=Active", "--property=LockedHint"])
+                .arg("--property=Class")
+                .output()
+            else {
+                return false;
+            };
+            let properties = String::from_utf8_lossy(&output.stdout);
+            let properties: Vec<_> = properties.lines().collect();
+            properties.contains(&"Active=yes") && matches(&properties)
+        })
+}
+
+pub(crate) fn open() -> bool {
+    prompt_running() || login_prompt_active()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recognizes_prompts() {
+        assert!(is_prompt("pinentry-gnome3"));
+        assert!(is_prompt("ksshaskpass"));
+        assert!(is_prompt("polkit-agent-helper-1"));
+        assert!(!is_prompt("bash"));
+    }
+}
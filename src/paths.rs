@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use nix::unistd::Uid;
+
+const DEFAULT_STATE_DIR: &str = "/var/lib/break_enforcer";
+const DEFAULT_RUNTIME_DIR: &str = "/run/break_enforcer";
+const DEFAULT_DEV_DIR: &str = "/dev/input";
+
+static DEV_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Call before any device is opened, the privileged helper inherits it
+pub(crate) fn set_dev_dir(dir: Option<PathBuf>) {
+    if let Some(dir) = dir {
+        DEV_DIR.set(dir).expect("the dev dir is set only once");
+    }
+}
+
+/// Where the input devices are, `/dev/input` unless set with `--dev-dir`.
+/// In a container or Flatpak they can be bind mounted elsewhere.
+pub(crate) fn dev_dir() -> &'static Path {
+    DEV_DIR.get_or_init(|| PathBuf::from(DEFAULT_DEV_DIR))
+}
+
+/// Everything the daemon writes goes into one of these two directories.
+/// That makes it simple to confine it using SELinux or AppArmor.
+#[derive(Debug, Clone)]
+pub(crate) struct Dirs {
+    /// kept across reboots
+    state: PathBuf,
+    /// cleared on reboot, for example the status file
+    runtime: PathBuf,
+}
+
+impl Dirs {
+    pub(crate) fn new(state: Option<PathBuf>, runtime: Option<PathBuf>) -> Self {
+        Self {
+            state: state.unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_DIR)),
+            runtime: runtime.unwrap_or_else(|| PathBuf::from(DEFAULT_RUNTIME_DIR)),
+        }
+    }
+
+    pub(crate) fn state(&self) -> &Path {
+        &self.state
+    }
+
+    pub(crate) fn runtime(&self) -> &Path {
+        &self.runtime
+    }
+
+    pub(crate) fn status_file(&self) -> PathBuf {
+        self.runtime.join("status.txt")
+    }
+
+    pub(crate) fn history_file(&self) -> PathBuf {
+        self.state.join("history.jsonl")
+    }
+
+    pub(crate) fn daily_usage_file(&self) -> PathBuf {
+        self.state.join("daily_usage.ron")
+    }
+
+    pub(crate) fn curfew_overrides_file(&self) -> PathBuf {
+        self.state.join("curfew_overrides.ron")
+    }
+
+    pub(crate) fn seen_keyboards_file(&self) -> PathBuf {
+        self.state.join("seen_keyboards.ron")
+    }
+
+    pub(crate) fn crash_dir(&self) -> PathBuf {
+        self.state.join("crash")
+    }
+
+    /// Creates the dirs. Without root the default locations can not be
+    /// created, those are skipped. Returns the dirs that did not exist yet.
+    pub(crate) fn create(&self) -> Result<Vec<PathBuf>> {
+        let is_root = Uid::effective().is_root();
+        let dirs = [
+            (&self.state, DEFAULT_STATE_DIR),
+            (&self.runtime, DEFAULT_RUNTIME_DIR),
+        ];
+        let mut created = Vec::new();
+        for (dir, _) in dirs
+            .into_iter()
+            .filter(|(dir, default)| is_root || dir.as_path() != Path::new(default))
+        {
+            if dir.exists() {
+                continue;
+            }
+            fs::create_dir_all(dir)
+                .wrap_err("Could not create directory")
+                .with_note(|| format!("dir: {}", dir.display()))?;
+            created.push(dir.clone());
+        }
+        Ok(created)
+    }
+
+    /// The arguments needed to pass these dirs on to another invocation
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.state != Path::new(DEFAULT_STATE_DIR) {
+            args.push("--state-dir".to_string());
+            args.push(self.state.display().to_string());
+        }
+        if self.runtime != Path::new(DEFAULT_RUNTIME_DIR) {
+            args.push("--runtime-dir".to_string());
+            args.push(self.runtime.display().to_string());
+        }
+        if dev_dir() != Path::new(DEFAULT_DEV_DIR) {
+            args.push("--dev-dir".to_string());
+            args.push(dev_dir().display().to_string());
+        }
+        args
+    }
+
+    pub(crate) fn uses_default_runtime(&self) -> bool {
+        self.runtime == Path::new(DEFAULT_RUNTIME_DIR)
+    }
+}
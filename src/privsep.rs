@@ -0,0 +1,341 @@
+//! Privilege separation, see `--drop-privileges`. Before any thread is
+//! started the daemon forks a helper which keeps running as root. Once the
+//! daemon has set up its integrations it drops to an unprivileged user.
+//! Whenever it needs root after that, to open a newly plugged in device or to
+//! notify or speak to the logged in users, it asks the helper.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use nix::errno::Errno;
+use nix::sys::socket::{
+    recvmsg, sendmsg, socketpair, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags,
+    SockFlag, SockType,
+};
+use nix::unistd::{
+    access, chown, fork, setgid, setgroups, setuid, AccessFlags, ForkResult, Uid, User,
+};
+use tracing::{debug, warn};
+
+use crate::integration::{notification, speech};
+use crate::paths;
+
+static HELPER: OnceLock<Mutex<OwnedFd>> = OnceLock::new();
+/// see `set_passed_devices`
+static PASSED: OnceLock<HashMap<PathBuf, OwnedFd>> = OnceLock::new();
+
+/// requests and responses are small, they always fit in one packet
+const MAX_PACKET: usize = 4096;
+/// longest text the helper shows or speaks, anything from the daemon is
+/// untrusted and only ever passed on as a single argument
+const MAX_TEXT: usize = 1024;
+
+enum Request {
+    Open(PathBuf),
+    Notify(String),
+    Beep,
+    Say(String),
+}
+
+impl Request {
+    fn encode(&self) -> String {
+        match self {
+            Request::Open(path) => format!("open {}", path.display()),
+            Request::Notify(text) => format!("notify {text}"),
+            Request::Beep => "beep".to_string(),
+            Request::Say(text) => format!("say {text}"),
+        }
+    }
+
+    fn decode(packet: &str) -> Option<Self> {
+        let (kind, arg) = packet.split_once(' ').unwrap_or((packet, ""));
+        match kind {
+            "open" => Some(Request::Open(PathBuf::from(arg))),
+            "notify" => Some(Request::Notify(arg.to_string())),
+            "beep" => Some(Request::Beep),
+            "say" => Some(Request::Say(arg.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Must be called before any other thread is spawned.
+pub(crate) fn spawn_helper() -> Result<()> {
+    let (daemon_end, helper_end) = socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )
+    .wrap_err("Could not create socket pair")?;
+
+    // Safety: in a multi-threaded program the child may only use
+    // async-signal-safe functions. There are no other threads yet so that
+    // restriction does not apply.
+    match unsafe { fork() }.wrap_err("Could not fork the privileged helper")? {
+        ForkResult::Child => {
+            drop(daemon_end);
+            serve(&helper_end);
+            std::process::exit(0);
+        }
+        ForkResult::Parent { child } => {
+            debug!("started privileged helper, pid: {child}");
+            drop(helper_end);
+            assert!(
+                HELPER.set(Mutex::new(daemon_end)).is_ok(),
+                "spawn_helper is only called once"
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Drops root permanently, this also drops all capabilities. The directories
+/// we `created` are handed over to the user first. Others in `writable`
+/// could be anything, like `/run`, they are only checked.
+pub(crate) fn drop_privileges(user: &str, created: &[PathBuf], writable: &[&Path]) -> Result<()> {
+    let user = User::from_name(user)
+        .wrap_err("Could not look up user")?
+        .ok_or_else(|| eyre!("No such user: {user}"))
+        .suggestion("Pass an existing (system) user, for example nobody")?;
+    if user.uid.is_root() {
+        return Err(eyre!("Can not drop privileges to root"));
+    }
+
+    for dir in created {
+        chown(dir, Some(user.uid), Some(user.gid))
+            .wrap_err("Could not hand directory over to user")
+            .with_note(|| format!("dir: {}", dir.display()))?;
+    }
+    setgroups(&[]).wrap_err("Could not drop supplementary groups")?;
+    setgid(user.gid).wrap_err("Could not change group")?;
+    setuid(user.uid).wrap_err("Could not change user")?;
+    if setuid(Uid::from_raw(0)).is_ok() {
+        return Err(eyre!(
+            "Regained root after dropping it, refusing to continue"
+        ));
+    }
+    for dir in writable
+        .iter()
+        .filter(|dir| dir.exists() && !created.iter().any(|c| c == *dir))
+    {
+        access(*dir, AccessFlags::W_OK | AccessFlags::X_OK)
+            .wrap_err("The user can not write to the directory")
+            .with_note(|| format!("dir: {}, user: {}", dir.display(), user.name))
+            .suggestion("Give the user a directory of its own, or hand this one over")?;
+    }
+    debug!("dropped privileges, now running as: {}", user.name);
+    Ok(())
+}
+
+fn only_input_devices(path: &Path) -> io::Result<()> {
+    let escapes = path
+        .components()
+        .any(|c| matches!(c, Component::ParentDir | Component::CurDir));
+    if escapes || !path.starts_with(paths::dev_dir()) {
+        warn!("refusing to open: {}", path.display());
+        return Err(io::Error::from(Errno::EACCES));
+    }
+    Ok(())
+}
+
+fn open_directly(path: &Path) -> io::Result<File> {
+    File::options()
+        .read(true)
+        .write(true)
+        .open(path)
+        .or_else(|_| File::open(path))
+}
+
+fn handle(request: Request) -> (String, Option<File>) {
+    let res = match request {
+        Request::Open(path) => {
+            match only_input_devices(&path).and_then(|()| open_directly(&path)) {
+                Ok(file) => return ("ok".to_string(), Some(file)),
+                // keep the os error so the daemon can tell a removed device
+                // from other errors
+                Err(e) => return (format!("err {}", e.raw_os_error().unwrap_or(0)), None),
+            }
+        }
+        Request::Notify(text) => limited(&text).and_then(notification::notify),
+        Request::Beep => notification::beep_all_users(),
+        Request::Say(text) => limited(&text).and_then(speech::say),
+    };
+    match res {
+        Ok(()) => ("ok".to_string(), None),
+        Err(report) => (format!("err 0 {report:#}"), None),
+    }
+}
+
+fn limited(text: &str) -> Result<&str> {
+    if text.len() > MAX_TEXT {
+        return Err(eyre!("text is too long ({} bytes)", text.len()))
+            .with_note(|| format!("at most {MAX_TEXT} bytes are allowed"));
+    }
+    Ok(text)
+}
+
+fn serve(socket: &OwnedFd) {
+    let mut buf = [0u8; MAX_PACKET];
+    loop {
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let n_read = match recvmsg::<()>(socket.as_raw_fd(), &mut iov, None, MsgFlags::empty()) {
+            Ok(msg) if msg.bytes == 0 => return, // daemon exited
+            Ok(msg) => msg.bytes,
+            Err(e) => {
+                warn!("privileged helper could not receive request: {e}");
+                return;
+            }
+        };
+
+        let packet = String::from_utf8_lossy(&buf[..n_read]).to_string();
+        let (response, file) = match Request::decode(&packet) {
+            Some(request) => handle(request),
+            None => ("err 0 unknown request".to_string(), None),
+        };
+
+        let iov = [IoSlice::new(response.as_bytes())];
+        let fds: Vec<_> = file.iter().map(AsRawFd::as_raw_fd).collect();
+        let cmsgs: Vec<_> = if fds.is_empty() {
+            Vec::new()
+        } else {
+            vec![ControlMessage::ScmRights(&fds)]
+        };
+        if let Err(e) = sendmsg::<()>(socket.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None) {
+            warn!("privileged helper could not send response: {e}");
+            return;
+        }
+    }
+}
+
+fn request(helper: &Mutex<OwnedFd>, request: &Request) -> io::Result<Option<File>> {
+    let socket = helper.lock().unwrap();
+    let packet = request.encode();
+    let iov = [IoSlice::new(packet.as_bytes())];
+    sendmsg::<()>(socket.as_raw_fd(), &iov, &[], MsgFlags::empty(), None)?;
+
+    let mut buf = [0u8; MAX_PACKET];
+    let mut cmsg_buf = nix::cmsg_space!([std::os::fd::RawFd; 1]);
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let msg = recvmsg::<()>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buf),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+    if msg.bytes == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "privileged helper exited",
+        ));
+    }
+
+    let mut file = None;
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            // Safety: the kernel just gave us these, nothing else owns them
+            file = fds.first().map(|fd| unsafe { File::from_raw_fd(*fd) });
+        }
+    }
+    let bytes = msg.bytes;
+    let response = String::from_utf8_lossy(&buf[..bytes]);
+
+    match response.split_once(' ') {
+        None if response == "ok" => Ok(file),
+        Some(("err", error)) => {
+            let (errno, msg) = error.split_once(' ').unwrap_or((error, ""));
+            match errno.parse() {
+                Ok(0) | Err(_) => Err(io::Error::other(msg.to_string())),
+                Ok(errno) => Err(io::Error::from_raw_os_error(errno)),
+            }
+        }
+        _ => Err(io::Error::other(format!(
+            "unexpected response from privileged helper: {response}"
+        ))),
+    }
+}
+
+/// Input devices passed in by the container runtime, opening one of these
+/// uses the passed in fd instead. Only the first call counts.
+pub(crate) fn set_passed_devices(devices: HashMap<PathBuf, OwnedFd>) {
+    if !devices.is_empty() {
+        let _already_set = PASSED.set(devices);
+    }
+}
+
+pub(crate) fn passed_devices() -> impl Iterator<Item = (&'static Path, RawFd)> {
+    PASSED
+        .get()
+        .into_iter()
+        .flatten()
+        .map(|(path, fd)| (path.as_path(), fd.as_raw_fd()))
+}
+
+/// Opens the device: a passed in one, else through the privileged helper if
+/// there is one
+pub(crate) fn open_device(path: &Path) -> io::Result<File> {
+    if let Some(fd) = PASSED.get().and_then(|passed| passed.get(path)) {
+        return fd.try_clone().map(File::from);
+    }
+    let Some(helper) = HELPER.get() else {
+        return open_directly(path);
+    };
+    request(helper, &Request::Open(path.to_path_buf()))?
+        .ok_or_else(|| io::Error::other("privileged helper did not send the device"))
+}
+
+/// `None` if there is no helper and the caller should notify itself
+pub(crate) fn notify(text: &str) -> Option<Result<()>> {
+    let helper = HELPER.get()?;
+    Some(
+        request(helper, &Request::Notify(text.to_string()))
+            .map(|_| ())
+            .wrap_err("Privileged helper could not notify"),
+    )
+}
+
+/// `None` if there is no helper and the caller should beep itself
+pub(crate) fn beep() -> Option<Result<()>> {
+    let helper = HELPER.get()?;
+    Some(
+        request(helper, &Request::Beep)
+            .map(|_| ())
+            .wrap_err("Privileged helper could not beep"),
+    )
+}
+
+/// `None` if there is no helper and the caller should speak itself
+pub(crate) fn say(text: &str) -> Option<Result<()>> {
+    let helper = HELPER.get()?;
+    Some(
+        request(helper, &Request::Say(text.to_string()))
+            .map(|_| ())
+            .wrap_err("Privileged helper could not speak"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_is_opaque_and_limited() {
+        let text = "\"; touch /tmp/pwned #";
+        let Some(Request::Notify(decoded)) =
+            Request::decode(&Request::Notify(text.into()).encode())
+        else {
+            panic!("notify request did not round trip");
+        };
+        assert_eq!(decoded, text);
+
+        let (response, file) = handle(Request::Notify("a".repeat(MAX_TEXT + 1)));
+        assert!(response.starts_with("err 0 text is too long"), "{response}");
+        assert!(file.is_none());
+    }
+}
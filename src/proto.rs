@@ -0,0 +1,209 @@
+//! The wire format of the api, used by both the service and [`crate::Api`].
+//! Clients in other languages can implement it from this description.
+//!
+//! # Transport
+//! The service listens on the abstract unix socket [`ABSTRACT_SOCKET`] and
+//! on the first free port of [`PORTS`] on the ipv4 and ipv6 loopback
+//! addresses, or only on the port in [`PORT_ENV`] if set. With `--listen` it
+//! also serves over tls on the network, there clients have to send
+//! `auth <token>` before any request other than `hello`. A wrong token
+//! closes the connection.
+//!
+//! # Framing
+//! Requests and responses are utf8 text ending in a [`STOP_BYTE`]. A request
+//! is a name optionally followed by a space and an argument. The service
+//! answers every request with one frame, an error frame looks like
+//! `ERR <code> <message>` (see [`ErrorCode`]). Requests that change
+//! something answer with an empty frame.
+//!
+//! A request can also end in a newline (line mode), the answer then ends in
+//! a newline too and has any newlines in it replaced by spaces. That way the
+//! api can be used with `nc`, for example:
+//! `echo status_msg | nc -q 1 localhost 49151`
+//!
+//! # Handshake
+//! Clients send `hello` first, the answer is `<NAME> <version> <protocol>`.
+//! A client should refuse to continue if `protocol` is not the
+//! [`PROTOCOL_VERSION`] it was written for. The same can be asked later on
+//! using `version`.
+//!
+//! # Requests
+//! | request                | argument       | answer                           |
+//! |------------------------|----------------|----------------------------------|
+//! | `hello`                |                | see handshake                    |
+//! | `auth`                 | token          | empty                            |
+//! | `version`              |                | `<version> <protocol>`           |
+//! | `status_msg`           |                | the status line                  |
+//! | `idle_since`           |                | whole seconds without input      |
+//! | `idle_since_ms`        |                | `<millis idle> <since>`          |
+//! | `relock_latency_ms`    |                | millis to block a reconnect      |
+//! | `health`               |                | ron [`Health`]                   |
+//! | `progress`             |                | fraction of the period done, 0-1 |
+//! | `daily_budget`         |                | whole seconds left today         |
+//! | `suggest_activity`     |                | a break activity                 |
+//! | `history`              |                | ron list of [`StateUpdate`]      |
+//! | `activity_map`         |                | ron [`ActivityMap`]              |
+//! | `schedule_preview`     | number, max 50 | ron list of [`PlannedBreak`]     |
+//! | `block_device`         | device id      | empty                            |
+//! | `unblock_device`       | device id      | empty                            |
+//! | `reload_config`        |                | empty                            |
+//! | `pause`                | seconds        | empty                            |
+//! | `resume`               |                | empty                            |
+//! | `skip`                 |                | empty                            |
+//! | `snooze`               | seconds, opt.  | empty                            |
+//! | `unlock`               |                | empty                            |
+//! | `break_now`            | seconds, opt.  | empty                            |
+//! | `extend`               | seconds        | empty                            |
+//! | `lift_curfew`          |                | empty                            |
+//! | `meeting`              | seconds        | empty                            |
+//! | `end_meeting`          |                | empty                            |
+//! | `reexec`               |                | empty                            |
+//! | `subscribe`            |                | a ron [`StateUpdate`] per frame  |
+//!
+//! After `subscribe` the connection only carries updates, the first is the
+//! current state. The `since` of `idle_since_ms` is when the input stopped
+//! in milliseconds on `CLOCK_BOOTTIME`. Unlike `CLOCK_MONOTONIC` (see
+//! [`Timestamp`]) it keeps counting during suspend, as the idle time does.
+//! Without an argument `snooze` uses 5 minutes and `break_now` the break
+//! duration of the service. The seconds are at most a day (86400), longer
+//! is answered with [`ErrorCode::InvalidArgument`].
+//!
+//! # Compatibility
+//! Within a [`PROTOCOL_VERSION`]:
+//! - requests and their answers do not change
+//! - new requests can be added, older services answer them with
+//!   [`ErrorCode::UnknownRequest`]
+//! - new error codes can be added, clients should treat unknown ones as
+//!   failures (see [`ErrorCode::Other`])
+//!
+//! Anything else, including new [`StateUpdate`] variants or fields, bumps
+//! the version.
+//!
+//! Version 2 added [`StateUpdate::Warning`] and [`StateUpdate::Meeting`].
+
+mod state_update;
+
+pub use state_update::{
+    ActivityMap, BreakKind, Health, PlannedBreak, ResetReason, StateUpdate, SubsystemHealth,
+    Timestamp,
+};
+
+/// Ends every frame
+pub const STOP_BYTE: u8 = 0;
+/// answered to `hello` together with the version and `PROTOCOL_VERSION`
+pub const NAME: &str = "break-enforcer";
+/// increase on any incompatible change to the requests or responses
+pub const PROTOCOL_VERSION: u32 = 2;
+// first 4 are taken with care from
+// https://en.wikipedia.org/wiki/List_of_TCP_and_UDP_port_numbers
+// the rest are randomly picked
+/// Tried in order by the service until one is free, clients try them all
+pub const PORTS: [u16; 7] = [49_151, 28_769, 19_788, 62_738, 34_342, 12_846, 8_797];
+/// pins the api to a single port instead of scanning `PORTS`, read by both
+/// the daemon and the library
+pub const PORT_ENV: &str = "BREAK_ENFORCER_API_PORT";
+/// name of the abstract unix socket, linux only
+pub const ABSTRACT_SOCKET: &str = "break-enforcer";
+
+/// Code in an error frame. An error frame looks like: `ERR <code> <message>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The request is not known, the service might be older then the client
+    UnknownRequest,
+    /// The request is known but its argument is not valid
+    InvalidArgument,
+    /// The request is valid but could not be carried out
+    Failed,
+    /// The connection came in over the network (`--listen`) and did not
+    /// authenticate yet, or used the wrong token
+    Unauthorized,
+    /// A code this version does not know about
+    Other(u16),
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => ErrorCode::UnknownRequest,
+            2 => ErrorCode::InvalidArgument,
+            3 => ErrorCode::Failed,
+            4 => ErrorCode::Unauthorized,
+            other => ErrorCode::Other(other),
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::UnknownRequest => 1,
+            ErrorCode::InvalidArgument => 2,
+            ErrorCode::Failed => 3,
+            ErrorCode::Unauthorized => 4,
+            ErrorCode::Other(other) => other,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::UnknownRequest => f.write_str("unknown request"),
+            ErrorCode::InvalidArgument => f.write_str("invalid argument"),
+            ErrorCode::Failed => f.write_str("failed"),
+            ErrorCode::Unauthorized => f.write_str("unauthorized"),
+            ErrorCode::Other(code) => write!(f, "code {code}"),
+        }
+    }
+}
+
+/// The request name and its argument, the argument is empty if there is none
+#[must_use]
+pub fn split_request(frame: &str) -> (&str, &str) {
+    frame.split_once(' ').unwrap_or((frame, ""))
+}
+
+/// The content of an error frame, stop bytes are left out of the message as
+/// they would end the frame early
+#[must_use]
+pub fn error_frame(code: ErrorCode, message: &str) -> String {
+    let message = message.replace(char::from(STOP_BYTE), "");
+    format!("ERR {} {message}", u16::from(code))
+}
+
+/// The code and message if this is an error frame
+#[must_use]
+pub fn parse_error_frame(frame: &str) -> Option<(ErrorCode, &str)> {
+    let error = frame.strip_prefix("ERR ")?;
+    let (code, message) = error.split_once(' ').unwrap_or((error, ""));
+    let code = code
+        .parse::<u16>()
+        .map_or(ErrorCode::Other(0), ErrorCode::from);
+    Some((code, message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_frames_round_trip() {
+        for code in 0..=u16::MAX {
+            assert_eq!(u16::from(ErrorCode::from(code)), code);
+        }
+        for code in [
+            ErrorCode::UnknownRequest,
+            ErrorCode::InvalidArgument,
+            ErrorCode::Failed,
+            ErrorCode::Unauthorized,
+            ErrorCode::Other(42),
+        ] {
+            let frame = error_frame(code, "went\0 wrong");
+            assert_eq!(parse_error_frame(&frame), Some((code, "went wrong")));
+        }
+        assert_eq!(error_frame(ErrorCode::Failed, "no"), "ERR 3 no");
+        assert_eq!(parse_error_frame("3 no"), None);
+        assert_eq!(split_request("pause 300"), ("pause", "300"));
+        assert_eq!(split_request("resume"), ("resume", ""));
+    }
+}
@@ -0,0 +1,328 @@
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "subscribe")]
+use serde::{Deserialize, Serialize};
+
+/// When an update was send. Durations in updates are relative to this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub struct Timestamp {
+    /// `CLOCK_MONOTONIC`, does not jump when the wall clock is changed
+    pub monotonic: Duration,
+    pub wall_clock: SystemTime,
+}
+
+// Only short breaks are scheduled right now. The other kinds are part of the
+// protocol so clients can handle them once they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub enum BreakKind {
+    /// The regular break between work periods
+    Short,
+    /// A longer break after multiple work periods
+    Long,
+    /// A few seconds to look away from the screen
+    Micro,
+}
+
+/// Why a work period ended without a break
+// Only idle resets happen right now. The other reasons are part of the
+// protocol so clients can handle them once they do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub enum ResetReason {
+    /// The user was idle for longer then a break
+    Idle,
+    /// The user was idle for longer then a long break
+    LongIdle,
+    /// Reset through the api
+    Manual,
+}
+
+/// Send to api clients that subscribed
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub enum StateUpdate {
+    /// Waiting for the user to become active before starting a work period
+    Waiting {
+        at: Timestamp,
+        /// None right after start up
+        reset: Option<ResetReason>,
+    },
+    Work {
+        at: Timestamp,
+        until_break: Duration,
+    },
+    /// Devices are blocked
+    Break {
+        at: Timestamp,
+        until_work: Duration,
+        kind: BreakKind,
+    },
+    /// Paused through the api, no breaks until resumed
+    Paused {
+        at: Timestamp,
+        until_resume: Duration,
+    },
+    /// Work time keeps counting but breaks wait until the meeting ends
+    Meeting {
+        at: Timestamp,
+        until_end: Duration,
+        /// break time owed for breaks held off, taken when the meeting ends
+        debt: Duration,
+    },
+    /// Send periodically during a break
+    Progress {
+        at: Timestamp,
+        remaining: Duration,
+        total: Duration,
+    },
+    /// Enforcement is degraded, for example a device could not be blocked.
+    /// Does not change the state.
+    Warning { at: Timestamp, message: String },
+}
+
+/// A break the service expects to take if the schedule is followed, see
+/// `Api::schedule_preview`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub struct PlannedBreak {
+    pub at: SystemTime,
+    pub duration: Duration,
+    pub kind: BreakKind,
+    /// The curfew or the daily limit, these can not be skipped. Nothing is
+    /// planned after one.
+    pub hard: bool,
+}
+
+/// Which minutes of the last week had input on a blocked device, see
+/// `Api::activity_map`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub struct ActivityMap {
+    /// Start of the oldest minute
+    pub start: SystemTime,
+    /// One bit per minute oldest first, starting at the lowest bit of each
+    /// byte
+    pub minutes: Vec<u8>,
+}
+
+impl ActivityMap {
+    /// Number of minutes covered
+    pub fn len(&self) -> usize {
+        self.minutes.len() * 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.minutes.is_empty()
+    }
+
+    /// Whether there was input in the minute `minute` minutes after `start`
+    pub fn active(&self, minute: usize) -> bool {
+        self.minutes
+            .get(minute / 8)
+            .is_some_and(|byte| byte & (1 << (minute % 8)) != 0)
+    }
+
+    /// When minute `minute` starts
+    pub fn minute_start(&self, minute: usize) -> SystemTime {
+        self.start + Duration::from_secs(60 * minute as u64)
+    }
+}
+
+/// How the parts of the service are doing, see `Api::health`. A service
+/// can keep running without enforcing breaks, for example while its device
+/// watcher fails over and over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub struct Health {
+    pub subsystems: Vec<SubsystemHealth>,
+    /// How long the oldest update the integrator (status line, notifications
+    /// and api) did not get to yet has been waiting
+    pub integrator_lag: Duration,
+    /// The last device that could not be blocked and why
+    pub last_grab_error: Option<String>,
+    /// Input events dropped because the service could not keep up
+    pub dropped_events: u64,
+}
+
+/// A thread of the service that is restarted when it fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "subscribe", derive(Serialize, Deserialize))]
+pub struct SubsystemHealth {
+    /// For example `device watcher` or `inotify`
+    pub name: String,
+    /// False while waiting to be restarted
+    pub running: bool,
+    pub restarts: u32,
+    pub last_failure: Option<String>,
+    /// Stable unlike the `name`: `device_watcher`, `inotify`, `integrator`,
+    /// `api`, `remote_api` or `http_api`. Empty from services that predate it.
+    #[cfg_attr(feature = "subscribe", serde(default))]
+    pub id: String,
+}
+
+// The strings below are the wire format, if one needs to change the protocol
+// version does too
+#[cfg(all(test, feature = "subscribe"))]
+mod test {
+    use std::fmt::Debug;
+
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    const AT: &str = "(monotonic:(secs:1,nanos:500000000),\
+        wall_clock:(secs_since_epoch:1700000000,nanos_since_epoch:0))";
+
+    fn at() -> Timestamp {
+        Timestamp {
+            monotonic: Duration::from_millis(1500),
+            wall_clock: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        }
+    }
+
+    fn assert_wire<T>(value: &T, wire: &str)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        assert_eq!(ron::to_string(value).unwrap(), wire);
+        assert_eq!(&ron::from_str::<T>(wire).unwrap(), value);
+    }
+
+    #[test]
+    fn wire_format_is_stable() {
+        let at = at();
+        let minutes = Duration::from_secs(300);
+        assert_wire(&at, AT);
+        for (kind, wire) in [
+            (BreakKind::Short, "Short"),
+            (BreakKind::Long, "Long"),
+            (BreakKind::Micro, "Micro"),
+        ] {
+            assert_wire(&kind, wire);
+        }
+        for (reason, wire) in [
+            (ResetReason::Idle, "Idle"),
+            (ResetReason::LongIdle, "LongIdle"),
+            (ResetReason::Manual, "Manual"),
+        ] {
+            assert_wire(&reason, wire);
+        }
+
+        let updates = [
+            (
+                StateUpdate::Waiting { at, reset: None },
+                format!("Waiting(at:{AT},reset:None)"),
+            ),
+            (
+                StateUpdate::Waiting {
+                    at,
+                    reset: Some(ResetReason::Idle),
+                },
+                format!("Waiting(at:{AT},reset:Some(Idle))"),
+            ),
+            (
+                StateUpdate::Work {
+                    at,
+                    until_break: minutes,
+                },
+                format!("Work(at:{AT},until_break:(secs:300,nanos:0))"),
+            ),
+            (
+                StateUpdate::Break {
+                    at,
+                    until_work: minutes,
+                    kind: BreakKind::Short,
+                },
+                format!("Break(at:{AT},until_work:(secs:300,nanos:0),kind:Short)"),
+            ),
+            (
+                StateUpdate::Paused {
+                    at,
+                    until_resume: minutes,
+                },
+                format!("Paused(at:{AT},until_resume:(secs:300,nanos:0))"),
+            ),
+            (
+                StateUpdate::Meeting {
+                    at,
+                    until_end: minutes,
+                    debt: minutes,
+                },
+                format!("Meeting(at:{AT},until_end:(secs:300,nanos:0),debt:(secs:300,nanos:0))"),
+            ),
+            (
+                StateUpdate::Progress {
+                    at,
+                    remaining: minutes,
+                    total: minutes,
+                },
+                format!("Progress(at:{AT},remaining:(secs:300,nanos:0),total:(secs:300,nanos:0))"),
+            ),
+            (
+                StateUpdate::Warning {
+                    at,
+                    message: "could not block Keyboard".to_string(),
+                },
+                format!("Warning(at:{AT},message:\"could not block Keyboard\")"),
+            ),
+        ];
+        for (update, wire) in &updates {
+            // a new variant has to be added here
+            match update {
+                StateUpdate::Waiting { .. }
+                | StateUpdate::Work { .. }
+                | StateUpdate::Break { .. }
+                | StateUpdate::Paused { .. }
+                | StateUpdate::Meeting { .. }
+                | StateUpdate::Progress { .. }
+                | StateUpdate::Warning { .. } => assert_wire(update, wire),
+            }
+        }
+
+        let map = ActivityMap {
+            start: at.wall_clock,
+            minutes: vec![1, 128],
+        };
+        assert_wire(
+            &map,
+            "(start:(secs_since_epoch:1700000000,nanos_since_epoch:0),minutes:[1,128])",
+        );
+        assert!(map.active(0) && map.active(15) && !map.active(1));
+
+        let planned = PlannedBreak {
+            at: at.wall_clock,
+            duration: minutes,
+            kind: BreakKind::Short,
+            hard: false,
+        };
+        assert_wire(
+            &planned,
+            "(at:(secs_since_epoch:1700000000,nanos_since_epoch:0),\
+            duration:(secs:300,nanos:0),kind:Short,hard:false)",
+        );
+
+        let health = Health {
+            subsystems: vec![SubsystemHealth {
+                name: "inotify".to_string(),
+                running: false,
+                restarts: 1,
+                last_failure: Some("inotify could not read events".to_string()),
+                id: "inotify".to_string(),
+            }],
+            integrator_lag: Duration::ZERO,
+            last_grab_error: None,
+            dropped_events: 3,
+        };
+        assert_wire(
+            &health,
+            "(subsystems:[(name:\"inotify\",running:false,restarts:1,\
+            last_failure:Some(\"inotify could not read events\"),id:\"inotify\")],\
+            integrator_lag:(secs:0,nanos:0),last_grab_error:None,dropped_events:3)",
+        );
+        let without_id = "(name:\"inotify\",running:true,restarts:0,last_failure:None)";
+        let subsystem: SubsystemHealth = ron::from_str(without_id).unwrap();
+        assert_eq!(subsystem.id, "");
+    }
+}
@@ -0,0 +1,177 @@
+//! Replaces the running service with the (possibly upgraded) binary on disk
+//! without losing the schedule. Devices grabbed during a break stay grabbed:
+//! their file descriptors are passed to the new process, like those of input
+//! devices passed in by a container runtime.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::signal::{SigSet, Signal};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::check_inputs::Event;
+use crate::control::Control;
+use crate::privsep;
+
+const SNAPSHOT_ENV: &str = "BREAK_ENFORCER_RESTORE";
+const GRABBED_ENV: &str = "BREAK_ENFORCER_GRABBED";
+const PASSED_ENV: &str = "BREAK_ENFORCER_PASSED_DEVICES";
+
+/// Where the schedule was, durations are what was left
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum Snapshot {
+    Waiting,
+    Work {
+        left: Duration,
+    },
+    Break {
+        left: Duration,
+        /// the stage of the escalation and how long it has been enforced
+        #[serde(default)]
+        stage: Option<(usize, Duration)>,
+    },
+    Paused {
+        left: Duration,
+        work_left: Option<Duration>,
+    },
+    Meeting {
+        left: Duration,
+        work_left: Duration,
+        debt: Duration,
+    },
+    /// the lid is closed or undocked, until opened or docked again
+    Away {
+        work_left: Option<Duration>,
+    },
+}
+
+/// The snapshot passed by the process that re-executed us
+pub(crate) fn take_snapshot() -> Option<Snapshot> {
+    let snapshot = std::env::var(SNAPSHOT_ENV).ok()?;
+    std::env::remove_var(SNAPSHOT_ENV);
+    ron::from_str(&snapshot)
+        .inspect_err(|e| warn!("Could not restore the schedule: {e}"))
+        .ok()
+}
+
+/// Grabbed devices passed by the process that re-executed us, they are still
+/// grabbed.
+pub(crate) fn take_grabbed() -> HashMap<PathBuf, OwnedFd> {
+    take_fds(GRABBED_ENV, "grabbed")
+}
+
+/// Input devices the process that re-executed us got from the container
+/// runtime, see `privsep::set_passed_devices`
+pub(crate) fn take_passed() -> HashMap<PathBuf, OwnedFd> {
+    take_fds(PASSED_ENV, "passed in")
+}
+
+fn take_fds(env: &str, what: &str) -> HashMap<PathBuf, OwnedFd> {
+    let Ok(fds) = std::env::var(env) else {
+        return HashMap::new();
+    };
+    std::env::remove_var(env);
+    let fds: Vec<(PathBuf, RawFd)> = match ron::from_str(&fds) {
+        Ok(fds) => fds,
+        Err(e) => {
+            warn!("Could not take over {what} devices: {e}");
+            return HashMap::new();
+        }
+    };
+
+    fds.into_iter()
+        // make sure the fd is really open before owning it
+        .filter(|(_, fd)| fcntl(unsafe_borrow(*fd), FcntlArg::F_GETFD).is_ok())
+        // SAFETY: the fd was passed to us on purpose and nothing else owns it
+        .map(|(path, fd)| (path, unsafe { OwnedFd::from_raw_fd(fd) }))
+        .collect()
+}
+
+fn unsafe_borrow(fd: RawFd) -> std::os::fd::BorrowedFd<'static> {
+    // SAFETY: only used for fcntl calls on fds we were told are open, fcntl
+    // fails gracefully if they are not
+    unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }
+}
+
+/// Runs the binary we were started from again with the same arguments. Only
+/// returns on failure.
+pub(crate) fn exec(snapshot: Snapshot, grabbed: Vec<(PathBuf, RawFd)>) -> Result<Infallible> {
+    // not /proc/self/exe, that still points to the old binary after an upgrade
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| eyre!("Started without a program name"))?;
+    let passed: Vec<_> = privsep::passed_devices()
+        .map(|(path, fd)| (path.to_path_buf(), fd))
+        .collect();
+    let mut command = Command::new(&program);
+    command
+        .args(args)
+        .env(SNAPSHOT_ENV, ron::to_string(&snapshot)?)
+        .env(GRABBED_ENV, ron::to_string(&grabbed)?)
+        .env(PASSED_ENV, ron::to_string(&passed)?);
+
+    set_close_on_exec(grabbed.iter().chain(&passed), false)
+        .wrap_err("Could not keep grabbed devices open across exec")?;
+    info!("Re-executing {}", program.to_string_lossy());
+    let error = command.exec();
+    // otherwise notification commands we spawn would inherit them
+    set_close_on_exec(grabbed.iter().chain(&passed), true)
+        .wrap_err("Could not restore close on exec for grabbed devices")?;
+    Err(error).wrap_err("Could not execute the binary")
+}
+
+fn set_close_on_exec<'a>(
+    fds: impl Iterator<Item = &'a (PathBuf, RawFd)>,
+    close: bool,
+) -> nix::Result<()> {
+    let flags = if close {
+        FdFlag::FD_CLOEXEC
+    } else {
+        FdFlag::empty()
+    };
+    for (_, fd) in fds {
+        fcntl(unsafe_borrow(*fd), FcntlArg::F_SETFD(flags))?;
+    }
+    Ok(())
+}
+
+/// Must be called before spawning any thread, they all need to block the
+/// signal for `reexec_on_signal` to receive it.
+pub(crate) fn block_signal() -> Result<()> {
+    let mut set = SigSet::empty();
+    set.add(Signal::SIGUSR2);
+    set.thread_block()
+        .wrap_err("Could not block SIGUSR2 for handling it on a thread")
+}
+
+/// SIGUSR2 asks the run loop to re-execute, like the `reexec` api request
+pub(crate) fn reexec_on_signal(events: mpsc::Sender<Event>) {
+    let mut set = SigSet::empty();
+    set.add(Signal::SIGUSR2);
+    thread::spawn(move || loop {
+        match set.wait() {
+            Ok(_) => {
+                let (reply, _) = mpsc::channel();
+                if events.send(Event::Control(Control::Reexec, reply)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                warn!("Could not wait for SIGUSR2: {e}");
+                return;
+            }
+        }
+    });
+}
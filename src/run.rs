@@ -1,106 +1,1764 @@
+use std::collections::hash_map::RandomState;
+use std::fmt::Display;
+use std::hash::{BuildHasher, Hasher};
 use std::path::PathBuf;
-use std::time::Instant;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+use break_enforcer::{BreakKind, PlannedBreak, ResetReason};
 use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
+use tracing::{debug, error, info, warn};
 
-use crate::check_inputs::{InactivityTracker, InputResult, TrackResult};
+use crate::break_command::{self, BreakCommand};
+use crate::check_inputs::{Event, InactivityTracker, PowerEvent};
 use crate::cli::RunArgs;
-use crate::integration::Status;
-use crate::{check_inputs, watch_and_block};
-use crate::{config, integration};
-use std::{sync::mpsc::Receiver, thread};
+use crate::clock::{Clock, SystemClock};
+use crate::compositor::{self, Backend};
+use crate::config::BlockList;
+use crate::control::{Control, Reply};
+use crate::curfew::Curfew;
+use crate::daily_limit::DailyLimit;
+use crate::enforcement::{Enforcement, Escalation, Stage};
+use crate::integration::{fmt_dur, notification, Status};
+use crate::paths::Dirs;
+use crate::reexec::{self, Snapshot};
+use crate::sync::{self, Message, Peers, SyncConfig};
+use crate::watch_and_block::OnlineDevices;
+use crate::{check_inputs, forward, new_keyboards, password_prompt, privsep, watch_and_block};
+use crate::{config, crash, install, integration, lock_session};
 
 pub(crate) fn run(
     RunArgs {
+        preset: _,
         work_duration,
+        break_jitter,
+        first_work_duration,
         break_duration,
+        long_break,
+        long_break_every,
+        idle_credit,
         lock_warning,
         lock_warning_type,
+        lock_delay,
+        defer_for_passwords,
+        nag_bar,
+        break_workspace,
+        daily_limit,
+        day_starts_at,
+        curfew,
+        curfew_overrides,
+        break_coach,
+        break_activities,
+        daily_goal,
+        summary_at,
         status_file,
         tcp_api,
+        api_port,
+        progress_interval,
+        #[cfg(feature = "http")]
+        http_port,
+        listen,
+        api_cert,
+        api_key,
+        api_token_file,
         notifications,
+        notify_warnings,
+        ask_new_keyboards,
+        min_notice,
+        announce,
+        on_battery,
+        persist_history,
+        block_remappers,
+        no_follow_screen_lock,
+        block_backend,
+        allow_pointer,
+        when_unplugged,
+        on_lid_close,
+        on_undock,
+        break_chord,
+        break_chord_hold,
+        sync_peer,
+        sync_listen,
+        sync_cert,
+        sync_key,
+        sync_ca,
+        drop_privileges,
+        no_block,
+        escalate: escalation,
+        break_command,
     }: RunArgs,
     config_path: Option<PathBuf>,
+    dirs: Dirs,
 ) -> Result<()> {
+    // before starting any thread, see `reexec::block_signal`
+    reexec::block_signal()?;
+    let restored = reexec::take_snapshot();
+    let grabbed = reexec::take_grabbed();
+    let mut inherited_api = integration::InheritedApi::take();
+    let mut passed = reexec::take_passed();
+    passed.extend(inherited_api.take_devices());
+    privsep::set_passed_devices(passed);
+
+    if drop_privileges.is_some() {
+        privsep::spawn_helper().wrap_err("Could not set up privilege separation")?;
+    }
     let (online_devices, new) = watch_and_block::devices();
+    // outside a break the devices should not be grabbed, dropping them
+    // releases them
+    if matches!(restored, Some(Snapshot::Break { .. })) {
+        online_devices.adopt(grabbed);
+    }
 
-    let to_block =
-        config::read(config_path).wrap_err("Could not read devices to block from config")?;
-    if to_block.is_empty() {
+    // with --no-block the devices in the config only count as activity
+    let to_block = config::read(config_path.clone())
+        .wrap_err("Could not read devices to block from config")?;
+    if to_block.is_empty() && !no_block {
         return Err(eyre!(
             "No config, do not know what to block. Please run the wizard. \nExiting"
         ))
         .suppress_backtrace(true)
         .suggestion("Run the wizard")
-        .suggestion("Maybe you have a (wrong) custom location set?");
+        .suggestion("Maybe you have a (wrong) custom location set?")
+        .suggestion("Only want the schedule? Pass --no-block");
+    }
+    let settings =
+        config::settings(config_path.clone()).wrap_err("Could not read settings from config")?;
+    let block_backend = match settings.enforcement {
+        Some(enforcement) if block_backend != Backend::Grab && block_backend != enforcement => {
+            return Err(eyre!(
+                "The config says enforcement {enforcement} but --block-backend is {block_backend}"
+            ))
+            .suggestion("Drop one of them");
+        }
+        Some(enforcement) => enforcement,
+        None => block_backend,
+    };
+    if progress_interval.is_zero() {
+        return Err(eyre!("The progress interval can not be zero"));
+    }
+    if break_jitter.is_some_and(|jitter| jitter >= work_duration) {
+        return Err(eyre!(
+            "The break jitter must be shorter than the work duration"
+        ));
+    }
+    if long_break.is_some_and(|long| long <= break_duration) {
+        return Err(eyre!(
+            "The long break must be longer than the break duration"
+        ));
+    }
+    if first_work_duration.is_some_and(|first| first.is_zero()) {
+        return Err(eyre!("The first work duration can not be zero"));
+    }
+    if !no_block {
+        block_backend
+            .check_dependency()
+            .wrap_err("Can not block using the configured backend")?;
+    }
+    if escalation
+        .as_ref()
+        .is_some_and(|escalation| escalation.contains(Stage::Warn))
+    {
+        integration::NotificationType::System
+            .check_dependency()
+            .wrap_err("Can not warn before escalating")?;
+    }
+    if escalation
+        .as_ref()
+        .is_some_and(|escalation| escalation.contains(Stage::ScreenLock))
+    {
+        Backend::ScreenLock
+            .check_dependency()
+            .wrap_err("Can not lock the screen when escalating")?;
+    }
+    if allow_pointer {
+        if block_backend.resolve() != Backend::Grab {
+            return Err(eyre!(
+                "The pointer can only be kept moving by the grab backend"
+            ));
+        }
+        forward::check_available().wrap_err("Can not keep the pointer moving")?;
     }
     for warning_type in &lock_warning_type {
         warning_type
             .check_dependency()
             .wrap_err("Can not provide configured warning/notification")?;
     }
+    if daily_goal.is_some() || summary_at.is_some() {
+        integration::NotificationType::System
+            .check_dependency()
+            .wrap_err("Can not send the summary of the day")?;
+    }
+    if notify_warnings {
+        integration::NotificationType::System
+            .check_dependency()
+            .wrap_err("Can not send warnings")?;
+    }
+    if ask_new_keyboards {
+        integration::NotificationType::System
+            .check_dependency()
+            .wrap_err("Can not ask about new keyboards")?;
+    }
+    if min_notice.is_some() {
+        integration::NotificationType::System
+            .check_dependency()
+            .wrap_err("Can not notify ahead of locking")?;
+    }
+    if announce.is_some() {
+        integration::speech::check_available().wrap_err("Can not announce breaks")?;
+    }
+    if when_unplugged == Some(WhenUnplugged::Notify) {
+        integration::NotificationType::System
+            .check_dependency()
+            .wrap_err("Can not notify when the devices are unplugged")?;
+    }
+    if nag_bar || break_workspace.is_some() {
+        integration::check_window_manager(nag_bar)
+            .wrap_err("Can not show the nag bar or switch workspace")?;
+    }
 
-    let (recv_any_input, recv_any_input2) = check_inputs::watcher(new, to_block.clone());
+    let activities = match break_activities {
+        Some(path) => Some(integration::Activities::read(&path)?),
+        None => break_coach.then(integration::Activities::builtin),
+    };
 
-    let mut inactivity_tracker = InactivityTracker::new(recv_any_input2, break_duration);
+    // without devices in the config or access to them there is no input
+    let follows_input = !no_block || (!to_block.is_empty() && install::has_input_access());
+    let assume_active = (!follows_input).then(|| {
+        info!("Not following input, time the screen is unlocked counts as activity");
+        (break_duration / 2).min(ASSUME_ACTIVE_EVERY)
+    });
+    let to_block = config::BlockList::new(to_block, config_path, block_remappers);
+    let break_chord = break_chord.map(|chord| (chord, break_chord_hold));
+    let recv_any_input = check_inputs::watcher(
+        new,
+        to_block.clone(),
+        check_inputs::WatchOptions {
+            break_chord,
+            follow_screen_lock: !no_follow_screen_lock,
+            legacy_joysticks: true,
+            assume_active,
+        },
+    );
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let inactivity_tracker = InactivityTracker::new(recv_any_input, break_duration, clock.clone());
+
+    reexec::reexec_on_signal(inactivity_tracker.sender());
+    {
+        let events = inactivity_tracker.sender();
+        online_devices.on_change(move || {
+            let _tracker_gone = events.send(Event::DevicesChanged);
+        });
+        let events = inactivity_tracker.sender();
+        online_devices.on_grab_failed(move |failure| {
+            let _tracker_gone = events.send(Event::GrabFailed(failure));
+        });
+        let events = inactivity_tracker.sender();
+        online_devices.on_grab_retried(move |device| {
+            let _tracker_gone = events.send(Event::GrabRetried(device));
+        });
+        let events = inactivity_tracker.sender();
+        online_devices.on_slow_relock(move |relock| {
+            let _tracker_gone = events.send(Event::SlowRelock(relock));
+        });
+    }
+    let idle = inactivity_tracker.idle_handle();
+    let sync = sync_listen
+        .map(|listen| SyncConfig {
+            listen,
+            peers: sync_peer,
+            cert: sync_cert.expect("clap requires it with sync_listen"),
+            key: sync_key.expect("clap requires it with sync_listen"),
+            ca: sync_ca.expect("clap requires it with sync_listen"),
+        })
+        .map(|config| {
+            sync::start(
+                &config,
+                idle.clone(),
+                clock.clone(),
+                inactivity_tracker.sender(),
+            )
+        })
+        .transpose()
+        .wrap_err("Could not set up syncing with peers")?;
+    let remote_api = listen
+        .map(|listen| {
+            integration::RemoteApi::set_up(
+                listen,
+                &api_cert.expect("clap requires it with listen"),
+                &api_key.expect("clap requires it with listen"),
+                &api_token_file.expect("clap requires it with listen"),
+            )
+        })
+        .transpose()
+        .wrap_err("Could not set up the network api")?;
+    let created_dirs = dirs
+        .create()
+        .wrap_err("Could not set up state directories")?;
+    crash::install_hook(dirs.crash_dir());
+    if ask_new_keyboards {
+        new_keyboards::ask(
+            &online_devices,
+            to_block.clone(),
+            dirs.seen_keyboards_file(),
+        )?;
+    }
+    let history =
+        integration::History::new(persist_history.then(|| dirs.history_file()).as_deref())
+            .wrap_err("Could not set up history")?;
+    let daily_limit = daily_limit
+        .map(|limit| DailyLimit::new(limit, day_starts_at, dirs.daily_usage_file(), clock.clone()))
+        .transpose()
+        .wrap_err("Could not set up the daily limit")?;
+    let curfew = curfew
+        .map(|window| {
+            Curfew::new(
+                window,
+                curfew_overrides,
+                dirs.curfew_overrides_file(),
+                clock.clone(),
+            )
+        })
+        .transpose()
+        .wrap_err("Could not set up the curfew")?;
     let notify_config = integration::NotifyConfig {
         lock_warning,
         lock_notify_type: lock_warning_type,
-        last_lock_warning: Instant::now(),
+        last_lock_warning: clock.now(),
         state_notifications: notifications,
+        nag_bar,
+        break_workspace,
+        activities: activities.map(Arc::new),
+        daily_limit: daily_limit.clone(),
+        daily_goal,
+        summary_at,
+        export: settings.activitywatch.clone(),
+        unplugged_notification: when_unplugged == Some(WhenUnplugged::Notify),
+        warning_notifications: notify_warnings,
+        announce,
+        idle_credit,
+        battery: integration::Battery::new(on_battery),
+        clock: clock.clone(),
     };
+    let status = Status::new(
+        status_file.then(|| dirs.status_file()),
+        history,
+        (tcp_api || !inherited_api.is_empty()).then_some(integration::ApiConfig {
+            port: api_port,
+            progress_interval,
+            remote: remote_api,
+            #[cfg(feature = "http")]
+            http_port,
+            activity_map: inactivity_tracker.activity_map(),
+            inherited: inherited_api,
+        }),
+        notify_config,
+        idle,
+        break_duration,
+        online_devices.clone(),
+        to_block.clone(),
+        inactivity_tracker.sender(),
+    )
+    .wrap_err("Could not setup status reporting")?;
 
-    let idle = inactivity_tracker.idle_handle();
-    let mut status = Status::new(status_file, tcp_api, notify_config, idle, break_duration)
-        .wrap_err("Could not setup status reporting")?;
+    // the new process would not have the privileges it needs to start
+    let can_reexec = drop_privileges.is_none();
+    if let Some(user) = drop_privileges {
+        privsep::drop_privileges(&user, &created_dirs, &[dirs.state(), dirs.runtime()])
+            .wrap_err("Could not drop privileges")?;
+    }
+
+    Scheduler {
+        can_reexec,
+        block: !no_block,
+        escalation,
+        stage: None,
+        break_command,
+        running_command: None,
+        backend: block_backend.resolve(),
+        allow_pointer,
+        clock,
+        tracker: inactivity_tracker,
+        status,
+        online_devices,
+        to_block,
+        work_duration,
+        break_jitter,
+        first_work: first_work_duration,
+        break_duration,
+        long_break: long_break.map(|long| (long, long_break_every)),
+        breaks_since_long: 0,
+        idle_credit,
+        lock_delay,
+        defer_for_passwords,
+        daily_limit,
+        curfew,
+        sync,
+        when_unplugged,
+        unplugged: false,
+        on_lid_close,
+        on_undock,
+        away: false,
+        min_notice,
+        noticed_at: None,
+    }
+    .run(restored)
+}
+
+/// What to do while none of the devices to block are connected
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Eq, PartialEq)]
+pub(crate) enum WhenUnplugged {
+    /// Send a system notification, needs notify-send
+    Notify,
+    /// Pause the schedule until a device to block is connected again, the
+    /// work period then continues
+    Pause,
+    /// Block every keyboard during breaks instead
+    GrabKeyboards,
+}
+
+impl Display for WhenUnplugged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WhenUnplugged::Notify => f.write_str("notify"),
+            WhenUnplugged::Pause => f.write_str("pause"),
+            WhenUnplugged::GrabKeyboards => f.write_str("grab-keyboards"),
+        }
+    }
+}
+
+/// What closing the lid or undocking does, see `--on-lid-close`
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Eq, PartialEq)]
+pub(crate) enum PowerAction {
+    /// Input from external devices is ignored until the lid opens or the
+    /// dock is back, the time counts as idle and can reset the work period
+    Idle,
+    /// Pause the schedule until the lid opens or the dock is back, the work
+    /// period then continues
+    Pause,
+}
+
+impl Display for PowerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerAction::Idle => f.write_str("idle"),
+            PowerAction::Pause => f.write_str("pause"),
+        }
+    }
+}
+
+/// How much of the idle time during a work period the break is shortened by
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum IdleCredit {
+    Full,
+    None,
+    /// This percentage of the idle time
+    Percent(u8),
+    /// All idle time up to this much
+    Capped(Duration),
+}
+
+impl IdleCredit {
+    /// Parses `full`, `none`, a percentage like `50%` or a duration
+    pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+        match arg {
+            "full" => return Ok(Self::Full),
+            "none" => return Ok(Self::None),
+            _ => (),
+        }
+        if let Some(percent) = arg.strip_suffix('%') {
+            return match percent.parse::<u8>() {
+                Ok(percent) if percent <= 100 => Ok(Self::Percent(percent)),
+                _ => Err(format!("Expected a percentage from 0 to 100: {arg}")),
+            };
+        }
+        crate::cli::parse_duration(arg)
+            .map(Self::Capped)
+            .map_err(|e| format!("Expected full, none, a percentage or a duration: {e}"))
+    }
+
+    pub(crate) fn of(self, idle: Duration) -> Duration {
+        match self {
+            IdleCredit::Full => idle,
+            IdleCredit::None => Duration::ZERO,
+            IdleCredit::Percent(percent) => idle * u32::from(percent) / 100,
+            IdleCredit::Capped(cap) => idle.min(cap),
+        }
+    }
+}
+
+impl Display for IdleCredit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdleCredit::Full => f.write_str("full"),
+            IdleCredit::None => f.write_str("none"),
+            IdleCredit::Percent(percent) => write!(f, "{percent}%"),
+            IdleCredit::Capped(cap) => f.write_str(&fmt_dur(*cap)),
+        }
+    }
+}
+
+enum Phase {
+    /// For activity before starting a work period
+    Waiting,
+    Work {
+        next_break: Instant,
+    },
+    /// The break is due, waiting for a pause in typing or for a password
+    /// prompt to close before locking
+    Finishing {
+        due: Instant,
+        lock_at: Instant,
+        lock_by: Instant,
+    },
+    Break {
+        next_work: Instant,
+        locks: Vec<Lock>,
+        /// set if the break can not be ended early
+        hard: Option<HardLock>,
+    },
+    Paused {
+        until: Instant,
+        /// Set if paused during a work period, it continues after the pause
+        work_left: Option<Duration>,
+    },
+    /// Breaks that become due add to the debt, taken once the meeting ends
+    Meeting {
+        until: Instant,
+        next_break: Instant,
+        debt: Duration,
+    },
+    /// Waiting for a notice to run out before locking, see
+    /// `Scheduler::notice`
+    Notice {
+        lock_at: Instant,
+        then: Locking,
+    },
+    /// None of the devices to block are connected, see `WhenUnplugged::Pause`
+    Unplugged {
+        /// Set if unplugged during a work period, it continues once a
+        /// device is connected again
+        work_left: Option<Duration>,
+    },
+    /// The lid is closed or undocked, see `PowerAction::Pause`
+    Away {
+        /// Set if it happened during a work period, it continues once back
+        work_left: Option<Duration>,
+    },
+}
+
+impl Phase {
+    fn deadline(&self) -> Option<Instant> {
+        match self {
+            Phase::Waiting | Phase::Unplugged { .. } | Phase::Away { .. } => None,
+            Phase::Work { next_break } => Some(*next_break),
+            Phase::Finishing {
+                lock_at, lock_by, ..
+            } => Some((*lock_at).min(*lock_by)),
+            Phase::Break { next_work, .. } => Some(*next_work),
+            Phase::Paused { until, .. } => Some(*until),
+            Phase::Notice { lock_at, .. } => Some(*lock_at),
+            Phase::Meeting {
+                until, next_break, ..
+            } => Some((*until).min(*next_break)),
+        }
+    }
+
+    /// `stage` is that of the escalation, see `Runner::stage`
+    fn snapshot(&self, now: Instant, stage: Option<(usize, Instant)>) -> Snapshot {
+        let left = |deadline: &Instant| deadline.saturating_duration_since(now);
+        match self {
+            Phase::Waiting => Snapshot::Waiting,
+            Phase::Work { next_break } => Snapshot::Work {
+                left: left(next_break),
+            },
+            Phase::Finishing { .. } => Snapshot::Work {
+                left: Duration::ZERO,
+            },
+            // the new process sends the notice again
+            Phase::Notice { lock_at, .. } => Snapshot::Work {
+                left: left(lock_at),
+            },
+            Phase::Break { next_work, .. } => Snapshot::Break {
+                left: left(next_work),
+                stage: stage.map(|(index, since)| (index, now.saturating_duration_since(since))),
+            },
+            Phase::Paused { until, work_left } => Snapshot::Paused {
+                left: left(until),
+                work_left: *work_left,
+            },
+            Phase::Meeting {
+                until,
+                next_break,
+                debt,
+            } => Snapshot::Meeting {
+                left: left(until),
+                work_left: left(next_break),
+                debt: *debt,
+            },
+            // the new process pauses again if still unplugged
+            Phase::Unplugged {
+                work_left: Some(left),
+            } => Snapshot::Work { left: *left },
+            Phase::Unplugged { work_left: None } => Snapshot::Waiting,
+            // only a switch changing tells about the lid or dock
+            Phase::Away { work_left } => Snapshot::Away {
+                work_left: *work_left,
+            },
+        }
+    }
+}
+
+/// Why a break can not be ended early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HardLock {
+    DailyLimit,
+    Curfew,
+}
+
+/// What to do once the notice ran out, see `Scheduler::notice`
+#[derive(Debug)]
+enum Locking {
+    /// The work period is over, see `Scheduler::break_due`
+    Due,
+    Break(Duration, BreakKind),
+    Hard(HardLock, Instant),
+}
+
+/// How long to wait before trying to send a notice again
+const NOTICE_RETRY: Duration = Duration::from_secs(30);
+/// A notice that ran out covers locking for this long, a lock that follows
+/// another (the curfew right after a break became due) does not wait again
+const NOTICE_VALID: Duration = Duration::from_secs(60);
+
+/// Input must pause this long before a delayed lock starts
+const TYPING_PAUSE: Duration = Duration::from_secs(1);
+
+/// How long a break can be put off for an open password prompt, a prompt
+/// left open should not prevent breaks
+const PROMPT_DEFER: Duration = Duration::from_secs(2 * 60);
+const PROMPT_POLL: Duration = Duration::from_secs(1);
+
+/// Depends on the blocking backend and the stage of the escalation
+type Lock = Box<dyn Enforcement>;
+
+/// Without access to the input devices the user counts as active this
+/// often, see `--no-block`
+const ASSUME_ACTIVE_EVERY: Duration = Duration::from_secs(30);
+
+/// Time for the api to send the reply before the re-exec closes the
+/// connection
+const REEXEC_GRACE: Duration = Duration::from_millis(200);
+
+struct Scheduler {
+    can_reexec: bool,
+    /// false with `--no-block`, breaks then only change the status
+    block: bool,
+    escalation: Option<Escalation>,
+    /// the stage of the escalation and since when, set during a break
+    stage: Option<(usize, Instant)>,
+    break_command: Option<BreakCommand>,
+    /// the break command, running during a break
+    running_command: Option<break_command::Running>,
+    backend: Backend,
+    allow_pointer: bool,
+    clock: Arc<dyn Clock>,
+    tracker: InactivityTracker,
+    status: Status,
+    online_devices: OnlineDevices,
+    to_block: BlockList,
+    work_duration: Duration,
+    break_jitter: Option<Duration>,
+    /// used instead of the work duration until the first break
+    first_work: Option<Duration>,
+    break_duration: Duration,
+    /// the length of long breaks and which break is one
+    long_break: Option<(Duration, u32)>,
+    /// the breaks that became due since the last long break
+    breaks_since_long: u32,
+    idle_credit: IdleCredit,
+    lock_delay: Option<Duration>,
+    defer_for_passwords: bool,
+    daily_limit: Option<DailyLimit>,
+    curfew: Option<Curfew>,
+    sync: Option<Peers>,
+    when_unplugged: Option<WhenUnplugged>,
+    /// none of the devices to block are connected
+    unplugged: bool,
+    on_lid_close: Option<PowerAction>,
+    on_undock: Option<PowerAction>,
+    /// the lid is closed or undocked and that pauses the schedule
+    away: bool,
+    min_notice: Option<Duration>,
+    /// when the last notice ahead of locking was delivered
+    noticed_at: Option<Instant>,
+}
+
+impl Scheduler {
+    fn run(mut self, restored: Option<Snapshot>) -> Result<()> {
+        let phase = self.restore(restored)?;
+        let mut phase = self.devices_changed(phase)?;
+        loop {
+            let timeout = phase.deadline().map_or(Duration::MAX, |deadline| {
+                self.clock.duration_until(deadline)
+            });
+            let event = self.tracker.next(timeout);
+            if let (Some(Event::Input), Some(sync)) = (&event, &mut self.sync) {
+                sync.share(Message::Input);
+            }
+            // changes caused by peers are not send back to them
+            let local = !matches!(event, Some(Event::Remote(_)));
+            let deadline = phase.deadline();
+            phase = match (phase, event) {
+                (_, Some(Event::InputError(e))) => {
+                    return Err(e).wrap_err("Could not track activity")
+                }
+                (phase, Some(Event::Control(Control::Reexec, reply))) => {
+                    self.reexec(&phase, reply);
+                    phase
+                }
+                (phase, Some(Event::Control(control, reply))) => {
+                    let (phase, res) = self.control(phase, control)?;
+                    let _client_gone = reply.send(res);
+                    phase
+                }
+                (phase, Some(Event::Preview(count, reply))) => {
+                    let _client_gone = reply.send(self.preview(&phase, count));
+                    phase
+                }
+                (phase, Some(Event::DevicesChanged)) => self.devices_changed(phase)?,
+                (phase, Some(Event::Power(event))) => self.power_event(phase, event)?,
+                (phase, Some(Event::GrabFailed(failure))) => {
+                    self.status.grab_failed(failure);
+                    phase
+                }
+                (phase, Some(Event::GrabRetried(device))) => {
+                    self.status.grab_retried(device);
+                    phase
+                }
+                (phase, Some(Event::SlowRelock(relock))) => {
+                    self.status.warning(relock.to_string());
+                    phase
+                }
+                (Phase::Waiting, Some(Event::Input)) => {
+                    let period = self.first_work.unwrap_or_else(|| self.work_period());
+                    self.work(self.clock.now() + period)?
+                }
+                (Phase::Work { .. }, Some(Event::Idle)) => self.wait(Some(ResetReason::Idle))?,
+                (Phase::Work { .. } | Phase::Finishing { .. }, Some(Event::BreakChord)) => {
+                    info!("Break chord held, starting the break early");
+                    self.start_break()?
+                }
+                (Phase::Work { .. }, None) => self.notice(Locking::Due)?,
+                (Phase::Notice { then, .. }, None) => self.notice(then)?,
+                (
+                    Phase::Break {
+                        next_work,
+                        locks,
+                        hard,
+                    },
+                    Some(Event::Input),
+                ) if self.stage.is_some() => Phase::Break {
+                    next_work,
+                    locks: self.escalate(locks)?,
+                    hard,
+                },
+                (Phase::Finishing { due, lock_by, .. }, Some(Event::Input))
+                    if self.lock_delay.is_some() =>
+                {
+                    Phase::Finishing {
+                        due,
+                        lock_at: self.clock.now() + TYPING_PAUSE,
+                        lock_by,
+                    }
+                }
+                (Phase::Finishing { due, .. }, None) => self.finish(due)?,
+                (Phase::Break { locks, .. }, None) => {
+                    self.end_break(locks)?;
+                    self.wait(None)?
+                }
+                (Phase::Paused { work_left, .. }, None) => self.resume(work_left)?,
+                (
+                    Phase::Meeting {
+                        until,
+                        next_break,
+                        debt,
+                    },
+                    None,
+                ) => self.meeting(until, next_break, debt)?,
+                (
+                    Phase::Waiting | Phase::Work { .. } | Phase::Finishing { .. },
+                    Some(Event::Remote(Message::Work { left })),
+                ) => {
+                    // a peer can not keep us working or locked for longer
+                    // than we are configured to
+                    let longest = self.work_duration + self.break_jitter.unwrap_or_default();
+                    self.work(self.clock.now() + left.min(longest))?
+                }
+                (
+                    Phase::Waiting | Phase::Work { .. } | Phase::Finishing { .. },
+                    Some(Event::Remote(Message::Break { left })),
+                ) => self.notice(Locking::Break(
+                    left.min(self.break_duration),
+                    BreakKind::Short,
+                ))?,
+                (phase, _) => phase,
+            };
+            if local && phase.deadline() != deadline {
+                self.share(&phase);
+            }
+            if let Some(limit) = &self.daily_limit {
+                limit.set_counting(matches!(
+                    phase,
+                    Phase::Work { .. }
+                        | Phase::Finishing { .. }
+                        | Phase::Notice { .. }
+                        | Phase::Paused { .. }
+                        | Phase::Meeting { .. }
+                ));
+            }
+        }
+    }
+
+    /// Lets peers start the same work period or break
+    fn share(&mut self, phase: &Phase) {
+        let Some(sync) = &mut self.sync else {
+            return;
+        };
+        let now = self.clock.now();
+        match phase {
+            Phase::Work { next_break } => sync.share(Message::Work {
+                left: next_break.saturating_duration_since(now),
+            }),
+            // the daily limit and curfew are per machine
+            Phase::Break {
+                next_work,
+                hard: None,
+                ..
+            } => sync.share(Message::Break {
+                left: next_work.saturating_duration_since(now),
+            }),
+            Phase::Waiting
+            | Phase::Finishing { .. }
+            | Phase::Break { hard: Some(_), .. }
+            | Phase::Paused { .. }
+            | Phase::Meeting { .. }
+            | Phase::Notice { .. }
+            | Phase::Unplugged { .. }
+            | Phase::Away { .. } => (),
+        }
+    }
+
+    fn wait(&mut self, reset: Option<ResetReason>) -> Result<Phase> {
+        self.status.set_waiting(reset);
+        self.tracker.discard_stale()?;
+        Ok(Phase::Waiting)
+    }
+
+    fn work(&mut self, next_break: Instant) -> Result<Phase> {
+        if let Some((reason, until)) = self.hard_lock_due() {
+            return self.hard_lock(reason, until);
+        }
+        let next_break = self
+            .next_hard_lock()
+            .map_or(next_break, |at| next_break.min(at));
+        if self.unplugged && self.when_unplugged == Some(WhenUnplugged::Pause) {
+            self.status.set_waiting(None);
+            return Ok(Phase::Unplugged {
+                work_left: Some(self.clock.duration_until(next_break)),
+            });
+        }
+        if self.away {
+            self.status.set_waiting(None);
+            return Ok(Phase::Away {
+                work_left: Some(self.clock.duration_until(next_break)),
+            });
+        }
+        self.status.set_working(next_break);
+        self.tracker.discard_stale()?;
+        Ok(Phase::Work { next_break })
+    }
+
+    /// The work duration, moved by up to the jitter
+    fn work_period(&self) -> Duration {
+        let Some(jitter) = self.break_jitter else {
+            return self.work_duration;
+        };
+        // randomly seeded, good enough to not be predictable
+        let roll = RandomState::new().build_hasher().finish();
+        jittered(self.work_duration, jitter, roll)
+    }
+
+    fn break_due(&mut self) -> Result<Phase> {
+        let now = self.clock.now();
+        match self.lock_delay {
+            Some(delay) => Ok(Phase::Finishing {
+                due: now,
+                lock_at: now + TYPING_PAUSE,
+                lock_by: now + delay,
+            }),
+            None => self.finish(now),
+        }
+    }
 
-    loop {
-        status.set_waiting();
+    fn finish(&mut self, due: Instant) -> Result<Phase> {
+        let now = self.clock.now();
+        let defer_until = due + PROMPT_DEFER;
+        if self.defer_for_passwords && now < defer_until && password_prompt::open() {
+            debug!("Password prompt open, not locking yet");
+            return Ok(Phase::Finishing {
+                due,
+                lock_at: now + PROMPT_POLL,
+                lock_by: defer_until,
+            });
+        }
+        self.start_break()
+    }
+
+    fn start_break(&mut self) -> Result<Phase> {
+        if let Some((reason, until)) = self.hard_lock_due() {
+            return self.hard_lock(reason, until);
+        }
+        let idle = self.tracker.user_idle();
+        let (length, kind) = self.due_break(0);
+        // the user was away long enough, for example the system was
+        // suspended right as the break should start
+        if idle >= length {
+            return self.wait(Some(ResetReason::Idle));
+        }
+
+        self.breaks_since_long = match kind {
+            BreakKind::Long => 0,
+            _ => self.breaks_since_long + 1,
+        };
+        let credit = self.idle_credit.of(idle);
+        self.notice(Locking::Break(length - credit, kind))
+    }
+
+    /// The length and kind of the `nth` break to become due from now on,
+    /// counting from 0
+    fn due_break(&self, nth: u32) -> (Duration, BreakKind) {
+        match self.long_break {
+            Some((length, every)) if (self.breaks_since_long + nth) % every == every - 1 => {
+                (length, BreakKind::Long)
+            }
+            _ => (self.break_duration, BreakKind::Short),
+        }
+    }
+
+    /// Like a break that became due, except idle time does not shorten it
+    fn break_now(&mut self, duration: Duration) -> Result<Phase> {
+        if let Some((reason, until)) = self.hard_lock_due() {
+            return self.hard_lock(reason, until);
+        }
+        info!("Starting a break early");
+        self.notice(Locking::Break(duration, BreakKind::Short))
+    }
+
+    /// Breaks due before `until` add to the debt instead of locking
+    fn meeting(
+        &mut self,
+        until: Instant,
+        mut next_break: Instant,
+        mut debt: Duration,
+    ) -> Result<Phase> {
+        // like pauses meetings end at the curfew or daily limit
+        let until = self.next_hard_lock().map_or(until, |at| until.min(at));
+        let now = self.clock.now();
+        while next_break <= now.min(until) {
+            debt += self.break_duration;
+            next_break += self.work_period();
+        }
+        if now >= until {
+            return self.end_meeting(next_break, debt);
+        }
+        self.status.set_meeting(until, debt);
+        Ok(Phase::Meeting {
+            until,
+            next_break,
+            debt,
+        })
+    }
+
+    /// Takes the breaks owed in one go
+    fn end_meeting(&mut self, next_break: Instant, debt: Duration) -> Result<Phase> {
+        if debt.is_zero() {
+            return self.work(next_break);
+        }
+        if let Some((reason, until)) = self.hard_lock_due() {
+            return self.hard_lock(reason, until);
+        }
+        info!("Meeting over, taking the {debt:?} of break owed");
+        self.notice(Locking::Break(debt, BreakKind::Short))
+    }
+
+    /// The next `count` breaks if the schedule is followed from now on.
+    /// Jitter is left out. Ends early with the curfew or daily limit.
+    fn preview(&self, phase: &Phase, count: usize) -> Vec<PlannedBreak> {
+        let now = self.clock.now();
+        // when work (re)starts, when the next break starts and its length
+        // and kind if it does not become due like the others
+        let (mut work_start, mut at, mut fixed) = match *phase {
+            Phase::Waiting
+            | Phase::Unplugged { work_left: None }
+            | Phase::Away { work_left: None } => {
+                let period = self.first_work.unwrap_or(self.work_duration);
+                (now, now + period, None)
+            }
+            Phase::Unplugged {
+                work_left: Some(left),
+            }
+            | Phase::Away {
+                work_left: Some(left),
+            } => (now, now + left, None),
+            Phase::Work { next_break } => (now, next_break, None),
+            Phase::Finishing { lock_at, .. }
+            | Phase::Notice {
+                lock_at,
+                then: Locking::Due,
+            } => (now, lock_at, None),
+            Phase::Notice {
+                lock_at,
+                then: Locking::Break(duration, kind),
+            } => (now, lock_at, Some((duration, kind))),
+            Phase::Notice {
+                lock_at,
+                then: Locking::Hard(_, until),
+            } => {
+                return vec![self.planned(lock_at, until - lock_at, BreakKind::Short, true)];
+            }
+            Phase::Break { next_work, .. } => (next_work, next_work + self.work_duration, None),
+            Phase::Paused { until, work_left } => {
+                (until, until + work_left.unwrap_or(self.work_duration), None)
+            }
+            Phase::Meeting {
+                until,
+                mut next_break,
+                mut debt,
+            } => {
+                while next_break <= until {
+                    debt += self.break_duration;
+                    next_break += self.work_duration;
+                }
+                if debt.is_zero() {
+                    (now, next_break, None)
+                } else {
+                    (now, until, Some((debt, BreakKind::Short)))
+                }
+            }
+        };
+
+        // a running curfew is a break of its own, the next one is a day off
+        let curfew = self
+            .curfew
+            .as_ref()
+            .filter(|curfew| curfew.active_until().is_none())
+            .map(|curfew| (curfew.next_start(), curfew.window().length()));
+        let mut limit_left = self.daily_limit.as_ref().map(DailyLimit::left);
+        let mut planned = Vec::new();
+        let mut due = 0;
+        while planned.len() < count {
+            let (duration, kind) = fixed.take().unwrap_or_else(|| {
+                due += 1;
+                self.due_break(due - 1)
+            });
+            let worked = at.saturating_duration_since(work_start);
+            let curfew_lock = curfew.filter(|(start, _)| *start < at);
+            let limit_lock = limit_left.filter(|left| *left < worked).map(|left| {
+                let start = work_start + left;
+                let next_day = self
+                    .daily_limit
+                    .as_ref()
+                    .map_or(start, DailyLimit::next_day);
+                (start, next_day.saturating_duration_since(start))
+            });
+            if let Some((start, length)) = curfew_lock.into_iter().chain(limit_lock).min() {
+                planned.push(self.planned(start, length, BreakKind::Short, true));
+                break;
+            }
+            planned.push(self.planned(at, duration, kind, false));
+            limit_left = limit_left.map(|left| left - worked);
+            work_start = at + duration;
+            at = work_start + self.work_duration;
+        }
+        planned
+    }
+
+    fn planned(
+        &self,
+        at: Instant,
+        duration: Duration,
+        kind: BreakKind,
+        hard: bool,
+    ) -> PlannedBreak {
+        PlannedBreak {
+            at: SystemTime::now() + self.clock.duration_until(at),
+            duration,
+            kind,
+            hard,
+        }
+    }
+
+    /// The curfew or daily limit applies now, returns until when
+    fn hard_lock_due(&self) -> Option<(HardLock, Instant)> {
+        if let Some(end) = self.curfew.as_ref().and_then(Curfew::active_until) {
+            return Some((HardLock::Curfew, end));
+        }
+        let limit = self.daily_limit.as_ref().filter(|limit| limit.reached())?;
+        Some((HardLock::DailyLimit, limit.next_day()))
+    }
+
+    /// The latest a hard lock starts if work or a pause continues
+    fn next_hard_lock(&self) -> Option<Instant> {
+        let now = self.clock.now();
+        let curfew = self.curfew.as_ref().map(Curfew::next_start);
+        let limit = self.daily_limit.as_ref().map(|limit| now + limit.left());
+        curfew.into_iter().chain(limit).min()
+    }
+
+    fn hard_lock(&mut self, reason: HardLock, until: Instant) -> Result<Phase> {
+        info!(
+            "{reason:?} applies, locking for {:?}",
+            self.clock.duration_until(until)
+        );
+        self.notice(Locking::Hard(reason, until))
+    }
+
+    /// Locks only once a system notification was delivered `min_notice`
+    /// ahead, until then the work period goes on
+    fn notice(&mut self, then: Locking) -> Result<Phase> {
+        let Some(notice) = self.min_notice else {
+            return self.proceed(then);
+        };
+        let now = self.clock.now();
+        if let Some(ran_out) = self.noticed_at.map(|at| at + notice) {
+            if ran_out <= now && now <= ran_out + NOTICE_VALID {
+                return self.proceed(then);
+            }
+            if now < ran_out {
+                return Ok(Phase::Notice {
+                    lock_at: ran_out,
+                    then,
+                });
+            }
+        }
+
+        let lock_at = match notification::notify(&format!("locking in {}", fmt_dur(notice))) {
+            Ok(()) => {
+                self.noticed_at = Some(now);
+                now + notice
+            }
+            Err(report) => {
+                warn!("Could not notify ahead of locking, not locking yet: {report:#}");
+                self.status
+                    .warning(format!("could not notify ahead of locking: {report:#}"));
+                now + NOTICE_RETRY
+            }
+        };
+        self.status.set_working(lock_at);
+        Ok(Phase::Notice { lock_at, then })
+    }
+
+    fn proceed(&mut self, then: Locking) -> Result<Phase> {
+        match then {
+            Locking::Due => self.break_due(),
+            Locking::Break(length, kind) => self.lock_break(self.clock.now() + length, None, kind),
+            Locking::Hard(reason, until) => self.lock_break(until, Some(reason), BreakKind::Short),
+        }
+    }
+
+    fn lock_break(
+        &mut self,
+        next_work: Instant,
+        hard: Option<HardLock>,
+        kind: BreakKind,
+    ) -> Result<Phase> {
+        self.lock_break_at_stage(next_work, hard, kind, (0, self.clock.now()))
+    }
+
+    /// Starts the escalation at the stage at `index` that has been enforced
+    /// since `since`, after re-exec that is not the first
+    fn lock_break_at_stage(
+        &mut self,
+        next_work: Instant,
+        hard: Option<HardLock>,
+        kind: BreakKind,
+        (index, since): (usize, Instant),
+    ) -> Result<Phase> {
+        self.first_work = None;
+        // the curfew and daily limit do not start gentle
+        let locks = match &self.escalation {
+            _ if !self.block => Vec::new(),
+            Some(escalation) if hard.is_none() => {
+                let (stage, _) = escalation.stage(index);
+                self.stage = Some((index, since));
+                self.enforce(stage)?
+            }
+            _ => self.block_devices()?,
+        };
 
-        wait_for_user_activity(&recv_any_input).wrap_err("Could not wait for activity")?;
-        status.set_working(Instant::now() + work_duration);
+        self.start_command();
+        self.status.set_break(next_work, kind);
+        Ok(Phase::Break {
+            next_work,
+            locks,
+            hard,
+        })
+    }
 
-        let idle = match inactivity_tracker.reset_or_timeout(work_duration) {
-            TrackResult::Error(e) => Err(e).wrap_err("Could not track inactivity")?,
-            TrackResult::ShouldReset => continue,
-            TrackResult::ShouldBreak { user_idle } => user_idle,
+    fn enforce(&self, stage: Stage) -> Result<Vec<Lock>> {
+        info!("Enforcing the break: {stage}");
+        match stage {
+            Stage::Warn => {
+                let text = "Time for a break, step away from the computer";
+                if let Err(report) = notification::notify(text) {
+                    warn!("Could not warn about the break: {report:#}");
+                }
+                Ok(Vec::new())
+            }
+            Stage::ScreenLock => Ok(vec![Box::new(lock_session::start())]),
+            Stage::Block => self.block_devices(),
+        }
+    }
+
+    /// Moves on to the next stage if there is still input after the time
+    /// the current stage got
+    fn escalate(&mut self, mut locks: Vec<Lock>) -> Result<Vec<Lock>> {
+        let (Some(escalation), Some((index, since))) = (&self.escalation, self.stage) else {
+            return Ok(locks);
+        };
+        let (_, Some(after)) = escalation.stage(index) else {
+            return Ok(locks); // the last stage
         };
+        if self.clock.elapsed(since) < after {
+            return Ok(locks);
+        }
+        let (next, _) = escalation.stage(index + 1);
+        self.stage = Some((index + 1, self.clock.now()));
+        // the stronger stage first, no gap in between
+        let mut next = self.enforce(next)?;
+        std::mem::swap(&mut locks, &mut next);
+        self.release(next)?;
+        Ok(locks)
+    }
+
+    fn block_devices(&self) -> Result<Vec<Lock>> {
+        let mut locks: Vec<Lock> = Vec::new();
+        match self.backend {
+            Backend::Sway => locks.push(Box::new(compositor::disable(&self.to_block.filters())?)),
+            Backend::ScreenLock => locks.push(Box::new(lock_session::start())),
+            Backend::Grab | Backend::Auto => {
+                for device_id in self.to_block.filters() {
+                    locks.push(Box::new(
+                        self.online_devices
+                            .lock(device_id)
+                            .wrap_err("failed to lock one of the inputs")?,
+                    ));
+                }
+            }
+        }
+        // remapper devices are not known to the config, these are always
+        // grabbed unless only the session is locked
+        if self.to_block.remappers() && self.backend != Backend::ScreenLock {
+            locks.push(Box::new(
+                self.online_devices
+                    .lock_remappers()
+                    .wrap_err("failed to lock the remapper inputs")?,
+            ));
+        }
+        if self.unplugged
+            && self.when_unplugged == Some(WhenUnplugged::GrabKeyboards)
+            && self.backend != Backend::ScreenLock
+        {
+            locks.push(self.lock_keyboards()?);
+        }
+        if self.allow_pointer {
+            let pointers = self.online_devices.grabbed_pointers();
+            // stopped before the mice are released, see `Forwarder`
+            locks.insert(0, Box::new(forward::start(pointers)));
+        }
+        Ok(locks)
+    }
 
-        let mut locks = Vec::new();
-        for device_id in to_block.iter().cloned() {
-            locks.push(
-                online_devices
-                    .lock(device_id)
-                    .wrap_err("failed to lock one of the inputs")?,
-            );
+    /// Failing to start the break command does not stop the break
+    fn start_command(&mut self) {
+        let Some(command) = &self.break_command else {
+            return;
+        };
+        match break_command::start(command) {
+            Ok(running) => self.running_command = Some(running),
+            Err(report) => warn!("Could not run the break command: {report:#}"),
         }
+    }
 
-        status.set_break(Instant::now() + break_duration - idle);
-        thread::sleep(break_duration - idle);
+    fn end_break(&mut self, locks: Vec<Lock>) -> Result<()> {
+        self.stage = None;
+        if let Some(running) = self.running_command.take() {
+            running.stop();
+        }
+        self.release(locks)
+    }
 
+    fn release(&self, locks: Vec<Lock>) -> Result<()> {
         for lock in locks {
-            lock.unlock()?;
+            lock.release()?;
+        }
+        Ok(())
+    }
+
+    fn lock_keyboards(&self) -> Result<Lock> {
+        info!("None of the devices to block are connected, locking all keyboards");
+        let guard = self
+            .online_devices
+            .lock_keyboards()
+            .wrap_err("failed to lock the keyboards")?;
+        Ok(Box::new(guard))
+    }
+
+    /// Applies `when_unplugged` once none of the devices to block are
+    /// connected, undoes it once one is connected again
+    fn devices_changed(&mut self, phase: Phase) -> Result<Phase> {
+        if !self.block {
+            return Ok(phase);
+        }
+        let unplugged = !self.online_devices.any_to_block(&self.to_block);
+        if unplugged == self.unplugged {
+            return Ok(phase);
+        }
+        self.unplugged = unplugged;
+        self.status.set_unplugged(unplugged);
+        if unplugged {
+            warn!("None of the devices to block are connected");
+        } else {
+            info!("A device to block is connected again");
+        }
+
+        let now = self.clock.now();
+        let pause = self.when_unplugged == Some(WhenUnplugged::Pause);
+        Ok(match phase {
+            Phase::Work { next_break } if unplugged && pause => self.work(next_break)?,
+            // the break was due, it starts once a device is back
+            Phase::Finishing { .. } if unplugged && pause => self.work(now)?,
+            Phase::Waiting if unplugged && pause => Phase::Unplugged { work_left: None },
+            Phase::Unplugged { work_left } if !unplugged => self.resume(work_left)?,
+            Phase::Break {
+                next_work,
+                mut locks,
+                hard,
+            } if unplugged && self.when_unplugged == Some(WhenUnplugged::GrabKeyboards) => {
+                locks.push(self.lock_keyboards()?);
+                Phase::Break {
+                    next_work,
+                    locks,
+                    hard,
+                }
+            }
+            phase => phase,
+        })
+    }
+
+    /// Applies `on_lid_close` or `on_undock`, undoes it once back
+    fn power_event(&mut self, phase: Phase, event: PowerEvent) -> Result<Phase> {
+        let action = match event {
+            PowerEvent::LidClosed | PowerEvent::LidOpened => self.on_lid_close,
+            PowerEvent::Undocked | PowerEvent::Docked => self.on_undock,
+        };
+        let Some(action) = action else {
+            debug!("{event}, no action configured");
+            return Ok(phase);
+        };
+        let away = event.away();
+        info!("{event}, {}", if away { "applying" } else { "undoing" });
+        if action == PowerAction::Idle {
+            self.tracker.set_away(away);
+            return Ok(phase);
+        }
+
+        self.away = away;
+        let now = self.clock.now();
+        Ok(match phase {
+            Phase::Work { next_break } if away => self.work(next_break)?,
+            // the break was due, it starts once back
+            Phase::Finishing { .. } if away => self.work(now)?,
+            Phase::Waiting if away => Phase::Away { work_left: None },
+            Phase::Away { work_left } if !away => self.resume(work_left)?,
+            phase => phase,
+        })
+    }
+
+    /// Continues where the process that re-executed us left off
+    fn restore(&mut self, snapshot: Option<Snapshot>) -> Result<Phase> {
+        let Some(snapshot) = snapshot else {
+            return self.wait(None);
+        };
+        info!("Continuing the schedule after re-exec: {snapshot:?}");
+        // continuing the schedule, this is not a fresh start
+        self.first_work = None;
+        let now = self.clock.now();
+        match snapshot {
+            Snapshot::Waiting => self.wait(None),
+            Snapshot::Work { left } => self.work(now + left),
+            Snapshot::Break { left, stage } => {
+                // the snapshot does not say, still locked if either applies
+                let hard = self.hard_lock_due().map(|(reason, _)| reason);
+                let stage = stage.map_or((0, now), |(index, lasted)| {
+                    (index, now.checked_sub(lasted).unwrap_or(now))
+                });
+                self.lock_break_at_stage(now + left, hard, BreakKind::Short, stage)
+            }
+            Snapshot::Paused { left, work_left } => Ok(self.pause(now + left, work_left)),
+            Snapshot::Meeting {
+                left,
+                work_left,
+                debt,
+            } => self.meeting(now + left, now + work_left, debt),
+            Snapshot::Away { work_left } => {
+                self.away = true;
+                self.status.set_waiting(None);
+                Ok(Phase::Away { work_left })
+            }
+        }
+    }
+
+    /// Only returns if re-executing is not possible or failed
+    fn reexec(&mut self, phase: &Phase, reply: Reply) {
+        if !self.can_reexec {
+            let _client_gone = reply.send(Err(
+                "Can not re-execute with dropped privileges, restart the service instead"
+                    .to_string(),
+            ));
+            return;
+        }
+
+        let _client_gone = reply.send(Ok(()));
+        thread::sleep(REEXEC_GRACE);
+        let snapshot = phase.snapshot(self.clock.now(), self.stage);
+        let grabbed = self.online_devices.grabbed_fds();
+        // the new process starts it again, it would be left running otherwise
+        let command_stopped = self
+            .running_command
+            .take()
+            .map(break_command::Running::stop);
+        let Err(e) = reexec::exec(snapshot, grabbed);
+        error!("Could not re-execute, continuing: {e:?}");
+        if command_stopped.is_some() {
+            self.start_command();
         }
     }
-}
 
-fn wait_for_user_activity(recv_any_input: &Receiver<InputResult>) -> color_eyre::Result<()> {
-    loop {
-        // clear old events
-        match recv_any_input.try_recv() {
-            Err(_) => break,
-            Ok(Err(e)) => return Err(e).wrap_err("Error with device file"),
-            Ok(Ok(_)) => (), // old event, ignore
+    fn resume(&mut self, work_left: Option<Duration>) -> Result<Phase> {
+        match work_left {
+            Some(left) => self.work(self.clock.now() + left),
+            None => self.wait(None),
         }
     }
 
-    loop {
-        #[allow(clippy::match_same_arms)]
-        match recv_any_input.recv() {
-            Err(_) => (), // device disconnected, ignore
-            Ok(Err(e)) => return Err(e).wrap_err("Error with device file"),
-            Ok(Ok(_)) => return Ok(()), // new event! stop blocking
+    /// The error in the result is send to the api client
+    fn control(
+        &mut self,
+        phase: Phase,
+        control: Control,
+    ) -> Result<(Phase, std::result::Result<(), String>)> {
+        let now = self.clock.now();
+        match (&phase, control) {
+            (
+                Phase::Break {
+                    hard: Some(HardLock::Curfew),
+                    ..
+                },
+                Control::LiftCurfew,
+            ) => (),
+            (
+                Phase::Break {
+                    hard: Some(HardLock::Curfew),
+                    ..
+                },
+                _,
+            ) => {
+                let curfew = self.curfew.as_ref().expect("locked by the curfew");
+                let msg = format!(
+                    "Locked during the curfew ({}), {} override(s) left this week",
+                    curfew.window(),
+                    curfew.overrides_left()
+                );
+                return Ok((phase, Err(msg)));
+            }
+            (
+                Phase::Break {
+                    hard: Some(HardLock::DailyLimit),
+                    ..
+                },
+                _,
+            ) => {
+                let limit = self.daily_limit.as_ref().expect("locked by the limit");
+                let msg = format!(
+                    "The daily limit is reached, locked until {}",
+                    limit.rollover()
+                );
+                return Ok((phase, Err(msg)));
+            }
+            (
+                Phase::Notice {
+                    then: Locking::Hard(reason, _),
+                    ..
+                },
+                _,
+            ) => {
+                let msg = match reason {
+                    HardLock::Curfew => "About to lock for the curfew",
+                    HardLock::DailyLimit => "About to lock for the daily limit",
+                };
+                return Ok((phase, Err(msg.to_string())));
+            }
+            // pausing would drop the breaks owed
+            (Phase::Meeting { debt, .. }, Control::Pause(_)) if !debt.is_zero() => {
+                let msg = format!(
+                    "{} of break is owed from the meeting, end it first",
+                    fmt_dur(*debt)
+                );
+                return Ok((phase, Err(msg)));
+            }
+            _ => (),
+        }
+        // waiting for a pause in typing is still part of the work period
+        let phase = match phase {
+            Phase::Finishing { .. } => Phase::Work { next_break: now },
+            Phase::Notice { lock_at, .. } => Phase::Work {
+                next_break: lock_at,
+            },
+            phase => phase,
+        };
+        // the durations are added to now or to the end of the current period
+        let from = match &phase {
+            Phase::Work { next_break } | Phase::Meeting { next_break, .. } => *next_break,
+            Phase::Break { next_work, .. } => *next_work,
+            _ => now,
+        };
+        let fits = |duration| from.max(now).checked_add(duration).is_some();
+        if !control.duration().is_none_or(fits) {
+            return Ok((phase, Err("The duration is too long".to_string())));
+        }
+        let phase = match (phase, control) {
+            (Phase::Work { next_break }, Control::Pause(duration)) => self.pause(
+                now + duration,
+                Some(next_break.saturating_duration_since(now)),
+            ),
+            (Phase::Break { locks, .. }, Control::Pause(duration)) => {
+                self.end_break(locks)?;
+                self.pause(now + duration, None)
+            }
+            (Phase::Waiting, Control::Pause(duration)) => self.pause(now + duration, None),
+            (
+                Phase::Unplugged { work_left } | Phase::Away { work_left },
+                Control::Pause(duration),
+            ) => self.pause(now + duration, work_left),
+            (Phase::Paused { work_left, .. }, Control::Pause(duration)) => {
+                self.pause(now + duration, work_left)
+            }
+            (Phase::Paused { work_left, .. }, Control::Resume) => self.resume(work_left)?,
+            (Phase::Work { next_break }, Control::Meeting(duration)) => {
+                self.meeting(now + duration, next_break, Duration::ZERO)?
+            }
+            (Phase::Waiting, Control::Meeting(duration)) => {
+                self.meeting(now + duration, now + self.work_period(), Duration::ZERO)?
+            }
+            (
+                Phase::Paused { work_left, .. }
+                | Phase::Unplugged { work_left }
+                | Phase::Away { work_left },
+                Control::Meeting(duration),
+            ) => {
+                let next_break = now + work_left.unwrap_or_else(|| self.work_period());
+                self.meeting(now + duration, next_break, Duration::ZERO)?
+            }
+            // the rest of the break is owed
+            (
+                Phase::Break {
+                    next_work, locks, ..
+                },
+                Control::Meeting(duration),
+            ) => {
+                self.end_break(locks)?;
+                let debt = next_work.saturating_duration_since(now);
+                self.meeting(now + duration, now + self.work_period(), debt)?
+            }
+            (
+                Phase::Meeting {
+                    next_break, debt, ..
+                },
+                Control::Meeting(duration),
+            ) => self.meeting(now + duration, next_break, debt)?,
+            (
+                Phase::Meeting {
+                    next_break, debt, ..
+                },
+                Control::EndMeeting,
+            ) => self.end_meeting(next_break, debt)?,
+            (Phase::Meeting { next_break, .. }, Control::Pause(duration)) => self.pause(
+                now + duration,
+                Some(next_break.saturating_duration_since(now)),
+            ),
+            (Phase::Work { .. }, Control::Skip) => self.work(now + self.work_period())?,
+            (Phase::Break { locks, .. }, Control::Skip) => {
+                self.end_break(locks)?;
+                self.work(now + self.work_period())?
+            }
+            (Phase::Work { next_break }, Control::Snooze(duration)) => {
+                self.work(next_break + duration)?
+            }
+            (Phase::Break { locks, .. }, Control::Snooze(duration)) => {
+                self.end_break(locks)?;
+                self.work(now + duration)?
+            }
+            (
+                Phase::Break {
+                    next_work,
+                    locks,
+                    hard: hard @ Some(HardLock::Curfew),
+                },
+                Control::LiftCurfew,
+            ) => {
+                let curfew = self.curfew.as_mut().expect("locked by the curfew");
+                if let Err(msg) = curfew.lift() {
+                    let phase = Phase::Break {
+                        next_work,
+                        locks,
+                        hard,
+                    };
+                    return Ok((phase, Err(msg)));
+                }
+                info!(
+                    "Curfew lifted for tonight, {} override(s) left this week",
+                    curfew.overrides_left()
+                );
+                self.end_break(locks)?;
+                self.wait(None)?
+            }
+            (Phase::Break { locks, .. }, Control::Unlock) => {
+                self.end_break(locks)?;
+                self.wait(None)?
+            }
+            (
+                Phase::Break {
+                    next_work,
+                    locks,
+                    hard,
+                },
+                Control::Extend(duration),
+            ) => {
+                let next_work = next_work + duration;
+                self.status.extend_break(next_work);
+                Phase::Break {
+                    next_work,
+                    locks,
+                    hard,
+                }
+            }
+            // the break owed is taken too
+            (Phase::Meeting { debt, .. }, Control::BreakNow(duration)) => {
+                self.break_now(duration.unwrap_or(self.break_duration).max(debt))?
+            }
+            (
+                Phase::Waiting
+                | Phase::Work { .. }
+                | Phase::Paused { .. }
+                | Phase::Unplugged { .. }
+                | Phase::Away { .. },
+                Control::BreakNow(duration),
+            ) => self.break_now(duration.unwrap_or(self.break_duration))?,
+            (phase, control) => {
+                let msg = match control {
+                    Control::Resume => "Not paused",
+                    Control::Skip | Control::Snooze(_) => "No work period or break to skip",
+                    Control::Unlock | Control::Extend(_) => "Not in a break",
+                    Control::BreakNow(_) => "Already in a break",
+                    Control::LiftCurfew => "The curfew does not apply right now",
+                    Control::EndMeeting => "Not in a meeting",
+                    Control::Pause(_) => unreachable!("can always pause"),
+                    Control::Meeting(_) => unreachable!("can always start a meeting"),
+                    Control::Reexec => unreachable!("handled by the run loop"),
+                };
+                return Ok((phase, Err(msg.to_string())));
+            }
+        };
+        Ok((phase, Ok(())))
+    }
+
+    fn pause(&mut self, until: Instant, work_left: Option<Duration>) -> Phase {
+        // pauses count towards the daily limit and end at the curfew,
+        // resuming then locks
+        let until = self.next_hard_lock().map_or(until, |at| until.min(at));
+        self.status.set_paused(until);
+        Phase::Paused { until, work_left }
+    }
+}
+
+/// Somewhere in `duration` ± `jitter` picked by `roll`, `jitter` must be
+/// shorter than `duration`
+fn jittered(duration: Duration, jitter: Duration, roll: u64) -> Duration {
+    let range = u64::try_from(jitter.as_millis() * 2 + 1).unwrap_or(u64::MAX);
+    duration - jitter + Duration::from_millis(roll % range)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let work = Duration::from_secs(25 * 60);
+        let jitter = Duration::from_secs(3 * 60);
+        assert_eq!(jittered(work, jitter, 0), work - jitter);
+        assert_eq!(jittered(work, jitter, 180_000), work);
+        assert_eq!(jittered(work, jitter, 360_000), work + jitter);
+        for roll in [1, 7_919, u64::MAX / 3, u64::MAX] {
+            let period = jittered(work, jitter, roll);
+            assert!(work - jitter <= period && period <= work + jitter);
         }
     }
+
+    #[test]
+    fn idle_credit_policies() {
+        let idle = Duration::from_secs(4 * 60);
+        let credit = |arg: &str| IdleCredit::parse(arg).unwrap().of(idle);
+        assert_eq!(credit("full"), idle);
+        assert_eq!(credit("none"), Duration::ZERO);
+        assert_eq!(credit("50%"), Duration::from_secs(2 * 60));
+        assert_eq!(credit("1m"), Duration::from_secs(60));
+        assert_eq!(credit("10m"), idle);
+        assert!(IdleCredit::parse("101%").is_err());
+        assert!(IdleCredit::parse("half").is_err());
+        assert_eq!(IdleCredit::Percent(50).to_string(), "50%");
+    }
+
+    #[test]
+    fn snapshot_keeps_the_escalation_stage_and_away() {
+        let since = Instant::now();
+        let now = since + Duration::from_secs(30);
+        let phase = Phase::Break {
+            next_work: now + Duration::from_secs(60),
+            locks: Vec::new(),
+            hard: None,
+        };
+        let snapshot = phase.snapshot(now, Some((1, since)));
+        assert!(matches!(
+            snapshot,
+            Snapshot::Break { left, stage: Some((1, lasted)) }
+                if left == Duration::from_secs(60) && lasted == Duration::from_secs(30)
+        ));
+
+        let phase = Phase::Away {
+            work_left: Some(Duration::from_secs(60)),
+        };
+        assert!(matches!(
+            phase.snapshot(now, None),
+            Snapshot::Away { work_left: Some(left) } if left == Duration::from_secs(60)
+        ));
+
+        // from a process that did not know about stages yet
+        let old: Snapshot = ron::from_str("Break(left:(secs:60,nanos:0))").unwrap();
+        assert!(matches!(old, Snapshot::Break { stage: None, .. }));
+    }
 }
@@ -0,0 +1,177 @@
+//! Follows logind as the session of the user is locked and unlocked, see
+//! `session::active`. Unlocking then starts a work period right away and
+//! locking starts the idle time, instead of waiting for the next input to ask
+//! logind. Uses `gdbus monitor`, which needs no privileges to receive the
+//! signals. A signal only says something changed, the lock state is then
+//! asked from logind. Off with `--no-follow-screen-lock`.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use tracing::{debug, info};
+
+use crate::check_inputs::Input;
+use crate::input_queue::InputSender;
+use crate::session::{self, Session};
+
+const SESSION: &str = "org.freedesktop.login1.Session";
+
+/// Reports lock changes as `Input::ScreenLock`. Without gdbus the lock state
+/// is only known when asked for, see `password_prompt::screen_locked`.
+pub(crate) fn follow(tx: InputSender) {
+    thread::spawn(move || {
+        if let Err(report) = monitor(&tx) {
+            info!("Not following screen locks as they happen: {report:#}");
+        }
+    });
+}
+
+fn monitor(tx: &InputSender) -> Result<()> {
+    let mut gdbus = Command::new("gdbus")
+        .args(["monitor", "--system", "--dest", "org.freedesktop.login1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .wrap_err("Could not run gdbus")?;
+    let stdout = gdbus.stdout.take().expect("is set to piped");
+    let mut session = find_session();
+    for line in BufReader::new(stdout).lines() {
+        let line = line.wrap_err("Could not read from gdbus")?;
+        if sessions_changed(&line) {
+            session = find_session();
+        } else if !session
+            .as_ref()
+            .is_some_and(|(_, object)| lock_changed(&line, object))
+        {
+            continue;
+        }
+        let Some((session, _)) = &session else {
+            continue;
+        };
+        let locked = match session.locked() {
+            Ok(locked) => locked,
+            Err(report) => {
+                debug!("Could not check the screen lock: {report:#}");
+                continue;
+            }
+        };
+        debug!("Screen {}", if locked { "locked" } else { "unlocked" });
+        if tx.send(Ok(Input::ScreenLock(locked))).is_err() {
+            let _ = gdbus.kill();
+            return Ok(()); // the activity watcher is gone
+        }
+    }
+    let status = gdbus.wait().wrap_err("Could not wait for gdbus")?;
+    Err(eyre!("gdbus monitor stopped ({status})"))
+}
+
+/// The session to follow with its object path, `None` while there is none
+fn find_session() -> Option<(Session, String)> {
+    match session::active() {
+        Ok(session) => {
+            let object = session.object_path();
+            debug!("Following screen locks of session {}", session.id);
+            Some((session, object))
+        }
+        Err(report) => {
+            debug!("Not following screen locks for now: {report:#}");
+            None
+        }
+    }
+}
+
+/// Whether a line of `gdbus monitor` output is a lock, unlock or switch of
+/// the session at `object`
+fn lock_changed(line: &str, object: &str) -> bool {
+    let Some((signal_object, signal)) = line.split_once(": ") else {
+        return false;
+    };
+    let Some((name, arguments)) = signal.split_once(' ') else {
+        return false;
+    };
+    if signal_object != object {
+        return false;
+    }
+    if matches!(name.strip_prefix(SESSION), Some(".Lock" | ".Unlock")) {
+        return true;
+    }
+    name == "org.freedesktop.DBus.Properties.PropertiesChanged"
+        && arguments.starts_with(&format!("('{SESSION}'"))
+        && (arguments.contains("'LockedHint'") || arguments.contains("'Active'"))
+}
+
+/// Sessions came or went or another became active, the session to follow
+/// could be another one
+fn sessions_changed(line: &str) -> bool {
+    let Some((_, signal)) = line.split_once(": ") else {
+        return false;
+    };
+    signal.starts_with("org.freedesktop.login1.Manager.SessionNew ")
+        || signal.starts_with("org.freedesktop.login1.Manager.SessionRemoved ")
+        || (signal.starts_with("org.freedesktop.DBus.Properties.PropertiesChanged ")
+            && signal.contains("('org.freedesktop.login1.Seat'")
+            && signal.contains("'ActiveSession'"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_follows_own_session() {
+        let own = "/org/freedesktop/login1/session/_32";
+        let other = "/org/freedesktop/login1/session/_33";
+        let line = |object: &str, signal: &str| format!("{object}: {signal}");
+        assert!(lock_changed(
+            &line(own, "org.freedesktop.login1.Session.Lock ()"),
+            own
+        ));
+        assert!(lock_changed(
+            &line(own, "org.freedesktop.login1.Session.Unlock ()"),
+            own
+        ));
+        assert!(!lock_changed(
+            &line(other, "org.freedesktop.login1.Session.Lock ()"),
+            own
+        ));
+        let changed = |object: &str, properties: &str| {
+            line(
+                object,
+                &format!(
+                    "org.freedesktop.DBus.Properties.PropertiesChanged \
+                    ('org.freedesktop.login1.Session', {{{properties}}}, @as [])"
+                ),
+            )
+        };
+        assert!(lock_changed(&changed(own, "'LockedHint': <true>"), own));
+        assert!(lock_changed(&changed(own, "'Active': <false>"), own));
+        assert!(!lock_changed(&changed(other, "'Active': <false>"), own));
+        assert!(!lock_changed(&changed(own, "'IdleHint': <true>"), own));
+        assert!(!lock_changed(
+            "The name org.freedesktop.login1 is owned by :1.5",
+            own
+        ));
+    }
+
+    #[test]
+    fn notices_other_sessions() {
+        let manager = "/org/freedesktop/login1";
+        assert!(sessions_changed(&format!(
+            "{manager}: org.freedesktop.login1.Manager.SessionNew \
+            ('3', objectpath '/org/freedesktop/login1/session/_33')"
+        )));
+        assert!(sessions_changed(
+            "/org/freedesktop/login1/seat/seat0: \
+            org.freedesktop.DBus.Properties.PropertiesChanged \
+            ('org.freedesktop.login1.Seat', {'ActiveSession': <('3', \
+            objectpath '/org/freedesktop/login1/session/_33')>}, @as [])"
+        ));
+        assert!(!sessions_changed(
+            "/org/freedesktop/login1/session/_32: \
+            org.freedesktop.login1.Session.Lock ()"
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+//! The logind session of the user at the machine. Following and forcing
+//! screen locks and the break command act on this session only, not on
+//! those of other users logged in at the same time.
+
+use std::process::Command;
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use nix::unistd::Uid;
+
+use crate::integration::notification::User;
+
+pub(crate) struct Session {
+    pub(crate) id: String,
+    pub(crate) user: User,
+}
+
+/// The active user session. Without root only sessions of the user running
+/// the service count, when none of these is active the first is used.
+pub(crate) fn active() -> Result<Session> {
+    let output = loginctl(&["list-sessions", "--no-legend"])?;
+    let own_uid = (!Uid::current().is_root()).then(|| Uid::current().to_string());
+    let mut fallback = None;
+    for id in output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+    {
+        let properties = loginctl(&[
+            "show-session",
+            id,
+            "--property=Active",
+            "--property=Class",
+            "--property=Name",
+            "--property=User",
+        ])?;
+        let (Some(uid), Some(name)) =
+            (property(&properties, "User"), property(&properties, "Name"))
+        else {
+            continue;
+        };
+        if property(&properties, "Class") != Some("user")
+            || own_uid.as_deref().is_some_and(|own| own != uid)
+        {
+            continue;
+        }
+        let session = Session {
+            id: id.to_string(),
+            user: User {
+                id: uid.to_string(),
+                name: name.to_string(),
+            },
+        };
+        if property(&properties, "Active") == Some("yes") {
+            return Ok(session);
+        }
+        if own_uid.is_some() && fallback.is_none() {
+            fallback = Some(session);
+        }
+    }
+    fallback
+        .ok_or_else(|| eyre!("No active user session"))
+        .suggestion("Log in on the machine, graphical or on a console")
+}
+
+impl Session {
+    /// The object logind signals changes of this session on. Escaped like
+    /// `sd_bus_path_encode`: anything but letters and digits, and a leading
+    /// digit, become `_` followed by the hex value.
+    pub(crate) fn object_path(&self) -> String {
+        let mut path = "/org/freedesktop/login1/session/".to_string();
+        for (i, byte) in self.id.bytes().enumerate() {
+            if byte.is_ascii_alphabetic() || (byte.is_ascii_digit() && i > 0) {
+                path.push(byte as char);
+            } else {
+                path.push_str(&format!("_{byte:02x}"));
+            }
+        }
+        path
+    }
+
+    /// Locked or switched away from, asked from logind
+    pub(crate) fn locked(&self) -> Result<bool> {
+        let properties = loginctl(&[
+            "show-session",
+            &self.id,
+            "--property=Active",
+            "--property=LockedHint",
+        ])?;
+        Ok(property(&properties, "LockedHint") == Some("yes")
+            || property(&properties, "Active") == Some("no"))
+    }
+
+    pub(crate) fn lock(&self) -> Result<()> {
+        loginctl(&["lock-session", &self.id])
+            .map(|_| ())
+            .with_note(|| format!("session: {}", self.id))
+    }
+}
+
+fn property<'a>(properties: &'a str, name: &str) -> Option<&'a str> {
+    properties
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn loginctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("loginctl")
+        .args(args)
+        .output()
+        .wrap_err("Could not run loginctl")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "loginctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).wrap_err("loginctl output is not utf8")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session(id: &str) -> Session {
+        Session {
+            id: id.to_string(),
+            user: User {
+                id: "1000".to_string(),
+                name: "eva".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn object_path_is_escaped_like_logind() {
+        assert_eq!(
+            session("2").object_path(),
+            "/org/freedesktop/login1/session/_32"
+        );
+        assert_eq!(
+            session("12").object_path(),
+            "/org/freedesktop/login1/session/_312"
+        );
+        assert_eq!(
+            session("c1").object_path(),
+            "/org/freedesktop/login1/session/c1"
+        );
+    }
+
+    #[test]
+    fn reads_properties() {
+        let properties = "Name=eva\nUser=1000\nActive=yes\n";
+        assert_eq!(property(properties, "User"), Some("1000"));
+        assert_eq!(property(properties, "Active"), Some("yes"));
+        assert_eq!(property(properties, "Class"), None);
+    }
+}
@@ -0,0 +1,42 @@
+// shared between the lib and the bin target
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Lets a stream that can not be cloned, like a tls stream, be used as a
+/// separate reader and writer. The api only writes while not reading so the
+/// two never wait on each other.
+pub(crate) struct SharedStream<S>(Arc<Mutex<S>>);
+
+impl<S> SharedStream<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self(Arc::new(Mutex::new(stream)))
+    }
+
+    #[allow(dead_code)] // only used by the lib
+    pub(crate) fn with<T>(&self, f: impl FnOnce(&mut S) -> T) -> T {
+        f(&mut self.0.lock().expect("stream users do not panic"))
+    }
+}
+
+impl<S> Clone for SharedStream<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S: Read> Read for SharedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.lock().expect("stream users do not panic").read(buf)
+    }
+}
+
+impl<S: Write> Write for SharedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().expect("stream users do not panic").write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.lock().expect("stream users do not panic").flush()
+    }
+}
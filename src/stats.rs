@@ -0,0 +1,344 @@
+//! Work periods and breaks reconstructed from the state transitions in the
+//! history.
+
+use std::time::{Duration, SystemTime};
+
+use break_enforcer::{Api, StateUpdate};
+use chrono::{DateTime, Local, NaiveDate};
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use serde::Serialize;
+
+use crate::cli::{ExportFormat, StatsArgs, StatsCommand};
+use crate::integration::{self, fmt_dur};
+use crate::paths::Dirs;
+
+/// A break ending this much before planned still counts as taken
+const TAKEN_MARGIN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Work,
+    Break,
+    Pause,
+}
+
+impl Kind {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Kind::Work => "work",
+            Kind::Break => "break",
+            Kind::Pause => "pause",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Period {
+    pub(crate) kind: Kind,
+    pub(crate) start: SystemTime,
+    pub(crate) end: SystemTime,
+    /// how long it was scheduled to last when it started
+    pub(crate) planned: Duration,
+    /// a work period that ended in a break or a break that was not ended
+    /// early
+    pub(crate) completed: bool,
+}
+
+impl Period {
+    pub(crate) fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+
+    fn day(&self) -> NaiveDate {
+        DateTime::<Local>::from(self.start).date_naive()
+    }
+}
+
+/// Each transition ends the period before it. The last period is still
+/// going, it ends at `now` or is left out if that is None.
+///
+/// Without updates the service was not running or the machine suspended. A
+/// period running over by more than its planned length is split there: it
+/// ends as planned and the rest is left out.
+pub(crate) fn periods(updates: &[StateUpdate], now: Option<SystemTime>) -> Vec<Period> {
+    let mut transitions: Vec<_> = updates
+        .iter()
+        .filter_map(|update| match update {
+            StateUpdate::Work { at, until_break } => {
+                Some((Some(Kind::Work), at.wall_clock, *until_break))
+            }
+            StateUpdate::Break { at, until_work, .. } => {
+                Some((Some(Kind::Break), at.wall_clock, *until_work))
+            }
+            StateUpdate::Paused { at, until_resume } => {
+                Some((Some(Kind::Pause), at.wall_clock, *until_resume))
+            }
+            // work time keeps counting during a meeting
+            StateUpdate::Meeting { at, until_end, .. } => {
+                Some((Some(Kind::Work), at.wall_clock, *until_end))
+            }
+            StateUpdate::Waiting { at, .. } => Some((None, at.wall_clock, Duration::ZERO)),
+            StateUpdate::Progress { .. } | StateUpdate::Warning { .. } => None,
+        })
+        .collect();
+    // an extended break is recorded as a new break, it is still one break
+    transitions.dedup_by(|extended, earlier| match (*earlier, *extended) {
+        ((Some(Kind::Break), start, _), (Some(Kind::Break), at, until_work)) => {
+            earlier.2 = at.duration_since(start).unwrap_or_default() + until_work;
+            true
+        }
+        _ => false,
+    });
+
+    let mut periods = Vec::new();
+    for (i, &(kind, start, planned)) in transitions.iter().enumerate() {
+        let Some(kind) = kind else {
+            continue;
+        };
+        let next = transitions.get(i + 1);
+        let Some(mut end) = next.map(|&(_, end, _)| end).or(now) else {
+            continue;
+        };
+        let mut lasted = end.duration_since(start).unwrap_or_default();
+        let gap = !planned.is_zero() && lasted > planned.saturating_mul(2);
+        if gap {
+            lasted = planned;
+            end = start + planned;
+        }
+        let completed = match kind {
+            Kind::Work => !gap && next.is_some_and(|&(kind, ..)| kind == Some(Kind::Break)),
+            Kind::Break => lasted + TAKEN_MARGIN >= planned,
+            Kind::Pause => false,
+        };
+        periods.push(Period {
+            kind,
+            start,
+            end,
+            planned,
+            completed,
+        });
+    }
+    periods
+}
+
+/// Totals for the periods that started on a day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct DayStats {
+    pub(crate) work: Duration,
+    pub(crate) completed_work_periods: u32,
+    pub(crate) breaks_taken: u32,
+    pub(crate) breaks_skipped: u32,
+}
+
+impl DayStats {
+    pub(crate) fn of(periods: &[Period], day: NaiveDate) -> Self {
+        let mut stats = Self::default();
+        for period in periods.iter().filter(|period| period.day() == day) {
+            match (period.kind, period.completed) {
+                (Kind::Work, completed) => {
+                    stats.work += period.duration();
+                    stats.completed_work_periods += u32::from(completed);
+                }
+                (Kind::Break, true) => stats.breaks_taken += 1,
+                (Kind::Break, false) => stats.breaks_skipped += 1,
+                (Kind::Pause, _) => (),
+            }
+        }
+        stats
+    }
+}
+
+impl std::fmt::Display for DayStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of work in {} completed work periods, {} breaks taken and {} skipped",
+            fmt_dur(self.work),
+            self.completed_work_periods,
+            self.breaks_taken,
+            self.breaks_skipped
+        )
+    }
+}
+
+/// All transitions, the service only remembers the recent ones
+fn history(dirs: &Dirs) -> Result<Vec<StateUpdate>> {
+    let path = dirs.history_file();
+    if path.exists() {
+        return integration::read_history(&path);
+    }
+    let mut api = Api::new()
+        .wrap_err("Could not connect to the service")
+        .suggestion("Is break-enforcer running with its tcp api enabled? (use --tcp-api)")
+        .suggestion(
+            "Run the service with --persist-history to keep stats while it is not running",
+        )?;
+    api.history().wrap_err("Could not get history")
+}
+
+/// A period as exported, times are rfc 3339 in the local timezone
+#[derive(Serialize)]
+struct Row {
+    kind: &'static str,
+    start: String,
+    end: String,
+    seconds: u64,
+    planned_seconds: u64,
+    completed: bool,
+}
+
+impl From<&Period> for Row {
+    fn from(period: &Period) -> Self {
+        let time =
+            |at| DateTime::<Local>::from(at).to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+        Self {
+            kind: period.kind.name(),
+            start: time(period.start),
+            end: time(period.end),
+            seconds: period.duration().as_secs(),
+            planned_seconds: period.planned.as_secs(),
+            completed: period.completed,
+        }
+    }
+}
+
+fn export(format: ExportFormat, since: Option<NaiveDate>, dirs: &Dirs) -> Result<()> {
+    // a period still going has no end yet
+    let rows: Vec<Row> = periods(&history(dirs)?, None)
+        .iter()
+        .filter(|period| since.is_none_or(|since| period.day() >= since))
+        .map(Row::from)
+        .collect();
+    match format {
+        ExportFormat::Csv => {
+            println!("kind,start,end,seconds,planned_seconds,completed");
+            for row in rows {
+                println!(
+                    "{},{},{},{},{},{}",
+                    row.kind, row.start, row.end, row.seconds, row.planned_seconds, row.completed
+                );
+            }
+        }
+        ExportFormat::Json => {
+            let json =
+                serde_json::to_string_pretty(&rows).wrap_err("Could not serialize the periods")?;
+            println!("{json}");
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn run(StatsArgs { command }: &StatsArgs, dirs: &Dirs) -> Result<()> {
+    match *command {
+        StatsCommand::Export { format, since } => export(format, since, dirs),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use break_enforcer::{BreakKind, Timestamp};
+
+    use super::*;
+
+    fn at(secs: u64) -> Timestamp {
+        Timestamp {
+            monotonic: Duration::from_secs(secs),
+            wall_clock: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn skipped_break_ends_early() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let updates = [
+            StateUpdate::Work {
+                at: at(0),
+                until_break: minutes(20),
+            },
+            StateUpdate::Break {
+                at: at(20 * 60),
+                until_work: minutes(5),
+                kind: BreakKind::Short,
+            },
+            StateUpdate::Waiting {
+                at: at(25 * 60),
+                reset: None,
+            },
+            StateUpdate::Work {
+                at: at(30 * 60),
+                until_break: minutes(20),
+            },
+            StateUpdate::Break {
+                at: at(50 * 60),
+                until_work: minutes(5),
+                kind: BreakKind::Short,
+            },
+            StateUpdate::Work {
+                at: at(51 * 60),
+                until_break: minutes(20),
+            },
+        ];
+        assert_eq!(periods(&updates, None).len(), 4);
+        let periods = periods(&updates, Some(SystemTime::UNIX_EPOCH + minutes(60)));
+        assert_eq!(periods.len(), 5);
+        let day = periods[0].day();
+        let stats = DayStats::of(&periods, day);
+        assert_eq!(
+            stats,
+            DayStats {
+                work: minutes(49),
+                completed_work_periods: 2,
+                breaks_taken: 1,
+                breaks_skipped: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn splits_periods_at_gaps() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let updates = [
+            StateUpdate::Work {
+                at: at(0),
+                until_break: minutes(20),
+            },
+            // suspended from minute 10 to 60
+            StateUpdate::Break {
+                at: at(60 * 60),
+                until_work: minutes(5),
+                kind: BreakKind::Short,
+            },
+        ];
+        let periods = periods(&updates, Some(SystemTime::UNIX_EPOCH + minutes(24 * 60)));
+        assert_eq!(periods[0].duration(), minutes(20));
+        assert!(!periods[0].completed);
+        assert_eq!(periods[1].start, SystemTime::UNIX_EPOCH + minutes(60));
+        assert_eq!(periods[1].duration(), minutes(5));
+    }
+
+    #[test]
+    fn extended_break_is_one_break() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let updates = [
+            StateUpdate::Break {
+                at: at(0),
+                until_work: minutes(5),
+                kind: BreakKind::Short,
+            },
+            StateUpdate::Break {
+                at: at(3 * 60),
+                until_work: minutes(12),
+                kind: BreakKind::Short,
+            },
+            StateUpdate::Waiting {
+                at: at(15 * 60),
+                reset: None,
+            },
+        ];
+        let periods = periods(&updates, None);
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].planned, minutes(15));
+        assert!(periods[0].completed);
+    }
+}
@@ -1,48 +1,376 @@
-use crate::cli::StatusArgs;
-use break_enforcer::Api;
+use crate::cli::{StatusArgs, StatusFormat};
+use crate::crash;
+use crate::integration::notification;
+use crate::paths::Dirs;
+use break_enforcer::proto::ErrorCode;
+use break_enforcer::{Api, StateUpdate};
 use color_eyre::eyre::WrapErr;
 use color_eyre::Section;
+use nix::time::{clock_gettime, ClockId};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-fn format_status(status: Result<String, break_enforcer::Error>, use_json: bool) -> String {
-    match (status, use_json) {
-        (Ok(msg), true) => format!("{{\"msg\": \"{msg}\"}}"),
-        (Ok(msg), false) => msg,
-        (Err(err), true) => format!("{{\"msg\": \"{err}\"}}"),
-        (Err(err), false) => err.to_string(),
+/// The state as bars see it, they can style on it without parsing the
+/// status line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    NotRunning,
+    Waiting,
+    Work,
+    /// work with the break close, see `--imminent`
+    Imminent,
+    Break,
+    Paused,
+    Meeting,
+}
+
+impl Class {
+    fn name(self) -> &'static str {
+        match self {
+            Class::NotRunning => "not-running",
+            Class::Waiting => "waiting",
+            Class::Work => "work",
+            Class::Imminent => "imminent",
+            Class::Break => "break",
+            Class::Paused => "paused",
+            Class::Meeting => "meeting",
+        }
+    }
+
+    /// See `status --exit-code`
+    fn exit_code(self) -> i32 {
+        match self {
+            Class::Work | Class::Imminent => 0,
+            Class::Break => 1,
+            Class::Waiting => 2,
+            Class::Paused => 3,
+            Class::Meeting => 4,
+            Class::NotRunning => 10,
+        }
+    }
+
+    /// Also how long is left in the state. `elapsed` is the time since the
+    /// update was send.
+    fn of(
+        update: Option<&StateUpdate>,
+        elapsed: Duration,
+        imminent: Duration,
+    ) -> (Self, Option<Duration>) {
+        let left = |in_state: Duration| Some(in_state.saturating_sub(elapsed));
+        match update {
+            None | Some(StateUpdate::Waiting { .. } | StateUpdate::Warning { .. }) => {
+                (Class::Waiting, None)
+            }
+            Some(StateUpdate::Work { until_break, .. }) => {
+                let class = if until_break.saturating_sub(elapsed) <= imminent {
+                    Class::Imminent
+                } else {
+                    Class::Work
+                };
+                (class, left(*until_break))
+            }
+            Some(
+                StateUpdate::Break {
+                    until_work: left_in_state,
+                    ..
+                }
+                | StateUpdate::Progress {
+                    remaining: left_in_state,
+                    ..
+                },
+            ) => (Class::Break, left(*left_in_state)),
+            Some(StateUpdate::Paused { until_resume, .. }) => (Class::Paused, left(*until_resume)),
+            Some(StateUpdate::Meeting { until_end, .. }) => (Class::Meeting, left(*until_end)),
+        }
+    }
+}
+
+/// Text and icon per class for `--format waybar`, read from `--style`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Style {
+    #[serde(default)]
+    not_running: Look,
+    #[serde(default)]
+    waiting: Look,
+    #[serde(default)]
+    work: Look,
+    #[serde(default)]
+    imminent: Look,
+    #[serde(default, rename = "break")]
+    break_: Look,
+    #[serde(default)]
+    paused: Look,
+    #[serde(default)]
+    meeting: Look,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Look {
+    /// replaces the status line, `{msg}` is the status line and `{left}` the
+    /// whole minutes left in the state
+    #[serde(default)]
+    text: Option<String>,
+    /// shown before the text
+    #[serde(default)]
+    icon: Option<String>,
+}
+
+impl Style {
+    fn read(path: &Path) -> color_eyre::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .wrap_err("Could not read the status style")
+            .with_note(|| format!("path: {}", path.display()))?;
+        ron::from_str(&data)
+            .wrap_err("Could not parse the status style")
+            .with_note(|| format!("path: {}", path.display()))
+    }
+
+    fn look(&self, class: Class) -> &Look {
+        match class {
+            Class::NotRunning => &self.not_running,
+            Class::Waiting => &self.waiting,
+            Class::Work => &self.work,
+            Class::Imminent => &self.imminent,
+            Class::Break => &self.break_,
+            Class::Paused => &self.paused,
+            Class::Meeting => &self.meeting,
+        }
+    }
+
+    fn text(&self, class: Class, msg: &str, left: Option<Duration>) -> String {
+        let look = self.look(class);
+        let text = look.text.as_deref().map_or_else(
+            || msg.to_string(),
+            |text| {
+                let minutes = left.map_or(0, |left| left.as_secs().div_ceil(60));
+                text.replace("{msg}", msg)
+                    .replace("{left}", &minutes.to_string())
+            },
+        );
+        match &look.icon {
+            Some(icon) if text.is_empty() => icon.clone(),
+            Some(icon) => format!("{icon} {text}"),
+            None => text,
+        }
+    }
+}
+
+/// What the service reported
+struct Status {
+    msg: String,
+    /// the last state change, only fetched if the class is needed
+    last: Option<StateUpdate>,
+    /// through the current period, None if not fetched or the service is
+    /// too old to tell
+    progress: Option<f32>,
+}
+
+impl Status {
+    fn class(&self, imminent: Duration) -> (Class, Option<Duration>) {
+        let elapsed = self
+            .last
+            .as_ref()
+            .map_or(Duration::ZERO, |update| elapsed_since(update_time(update)));
+        Class::of(self.last.as_ref(), elapsed, imminent)
     }
 }
 
-#[derive(Default)]
+/// Time since `at` on the same clock the service uses
+fn elapsed_since(at: break_enforcer::Timestamp) -> Duration {
+    match clock_gettime(ClockId::CLOCK_MONOTONIC) {
+        Ok(now) => Duration::from(now).saturating_sub(at.monotonic),
+        Err(_) => at.wall_clock.elapsed().unwrap_or_default(),
+    }
+}
+
+/// `status` errs with the message to show
+fn format_status(
+    status: Result<Status, String>,
+    format: StatusFormat,
+    imminent: Duration,
+    style: &Style,
+) -> String {
+    let (msg, class, left, progress) = match status {
+        Ok(status) => {
+            let (class, left) = status.class(imminent);
+            (status.msg, class, left, status.progress)
+        }
+        Err(msg) => (msg, Class::NotRunning, None, None),
+    };
+    match format {
+        StatusFormat::Plain => msg,
+        StatusFormat::Json => json!({
+            "msg": msg,
+            "class": class.name(),
+            "progress": progress,
+        })
+        .to_string(),
+        StatusFormat::Waybar => json!({
+            "text": style.text(class, &msg, left),
+            "tooltip": msg,
+            "alt": class.name(),
+            "class": class.name(),
+            "percentage": progress.map(percentage),
+        })
+        .to_string(),
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn percentage(done: f32) -> u8 {
+    (done.clamp(0.0, 1.0) * 100.0).round() as u8
+}
+
+fn update_time(update: &StateUpdate) -> break_enforcer::Timestamp {
+    match *update {
+        StateUpdate::Waiting { at, .. }
+        | StateUpdate::Work { at, .. }
+        | StateUpdate::Break { at, .. }
+        | StateUpdate::Paused { at, .. }
+        | StateUpdate::Meeting { at, .. }
+        | StateUpdate::Progress { at, .. }
+        | StateUpdate::Warning { at, .. } => at,
+    }
+}
+
+/// The status line and, if `with_state`, the last state change
+fn fetch(api: &mut Api, with_state: bool) -> Result<Status, break_enforcer::Error> {
+    let msg = api.status()?;
+    if !with_state {
+        return Ok(Status {
+            msg,
+            last: None,
+            progress: None,
+        });
+    }
+    let last = api
+        .history()?
+        .into_iter()
+        .rev()
+        .find(|update| !matches!(update, StateUpdate::Warning { .. }));
+    let progress = match api.progress() {
+        Ok(progress) => Some(progress),
+        Err(break_enforcer::Error::ServerError {
+            code: ErrorCode::UnknownRequest,
+            ..
+        }) => None,
+        Err(other) => return Err(other),
+    };
+    Ok(Status {
+        msg,
+        last,
+        progress,
+    })
+}
+
 enum ReconnectingApi {
-    #[default]
-    Disconnected,
+    Disconnected {
+        retry_at: Instant,
+        /// doubles on every failed attempt
+        backoff: Duration,
+    },
     Connected(Api),
 }
 
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// See `--notify-unreachable`
+const UNREACHABLE_NOTIFY_AFTER: Duration = Duration::from_secs(30);
+const UNREACHABLE_NOTIFY_EVERY: Duration = Duration::from_secs(60 * 60);
+const UNREACHABLE: &str = "break-enforcer daemon is not running, breaks are not enforced";
+
+/// When to notify about the service being unreachable, not on the first
+/// failure, a restart of the service should not notify
+#[derive(Debug, Default)]
+struct Unreachable {
+    since: Option<Instant>,
+    notified: Option<Instant>,
+}
+
+impl Unreachable {
+    /// Whether to notify now
+    fn notify(&mut self, reachable: bool, now: Instant) -> bool {
+        if reachable {
+            self.since = None;
+            return false;
+        }
+        let since = *self.since.get_or_insert(now);
+        let due = now.duration_since(since) >= UNREACHABLE_NOTIFY_AFTER
+            && self
+                .notified
+                .is_none_or(|at| now.duration_since(at) >= UNREACHABLE_NOTIFY_EVERY);
+        if due {
+            self.notified = Some(now);
+        }
+        due
+    }
+}
+
+/// Connecting fails if the service speaks another protocol version. If only
+/// the version differs this warns on stderr: after an update the service
+/// keeps running the old version until it is restarted.
+pub(crate) fn connect(port: Option<u16>) -> Result<Api, break_enforcer::Error> {
+    let api = match port {
+        Some(port) => Api::with_port(port),
+        None => Api::new(),
+    }?;
+    if let Some(warning) = outdated(api.server_version()) {
+        eprintln!("Warning: {warning}, restart the service to use the new version");
+    }
+    Ok(api)
+}
+
+pub(crate) fn outdated(server_version: &str) -> Option<String> {
+    let ours = env!("CARGO_PKG_VERSION");
+    (server_version != ours)
+        .then(|| format!("the running service is version {server_version} while this is {ours}"))
+}
+
 impl ReconnectingApi {
     fn new() -> Self {
-        ReconnectingApi::Disconnected
+        ReconnectingApi::Disconnected {
+            retry_at: Instant::now(),
+            backoff: Duration::ZERO,
+        }
     }
 
-    fn status(&mut self) -> Result<String, break_enforcer::Error> {
-        let placeholder = ReconnectingApi::default();
+    /// None while waiting before trying to reconnect
+    fn status(
+        &mut self,
+        port: Option<u16>,
+        with_state: bool,
+    ) -> Option<Result<Status, break_enforcer::Error>> {
+        let placeholder = ReconnectingApi::new();
         let owned_self = core::mem::replace(self, placeholder);
 
-        let mut api = match owned_self {
-            ReconnectingApi::Disconnected => break_enforcer::Api::new()?,
-            ReconnectingApi::Connected(api) => api,
+        let (api, backoff) = match owned_self {
+            ReconnectingApi::Disconnected { retry_at, backoff } if Instant::now() < retry_at => {
+                *self = ReconnectingApi::Disconnected { retry_at, backoff };
+                return None;
+            }
+            ReconnectingApi::Disconnected { backoff, .. } => (connect(port), backoff),
+            ReconnectingApi::Connected(api) => (Ok(api), Duration::ZERO),
         };
 
-        match api.status() {
-            Ok(status) => {
-                *self = ReconnectingApi::Connected(api);
-                Ok(status)
-            }
-            Err(e) => {
-                *self = ReconnectingApi::Disconnected;
-                Err(e)
-            }
+        let res = api.and_then(|mut api| {
+            let status = fetch(&mut api, with_state)?;
+            *self = ReconnectingApi::Connected(api);
+            Ok(status)
+        });
+        if res.is_err() {
+            let backoff = (backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+            *self = ReconnectingApi::Disconnected {
+                retry_at: Instant::now() + backoff,
+                backoff,
+            };
         }
+        Some(res)
     }
 }
 
@@ -50,26 +378,158 @@ pub fn run(
     StatusArgs {
         update_period,
         use_json,
+        format,
+        imminent,
+        style,
+        api_port,
+        quiet_errors,
+        exit_code,
+        notify_unreachable,
     }: StatusArgs,
+    dirs: &Dirs,
 ) -> color_eyre::Result<()> {
-    let mut api = ReconnectingApi::new();
+    let format = if use_json { StatusFormat::Json } else { format };
+    let with_state = format != StatusFormat::Plain || exit_code;
+    let style = match style {
+        Some(path) => Style::read(&path)?,
+        None => Style::default(),
+    };
     let Some(period) = update_period else {
-        let msg = api
-            .status()
+        // on stderr, bars and scripts read stdout
+        if let Some((at, report)) = crash::recent(&dirs.crash_dir()) {
+            eprintln!(
+                "daemon crashed at {}, report: {}",
+                at.format("%Y-%m-%d %H:%M:%S"),
+                report.display()
+            );
+        }
+        let status = connect(api_port).and_then(|mut api| fetch(&mut api, with_state));
+        if exit_code {
+            let class = match &status {
+                Ok(status) => status.class(imminent).0,
+                Err(e) => {
+                    eprintln!("Error requesting status message: {e}");
+                    Class::NotRunning
+                }
+            };
+            if let Ok(status) = status {
+                println!("{}", format_status(Ok(status), format, imminent, &style));
+            }
+            let _ = std::io::stdout().flush();
+            std::process::exit(class.exit_code());
+        }
+        let status = status
             .wrap_err("Error requesting status message")
             .suggestion(
                 "Is break-enforcer running and is it running with its tcp api \
                 enabled? (use --tcp-api)",
             )?;
-        let output = format_status(Ok(msg), use_json);
+        let output = format_status(Ok(status), format, imminent, &style);
         println!("{output}");
         return Ok(());
     };
 
+    let mut api = ReconnectingApi::new();
+    let mut output = String::new();
+    let mut unreachable = Unreachable::default();
     loop {
-        let msg = api.status();
-        let output = format_status(msg, use_json);
+        let status = api.status(api_port, with_state);
+        if let Some(status) = &status {
+            if notify_unreachable && unreachable.notify(status.is_ok(), Instant::now()) {
+                if let Err(report) = notification::notify(UNREACHABLE) {
+                    eprintln!("Could not notify that the service is unreachable: {report}");
+                }
+            }
+        }
+        match status {
+            Some(Err(_)) if quiet_errors => {
+                output = format_status(Err(String::new()), format, imminent, &style);
+            }
+            Some(status) => {
+                let status = status.map_err(|err| err.to_string());
+                output = format_status(status, format, imminent, &style);
+            }
+            None => (), // repeat the last output
+        }
         println!("{output}");
         std::thread::sleep(period);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use break_enforcer::Timestamp;
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn close_to_a_break_is_imminent() {
+        let at = Timestamp {
+            monotonic: Duration::ZERO,
+            wall_clock: SystemTime::now(),
+        };
+        let work = StateUpdate::Work {
+            at,
+            until_break: Duration::from_secs(20 * 60),
+        };
+        let imminent = Duration::from_secs(5 * 60);
+        let elapsed = Duration::from_secs(10 * 60);
+        assert_eq!(
+            Class::of(Some(&work), elapsed, imminent),
+            (Class::Work, Some(Duration::from_secs(10 * 60)))
+        );
+        let elapsed = Duration::from_secs(16 * 60);
+        assert_eq!(
+            Class::of(Some(&work), elapsed, imminent),
+            (Class::Imminent, Some(Duration::from_secs(4 * 60)))
+        );
+        assert_eq!(Class::of(None, elapsed, imminent), (Class::Waiting, None));
+    }
+
+    #[test]
+    fn notifies_about_unreachable_service_rarely() {
+        let mut unreachable = Unreachable::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(!unreachable.notify(false, at(0)));
+        assert!(!unreachable.notify(true, at(20)), "restarted in time");
+        assert!(!unreachable.notify(false, at(40)));
+        assert!(unreachable.notify(false, at(70)));
+        assert!(!unreachable.notify(false, at(140)));
+        assert!(unreachable.notify(false, at(70 + 60 * 60)));
+    }
+
+    #[test]
+    fn imminent_work_still_exits_as_work() {
+        assert_eq!(Class::Imminent.exit_code(), Class::Work.exit_code());
+        let cli = crate::cli::Cli::try_parse_from(["break-enforcer", "status", "--exit-code"]);
+        assert!(cli.is_ok());
+        let both = ["break-enforcer", "status", "--exit-code", "-u", "1s"];
+        assert!(crate::cli::Cli::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn style_replaces_text_per_class() {
+        let style: Style = ron::from_str(
+            r#"(imminent: (icon: Some("!"), text: Some("in {left}m")), break: (icon: Some("B")))"#,
+        )
+        .unwrap();
+        let left = Some(Duration::from_secs(150));
+        assert_eq!(style.text(Class::Imminent, "2m", left), "! in 3m");
+        assert_eq!(style.text(Class::Break, "2m left", left), "B 2m left");
+        assert_eq!(
+            style.text(Class::Work, "break in 20m", left),
+            "break in 20m"
+        );
+    }
+
+    #[test]
+    fn warns_about_other_service_version() {
+        assert_eq!(outdated(env!("CARGO_PKG_VERSION")), None);
+        let warning = outdated("0.0.1").unwrap();
+        assert!(warning.contains("version 0.0.1"), "{warning}");
+    }
+}
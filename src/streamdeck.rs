@@ -0,0 +1,251 @@
+//! Shows the break state on an Elgato Stream Deck and turns key presses into
+//! api calls. Supports the models that take jpeg key images: the Original
+//! (v2), MK.2 and XL.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use break_enforcer::streamdeck::{Action, StreamDeck};
+//!
+//! let deck = StreamDeck::open().unwrap();
+//! let last = deck.keys() - 1;
+//! deck.run(&[
+//!     (last - 1, Action::Pause(Duration::from_secs(30 * 60))),
+//!     (last, Action::Skip),
+//! ])
+//! .unwrap();
+//! ```
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use hidapi::{HidApi, HidDevice};
+use jpeg_encoder::{ColorType, Encoder};
+
+use crate::{Api, Error, StateUpdate};
+
+const ELGATO: u16 = 0x0fd9;
+
+struct Model {
+    product_id: u16,
+    keys: u8,
+    /// width and height of a key image in pixels
+    key_size: u16,
+}
+
+const MODELS: [Model; 4] = [
+    // Original v2
+    Model {
+        product_id: 0x006d,
+        keys: 15,
+        key_size: 72,
+    },
+    // MK.2
+    Model {
+        product_id: 0x0080,
+        keys: 15,
+        key_size: 72,
+    },
+    // XL
+    Model {
+        product_id: 0x006c,
+        keys: 32,
+        key_size: 96,
+    },
+    // XL v2
+    Model {
+        product_id: 0x008f,
+        keys: 32,
+        key_size: 96,
+    },
+];
+
+/// Key images are send in reports of this size, the first 8 bytes are a
+/// header
+const IMAGE_REPORT_LEN: usize = 1024;
+const IMAGE_HEADER_LEN: usize = 8;
+/// Key states start at this offset in an input report
+const KEY_STATES_OFFSET: usize = 4;
+/// How long `run` waits for a key press before checking for state updates
+const POLL_PRESSES: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// Shown on keys with an action
+const ACTION_KEY: Rgb = Rgb(60, 60, 60);
+
+/// The color showing the state: grey while waiting, green during work turning
+/// orange in the last five minutes, red during a break, blue while paused and
+/// purple during a meeting. `None` for progress updates and warnings, they do not change the
+/// state.
+#[must_use]
+pub fn color(update: &StateUpdate) -> Option<Rgb> {
+    Some(match update {
+        StateUpdate::Waiting { .. } => Rgb(90, 90, 90),
+        StateUpdate::Work { until_break, .. } if *until_break < Duration::from_secs(5 * 60) => {
+            Rgb(230, 130, 0)
+        }
+        StateUpdate::Work { .. } => Rgb(0, 170, 60),
+        StateUpdate::Break { .. } => Rgb(200, 0, 0),
+        StateUpdate::Paused { .. } => Rgb(0, 90, 200),
+        StateUpdate::Meeting { .. } => Rgb(130, 60, 200),
+        StateUpdate::Progress { .. } | StateUpdate::Warning { .. } => return None,
+    })
+}
+
+/// What pressing a key does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Pause(Duration),
+    Resume,
+    Skip,
+    /// With `None` the service picks the duration
+    Snooze(Option<Duration>),
+}
+
+impl Action {
+    pub fn send(self, api: &mut Api) -> Result<(), Error> {
+        match self {
+            Action::Pause(duration) => api.pause(duration),
+            Action::Resume => api.resume(),
+            Action::Skip => api.skip(),
+            Action::Snooze(duration) => api.snooze(duration),
+        }
+    }
+}
+
+pub struct StreamDeck {
+    device: HidDevice,
+    keys: u8,
+    key_size: u16,
+    pressed: Vec<bool>,
+}
+
+impl StreamDeck {
+    /// Opens the first supported Stream Deck
+    pub fn open() -> Result<Self, Error> {
+        let hid = HidApi::new().map_err(Error::StreamDeck)?;
+        let (info, model) = hid
+            .device_list()
+            .filter(|info| info.vendor_id() == ELGATO)
+            .find_map(|info| {
+                MODELS
+                    .iter()
+                    .find(|model| model.product_id == info.product_id())
+                    .map(|model| (info, model))
+            })
+            .ok_or(Error::NoStreamDeck)?;
+        Ok(Self {
+            device: info.open_device(&hid).map_err(Error::StreamDeck)?,
+            keys: model.keys,
+            key_size: model.key_size,
+            pressed: vec![false; model.keys.into()],
+        })
+    }
+
+    /// Number of keys, they are numbered from the top left row by row
+    #[must_use]
+    pub fn keys(&self) -> u8 {
+        self.keys
+    }
+
+    pub fn set_brightness(&self, percent: u8) -> Result<(), Error> {
+        self.device
+            .send_feature_report(&[0x03, 0x08, percent.min(100)])
+            .map_err(Error::StreamDeck)
+    }
+
+    pub fn set_color(&self, key: u8, Rgb(r, g, b): Rgb) -> Result<(), Error> {
+        let pixels = usize::from(self.key_size).pow(2);
+        let image: Vec<u8> = [r, g, b].repeat(pixels);
+        let mut jpeg = Vec::new();
+        Encoder::new(&mut jpeg, 90)
+            .encode(&image, self.key_size, self.key_size, ColorType::Rgb)
+            .map_err(Error::EncodingKeyImage)?;
+
+        let chunks = jpeg.chunks(IMAGE_REPORT_LEN - IMAGE_HEADER_LEN);
+        let last_page = chunks.len() - 1;
+        for (page, chunk) in chunks.enumerate() {
+            let len = u16::try_from(chunk.len()).expect("chunks are smaller then a report");
+            let page_nr = u16::try_from(page).expect("key images are small");
+            let mut report = vec![0x02, 0x07, key, u8::from(page == last_page)];
+            report.extend_from_slice(&len.to_le_bytes());
+            report.extend_from_slice(&page_nr.to_le_bytes());
+            report.extend_from_slice(chunk);
+            report.resize(IMAGE_REPORT_LEN, 0);
+            self.device.write(&report).map_err(Error::StreamDeck)?;
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for keys to go down, returns those that did
+    pub fn read_pressed(&mut self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let mut report = vec![0; KEY_STATES_OFFSET + usize::from(self.keys)];
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        let n_read = self
+            .device
+            .read_timeout(&mut report, timeout)
+            .map_err(Error::StreamDeck)?;
+        if n_read < report.len() {
+            return Ok(Vec::new());
+        }
+
+        let mut newly_pressed = Vec::new();
+        for (key, (state, was_pressed)) in report[KEY_STATES_OFFSET..]
+            .iter()
+            .zip(self.pressed.iter_mut())
+            .enumerate()
+        {
+            let pressed = *state != 0;
+            if pressed && !*was_pressed {
+                newly_pressed.push(u8::try_from(key).expect("there are less then 256 keys"));
+            }
+            *was_pressed = pressed;
+        }
+        Ok(newly_pressed)
+    }
+
+    /// Keys without an action show the state (see [`color`]), pressing a key
+    /// with an action sends it to the service. Returns when the service
+    /// closes the connection.
+    pub fn run(mut self, actions: &[(u8, Action)]) -> Result<(), Error> {
+        let mut api = Api::new()?;
+        let updates = Api::new()?.subscribe()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for update in updates {
+                if tx.send(update).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let state_keys: Vec<u8> = (0..self.keys)
+            .filter(|key| actions.iter().all(|(action_key, _)| action_key != key))
+            .collect();
+        for (key, _) in actions {
+            self.set_color(*key, ACTION_KEY)?;
+        }
+        loop {
+            loop {
+                match rx.try_recv() {
+                    Ok(update) => {
+                        if let Some(color) = color(&update?) {
+                            for key in &state_keys {
+                                self.set_color(*key, color)?;
+                            }
+                        }
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            for key in self.read_pressed(POLL_PRESSES)? {
+                if let Some((_, action)) = actions.iter().find(|(k, _)| *k == key) {
+                    action.send(&mut api)?;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Keeps the threads of the service running. A subsystem that panics or
+//! returns an error is restarted after a backoff instead of the service
+//! carrying on without it, for example no longer blocking devices that
+//! connect. While a subsystem waits to be restarted the service is degraded,
+//! the status says so.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use break_enforcer::SubsystemHealth;
+use color_eyre::Result;
+use tracing::{debug, error, info};
+
+use crate::integration::fmt_dur;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Subsystem {
+    /// locks and unlocks the devices, see `watch_and_block`
+    DeviceWatcher,
+    /// notices devices connecting and disconnecting
+    Inotify,
+    /// keeps the status, notifications and api up to date
+    Integrator,
+    /// accepts api connections, each listener on its own
+    Api(Listener),
+    #[cfg(feature = "http")]
+    HttpApi,
+    #[cfg(test)]
+    Test,
+}
+
+/// The api listens on the remote port, abstract unix sockets and local tcp
+/// ports, numbered in the order they were set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Listener {
+    Remote,
+    Unix(usize),
+    Tcp(usize),
+}
+
+impl Subsystem {
+    /// See `SubsystemHealth::id`
+    fn id(self) -> &'static str {
+        match self {
+            Subsystem::DeviceWatcher => "device_watcher",
+            Subsystem::Inotify => "inotify",
+            Subsystem::Integrator => "integrator",
+            Subsystem::Api(Listener::Remote) => "remote_api",
+            Subsystem::Api(Listener::Unix(_) | Listener::Tcp(_)) => "api",
+            #[cfg(feature = "http")]
+            Subsystem::HttpApi => "http_api",
+            #[cfg(test)]
+            Subsystem::Test => "test",
+        }
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subsystem::DeviceWatcher => f.write_str("device watcher"),
+            Subsystem::Inotify => f.write_str("inotify"),
+            Subsystem::Integrator => f.write_str("integrator"),
+            Subsystem::Api(Listener::Remote) => f.write_str("remote api"),
+            Subsystem::Api(Listener::Unix(n)) => write!(f, "api (unix socket {n})"),
+            Subsystem::Api(Listener::Tcp(n)) => write!(f, "api (tcp {n})"),
+            #[cfg(feature = "http")]
+            Subsystem::HttpApi => f.write_str("http api"),
+            #[cfg(test)]
+            Subsystem::Test => f.write_str("test"),
+        }
+    }
+}
+
+/// Backoff between restarts, doubles up to `RESTART_MAX`
+const RESTART_FIRST: Duration = Duration::from_secs(1);
+const RESTART_MAX: Duration = Duration::from_secs(60);
+/// A subsystem that ran this long before failing starts the backoff over
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Health {
+    /// false while waiting to be restarted
+    pub(crate) running: bool,
+    pub(crate) restarts: u32,
+    /// and when it happened
+    pub(crate) last_failure: Option<(Instant, String)>,
+}
+
+static HEALTH: Mutex<BTreeMap<Subsystem, Health>> = Mutex::new(BTreeMap::new());
+type Callback = Box<dyn Fn() + Send>;
+static ON_CHANGE: Mutex<Option<Callback>> = Mutex::new(None);
+
+/// Nothing in here panics with the lock held, a poisoned lock would only
+/// make the supervisor itself fail
+fn health() -> MutexGuard<'static, BTreeMap<Subsystem, Health>> {
+    HEALTH.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn changed() {
+    if let Some(callback) = &*ON_CHANGE.lock().unwrap_or_else(PoisonError::into_inner) {
+        callback();
+    }
+}
+
+/// Called when a subsystem fails or is restarted, replaces the previous
+/// callback
+pub(crate) fn on_change(callback: impl Fn() + Send + 'static) {
+    *ON_CHANGE.lock().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(callback));
+}
+
+/// The subsystems waiting to be restarted
+pub(crate) fn degraded() -> Vec<Subsystem> {
+    health()
+        .iter()
+        .filter(|(_, health)| !health.running)
+        .map(|(subsystem, _)| *subsystem)
+        .collect()
+}
+
+/// For the `health` api request
+pub(crate) fn subsystems() -> Vec<SubsystemHealth> {
+    health()
+        .iter()
+        .map(|(subsystem, health)| SubsystemHealth {
+            name: subsystem.to_string(),
+            running: health.running,
+            restarts: health.restarts,
+            last_failure: health
+                .last_failure
+                .as_ref()
+                .map(|(at, failure)| format!("{failure} ({} ago)", fmt_dur(at.elapsed()))),
+            id: subsystem.id().to_string(),
+        })
+        .collect()
+}
+
+/// Runs `run` on its own thread, it is run again whenever it panics or
+/// returns an error. Returning `Ok` ends it, for when what it serves is gone.
+pub(crate) fn spawn(subsystem: Subsystem, mut run: impl FnMut() -> Result<()> + Send + 'static) {
+    health().insert(
+        subsystem,
+        Health {
+            running: true,
+            ..Health::default()
+        },
+    );
+    thread::Builder::new()
+        .name(subsystem.to_string())
+        .spawn(move || supervise(subsystem, &mut run))
+        .expect("the OS should be able to spawn a thread");
+}
+
+fn supervise(subsystem: Subsystem, run: &mut dyn FnMut() -> Result<()>) {
+    let mut failures = 0u32;
+    loop {
+        let started = Instant::now();
+        let failure = match panic::catch_unwind(AssertUnwindSafe(&mut *run)) {
+            Ok(Ok(())) => {
+                debug!("The {subsystem} stopped");
+                health().remove(&subsystem);
+                return;
+            }
+            Ok(Err(report)) => format!("{report:#}"),
+            Err(panic) => panic_message(&*panic),
+        };
+        if started.elapsed() >= HEALTHY_AFTER {
+            failures = 0;
+        }
+        let backoff = restart_after(failures);
+        failures += 1;
+        error!(
+            "The {subsystem} failed, restarting it in {}: {failure}",
+            fmt_dur(backoff)
+        );
+        {
+            let mut health = health();
+            let health = health.entry(subsystem).or_default();
+            health.running = false;
+            health.restarts += 1;
+            health.last_failure = Some((Instant::now(), failure));
+        }
+        changed();
+
+        thread::sleep(backoff);
+        info!("Restarting the {subsystem}");
+        health().entry(subsystem).or_default().running = true;
+        changed();
+    }
+}
+
+fn restart_after(failures: u32) -> Duration {
+    (RESTART_FIRST * 2u32.pow(failures.min(6))).min(RESTART_MAX)
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        format!("panicked: {msg}")
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        format!("panicked: {msg}")
+    } else {
+        "panicked".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn restarts_after_a_panic() {
+        let (tx, rx) = mpsc::channel();
+        let mut runs = 0;
+        spawn(Subsystem::Test, move || {
+            runs += 1;
+            tx.send(runs).unwrap();
+            if runs == 1 {
+                panic!("first run");
+            }
+            Ok(())
+        });
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        assert_eq!(restart_after(0), RESTART_FIRST);
+        assert_eq!(restart_after(20), RESTART_MAX);
+    }
+}
@@ -0,0 +1,346 @@
+//! Shares activity and the schedule with break-enforcer on other machines,
+//! for example when switching between them using a KVM. Input on any of
+//! them counts as activity on all and work periods and breaks start on all at
+//! once.
+//!
+//! Every daemon listens for peers and connects to each peer. Both sides
+//! authenticate using certificates signed by a shared CA (mutual TLS).
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use tracing::{debug, info, warn};
+
+use crate::check_inputs::Event;
+use crate::clock::Clock;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SyncConfig {
+    pub(crate) listen: SocketAddr,
+    /// host:port, the host must be in the peers certificate
+    pub(crate) peers: Vec<String>,
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+    pub(crate) ca: PathBuf,
+}
+
+/// Send between peers as a line of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Message {
+    /// The user is active on the sending machine
+    Input,
+    /// A work period started with this much left
+    Work { left: Duration },
+    /// A break started with this much left
+    Break { left: Duration },
+}
+
+impl Message {
+    fn encode(self) -> String {
+        match self {
+            Message::Input => "input".to_string(),
+            Message::Work { left } => format!("work {}", left.as_millis()),
+            Message::Break { left } => format!("break {}", left.as_millis()),
+        }
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let millis = |arg: &str| arg.parse().ok().map(Duration::from_millis);
+        match line.split_once(' ') {
+            None if line == "input" => Some(Message::Input),
+            Some(("work", left)) => Some(Message::Work {
+                left: millis(left)?,
+            }),
+            Some(("break", left)) => Some(Message::Break {
+                left: millis(left)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Local input is shared at most this often, enough to keep peers from
+/// seeing the user as idle
+const SHARE_INPUT_EVERY: Duration = Duration::from_secs(5);
+/// Messages that could not be send for this long are outdated
+const STALE: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+/// Time an accepted connection gets to authenticate, after that the peer
+/// may stay quiet for as long as the user is idle
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// A write to a peer that does not read is given up on after this
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections from peers at once, each peer needs one
+const MAX_INCOMING: usize = 16;
+/// The longest message is `break ` with a u128 of milliseconds
+const MAX_LINE: u64 = 64;
+
+/// Sends to all peers
+pub(crate) struct Peers {
+    peers: Vec<mpsc::Sender<(Instant, Message)>>,
+    last_input: Option<Instant>,
+}
+
+impl Peers {
+    pub(crate) fn share(&mut self, message: Message) {
+        if message == Message::Input {
+            let now = Instant::now();
+            if self
+                .last_input
+                .is_some_and(|last| now.duration_since(last) < SHARE_INPUT_EVERY)
+            {
+                return;
+            }
+            self.last_input = Some(now);
+        }
+        for peer in &self.peers {
+            let _peer_thread_ends_with_us = peer.send((Instant::now(), message));
+        }
+    }
+}
+
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect)
+        .map_err(|e| eyre!("{e}"))
+        .wrap_err("Could not read certificates")
+        .with_note(|| format!("path: {}", path.display()))
+}
+
+pub(crate) fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| eyre!("{e}"))
+        .wrap_err("Could not read private key")
+        .with_note(|| format!("path: {}", path.display()))
+}
+
+fn tls_configs(config: &SyncConfig) -> Result<(Arc<ServerConfig>, Arc<ClientConfig>)> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certs = load_certs(&config.cert)?;
+    let key = load_key(&config.key)?;
+    let mut roots = RootCertStore::empty();
+    for ca in load_certs(&config.ca)? {
+        roots.add(ca).wrap_err("Invalid CA certificate")?;
+    }
+    let roots = Arc::new(roots);
+
+    let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+        .build()
+        .wrap_err("Could not set up verifying peers")?;
+    let server = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .wrap_err("Could not set up tls")?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs.clone(), key.clone_key())
+        .wrap_err("Certificate and key do not form a valid pair")?;
+    let client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .wrap_err("Could not set up tls")?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(certs, key)
+        .wrap_err("Certificate and key do not form a valid pair")?;
+    Ok((Arc::new(server), Arc::new(client)))
+}
+
+/// Input from peers moves `idle_since`, work periods and breaks they start
+/// are send as `Event::Remote`.
+pub(crate) fn start(
+    config: &SyncConfig,
+    idle_since: Arc<Mutex<Instant>>,
+    clock: Arc<dyn Clock>,
+    events: mpsc::Sender<Event>,
+) -> Result<Peers> {
+    let (server, client) = tls_configs(config).wrap_err("Could not load certificates")?;
+    let listener = TcpListener::bind(config.listen)
+        .wrap_err("Could not listen for peers")
+        .with_note(|| format!("address: {}", config.listen))?;
+    info!("Listening for sync peers on {}", config.listen);
+    thread::spawn(move || accept(&listener, &server, &idle_since, &clock, &events));
+
+    let peers = config
+        .peers
+        .iter()
+        .map(|peer| {
+            let (tx, rx) = mpsc::channel();
+            let (peer, client) = (peer.clone(), client.clone());
+            thread::spawn(move || send_to_peer(&peer, &client, &rx));
+            tx
+        })
+        .collect();
+    Ok(Peers {
+        peers,
+        last_input: None,
+    })
+}
+
+fn accept(
+    listener: &TcpListener,
+    server: &Arc<ServerConfig>,
+    idle_since: &Arc<Mutex<Instant>>,
+    clock: &Arc<dyn Clock>,
+    events: &mpsc::Sender<Event>,
+) {
+    let incoming = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Could not accept sync peer: {e}");
+                continue;
+            }
+        };
+        if incoming.fetch_add(1, Ordering::Relaxed) >= MAX_INCOMING {
+            incoming.fetch_sub(1, Ordering::Relaxed);
+            warn!("Too many sync peers connected, refusing another");
+            continue;
+        }
+        let (incoming, server, idle_since, clock, events) = (
+            incoming.clone(),
+            server.clone(),
+            idle_since.clone(),
+            clock.clone(),
+            events.clone(),
+        );
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+            if let Err(e) = receive(stream, server, &idle_since, &*clock, &events) {
+                debug!("Sync peer {peer} disconnected: {e:#}");
+            }
+            incoming.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+fn receive(
+    mut stream: TcpStream,
+    server: Arc<ServerConfig>,
+    idle_since: &Mutex<Instant>,
+    clock: &dyn Clock,
+    events: &mpsc::Sender<Event>,
+) -> Result<()> {
+    stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+        .wrap_err("Could not set timeouts")?;
+    let mut conn = ServerConnection::new(server).wrap_err("Could not start tls")?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)
+            .wrap_err("Peer did not authenticate")?;
+    }
+    stream
+        .set_read_timeout(None)
+        .wrap_err("Could not set timeouts")?;
+    let mut reader = BufReader::new(rustls::StreamOwned::new(conn, stream));
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = (&mut reader)
+            .take(MAX_LINE)
+            .read_line(&mut line)
+            .wrap_err("Could not read from peer")?;
+        if read == 0 {
+            return Ok(());
+        }
+        if read as u64 == MAX_LINE && !line.ends_with('\n') {
+            return Err(eyre!("Peer send a line over {MAX_LINE} bytes"));
+        }
+        let line = line.trim_end_matches('\n');
+        match Message::decode(line) {
+            Some(Message::Input) => *idle_since.lock().unwrap() = clock.now(),
+            Some(message) => {
+                if events.send(Event::Remote(message)).is_err() {
+                    return Ok(());
+                }
+            }
+            None => warn!("Sync peer send an invalid message: {line}"),
+        }
+    }
+}
+
+type Connection = rustls::StreamOwned<ClientConnection, TcpStream>;
+
+fn connect(peer: &str, client: &Arc<ClientConfig>) -> Result<Connection> {
+    let (host, _) = peer
+        .rsplit_once(':')
+        .ok_or_else(|| eyre!("Peer should be host:port"))?;
+    let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+        .wrap_err("Invalid peer host")?;
+    let stream = TcpStream::connect(peer).wrap_err("Could not connect")?;
+    let conn = ClientConnection::new(client.clone(), name).wrap_err("Could not start tls")?;
+    Ok(rustls::StreamOwned::new(conn, stream))
+}
+
+fn send_to_peer(
+    peer: &str,
+    client: &Arc<ClientConfig>,
+    messages: &mpsc::Receiver<(Instant, Message)>,
+) {
+    let mut conn: Option<Connection> = None;
+    let mut backoff = Duration::from_secs(1);
+    let mut retry_at = Instant::now();
+    for (sent_at, message) in messages {
+        if sent_at.elapsed() > STALE {
+            continue;
+        }
+        if conn.is_none() && Instant::now() >= retry_at {
+            match connect(peer, client) {
+                Ok(new) => {
+                    info!("Connected to sync peer {peer}");
+                    backoff = Duration::from_secs(1);
+                    conn = Some(new);
+                }
+                Err(e) => {
+                    debug!("Could not reach sync peer {peer}: {e:#}");
+                    retry_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                }
+            }
+        }
+        let Some(stream) = &mut conn else {
+            continue;
+        };
+        let line = message.encode() + "\n";
+        if let Err(e) = stream
+            .write_all(line.as_bytes())
+            .and_then(|()| stream.flush())
+        {
+            warn!("Lost connection to sync peer {peer}: {e}");
+            conn = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::Input,
+            Message::Work {
+                left: Duration::from_millis(1500),
+            },
+            Message::Break {
+                left: Duration::from_secs(300),
+            },
+        ] {
+            assert_eq!(Message::decode(&message.encode()), Some(message));
+        }
+        assert_eq!(Message::decode("work"), None);
+        assert_eq!(Message::decode("input 5"), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! Tray icon for panels implementing StatusNotifierItem: KDE, GNOME with the
+//! AppIndicator extension, waybar and others. The icon turns from green to
+//! red as the break approaches, the menu pauses, skips and snoozes.
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use break_enforcer::{Api, StateUpdate};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use ksni::blocking::TrayMethods;
+use ksni::menu::{MenuItem, StandardItem};
+use ksni::{Icon, ToolTip};
+use tracing::{debug, error};
+
+use crate::cli::TrayArgs;
+use crate::status;
+
+/// The icon starts turning red this long before a break
+const REDDENS_FROM: Duration = Duration::from_secs(10 * 60);
+/// Often enough for the color and the time in the tooltip to follow along
+const REFRESH: Duration = Duration::from_secs(10);
+const RECONNECT: Duration = Duration::from_secs(5);
+const ICON_SIZE: i32 = 32;
+
+#[derive(Debug, Clone, Copy)]
+enum Shown {
+    NotRunning,
+    Waiting,
+    Work { next_break: Instant },
+    Break { next_work: Instant },
+    Paused { until: Instant },
+    Meeting { until: Instant },
+}
+
+impl Shown {
+    /// None for warnings, they do not change the state
+    fn from_update(update: &StateUpdate) -> Option<Self> {
+        let now = Instant::now();
+        Some(match *update {
+            StateUpdate::Waiting { .. } => Shown::Waiting,
+            StateUpdate::Work { until_break, .. } => Shown::Work {
+                next_break: now + until_break,
+            },
+            StateUpdate::Break { until_work, .. } => Shown::Break {
+                next_work: now + until_work,
+            },
+            StateUpdate::Progress { remaining, .. } => Shown::Break {
+                next_work: now + remaining,
+            },
+            StateUpdate::Paused { until_resume, .. } => Shown::Paused {
+                until: now + until_resume,
+            },
+            StateUpdate::Meeting { until_end, .. } => Shown::Meeting {
+                until: now + until_end,
+            },
+            StateUpdate::Warning { .. } => return None,
+        })
+    }
+
+    fn color(self) -> [u8; 3] {
+        match self {
+            Shown::NotRunning | Shown::Waiting => [130, 130, 130],
+            Shown::Work { next_break } => {
+                let left = next_break.saturating_duration_since(Instant::now());
+                let green = left.min(REDDENS_FROM).as_secs_f32() / REDDENS_FROM.as_secs_f32();
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let channel = |full: f32| (full * green) as u8;
+                [255 - channel(255.), channel(180.), 40]
+            }
+            Shown::Break { .. } => [210, 0, 0],
+            Shown::Paused { .. } => [0, 100, 210],
+            Shown::Meeting { .. } => [130, 60, 200],
+        }
+    }
+
+    fn description(self) -> String {
+        let minutes = |at: Instant| at.saturating_duration_since(Instant::now()).as_secs() / 60;
+        match self {
+            Shown::NotRunning => "not running".to_string(),
+            Shown::Waiting => "waiting for activity".to_string(),
+            Shown::Work { next_break } => format!("break in {}m", minutes(next_break)),
+            Shown::Break { next_work } => format!("break, {}m left", minutes(next_work)),
+            Shown::Paused { until } => format!("paused for {}m", minutes(until)),
+            Shown::Meeting { until } => format!("meeting for {}m", minutes(until)),
+        }
+    }
+}
+
+/// A filled circle
+fn icon([r, g, b]: [u8; 3]) -> Icon {
+    let radius = ICON_SIZE / 2;
+    let mut data = Vec::new();
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let (dx, dy) = (x - radius, y - radius);
+            if dx * dx + dy * dy < radius * radius {
+                data.extend_from_slice(&[255, r, g, b]);
+            } else {
+                data.extend_from_slice(&[0; 4]);
+            }
+        }
+    }
+    Icon {
+        width: ICON_SIZE,
+        height: ICON_SIZE,
+        data,
+    }
+}
+
+struct Tray {
+    shown: Shown,
+    api_port: Option<u16>,
+    config_path: PathBuf,
+}
+
+impl Tray {
+    fn control(&self, name: &str, f: impl FnOnce(&mut Api) -> Result<(), break_enforcer::Error>) {
+        let res = status::connect(self.api_port).and_then(|mut api| f(&mut api));
+        if let Err(e) = res {
+            error!("Could not {name}: {e}");
+        }
+    }
+
+    fn item(
+        label: &str,
+        enabled: bool,
+        activate: impl Fn(&mut Self) + Send + 'static,
+    ) -> MenuItem<Self> {
+        StandardItem {
+            label: label.to_string(),
+            enabled,
+            activate: Box::new(activate),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl ksni::Tray for Tray {
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").to_string()
+    }
+
+    fn title(&self) -> String {
+        env!("CARGO_PKG_NAME").to_string()
+    }
+
+    fn icon_pixmap(&self) -> Vec<Icon> {
+        vec![icon(self.shown.color())]
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: env!("CARGO_PKG_NAME").to_string(),
+            description: self.shown.description(),
+            ..Default::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let running = !matches!(self.shown, Shown::NotRunning);
+        let paused = matches!(self.shown, Shown::Paused { .. });
+        let pause = |label: &str, minutes: u64| {
+            Self::item(label, running, move |tray| {
+                tray.control("pause", |api| api.pause(Duration::from_secs(minutes * 60)));
+            })
+        };
+        vec![
+            pause("Pause 30 minutes", 30),
+            pause("Pause 1 hour", 60),
+            Self::item("Resume", paused, |tray| tray.control("resume", Api::resume)),
+            Self::item("Skip", running, |tray| tray.control("skip", Api::skip)),
+            Self::item("Snooze", running, |tray| {
+                tray.control("snooze", |api| api.snooze(None));
+            }),
+            MenuItem::Separator,
+            Self::item("Settings", true, |tray| {
+                if let Err(e) = Command::new("xdg-open").arg(&tray.config_path).spawn() {
+                    error!("Could not open the config: {e}");
+                }
+            }),
+        ]
+    }
+}
+
+pub(crate) fn run(args: &TrayArgs, config_path: Option<PathBuf>) -> Result<()> {
+    let tray = Tray {
+        shown: Shown::NotRunning,
+        api_port: args.api_port,
+        config_path: config_path.unwrap_or_else(crate::config::setup_default_path),
+    };
+    let handle = tray.spawn().wrap_err("Could not show the tray icon")?;
+
+    let refresh = handle.clone();
+    thread::spawn(move || {
+        // updating re-reads the icon and tooltip which follow the time
+        while refresh.update(|_| ()).is_some() {
+            thread::sleep(REFRESH);
+        }
+    });
+
+    loop {
+        match status::connect(args.api_port).and_then(Api::subscribe) {
+            Ok(updates) => {
+                for update in updates {
+                    match update {
+                        Ok(update) => {
+                            if let Some(shown) = Shown::from_update(&update) {
+                                handle.update(|tray| tray.shown = shown);
+                            }
+                        }
+                        Err(e) => {
+                            debug!("Lost connection to break-enforcer: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => debug!("Could not connect to break-enforcer: {e}"),
+        }
+        handle.update(|tray| tray.shown = Shown::NotRunning);
+        thread::sleep(RECONNECT);
+    }
+}
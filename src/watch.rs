@@ -0,0 +1,39 @@
+//! The state changes of the running service as they happen, for scripts
+//! reacting to breaks. See `examples/print-state-switches.rs` for doing the
+//! same using the library.
+
+use break_enforcer::StateUpdate;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+
+use crate::cli::{WatchArgs, WatchFormat};
+use crate::{log, status};
+
+pub(crate) fn run(
+    WatchArgs {
+        format,
+        progress,
+        api_port,
+    }: &WatchArgs,
+) -> Result<()> {
+    let updates = status::connect(*api_port)
+        .and_then(break_enforcer::Api::subscribe)
+        .wrap_err("Could not subscribe to the service")
+        .suggestion("Is break-enforcer running with its tcp api enabled? (use --tcp-api)")?;
+
+    for update in updates {
+        let update = update.wrap_err("Could not receive update")?;
+        if !progress && matches!(update, StateUpdate::Progress { .. }) {
+            continue;
+        }
+        match format {
+            WatchFormat::Plain => log::print(&update, false)?,
+            WatchFormat::Json => log::print(&update, true)?,
+            WatchFormat::Ron => {
+                let ron = ron::to_string(&update).wrap_err("Could not serialize state update")?;
+                println!("{ron}");
+            }
+        }
+    }
+    Err(eyre!("The service closed the connection")).suggestion("Did it stop?")
+}
@@ -1,28 +1,56 @@
 use core::fmt;
 use std::collections::{HashMap, HashSet};
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 use std::{fs, thread};
 
 use base64::{engine::general_purpose, Engine as _};
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
+use evdev::RelativeAxisCode;
 use inotify::{EventMask, Inotify, WatchMask};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::check_inputs::device_removed;
-use crate::config::InputFilter;
+use crate::config::{BlockList, InputFilter};
+use crate::device_class::{self, DeviceClass};
+use crate::supervisor::{self, Subsystem};
+use crate::{password_prompt, paths, privsep};
 
 struct Device {
     locked: bool,
     raw_dev: evdev::Device,
+    /// set if it could not be locked, cleared on unlock
+    failed: Option<Failed>,
+}
+
+struct Failed {
+    retries: u32,
+    /// only busy devices are retried, another program might release them
+    next_retry: Option<Instant>,
+    /// passed to `on_grab_failed`, not done while the screen locker holds
+    /// the device
+    reported: bool,
+}
+
+/// Backoff between grabbing a busy device, doubles up to `GRAB_RETRY_MAX`
+const GRAB_RETRY_FIRST: Duration = Duration::from_secs(1);
+const GRAB_RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// A device that reconnects during a break taking longer than this to be
+/// blocked again is reported, see `OnlineDevices::on_slow_relock`
+pub(crate) const RELOCK_WARN_AFTER: Duration = Duration::from_millis(500);
+
+fn retry_after(retries: u32) -> Duration {
+    (GRAB_RETRY_FIRST * 2u32.pow(retries.min(5))).min(GRAB_RETRY_MAX)
 }
 
 fn device_name(device: &evdev::Device) -> String {
@@ -36,10 +64,45 @@ fn device_name(device: &evdev::Device) -> String {
         .map_or_else(default, String::from)
 }
 
+/// The MAC address for bluetooth devices, the kernel puts it in `uniq`
+fn bluetooth_address(device: &evdev::Device) -> Option<String> {
+    if device.input_id().bus_type() != evdev::BusType::BUS_BLUETOOTH {
+        return None;
+    }
+    device
+        .unique_name()
+        .filter(|uniq| !uniq.is_empty())
+        .map(String::from)
+}
+
 impl Device {
     fn name(&self) -> String {
         device_name(&self.raw_dev)
     }
+
+    fn matches(&self, id: &InputId, target: &Target) -> bool {
+        match target {
+            Target::Filter(filter) => filter.matches(
+                id,
+                &self.name(),
+                bluetooth_address(&self.raw_dev).as_deref(),
+            ),
+            Target::Remappers => device_class::is_remapper(&self.name()),
+            Target::Keyboards => {
+                device_class::classify(&self.raw_dev).contains(&DeviceClass::Keyboard)
+            }
+        }
+    }
+}
+
+/// What to lock
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    Filter(InputFilter),
+    /// The virtual devices of key remappers, see `device_class::is_remapper`
+    Remappers,
+    /// Every device that can type letters, see `device_class::classify`
+    Keyboards,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
@@ -49,6 +112,16 @@ pub struct InputId {
     version: u16,
 }
 
+impl InputId {
+    pub(crate) fn vendor(&self) -> u16 {
+        self.vendor
+    }
+
+    pub(crate) fn product(&self) -> u16 {
+        self.product
+    }
+}
+
 impl fmt::Display for InputId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let data = [
@@ -62,6 +135,34 @@ impl fmt::Display for InputId {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ParseInputIdError {
+    #[error("Not valid url-safe base64")]
+    Base64(#[source] base64::DecodeError),
+    #[error("Should decode to 6 bytes, got: {0}")]
+    WrongLength(usize),
+}
+
+/// parses the format produced by the `Display` implementation
+impl FromStr for InputId {
+    type Err = ParseInputIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(ParseInputIdError::Base64)?;
+        let [v1, v2, p1, p2, r1, r2] = bytes[..] else {
+            return Err(ParseInputIdError::WrongLength(bytes.len()));
+        };
+
+        Ok(Self {
+            vendor: u16::from_be_bytes([v1, v2]),
+            product: u16::from_be_bytes([p1, p2]),
+            version: u16::from_be_bytes([r1, r2]),
+        })
+    }
+}
+
 impl From<evdev::InputId> for InputId {
     fn from(value: evdev::InputId) -> Self {
         Self {
@@ -75,7 +176,7 @@ impl From<evdev::InputId> for InputId {
 macro_rules! lock_and_call_inner {
     ($is_pub:vis $name:ident, $($arg:ident: $type:ty),* $(;$ret:ty)?) => {
         $is_pub fn $name(&self, $($arg: $type),*) $(-> $ret)? {
-            self.inner.lock().unwrap().$name($($arg),*)
+            self.inner().$name($($arg),*)
         }
     };
 }
@@ -88,42 +189,174 @@ pub struct OnlineDevices {
 
 impl OnlineDevices {
     lock_and_call_inner!(pub list_inputs,; Result<Vec<BlockableInput>>);
-    lock_and_call_inner!(insert, raw_dev: evdev::Device, event_path: PathBuf; bool);
+    lock_and_call_inner!(insert, raw_dev: evdev::Device, event_path: PathBuf, locked: bool; bool);
+    lock_and_call_inner!(pub(crate) grabbed_fds,; Vec<(PathBuf, RawFd)>);
+    lock_and_call_inner!(pub(crate) grabbed_pointers,; Vec<evdev::Device>);
     lock_and_call_inner!(remove, event_path: &Path);
-    lock_and_call_inner!(lock_all_matching, id: &InputFilter; Result<()>);
-    lock_and_call_inner!(unlock_all_matching, id: &InputFilter; Result<()>);
+    lock_and_call_inner!(lock_all_matching, target: &Target; Result<()>);
+    lock_and_call_inner!(unlock_all_matching, target: &Target; Result<()>);
+    lock_and_call_inner!(changed,);
+    lock_and_call_inner!(keyboard_added, event_path: &Path);
+    lock_and_call_inner!(next_retry,; Option<Instant>);
+    lock_and_call_inner!(pub(crate) any_to_block, to_block: &BlockList; bool);
+    lock_and_call_inner!(relocked, event_path: &Path, connected: Instant);
+
+    /// How long it took to block the last device that reconnected while
+    /// locked, from noticing it to grabbing it
+    pub(crate) fn relock_latency(&self) -> Option<Duration> {
+        self.inner().relock_latency
+    }
+
+    /// The last device that could not be blocked and why, until no device
+    /// is failing anymore: retried successfully or unlocked again
+    pub(crate) fn last_grab_failure(&self) -> Option<String> {
+        self.inner().last_grab_failure.clone()
+    }
+
+    /// A panic while locked is survived by restarting the device watcher,
+    /// see `supervisor`. The devices it left behind are still valid.
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
     /// will also ensure that if the device is connected before
     /// the lockguard is dropped that it is locked
     pub(crate) fn lock(&self, input: InputFilter) -> Result<LockGuard> {
+        self.lock_target(Target::Filter(input))
+    }
+
+    /// Locks the virtual devices key remappers create, also those created
+    /// while locked. Remappers grab the physical devices themselves so
+    /// locking those does not work.
+    pub(crate) fn lock_remappers(&self) -> Result<LockGuard> {
+        self.lock_target(Target::Remappers)
+    }
+
+    /// Locks every keyboard, also those connected while locked. Used when
+    /// none of the devices to block are connected.
+    pub(crate) fn lock_keyboards(&self) -> Result<LockGuard> {
+        self.lock_target(Target::Keyboards)
+    }
+
+    /// Called after a device is connected or disconnected, replaces the
+    /// previous callback
+    pub(crate) fn on_change(&self, callback: impl Fn() + Send + 'static) {
+        self.inner().on_change = Some(Box::new(callback));
+    }
+
+    /// Called for every device that should be locked but could not be
+    /// grabbed, replaces the previous callback. Locking continues without it.
+    pub(crate) fn on_grab_failed(&self, callback: impl Fn(GrabFailed) + Send + 'static) {
+        self.inner().on_grab_failed = Some(Box::new(callback));
+    }
+
+    /// Called with the name of a device that could not be grabbed before
+    /// but was on a retry, replaces the previous callback
+    pub(crate) fn on_grab_retried(&self, callback: impl Fn(String) + Send + 'static) {
+        self.inner().on_grab_retried = Some(Box::new(callback));
+    }
+
+    /// Called when a device that reconnected while locked took longer than
+    /// `RELOCK_WARN_AFTER` to be blocked again, replaces the previous callback
+    pub(crate) fn on_slow_relock(&self, callback: impl Fn(SlowRelock) + Send + 'static) {
+        self.inner().on_slow_relock = Some(Box::new(callback));
+    }
+
+    /// Called when a keyboard is connected, not for those connected at the
+    /// start. Replaces the previous callback. Runs while the devices are
+    /// locked, it must not call back into them.
+    pub(crate) fn on_keyboard_added(&self, callback: impl Fn(NewInput) + Send + 'static) {
+        self.inner().on_keyboard_added = Some(Box::new(callback));
+    }
+
+    fn lock_target(&self, target: Target) -> Result<LockGuard> {
         let (tx, rx) = std::sync::mpsc::channel();
         self.tx
-            .send(Event::LockRequested(input.clone(), tx))
-            .expect("devices should never end/panic");
+            .send(Event::LockRequested(target.clone(), tx))
+            .map_err(|_| eyre!("The device watcher stopped"))?;
 
-        let lock_res = rx.recv().expect("devices should never end/panic");
+        let lock_res = rx
+            .recv()
+            .map_err(|_| eyre!("The device watcher failed while locking, it is restarted"))?;
         lock_res.wrap_err("Could not lock device")?;
 
         Ok(LockGuard {
-            filter: input,
+            target,
             tx: self.tx.clone(),
             dropped: false,
         })
     }
+
+    /// Takes over devices a previous process grabbed, see `reexec`. Lock them
+    /// again using `lock` or they are released on the next unlock.
+    pub(crate) fn adopt(&self, grabbed: HashMap<PathBuf, OwnedFd>) {
+        for (event_path, fd) in grabbed {
+            match evdev::Device::from_fd(fd) {
+                Ok(device) => {
+                    debug!("Took over grabbed device: {}", device_name(&device));
+                    self.insert(device, event_path, true);
+                }
+                Err(e) => warn!(
+                    "Could not take over grabbed device at {}: {e}",
+                    event_path.display()
+                ),
+            }
+        }
+    }
 }
 
 enum Event {
-    LockRequested(InputFilter, mpsc::Sender<Result<()>>),
-    UnLockRequested(InputFilter, mpsc::Sender<Result<()>>),
-    DevError(color_eyre::Result<()>),
-    DevAdded(PathBuf),
+    LockRequested(Target, mpsc::Sender<Result<()>>),
+    UnLockRequested(Target, mpsc::Sender<Result<()>>),
+    /// with when it was noticed
+    DevAdded(PathBuf, Instant),
     DevRemoved(PathBuf),
 }
 
+/// A device that is not blocked even though it should be
+#[derive(Debug)]
+pub(crate) struct GrabFailed {
+    pub(crate) device: String,
+    pub(crate) error: io::Error,
+}
+
+impl fmt::Display for GrabFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // devices pick their own name and this ends up in notifications, do
+        // not let one add lines of its own
+        let device: String = self.device.chars().filter(|c| !c.is_control()).collect();
+        match self.error.kind() {
+            ErrorKind::ResourceBusy => {
+                write!(f, "could not block {device}, another program grabbed it")
+            }
+            _ => write!(f, "could not block {device}: {}", self.error),
+        }
+    }
+}
+
+/// A device that reconnected while locked and was not blocked again right
+/// away, input in between was not blocked
+#[derive(Debug)]
+pub(crate) struct SlowRelock {
+    pub(crate) device: String,
+    pub(crate) latency: Duration,
+}
+
+impl fmt::Display for SlowRelock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} reconnected and was only blocked again after {} ms",
+            self.device,
+            self.latency.as_millis()
+        )
+    }
+}
+
 /// use `unlock` to re-enable the disabled input device
 #[must_use]
 pub struct LockGuard {
-    filter: InputFilter,
+    target: Target,
     tx: mpsc::Sender<Event>,
     // skip backup unlock if user did things right
     dropped: bool,
@@ -133,10 +366,11 @@ impl LockGuard {
     pub(crate) fn unlock(mut self) -> Result<()> {
         let (tx, rx) = std::sync::mpsc::channel();
         self.tx
-            .send(Event::UnLockRequested(self.filter.clone(), tx))
-            .expect("devices should never end/panic");
+            .send(Event::UnLockRequested(self.target.clone(), tx))
+            .map_err(|_| eyre!("The device watcher stopped"))?;
 
-        rx.recv().expect("devices should never end/panic")?;
+        rx.recv()
+            .map_err(|_| eyre!("The device watcher failed while unlocking, it is restarted"))??;
         self.dropped = true;
         Ok(())
     }
@@ -151,7 +385,7 @@ impl Drop for LockGuard {
         let (tx, _) = std::sync::mpsc::channel();
         let _do_not_panic_in_drop = self
             .tx
-            .send(Event::UnLockRequested(self.filter.clone(), tx));
+            .send(Event::UnLockRequested(self.target.clone(), tx));
         eprintln!(
             "Should not drop LockGuard but instead destroy by calling unlock
             since drop can not return an error"
@@ -165,6 +399,15 @@ struct Inner {
     // device serial could be duplicate due to manufacturer mistake
     id_to_devices: HashMap<InputId, HashMap<PathBuf, Device>>,
     status: Result<()>,
+    on_change: Option<Box<dyn Fn() + Send>>,
+    on_grab_failed: Option<Box<dyn Fn(GrabFailed) + Send>>,
+    on_grab_retried: Option<Box<dyn Fn(String) + Send>>,
+    on_keyboard_added: Option<Box<dyn Fn(NewInput) + Send>>,
+    on_slow_relock: Option<Box<dyn Fn(SlowRelock) + Send>>,
+    /// see `OnlineDevices::relock_latency`
+    relock_latency: Option<Duration>,
+    /// see `OnlineDevices::last_grab_failure`
+    last_grab_failure: Option<String>,
 }
 
 impl Inner {
@@ -181,11 +424,12 @@ impl Inner {
     }
 
     /// if it was already present ignore
-    fn insert(&mut self, raw_dev: evdev::Device, event_path: PathBuf) -> bool {
+    fn insert(&mut self, raw_dev: evdev::Device, event_path: PathBuf, locked: bool) -> bool {
         let id = raw_dev.input_id().into();
         let device = Device {
             raw_dev,
-            locked: false,
+            locked,
+            failed: None,
         };
         if let Some(in_map) = self.id_to_devices.get_mut(&id) {
             let existing = in_map.insert(event_path, device);
@@ -208,12 +452,13 @@ impl Inner {
             .map(|(id, _)| id)
             .copied()
         {
-            self.id_to_devices
-                .remove(&empty_after_remove)
-                .expect("just found")
-                .values()
-                .map(Device::name)
-                .collect_into(&mut removed);
+            removed.extend(
+                self.id_to_devices
+                    .remove(&empty_after_remove)
+                    .expect("just found")
+                    .values()
+                    .map(Device::name),
+            );
         }
 
         for inputs in self.id_to_devices.values_mut() {
@@ -232,6 +477,123 @@ impl Inner {
         }
     }
 
+    fn changed(&mut self) {
+        if let Some(callback) = &self.on_change {
+            callback();
+        }
+    }
+
+    /// Calls `on_keyboard_added` if the device at `event_path` is a keyboard
+    fn keyboard_added(&mut self, event_path: &Path) {
+        let Some(callback) = &self.on_keyboard_added else {
+            return;
+        };
+        let Some((id, device)) = self
+            .id_to_devices
+            .iter()
+            .find_map(|(id, devices)| Some((id, devices.get(event_path)?)))
+        else {
+            return;
+        };
+        if device_class::classify(&device.raw_dev).contains(&DeviceClass::Keyboard) {
+            callback(NewInput {
+                id: *id,
+                name: device.name(),
+                address: bluetooth_address(&device.raw_dev),
+                path: event_path.to_path_buf(),
+            });
+        }
+    }
+
+    /// Records how long blocking the device at `event_path` took if it got
+    /// locked after connecting
+    fn relocked(&mut self, event_path: &Path, connected: Instant) {
+        let Some(device) = self
+            .id_to_devices
+            .values()
+            .find_map(|devices| devices.get(event_path))
+            .filter(|device| device.locked)
+        else {
+            return;
+        };
+        let latency = connected.elapsed();
+        self.relock_latency = Some(latency);
+        if latency <= RELOCK_WARN_AFTER {
+            debug!(
+                "Blocked {} again {} ms after it connected",
+                device.name(),
+                latency.as_millis()
+            );
+            return;
+        }
+        let relock = SlowRelock {
+            device: device.name(),
+            latency,
+        };
+        warn!(device = relock.device, "{relock}");
+        if let Some(callback) = &self.on_slow_relock {
+            callback(relock);
+        }
+    }
+
+    /// Whether any connected device is on the block list
+    fn any_to_block(&mut self, to_block: &BlockList) -> bool {
+        self.id_to_devices.iter().any(|(id, devices)| {
+            devices.values().any(|device| {
+                to_block.matches(
+                    id,
+                    &device.name(),
+                    bluetooth_address(&device.raw_dev).as_deref(),
+                )
+            })
+        })
+    }
+
+    /// When the next busy device should be grabbed again
+    fn next_retry(&mut self) -> Option<Instant> {
+        self.id_to_devices
+            .values()
+            .flat_map(HashMap::values)
+            .filter_map(|device| device.failed.as_ref()?.next_retry)
+            .min()
+    }
+
+    /// The devices currently grabbed and their event path
+    fn grabbed_fds(&mut self) -> Vec<(PathBuf, RawFd)> {
+        self.id_to_devices
+            .values()
+            .flatten()
+            .filter(|(_, device)| device.locked)
+            .map(|(path, device)| (path.clone(), device.raw_dev.as_raw_fd()))
+            .collect()
+    }
+
+    /// A second handle to every locked mouse, it reads the input the grab
+    /// holds back. See `forward`.
+    fn grabbed_pointers(&mut self) -> Vec<evdev::Device> {
+        self.id_to_devices
+            .values()
+            .flat_map(HashMap::values)
+            .filter(|device| device.locked)
+            .filter(|device| {
+                device
+                    .raw_dev
+                    .supported_relative_axes()
+                    .is_some_and(|axes| axes.contains(RelativeAxisCode::REL_X))
+            })
+            .filter_map(|device| {
+                let opened = device
+                    .raw_dev
+                    .as_fd()
+                    .try_clone_to_owned()
+                    .and_then(evdev::Device::from_fd);
+                opened
+                    .inspect_err(|e| warn!("Could not read {}: {e}", device.name()))
+                    .ok()
+            })
+            .collect()
+    }
+
     fn list_inputs(&mut self) -> Result<Vec<BlockableInput>> {
         self.check_status()?;
 
@@ -241,25 +603,51 @@ impl Inner {
             .map(|(id, devices)| {
                 let mut names: Vec<_> = devices.values().map(Device::name).collect();
                 names.sort();
-                BlockableInput { id: *id, names }
+                let mut details: Vec<_> = devices
+                    .iter()
+                    .map(|(path, device)| DeviceDetails {
+                        name: device.name(),
+                        path: path.clone(),
+                        address: bluetooth_address(&device.raw_dev),
+                        classes: device_class::classify(&device.raw_dev),
+                    })
+                    .collect();
+                details.sort_by(|a, b| a.path.cmp(&b.path));
+                BlockableInput {
+                    id: *id,
+                    names,
+                    details,
+                }
             })
             .collect())
     }
 
-    fn unlock_all_matching(&mut self, filter: &InputFilter) -> Result<()> {
-        self.check_status()?;
-        let Some(to_lock) = self.id_to_devices.get_mut(&filter.id) else {
-            return Ok(());
-        };
+    /// Not only those with the filters id, a bluetooth device can match on
+    /// address alone
+    fn matching<'a>(&'a mut self, target: &'a Target) -> impl Iterator<Item = &'a mut Device> {
+        self.id_to_devices
+            .iter_mut()
+            .flat_map(|(id, devices)| devices.values_mut().map(move |device| (id, device)))
+            .filter(|(id, device)| device.matches(id, target))
+            .map(|(_, device)| device)
+    }
 
-        for device in to_lock
-            .values_mut()
-            .filter(|device| device.locked)
-            .filter(|device| filter.names.contains(&device.name()))
-        {
+    fn unlock_all_matching(&mut self, target: &Target) -> Result<()> {
+        self.check_status()?;
+        for device in self.matching(target) {
+            if let Some(failed) = device.failed.take() {
+                info!(
+                    "Gave up locking {} after {} retries",
+                    device.name(),
+                    failed.retries
+                );
+            }
+            if !device.locked {
+                continue;
+            }
             match device.raw_dev.ungrab() {
                 Ok(()) => {
-                    debug!("Unlocked: {}", device.name());
+                    debug!(device = device.name(), "Unlocked: {}", device.name());
                     device.locked = false;
                 }
                 Err(e) if device_removed(&e) => {
@@ -275,123 +663,317 @@ impl Inner {
                 }
             }
         }
+        self.clear_resolved_grab_failure();
         Ok(())
     }
 
-    fn lock_all_matching(&mut self, filter: &InputFilter) -> Result<()> {
-        self.check_status()?;
-        let Some(to_lock) = self.id_to_devices.get_mut(&filter.id) else {
-            return Ok(());
-        };
+    fn clear_resolved_grab_failure(&mut self) {
+        let still_failing = self
+            .id_to_devices
+            .values()
+            .flat_map(HashMap::values)
+            .any(|device| device.failed.is_some());
+        if !still_failing {
+            self.last_grab_failure = None;
+        }
+    }
 
-        for device in to_lock
-            .values_mut()
-            .filter(|device| !device.locked)
-            .filter(|device| filter.names.contains(&device.name()))
-        {
+    /// Devices that can not be grabbed, for example because another program
+    /// grabbed them, are reported once to `on_grab_failed`. Busy devices are
+    /// tried again once their retry is due, see `next_retry`.
+    fn lock_all_matching(&mut self, target: &Target) -> Result<()> {
+        self.check_status()?;
+        let now = Instant::now();
+        let mut failed = Vec::new();
+        let mut retried = Vec::new();
+        // asked at most once, logind is slow to ask
+        let mut screen_locked = None;
+        for device in self.matching(target).filter(|device| !device.locked) {
+            let retry_due = device
+                .failed
+                .as_ref()
+                .is_none_or(|failed| failed.next_retry.is_some_and(|at| at <= now));
+            if !retry_due {
+                continue;
+            }
+            wait_for_keys_up(device);
             match device.raw_dev.grab() {
                 Ok(()) => {
-                    debug!("Locked: {}", device.name());
+                    debug!(device = device.name(), "Locked: {}", device.name());
                     device.locked = true;
-                }
-                Err(e) if e.kind() == ErrorKind::ResourceBusy => {
-                    warn!("Could not lock, device busy: {}", device.name());
+                    if let Some(failed) = device.failed.take() {
+                        info!(
+                            device = device.name(),
+                            "Locked {} on retry {}",
+                            device.name(),
+                            failed.retries
+                        );
+                        retried.push(device.name());
+                    }
                 }
                 Err(e) if device_removed(&e) => {
                     warn!("Could not lock, device probably removed: {}", device.name());
                 }
-                err @ Err(_) => {
-                    return err
-                        .wrap_err("Could not grab (acquire exclusive access) to device")
-                        .with_note(|| format!("device name: {}", device.name()))
+                Err(error) => {
+                    let busy = error.kind() == ErrorKind::ResourceBusy;
+                    // the locker lets go once unlocked, keep retrying quickly
+                    let locker =
+                        busy && *screen_locked.get_or_insert_with(password_prompt::screen_locked);
+                    if locker && device.failed.is_none() {
+                        info!(
+                            "The screen locker holds {}, blocking it once unlocked",
+                            device.name()
+                        );
+                    }
+                    let failed_before = device.failed.get_or_insert(Failed {
+                        retries: 0,
+                        next_retry: None,
+                        reported: false,
+                    });
+                    if failed_before.reported || locker {
+                        if !locker {
+                            failed_before.retries += 1;
+                        }
+                        failed_before.next_retry =
+                            busy.then(|| now + retry_after(failed_before.retries));
+                        debug!("Still could not lock {}: {error}", device.name());
+                        continue;
+                    }
+                    failed_before.reported = true;
+                    failed_before.next_retry = busy.then(|| now + retry_after(0));
+                    let failure = GrabFailed {
+                        device: device.name(),
+                        error,
+                    };
+                    warn!(device = failure.device, "Could not lock: {failure}");
+                    failed.push(failure);
                 }
             }
         }
+        if let Some(failure) = failed.last() {
+            self.last_grab_failure = Some(failure.to_string());
+        } else {
+            self.clear_resolved_grab_failure();
+        }
+        if let Some(callback) = &self.on_grab_failed {
+            failed.into_iter().for_each(callback);
+        }
+        if let Some(callback) = &self.on_grab_retried {
+            retried.into_iter().for_each(callback);
+        }
         Ok(())
     }
 }
 
+/// Grabbing swallows the release of keys held at that moment, the compositor
+/// then sees them (often a modifier) as pressed for the rest of the session.
+/// Give the user a moment to let go.
+const KEYS_UP_TIMEOUT: Duration = Duration::from_secs(2);
+const KEYS_UP_POLL: Duration = Duration::from_millis(20);
+
+fn wait_for_keys_up(device: &Device) {
+    let held = || {
+        device
+            .raw_dev
+            .get_key_state()
+            .is_ok_and(|keys| keys.iter().next().is_some())
+    };
+    let deadline = Instant::now() + KEYS_UP_TIMEOUT;
+    while held() {
+        if Instant::now() >= deadline {
+            warn!(
+                "Locking {} while keys are held, they may stay stuck",
+                device.name()
+            );
+            return;
+        }
+        thread::sleep(KEYS_UP_POLL);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BlockableInput {
     pub id: InputId,
     pub names: Vec<String>,
+    /// one entry per event device
+    pub details: Vec<DeviceDetails>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceDetails {
+    pub name: String,
+    pub path: PathBuf,
+    pub address: Option<String>,
+    pub(crate) classes: Vec<DeviceClass>,
 }
 
 #[derive(Clone, Debug)]
 pub struct NewInput {
     pub id: InputId,
     pub name: String,
+    pub address: Option<String>,
     pub path: PathBuf,
 }
 
 pub fn devices() -> (OnlineDevices, Receiver<NewInput>) {
+    devices_in(paths::dev_dir().to_path_buf())
+}
+
+/// Like `devices` but watches `dir` instead of `paths::dev_dir`. Tests use this
+/// to only see the virtual devices they create.
+pub(crate) fn devices_in(dir: PathBuf) -> (OnlineDevices, Receiver<NewInput>) {
     let (order_tx, order_rx) = mpsc::channel();
     let mut online = OnlineDevices {
         tx: order_tx.clone(),
         inner: Arc::new(Mutex::new(Inner {
             status: Ok(()),
             id_to_devices: HashMap::new(),
+            on_change: None,
+            on_grab_failed: None,
+            on_grab_retried: None,
+            on_keyboard_added: None,
+            on_slow_relock: None,
+            relock_latency: None,
+            last_grab_failure: None,
         })),
     };
 
     let (new_dev_tx, new_dev_rx) = mpsc::channel();
-    send_initial_devices(&mut online, &new_dev_tx);
-    thread::spawn(move || {
-        send_new_devices(&order_tx);
-    });
+    send_initial_devices(&dir, &mut online, &new_dev_tx);
+    // with only passed in devices there is nothing to watch
+    if dir.is_dir() || privsep::passed_devices().next().is_none() {
+        let mut restarted = false;
+        supervisor::spawn(Subsystem::Inotify, move || {
+            let res = send_new_devices(&dir, &order_tx, restarted);
+            restarted = true;
+            res
+        });
+    }
 
     let mut locked = HashSet::new();
     let mut online2 = online.clone();
-    thread::spawn(move || loop {
-        match order_rx.recv_timeout(Duration::from_secs(5)) {
-            Ok(Event::LockRequested(filter, answer)) => {
-                let res = online2.lock_all_matching(&filter);
-                locked.insert(filter);
-                answer.send(res).expect("lock fn does not panic");
+    supervisor::spawn(Subsystem::DeviceWatcher, move || {
+        follow_orders(&order_rx, &mut online2, &mut locked, &new_dev_tx);
+        Ok(())
+    });
+
+    (online, new_dev_rx)
+}
+
+/// Returns once all `OnlineDevices` are dropped. The targets that are
+/// `locked` outlive a restart.
+fn follow_orders(
+    order_rx: &Receiver<Event>,
+    online: &mut OnlineDevices,
+    locked: &mut HashSet<Target>,
+    new_dev_tx: &Sender<NewInput>,
+) {
+    loop {
+        // retries end with the lock
+        let next_retry = if locked.is_empty() {
+            None
+        } else {
+            online.next_retry()
+        };
+        let event = match next_retry {
+            Some(at) => order_rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+            None => order_rx.recv().map_err(RecvTimeoutError::from),
+        };
+        match event {
+            Err(RecvTimeoutError::Timeout) => {
+                for target in locked.iter() {
+                    if let Err(e) = online.lock_all_matching(target) {
+                        error!("Failed to lock devices matching filter, error: {e:?}");
+                        online.inner().status = Err(e);
+                    }
+                }
+            }
+            Ok(Event::LockRequested(target, answer)) => {
+                let res = online.lock_all_matching(&target);
+                locked.insert(target);
+                let _requester_gone = answer.send(res);
             }
-            Ok(Event::UnLockRequested(filter, answer)) => {
-                locked.remove(&filter);
-                let res = online2.unlock_all_matching(&filter);
-                answer.send(res).expect("unlock fn does not panic");
+            Ok(Event::UnLockRequested(target, answer)) => {
+                locked.remove(&target);
+                let res = online.unlock_all_matching(&target);
+                // gone if a dropped `LockGuard` asked
+                let _requester_gone = answer.send(res);
             }
-            Ok(Event::DevAdded(event_path)) => {
-                add_device(&mut online2, &new_dev_tx, event_path);
-                for filter in &locked {
-                    if let Err(e) = online2.lock_all_matching(filter) {
+            Ok(Event::DevAdded(event_path, connected)) => {
+                let added = add_device(
+                    online,
+                    new_dev_tx,
+                    event_path.clone(),
+                    HOTPLUG_OPEN_ATTEMPTS,
+                );
+                for target in locked.iter() {
+                    if let Err(e) = online.lock_all_matching(target) {
                         error!("Failed to lock devices matching filter, error: {e:?}");
-                        online2.inner.lock().unwrap().status = Err(e);
+                        online.inner().status = Err(e);
                     }
                 }
+                if added.is_some() {
+                    online.relocked(&event_path, connected);
+                    online.keyboard_added(&event_path);
+                    online.changed();
+                }
             }
             Ok(Event::DevRemoved(event_path)) => {
-                online2.remove(&event_path);
-            }
-            Ok(Event::DevError(error)) => {
-                // next time online devices is queried it will report this error
-                online2.inner.lock().unwrap().status = error;
+                online.remove(&event_path);
+                online.changed();
             }
-
-            Err(RecvTimeoutError::Timeout) => continue,
             Err(RecvTimeoutError::Disconnected) => return,
         }
-    });
-
-    (online, new_dev_rx)
+    }
 }
 
-const DEV_DIR: &str = "/dev/input";
-fn send_initial_devices(online: &mut OnlineDevices, new_dev_tx: &Sender<NewInput>) {
-    for entry in fs::read_dir(DEV_DIR).unwrap() {
+fn send_initial_devices(dir: &Path, online: &mut OnlineDevices, new_dev_tx: &Sender<NewInput>) {
+    let mut passed: Vec<_> = privsep::passed_devices()
+        .map(|(path, _)| path.to_path_buf())
+        .collect();
+    // a container may only get the devices passed in and not the dir
+    if !passed.is_empty() && !dir.is_dir() {
+        for path in passed {
+            add_device(online, new_dev_tx, path, 1);
+        }
+        return;
+    }
+    for entry in fs::read_dir(dir).unwrap() {
         let entry = entry.unwrap();
         let path = entry.path();
         let fname = path.file_name().unwrap();
+        passed.retain(|passed| *passed != path);
         // note, there are legacy events (mouse/js) these are
         // duplicates of the event<number> devices. Therefore we
         // do not add them.
         if fname.as_bytes().starts_with(b"event") {
-            add_device(online, new_dev_tx, path);
+            add_device(online, new_dev_tx, path, 1);
         }
     }
+    for path in passed {
+        add_device(online, new_dev_tx, path, 1);
+    }
+}
+
+/// Udev may still be setting up a node that was just created. A device that
+/// reconnects during a break, like a bluetooth keyboard that power cycles,
+/// must still be locked before it is used.
+const HOTPLUG_OPEN_ATTEMPTS: u32 = 10;
+const HOTPLUG_OPEN_RETRY: Duration = Duration::from_millis(20);
+
+fn open_device(event_path: &Path, attempts: u32) -> Option<evdev::Device> {
+    for attempt in 1..=attempts {
+        match privsep::open_device(event_path)
+            .and_then(|file| evdev::Device::from_fd(OwnedFd::from(file)))
+        {
+            Ok(device) => return Some(device),
+            // disconnected again
+            Err(e) if e.kind() == ErrorKind::NotFound => break,
+            Err(_) if attempt < attempts => thread::sleep(HOTPLUG_OPEN_RETRY),
+            Err(_) => (),
+        }
+    }
+    None
 }
 
 type DeviceName = String;
@@ -399,8 +981,9 @@ fn add_device(
     online: &mut OnlineDevices,
     new_dev_tx: &Sender<NewInput>,
     event_path: PathBuf,
+    attempts: u32,
 ) -> Option<DeviceName> {
-    let Ok(device) = evdev::Device::open(&event_path) else {
+    let Some(device) = open_device(&event_path, attempts) else {
         warn!(
             "Could not open device at: {}, ignoring the device",
             event_path.display()
@@ -409,12 +992,14 @@ fn add_device(
     };
     let id = InputId::from(device.input_id());
     let name = device_name(&device);
-    let new = online.insert(device, event_path.clone());
+    let address = bluetooth_address(&device);
+    let new = online.insert(device, event_path.clone(), false);
     if new {
         new_dev_tx
             .send(NewInput {
                 id,
                 name: name.clone(),
+                address,
                 path: event_path,
             })
             .expect("watcher should never end and drop rx");
@@ -426,24 +1011,34 @@ fn add_device(
     }
 }
 
-fn send_new_devices(tx: &Sender<Event>) {
-    let mut inotify = Inotify::init().unwrap();
+/// Returns once the device watcher is gone. After a `restart` devices
+/// connected in the meantime are added, those already known are ignored.
+fn send_new_devices(dir: &Path, tx: &Sender<Event>, restart: bool) -> Result<()> {
+    let mut inotify = Inotify::init().wrap_err("Could not set up inotify")?;
     let mut buffer = [0; 1024];
 
     inotify
         .watches()
-        .add(DEV_DIR, WatchMask::CREATE | WatchMask::DELETE)
-        .unwrap();
+        .add(dir, WatchMask::CREATE | WatchMask::DELETE)
+        .wrap_err("Could not watch for devices")
+        .with_note(|| format!("directory: {}", dir.display()))?;
 
-    loop {
-        let events = match inotify.read_events_blocking(&mut buffer) {
-            Err(err) => {
-                let res = Err(err).wrap_err("inotify could not read events");
-                tx.send(Event::DevError(res)).unwrap();
-                return;
+    if restart {
+        for entry in fs::read_dir(dir).wrap_err("Could not list devices")? {
+            let path = entry.wrap_err("Could not list devices")?.path();
+            let is_event = path
+                .file_name()
+                .is_some_and(|name| name.as_bytes().starts_with(b"event"));
+            if is_event && tx.send(Event::DevAdded(path, Instant::now())).is_err() {
+                return Ok(());
             }
-            Ok(events) => events,
-        };
+        }
+    }
+
+    loop {
+        let events = inotify
+            .read_events_blocking(&mut buffer)
+            .wrap_err("inotify could not read events")?;
 
         for event in events {
             let Some(file_name) = event.name else {
@@ -456,12 +1051,255 @@ fn send_new_devices(tx: &Sender<Event>) {
                 continue;
             }
 
-            let path = PathBuf::from_str(DEV_DIR).unwrap().join(file_name);
-            if event.mask.contains(EventMask::CREATE) {
-                tx.send(Event::DevAdded(path.clone())).unwrap();
+            let path = dir.join(file_name);
+            let event = if event.mask.contains(EventMask::CREATE) {
+                Event::DevAdded(path, Instant::now())
             } else if event.mask.contains(EventMask::DELETE) {
-                tx.send(Event::DevRemoved(path.clone())).unwrap();
+                Event::DevRemoved(path)
+            } else {
+                continue;
+            };
+            if tx.send(event).is_err() {
+                return Ok(()); // the device watcher is gone
+            }
+        }
+    }
+}
+
+/// These create virtual devices through uinput, that needs root. Run them
+/// using: `sudo cargo test --features uinput-tests`
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::symlink;
+    use std::time::{Duration, Instant};
+    use std::{fs, thread};
+
+    use evdev::uinput::VirtualDevice;
+    use evdev::{AttributeSet, BusType, EventType, InputEvent, KeyCode};
+
+    use super::*;
+    use crate::check_inputs::{watcher, WatchOptions};
+    use crate::clock::RecvTimeout;
+    use crate::config::BlockList;
+    use crate::input_queue::InputReceiver;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A virtual keyboard linked into its own device directory
+    struct VirtualInput {
+        device: VirtualDevice,
+        node: PathBuf,
+        dir: PathBuf,
+        filter: InputFilter,
+    }
+
+    impl VirtualInput {
+        fn new(name: &str) -> Self {
+            let keys = AttributeSet::from_iter([KeyCode::KEY_A]);
+            let id = evdev::InputId::new(BusType::BUS_VIRTUAL, 0xbe, 0xef, 1);
+            let mut device = VirtualDevice::builder()
+                .unwrap()
+                .name(name)
+                .input_id(id.clone())
+                .with_keys(&keys)
+                .unwrap()
+                .build()
+                .unwrap();
+            let node = device
+                .enumerate_dev_nodes_blocking()
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+
+            let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir(&dir).unwrap();
+            Self {
+                device,
+                node,
+                dir,
+                filter: InputFilter {
+                    id: InputId::from(id),
+                    names: vec![name.to_string()],
+                    address: None,
+                    activity: None,
+                },
+            }
+        }
+
+        /// makes the device show up in `dir` as if it was just connected
+        fn plug_in(&self) {
+            symlink(&self.node, self.dir.join("event0")).unwrap();
+        }
+
+        fn unplug(&self) {
+            fs::remove_file(self.dir.join("event0")).unwrap();
+        }
+
+        fn press_key(&mut self) {
+            self.key_down();
+            self.key_up();
+        }
+
+        fn key_down(&mut self) {
+            let down = InputEvent::new(EventType::KEY.0, KeyCode::KEY_A.0, 1);
+            self.device.emit(&[down]).unwrap();
+        }
+
+        fn key_up(&mut self) {
+            let up = InputEvent::new(EventType::KEY.0, KeyCode::KEY_A.0, 0);
+            self.device.emit(&[up]).unwrap();
+        }
+
+        /// Grabbing fails with EBUSY while someone else holds a grab
+        fn is_grabbed(&self) -> bool {
+            let mut device = evdev::Device::open(&self.node).unwrap();
+            match device.grab() {
+                Ok(()) => {
+                    device.ungrab().unwrap();
+                    false
+                }
+                Err(e) if e.raw_os_error() == Some(nix::libc::EBUSY) => true,
+                Err(e) => panic!("unexpected error grabbing: {e}"),
             }
         }
     }
+
+    impl Drop for VirtualInput {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    fn recv_input(rx: &InputReceiver) {
+        rx.recv_timeout(TIMEOUT)
+            .expect("watcher should report input")
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "uinput-tests"), ignore = "needs root and /dev/uinput")]
+    fn grabs_while_locked() {
+        let input = VirtualInput::new("break-enforcer-test-lock");
+        input.plug_in();
+        let (online, _new) = devices_in(input.dir.clone());
+        assert!(!input.is_grabbed());
+
+        let guard = online.lock(input.filter.clone()).unwrap();
+        assert!(input.is_grabbed());
+        guard.unlock().unwrap();
+        assert!(!input.is_grabbed());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "uinput-tests"), ignore = "needs root and /dev/uinput")]
+    fn grabs_device_connected_while_locked() {
+        let input = VirtualInput::new("break-enforcer-test-hotplug");
+        let (online, new) = devices_in(input.dir.clone());
+        let guard = online.lock(input.filter.clone()).unwrap();
+
+        input.plug_in();
+        let connected = new.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(connected.id, input.filter.id);
+        assert!(wait_until(|| input.is_grabbed()));
+        guard.unlock().unwrap();
+        assert!(!input.is_grabbed());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "uinput-tests"), ignore = "needs root and /dev/uinput")]
+    fn relocks_reconnected_device_quickly() {
+        let input = VirtualInput::new("break-enforcer-test-reconnect");
+        input.plug_in();
+        let (online, _new) = devices_in(input.dir.clone());
+        let guard = online.lock(input.filter.clone()).unwrap();
+        assert!(input.is_grabbed());
+
+        input.unplug();
+        assert!(wait_until(|| !input.is_grabbed()));
+        input.plug_in();
+        assert!(wait_until(|| online.relock_latency().is_some()));
+        assert!(input.is_grabbed());
+        let latency = online.relock_latency().expect("reconnected while locked");
+        assert!(latency <= RELOCK_WARN_AFTER, "took {latency:?}");
+        guard.unlock().unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "uinput-tests"), ignore = "needs root and /dev/uinput")]
+    fn retries_busy_device() {
+        let input = VirtualInput::new("break-enforcer-test-busy");
+        input.plug_in();
+        let (online, _new) = devices_in(input.dir.clone());
+        let mut other = evdev::Device::open(&input.node).unwrap();
+        other.grab().unwrap();
+
+        let guard = online.lock(input.filter.clone()).unwrap();
+        other.ungrab().unwrap();
+        assert!(wait_until(|| input.is_grabbed()));
+        guard.unlock().unwrap();
+        assert!(!input.is_grabbed());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "uinput-tests"), ignore = "needs root and /dev/uinput")]
+    fn reports_input_of_blocked_devices() {
+        let mut input = VirtualInput::new("break-enforcer-test-input");
+        input.plug_in();
+        let (_online, new) = devices_in(input.dir.clone());
+        let block_list = BlockList::new(vec![input.filter.clone()], None, false);
+        let activity = watcher(new, block_list, WatchOptions::default());
+
+        // give the watcher time to open the device
+        thread::sleep(Duration::from_millis(200));
+        input.press_key();
+        recv_input(&activity);
+    }
+
+    #[test]
+    fn grab_failure_warning_is_one_argument() {
+        let failure = GrabFailed {
+            device: "x\"$(touch /tmp/pwned)\n`id`".to_string(),
+            error: io::Error::from(ErrorKind::ResourceBusy),
+        };
+        let message = failure.to_string();
+        assert_eq!(
+            message,
+            "could not block x\"$(touch /tmp/pwned)`id`, another program grabbed it"
+        );
+
+        let command = crate::integration::notification::notify_send_as("user", "1000", &message);
+        assert_eq!(command.get_args().last(), Some(message.as_ref()));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "uinput-tests"), ignore = "needs root and /dev/uinput")]
+    fn waits_for_held_keys_before_grabbing() {
+        let mut input = VirtualInput::new("break-enforcer-test-held");
+        input.plug_in();
+        let (online, _new) = devices_in(input.dir.clone());
+
+        input.key_down();
+        let filter = input.filter.clone();
+        let locking = thread::spawn(move || online.lock(filter).unwrap());
+        thread::sleep(Duration::from_millis(300));
+        assert!(!input.is_grabbed(), "should not grab while a key is held");
+
+        input.key_up();
+        let guard = locking.join().unwrap();
+        assert!(input.is_grabbed());
+        guard.unlock().unwrap();
+    }
 }
@@ -1,43 +1,116 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
-use color_eyre::eyre::Context;
-use color_eyre::Result;
-use dialoguer::{Confirm, MultiSelect};
+use clap::Parser;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::{Result, Section};
+use dialoguer::{Confirm, Input, MultiSelect, Select};
 use itertools::Itertools;
 
+use crate::cli::{self, Cli, Commands};
 use crate::config::{self, InputFilter};
+use crate::device_class::DeviceClass;
+use crate::integration::{fmt_dur, NotificationType};
+use crate::paths::{self, Dirs};
 use crate::watch_and_block::{self, BlockableInput};
+use crate::{install, joystick};
 
 // todo deal with devices with multiple names
-pub fn run(custom_config_path: Option<PathBuf>) -> Result<()> {
+pub fn run(custom_config_path: Option<PathBuf>, dirs: &Dirs, log_args: &[String]) -> Result<()> {
     let (devices, _) = watch_and_block::devices();
 
-    let config: HashMap<_, _> = config::read(custom_config_path.clone())
-        .wrap_err("Could not read custom config")?
+    let filters =
+        config::read(custom_config_path.clone()).wrap_err("Could not read custom config")?;
+    // edited in by hand, kept for the devices that stay selected
+    let thresholds: HashMap<_, _> = filters
+        .iter()
+        .filter_map(|filter| Some((filter.id, filter.activity?)))
+        .collect();
+    let config: HashMap<_, _> = filters
         .into_iter()
-        .map(|InputFilter { id, names }| (id, names))
+        .map(|InputFilter { id, names, .. }| (id, names))
         .collect();
 
-    let mut inputs = devices.list_inputs().wrap_err("Could not list inputs")?;
-    for BlockableInput { names, .. } in &mut inputs {
-        names.sort();
-    }
-    let mut inputs: Vec<_> = inputs
+    let listed = devices.list_inputs().wrap_err("Could not list inputs")?;
+    // lets bluetooth devices be blocked after reconnecting under another name
+    let addresses: HashMap<_, _> = listed
+        .iter()
+        .filter_map(|input| {
+            let address = input.details.iter().find_map(|d| d.address.clone())?;
+            Some((input.id, address))
+        })
+        .collect();
+    let filter = |(id, names)| InputFilter {
+        id,
+        names,
+        address: addresses.get(&id).cloned(),
+        activity: thresholds.get(&id).copied(),
+    };
+
+    let mut inputs: Vec<_> = listed
+        .into_iter()
+        .flat_map(|BlockableInput { id, details, .. }| {
+            details
+                .into_iter()
+                .map(move |device| ((id, device.name), device.classes))
+        })
+        .into_grouping_map()
+        .fold(BTreeSet::new(), |mut all, _, classes| {
+            all.extend(classes);
+            all
+        })
+        .into_iter()
+        .map(|((id, name), classes)| (classes.first().copied(), id, name))
+        .collect();
+    // nothing to grab, see `joystick`
+    let legacy: Vec<_> = joystick::legacy_only(paths::dev_dir())
         .into_iter()
-        .flat_map(|BlockableInput { names, id }| names.into_iter().map(move |n| (id, n)))
+        .map(|joystick| joystick.name)
         .collect();
-    inputs.dedup_by(|a, b| *a == *b);
+    if !legacy.is_empty() {
+        println!(
+            "Not listed, these only have a legacy joystick node and can not be blocked: {}",
+            legacy.join(", ")
+        );
+    }
+    // group by class, keyboards first and unclassified devices last
+    inputs.sort_by(|(class_a, _, name_a), (class_b, _, name_b)| {
+        (class_a.is_none(), class_a, name_a).cmp(&(class_b.is_none(), class_b, name_b))
+    });
+
+    let game_controllers = config.is_empty()
+        && inputs
+            .iter()
+            .any(|(class, ..)| matches!(class, Some(DeviceClass::Gamepad | DeviceClass::Joystick)))
+        && Confirm::new()
+            .with_prompt("Gamepads or joysticks are connected, block those too?")
+            .default(false)
+            .interact()
+            .unwrap();
 
     let mut options: Vec<_> = inputs
         .iter()
-        .map(|(id, name)| {
-            let checked = config.get(id).is_some_and(|names| names.contains(name));
-            (name, checked)
+        .map(|(class, id, name)| {
+            let checked = if config.is_empty() {
+                match class {
+                    Some(DeviceClass::Keyboard | DeviceClass::Mouse) => true,
+                    Some(DeviceClass::Gamepad | DeviceClass::Joystick) => game_controllers,
+                    _ => false,
+                }
+            } else {
+                config.get(id).is_some_and(|names| names.contains(name))
+            };
+            let class = class.map_or_else(|| String::from("other"), |c| c.to_string());
+            (format!("[{class}] {name}"), checked)
         })
         .collect();
+    let inputs: Vec<_> = inputs.into_iter().map(|(_, id, name)| (id, name)).collect();
+
+    if config.is_empty() {
+        println!("No devices configured yet, keyboards and mice are preselected");
+    }
 
     loop {
         let Some(selection) = MultiSelect::new()
@@ -67,10 +140,7 @@ pub fn run(custom_config_path: Option<PathBuf>) -> Result<()> {
                 .map(|checked| inputs[*checked].clone())
                 .into_group_map()
                 .into_iter()
-                .map(|(id, names)| InputFilter {
-                    id,
-                    names: names.clone(),
-                })
+                .map(filter)
                 .map(|filter| devices.lock(filter))
                 .collect::<Result<_>>()?;
 
@@ -100,10 +170,264 @@ pub fn run(custom_config_path: Option<PathBuf>) -> Result<()> {
                 .map(|(_, (id, name))| (id, name))
                 .into_group_map()
                 .into_iter()
-                .map(|(id, names)| InputFilter { id, names })
+                .map(filter)
                 .collect();
-            config::write(&selected, custom_config_path).unwrap();
-            return Ok(());
+            config::write(&selected, custom_config_path.clone()).unwrap();
+            return set_up_schedule(custom_config_path, dirs, log_args);
+        }
+    }
+}
+
+/// The schedule is not part of the config, it is passed to the service as
+/// arguments. Therefore this ends in installing the service.
+fn set_up_schedule(config_path: Option<PathBuf>, dirs: &Dirs, log_args: &[String]) -> Result<()> {
+    let Some(true) = Confirm::new()
+        .with_prompt("Set up the schedule and install the service?")
+        .default(true)
+        .interact_opt()
+        .unwrap()
+    else {
+        println!("Devices saved, run `install` to start enforcing breaks");
+        return Ok(());
+    };
+
+    let (mut args, warns) = schedule();
+    let break_duration = install_args(args.clone())?.run_args.break_duration;
+    args.extend(long_break(break_duration));
+    let types = if warns { warning_types()? } else { Vec::new() };
+    if warns && types.is_empty() {
+        println!("Breaks start without a warning");
+        args = without_warning(args)?;
+    }
+    for warning_type in &types {
+        args.push("--lock-warning-type".to_string());
+        args.push(warning_type.to_string());
+    }
+    if types.contains(&NotificationType::System)
+        && Confirm::new()
+            .with_prompt("Also notify when breaks start and end?")
+            .default(false)
+            .interact()
+            .unwrap()
+    {
+        args.push("--notifications".to_string());
+    }
+
+    println!("Installing with: {}", args.join(" "));
+    install::set_up(&install_args(args)?, config_path, dirs, log_args)
+}
+
+fn install_args(args: Vec<String>) -> Result<cli::InstallArgs> {
+    let cli = Cli::try_parse_from(
+        [env!("CARGO_BIN_NAME"), "install"]
+            .into_iter()
+            .map(String::from)
+            .chain(args),
+    )
+    .wrap_err("The wizard built invalid arguments")?;
+    let Commands::Install(install_args) = cli.command else {
+        return Err(eyre!("The wizard built arguments for the wrong command"));
+    };
+    Ok(install_args)
+}
+
+/// Empty if the user wants no long breaks
+fn long_break(break_duration: Duration) -> Vec<String> {
+    let long = Input::<String>::new()
+        .with_prompt("Make every few breaks a long one, its duration, empty for none")
+        .allow_empty(true)
+        .validate_with(|answer: &String| {
+            if answer.is_empty() {
+                return Ok(());
+            }
+            match cli::parse_duration(answer) {
+                Ok(long) if long <= break_duration => Err(format!(
+                    "must be longer than the break duration ({})",
+                    fmt_dur(break_duration)
+                )),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+        .interact_text()
+        .unwrap();
+    if long.is_empty() {
+        return Vec::new();
+    }
+    let every = Input::<u32>::new()
+        .with_prompt("Every how many breaks")
+        .default(4)
+        .validate_with(|every: &u32| {
+            if *every < 2 {
+                Err("must be at least 2")
+            } else {
+                Ok(())
+            }
+        })
+        .interact_text()
+        .unwrap();
+    vec![
+        "--long-break".to_string(),
+        long,
+        "--long-break-every".to_string(),
+        every.to_string(),
+    ]
+}
+
+/// Sends a sample of each picked type, one the user did not notice can be
+/// swapped for the other. A missing notify-send or aplay then shows up now
+/// instead of at the first break. Picking none means no warning, it is an
+/// error only if none of the picked types works.
+fn warning_types() -> Result<Vec<NotificationType>> {
+    const TYPES: [(NotificationType, &str, &str); 2] = [
+        (NotificationType::System, "system notification", "see"),
+        (NotificationType::Audio, "sound", "hear"),
+    ];
+    let picked = MultiSelect::new()
+        .with_prompt("How to warn before a break (space to select)")
+        .items_checked(&[(TYPES[0].1, true), (TYPES[1].1, false)])
+        .interact()
+        .unwrap();
+    if picked.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut to_try: Vec<_> = picked.into_iter().map(|idx| &TYPES[idx]).collect();
+    let mut tried = Vec::new();
+    let mut working = Vec::new();
+    while let Some(entry) = to_try.pop() {
+        let (warning_type, name, sense) = entry;
+        tried.push(warning_type.clone());
+        println!("Sending a {name} to try it out");
+        let noticed = match warning_type.send_sample() {
+            Ok(()) => Confirm::new()
+                .with_prompt(format!("Did you {sense} it?"))
+                .default(true)
+                .interact()
+                .unwrap(),
+            Err(report) => {
+                println!("That did not work: {report:#}");
+                false
+            }
+        };
+        if noticed {
+            working.push(warning_type.clone());
+            continue;
         }
+        let Some(other) = TYPES.iter().find(|(other, ..)| !tried.contains(other)) else {
+            continue;
+        };
+        if !to_try.contains(&other)
+            && Confirm::new()
+                .with_prompt(format!("Try a {} instead?", other.1))
+                .default(true)
+                .interact()
+                .unwrap()
+        {
+            to_try.push(other);
+        }
+    }
+
+    if working.is_empty() {
+        return Err(eyre!("No way to warn before a break works"))
+            .suggestion("Install notify-send or aplay then run the wizard again");
+    }
+    Ok(working)
+}
+
+/// The `schedule` arguments without the warning. A preset always warns, it is
+/// replaced by the durations it expands to.
+fn without_warning(mut args: Vec<String>) -> Result<Vec<String>> {
+    if let Some(index) = args.iter().position(|arg| arg == "--lock-warning") {
+        args.drain(index..=index + 1);
+        return Ok(args);
+    }
+    let cli = Cli::try_parse_from(
+        [env!("CARGO_BIN_NAME"), "run"]
+            .into_iter()
+            .map(String::from)
+            .chain(args),
+    )
+    .wrap_err("The wizard built invalid arguments")?;
+    let Commands::Run(run_args) = cli.command else {
+        return Err(eyre!("The wizard built arguments for the wrong command"));
+    };
+    let mut args = vec![
+        "--work-duration".to_string(),
+        fmt_dur(run_args.work_duration),
+        "--break-duration".to_string(),
+        fmt_dur(run_args.break_duration),
+    ];
+    if let Some(delay) = run_args.lock_delay {
+        args.push("--lock-delay".to_string());
+        args.push(fmt_dur(delay));
+    }
+    Ok(args)
+}
+
+/// Arguments for a preset or custom durations and whether they include a
+/// warning before the break
+fn schedule() -> (Vec<String>, bool) {
+    const PRESETS: [(&str, &str); 3] = [
+        (
+            "pomodoro",
+            "pomodoro: 5 minutes off after 25 minutes of work",
+        ),
+        (
+            "rsi",
+            "rsi: 5 minutes off every half hour, against strain injury",
+        ),
+        (
+            "eyes",
+            "eyes: 20 seconds off every 20 minutes, against eye strain",
+        ),
+    ];
+    let items: Vec<_> = PRESETS
+        .iter()
+        .map(|(_, description)| *description)
+        .chain(["custom: pick the durations yourself"])
+        .collect();
+    let picked = Select::new()
+        .with_prompt("Pick a schedule")
+        .items(&items)
+        .default(0)
+        .interact()
+        .unwrap();
+    if let Some((preset, _)) = PRESETS.get(picked) {
+        // every preset warns
+        return (vec!["--preset".to_string(), (*preset).to_string()], true);
+    }
+
+    let mut args = vec![
+        "--work-duration".to_string(),
+        ask_duration("Work duration, for example 45m", Some("45m")),
+        "--break-duration".to_string(),
+        ask_duration("Break duration, for example 5m", Some("5m")),
+    ];
+    let warning = ask_duration("Warn this long before a break, empty for no warning", None);
+    let warns = !warning.is_empty();
+    if warns {
+        args.push("--lock-warning".to_string());
+        args.push(warning);
+    }
+    (args, warns)
+}
+
+/// Without a default an empty answer is allowed
+fn ask_duration(prompt: &str, default: Option<&str>) -> String {
+    let mut input = Input::<String>::new()
+        .with_prompt(prompt)
+        .allow_empty(default.is_none())
+        .validate_with(|answer: &String| {
+            if answer.is_empty() {
+                return Ok(());
+            }
+            cli::parse_duration(answer)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+    if let Some(default) = default {
+        input = input.default(default.to_string());
     }
+    input.interact_text().unwrap()
 }